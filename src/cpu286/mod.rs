@@ -7,13 +7,38 @@ pub trait Cpu286Context {
     fn mem_write_byte(&mut self, addr: u32, value: u8);
     fn io_read_byte(&mut self, addr: u16) -> u8;
     fn io_write_byte(&mut self, addr: u16, value: u8);
+    /// Word port access, split into two byte accesses unless a 16-bit
+    /// device answers at the port.
+    fn io_read_word(&mut self, addr: u16) -> u16 {
+        let lo = self.io_read_byte(addr);
+        let hi = self.io_read_byte(addr.wrapping_add(1));
+        u16::from_le_bytes([lo, hi])
+    }
+    fn io_write_word(&mut self, addr: u16, value: u16) {
+        self.io_write_byte(addr, value as u8);
+        self.io_write_byte(addr.wrapping_add(1), (value >> 8) as u8);
+    }
+    /// Level of the maskable INTR input.
+    fn intr_pending(&mut self) -> bool {
+        false
+    }
+    /// Runs the INTA bus cycle, returning the vector supplied by the
+    /// interrupt controller.
+    fn intr_acknowledge(&mut self) -> u8 {
+        0
+    }
+    /// Returns true once for every rising edge on the NMI input.
+    fn nmi_pending(&mut self) -> bool {
+        false
+    }
 }
 
 #[derive(Clone, Debug, Default)]
 pub struct Cpu286 {
     pub regs: Registers,
     pub opcode: u8,
-    pub floppy: Vec<u8>,
+    pub halted: bool,
+    pub inhibit_interrupts: bool,
 }
 
 impl Cpu286 {
@@ -21,7 +46,8 @@ impl Cpu286 {
         Cpu286 {
             regs: Registers::new(),
             opcode: 0,
-            floppy: vec![],
+            halted: false,
+            inhibit_interrupts: false,
         }
     }
     pub fn mem_read_byte<T: Cpu286Context>(&mut self, ctx: &mut T, addr: u32) -> u8 {
@@ -40,12 +66,69 @@ impl Cpu286 {
         u16::from_le_bytes([lo, hi])
     }
 
+    pub fn mem_write_word<T: Cpu286Context>(&mut self, ctx: &mut T, addr: u32, value: u16) {
+        self.mem_write_byte(ctx, addr, value as u8);
+        self.mem_write_byte(ctx, addr.wrapping_add(1), (value >> 8) as u8);
+    }
+
+    pub fn push16<T: Cpu286Context>(&mut self, ctx: &mut T, value: u16) {
+        let stack_pointer = self.regs.read16(Reg16::SP).wrapping_sub(2);
+        self.regs.write16(Reg16::SP, stack_pointer);
+        self.mem_write_word(
+            ctx,
+            self.regs.readseg16(SegReg::SS).base + stack_pointer as u32,
+            value,
+        );
+    }
+
+    /// Enters a real-mode interrupt handler through the IDT, pushing FLAGS,
+    /// CS and IP and clearing IF and TF.
+    pub fn interrupt<T: Cpu286Context>(&mut self, ctx: &mut T, vector: u8) {
+        if (self.regs.msw & 1) != 0 {
+            panic!("Protected mode interrupts not implemented yet!");
+        }
+        self.push16(ctx, self.regs.read16(Reg16::FLAGS));
+        self.regs.flags.set(Flags::INTERRUPT, false);
+        self.regs.flags.set(Flags::TRAP, false);
+        self.push16(ctx, self.regs.readseg16(SegReg::CS).selector);
+        self.push16(ctx, self.regs.ip);
+        let entry = self.regs.idtr.base + ((vector as u32) << 2);
+        let offset = self.mem_read_word(ctx, entry);
+        let segment = self.mem_read_word(ctx, entry + 2);
+        self.regs.writeseg16(SegReg::CS, segment);
+        self.regs.ip = offset;
+    }
+
+    /// Samples NMI and INTR at an instruction boundary, entering the handler
+    /// and leaving the halt state if an interrupt is accepted.
+    pub fn check_interrupts<T: Cpu286Context>(&mut self, ctx: &mut T) -> bool {
+        if self.inhibit_interrupts {
+            self.inhibit_interrupts = false;
+            return false;
+        }
+        if ctx.nmi_pending() {
+            self.halted = false;
+            self.interrupt(ctx, 2);
+            return true;
+        }
+        if self.regs.flags.contains(Flags::INTERRUPT) && ctx.intr_pending() {
+            self.halted = false;
+            let vector = ctx.intr_acknowledge();
+            self.interrupt(ctx, vector);
+            return true;
+        }
+        false
+    }
+
     pub fn tick<T: Cpu286Context>(&mut self, ctx: &mut T) -> usize {
+        if self.check_interrupts(ctx) || self.halted {
+            return 2;
+        }
         self.opcode = self.mem_read_byte(
             ctx,
             self.regs.readseg16(SegReg::CS).base + self.regs.ip as u32,
         );
-        println!(
+        trace!(
             "Opcode {:#02x} CS base {:#06x} IP {:#04x}",
             self.opcode,
             self.regs.readseg16(SegReg::CS).base,
@@ -53,7 +136,7 @@ impl Cpu286 {
         );
         match self.opcode {
             0x70 => {
-                println!("jo");
+                trace!("jo");
                 self.regs.ip = self.regs.ip.wrapping_add(1);
                 let offset: i16 = self.mem_read_byte(
                     ctx,
@@ -65,7 +148,7 @@ impl Cpu286 {
                 }
             }
             0x71 => {
-                println!("jno");
+                trace!("jno");
                 self.regs.ip = self.regs.ip.wrapping_add(1);
                 let offset: i16 = self.mem_read_byte(
                     ctx,
@@ -77,7 +160,7 @@ impl Cpu286 {
                 }
             }
             0x72 => {
-                println!("jc");
+                trace!("jc");
                 self.regs.ip = self.regs.ip.wrapping_add(1);
                 let offset: i16 = self.mem_read_byte(
                     ctx,
@@ -89,7 +172,7 @@ impl Cpu286 {
                 }
             }
             0x73 => {
-                println!("jnc");
+                trace!("jnc");
                 self.regs.ip = self.regs.ip.wrapping_add(1);
                 let offset: i16 = self.mem_read_byte(
                     ctx,
@@ -101,7 +184,7 @@ impl Cpu286 {
                 }
             }
             0x74 => {
-                println!("jz");
+                trace!("jz");
                 self.regs.ip = self.regs.ip.wrapping_add(1);
                 let offset: i16 = self.mem_read_byte(
                     ctx,
@@ -113,7 +196,7 @@ impl Cpu286 {
                 }
             }
             0x75 => {
-                println!("jnz");
+                trace!("jnz");
                 self.regs.ip = self.regs.ip.wrapping_add(1);
                 let offset: i16 = self.mem_read_byte(
                     ctx,
@@ -125,7 +208,7 @@ impl Cpu286 {
                 }
             }
             0x78 => {
-                println!("js");
+                trace!("js");
                 self.regs.ip = self.regs.ip.wrapping_add(1);
                 let offset: i16 = self.mem_read_byte(
                     ctx,
@@ -137,7 +220,7 @@ impl Cpu286 {
                 }
             }
             0x79 => {
-                println!("jns");
+                trace!("jns");
                 self.regs.ip = self.regs.ip.wrapping_add(1);
                 let offset: i16 = self.mem_read_byte(
                     ctx,
@@ -149,7 +232,7 @@ impl Cpu286 {
                 }
             }
             0x7a => {
-                println!("jp");
+                trace!("jp");
                 self.regs.ip = self.regs.ip.wrapping_add(1);
                 let offset: i16 = self.mem_read_byte(
                     ctx,
@@ -161,7 +244,7 @@ impl Cpu286 {
                 }
             }
             0x7b => {
-                println!("jnp");
+                trace!("jnp");
                 self.regs.ip = self.regs.ip.wrapping_add(1);
                 let offset: i16 = self.mem_read_byte(
                     ctx,
@@ -173,7 +256,7 @@ impl Cpu286 {
                 }
             }
             0x9e => {
-                println!("sahf");
+                trace!("sahf");
                 self.regs.flags = Flags::from_bits(
                     (self.regs.flags.bits() & 0xff02) | (self.regs.read8(Reg8::AH) as u16),
                 )
@@ -181,7 +264,7 @@ impl Cpu286 {
                 self.regs.ip = self.regs.ip.wrapping_add(1);
             }
             0x9f => {
-                println!("lahf");
+                trace!("lahf");
                 self.regs.write8(
                     Reg8::AH,
                     ((self.regs.flags.bits() & 0xd5) | (0x0002_u16)) as u8,
@@ -189,7 +272,7 @@ impl Cpu286 {
                 self.regs.ip = self.regs.ip.wrapping_add(1);
             }
             0xb0 => {
-                println!("mov al, imm");
+                trace!("mov al, imm");
                 let imm_value = self.mem_read_byte(
                     ctx,
                     self.regs.readseg16(SegReg::CS).base + self.regs.ip.wrapping_add(1) as u32,
@@ -198,7 +281,7 @@ impl Cpu286 {
                 self.regs.ip = self.regs.ip.wrapping_add(2);
             }
             0xb1 => {
-                println!("mov cl, imm");
+                trace!("mov cl, imm");
                 let imm_value = self.mem_read_byte(
                     ctx,
                     self.regs.readseg16(SegReg::CS).base + self.regs.ip.wrapping_add(1) as u32,
@@ -207,7 +290,7 @@ impl Cpu286 {
                 self.regs.ip = self.regs.ip.wrapping_add(2);
             }
             0xb2 => {
-                println!("mov dl, imm");
+                trace!("mov dl, imm");
                 let imm_value = self.mem_read_byte(
                     ctx,
                     self.regs.readseg16(SegReg::CS).base + self.regs.ip.wrapping_add(1) as u32,
@@ -216,7 +299,7 @@ impl Cpu286 {
                 self.regs.ip = self.regs.ip.wrapping_add(2);
             }
             0xb3 => {
-                println!("mov bl, imm");
+                trace!("mov bl, imm");
                 let imm_value = self.mem_read_byte(
                     ctx,
                     self.regs.readseg16(SegReg::CS).base + self.regs.ip.wrapping_add(1) as u32,
@@ -225,7 +308,7 @@ impl Cpu286 {
                 self.regs.ip = self.regs.ip.wrapping_add(2);
             }
             0xb4 => {
-                println!("mov ah, imm");
+                trace!("mov ah, imm");
                 let imm_value = self.mem_read_byte(
                     ctx,
                     self.regs.readseg16(SegReg::CS).base + self.regs.ip.wrapping_add(1) as u32,
@@ -234,7 +317,7 @@ impl Cpu286 {
                 self.regs.ip = self.regs.ip.wrapping_add(2);
             }
             0xb5 => {
-                println!("mov ch, imm");
+                trace!("mov ch, imm");
                 let imm_value = self.mem_read_byte(
                     ctx,
                     self.regs.readseg16(SegReg::CS).base + self.regs.ip.wrapping_add(1) as u32,
@@ -243,7 +326,7 @@ impl Cpu286 {
                 self.regs.ip = self.regs.ip.wrapping_add(2);
             }
             0xb6 => {
-                println!("mov dh, imm");
+                trace!("mov dh, imm");
                 let imm_value = self.mem_read_byte(
                     ctx,
                     self.regs.readseg16(SegReg::CS).base + self.regs.ip.wrapping_add(1) as u32,
@@ -252,7 +335,7 @@ impl Cpu286 {
                 self.regs.ip = self.regs.ip.wrapping_add(2);
             }
             0xb7 => {
-                println!("mov bh, imm");
+                trace!("mov bh, imm");
                 let imm_value = self.mem_read_byte(
                     ctx,
                     self.regs.readseg16(SegReg::CS).base + self.regs.ip.wrapping_add(1) as u32,
@@ -261,7 +344,7 @@ impl Cpu286 {
                 self.regs.ip = self.regs.ip.wrapping_add(2);
             }
             0xe9 => {
-                println!("jmp near");
+                trace!("jmp near");
                 let offset = self.mem_read_word(
                     ctx,
                     self.regs.readseg16(SegReg::CS).base + self.regs.ip.wrapping_add(1) as u32,
@@ -269,7 +352,7 @@ impl Cpu286 {
                 self.regs.ip = self.regs.ip.wrapping_add(offset);
             }
             0xea => {
-                println!("jmp far");
+                trace!("jmp far");
                 let offset = self.mem_read_word(
                     ctx,
                     self.regs.readseg16(SegReg::CS).base + self.regs.ip.wrapping_add(1) as u32,
@@ -282,7 +365,7 @@ impl Cpu286 {
                 self.regs.ip = offset;
             }
             0xeb => {
-                println!("jmp rel8");
+                trace!("jmp rel8");
                 let offset = self.mem_read_byte(
                     ctx,
                     self.regs.readseg16(SegReg::CS).base +
@@ -290,13 +373,21 @@ impl Cpu286 {
                 );
                 self.regs.ip = self.regs.ip.wrapping_add((offset as i8 as u16) + 2u16);
             }
+            0xf4 => {
+                trace!("hlt");
+                self.halted = true;
+                self.regs.ip = self.regs.ip.wrapping_add(1);
+            }
             0xfa => {
-                println!("cli");
+                trace!("cli");
                 self.regs.flags.set(Flags::INTERRUPT, false);
                 self.regs.ip = self.regs.ip.wrapping_add(1);
             }
             0xfb => {
-                println!("sti");
+                trace!("sti");
+                if !self.regs.flags.contains(Flags::INTERRUPT) {
+                    self.inhibit_interrupts = true;
+                }
                 self.regs.flags.set(Flags::INTERRUPT, true);
                 self.regs.ip = self.regs.ip.wrapping_add(1);
             }
@@ -305,3 +396,51 @@ impl Cpu286 {
         2
     }
 }
+
+#[cfg(test)]
+struct TestHardware {
+    ram: Vec<u8>,
+    intr: Option<u8>,
+}
+
+#[cfg(test)]
+impl Cpu286Context for TestHardware {
+    fn mem_read_byte(&mut self, addr: u32) -> u8 {
+        self.ram[addr as usize]
+    }
+    fn mem_write_byte(&mut self, addr: u32, value: u8) {
+        self.ram[addr as usize] = value;
+    }
+    fn io_read_byte(&mut self, _addr: u16) -> u8 {
+        0xff
+    }
+    fn io_write_byte(&mut self, _addr: u16, _value: u8) {}
+    fn intr_pending(&mut self) -> bool {
+        self.intr.is_some()
+    }
+    fn intr_acknowledge(&mut self) -> u8 {
+        self.intr.take().unwrap()
+    }
+}
+
+#[test]
+fn test_intr_wakes_halted_cpu() {
+    let mut hardware = TestHardware {
+        ram: vec![0; 0x100_0000],
+        intr: None,
+    };
+    // sti; hlt at the reset vector
+    hardware.ram[0xff_fff0..0xff_fff2].copy_from_slice(&[0xfb, 0xf4]);
+    hardware.ram[0x20..0x24].copy_from_slice(&[0x34, 0x12, 0x00, 0x20]);
+    let mut cpu = Cpu286::new();
+    cpu.regs.write16(Reg16::SP, 0x100);
+    cpu.tick(&mut hardware);
+    cpu.tick(&mut hardware);
+    assert!(cpu.halted);
+    hardware.intr = Some(0x08);
+    cpu.tick(&mut hardware);
+    assert!(!cpu.halted);
+    assert_eq!(cpu.regs.readseg16(SegReg::CS).selector, 0x2000);
+    assert_eq!(cpu.regs.ip, 0x1234);
+    assert_eq!(&hardware.ram[0xfa..0x100], &[0xf2, 0xff, 0x00, 0xf0, 0x02, 0xf2]);
+}
@@ -108,6 +108,7 @@ pub struct Registers {
     pub seg_regs: [SegmentRegister; 4],
     pub flags: Flags,
     pub msw: u16,
+    pub idtr: GDTRIDTR,
 }
 
 impl Registers {
@@ -123,6 +124,10 @@ impl Registers {
             ],
             flags: Flags::empty(),
             msw: 0xfff0,
+            idtr: GDTRIDTR {
+                base: 0,
+                limit: 0x3ff,
+            },
         }
     }
 
@@ -1,6 +1,9 @@
 //use crate::scheduler::Jiffies;
+use crate::hardware::floppy::FloppyImage;
+use crate::hardware::harddisk::HardDiskImage;
 use operand::*;
 use registers::*;
+use std::collections::HashSet;
 
 pub mod operand;
 pub mod registers;
@@ -10,12 +13,33 @@ pub trait Cpu8086Context {
     fn mem_write_byte(&mut self, addr: u32, value: u8);
     fn io_read_byte(&mut self, addr: u16) -> u8;
     fn io_write_byte(&mut self, addr: u16, value: u8);
+    /// Level of the maskable INTR input.
+    fn intr_pending(&mut self) -> bool {
+        false
+    }
+    /// Runs the INTA bus cycle, returning the vector supplied by the
+    /// interrupt controller.
+    fn intr_acknowledge(&mut self) -> u8 {
+        0
+    }
+    /// Returns true once for every rising edge on the NMI input.
+    fn nmi_pending(&mut self) -> bool {
+        false
+    }
+    /// Floppy image in BIOS drive `drive` (0 = A:), for HLE disk services.
+    fn floppy(&mut self, _drive: u8) -> Option<&mut FloppyImage> {
+        None
+    }
+    /// Hard disk in BIOS drive `0x80 + drive`, for HLE disk services.
+    fn hard_disk(&mut self, _drive: u8) -> Option<&mut HardDiskImage> {
+        None
+    }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum RepType {
     REPE,
-    REPNE
+    REPNE,
 }
 
 #[derive(Clone, Debug, Default)]
@@ -24,7 +48,13 @@ pub struct Cpu8086 {
     pub opcode: u8,
     pub seg_override: Option<SegReg>,
     pub rep_state: Option<RepType>,
-    pub floppy: Vec<u8>
+    /// IP of the current instruction's first prefix, where a REP string
+    /// instruction resumes for its next iteration.
+    pub instruction_ip: u16,
+    pub last_ea: Option<Operand>,
+    pub halted: bool,
+    pub inhibit_interrupts: bool,
+    pub hle_vectors: HashSet<u8>,
 }
 
 impl Cpu8086 {
@@ -34,60 +64,286 @@ impl Cpu8086 {
             opcode: 0,
             seg_override: None,
             rep_state: None,
-            floppy: vec![],
+            instruction_ip: 0,
+            last_ea: None,
+            halted: false,
+            inhibit_interrupts: false,
+            hle_vectors: HashSet::new(),
+        }
+    }
+    /// Enables or disables high-level emulation of a software interrupt
+    /// vector. Vectors without a hook are dispatched through the IVT.
+    pub fn set_hle_hook(&mut self, vector: u8, enabled: bool) {
+        if enabled {
+            self.hle_vectors.insert(vector);
+        } else {
+            self.hle_vectors.remove(&vector);
         }
     }
-    pub fn interrupt_hook<T: Cpu8086Context>(&mut self, ctx: &mut T, intr: u8) {
+    /// Services a software interrupt in Rust, returning false if the
+    /// requested function is not emulated.
+    pub fn interrupt_hook<T: Cpu8086Context>(&mut self, ctx: &mut T, intr: u8) -> bool {
         match intr {
             0x10 => {
                 match self.regs.read8(Reg8::AH) {
                     0x00 => {
-                        println!("Set video mode");
+                        trace!("Set video mode");
                     },
                     0x0e => {
-                        println!("Teletype output");
+                        trace!("Teletype output");
                         eprint!("{}", self.regs.read8(Reg8::AL) as char);
                     }
-                    _ => panic!("Unimplemented int 10"),
+                    _ => return false,
                 }
             }
-            0x13 => {
-                match self.regs.read8(Reg8::AH) {
-                    0x00 => {
-                        println!("reset disk system");
-                        self.regs.write8(Reg8::AH, 0);
-                        self.regs.flags.set(Flags::CARRY, false);
-                    },
-                    0x02 => {
-                        println!("read sectors");
-                        let _drive_num = self.regs.read8(Reg8::DL);
-                        let count: u32 = self.regs.read8(Reg8::AL) as u32;
-                        let head: u32 = self.regs.read8(Reg8::DH) as u32;
-                        let cylinder: u32 = self.regs.read8(Reg8::CH) as u32 | ((self.regs.read8(Reg8::CL) & 0xc0) as u32) << 2 as u32;
-                        let sector: u32 = (self.regs.read8(Reg8::CL) & 0x3f) as u32;
-                        let buf_seg = self.regs.readseg16(SegReg::ES);
-                        let buf_off = self.regs.read16(Reg16::BX);
-                        for i in 0..=(count-1) {
-                            let sectnum = ((cylinder * 2) + head) * 8 + sector + i - 1;
-                            if sectnum >= 320 {
-                                self.regs.flags.set(Flags::CARRY, true);
-                                return;
+            0x13 => return self.disk_service(ctx),
+            _ => return false,
+        }
+        true
+    }
+    /// HLE INT 13h for floppy drives A: to D:, backed by the images the
+    /// context exposes. Hard disk requests are left to the IVT.
+    fn disk_service<T: Cpu8086Context>(&mut self, ctx: &mut T) -> bool {
+        let drive = self.regs.read8(Reg8::DL);
+        if (drive & 0x80) != 0 {
+            return self.hard_disk_service(ctx, drive & 0x7f);
+        }
+        let count = self.regs.read8(Reg8::AL);
+        let mut cylinder = self.regs.read8(Reg8::CH);
+        let mut head = self.regs.read8(Reg8::DH);
+        let mut sector = self.regs.read8(Reg8::CL) & 0x3f;
+        let buf_seg = self.regs.readseg16(SegReg::ES);
+        let mut buf_off = self.regs.read16(Reg16::BX);
+        let geometry = ctx.floppy(drive).map(|image| image.geometry);
+        let status = match (self.regs.read8(Reg8::AH), geometry) {
+            (0x00, _) => 0,
+            (0x01, _) => {
+                let status = self.mem_read_byte(ctx, 0x40, 0x41);
+                self.regs.write8(Reg8::AL, status);
+                status
+            }
+            (0x02..=0x05, None) => 0x80,
+            (function @ 0x02..=0x04, Some(geometry)) => {
+                let mut status = 0;
+                let mut done = 0;
+                while done < count {
+                    let image = ctx.floppy(drive).unwrap();
+                    // IMD and TD0 images can hold sectors of any size.
+                    let size = image.sector_size(cylinder, head, sector).unwrap_or(512);
+                    let result = match function {
+                        0x02 => image
+                            .read_sector(cylinder, head, sector)
+                            .map(|data| data.to_vec()),
+                        0x03 => Ok(Vec::new()),
+                        _ => image
+                            .read_sector(cylinder, head, sector)
+                            .map(|_| Vec::new()),
+                    };
+                    let result = match (function, result) {
+                        (0x02, Ok(data)) => {
+                            for (i, &byte) in data.iter().enumerate() {
+                                self.mem_write_byte(
+                                    ctx,
+                                    buf_seg,
+                                    buf_off.wrapping_add(i as u16),
+                                    byte,
+                                );
                             }
-                            for j in 0..=511 {
-                                self.mem_write_byte(ctx, buf_seg, buf_off.wrapping_add(((sectnum << 9)+j) as u16), self.floppy[((sectnum<<9)+j) as usize]);
+                            Ok(())
+                        }
+                        (0x03, _) => {
+                            let mut data = vec![0u8; size];
+                            for (i, byte) in data.iter_mut().enumerate() {
+                                *byte = self.mem_read_byte(
+                                    ctx,
+                                    buf_seg,
+                                    buf_off.wrapping_add(i as u16),
+                                );
                             }
+                            ctx.floppy(drive)
+                                .unwrap()
+                                .write_sector(cylinder, head, sector, &data)
                         }
-                        self.regs.flags.set(Flags::CARRY, false);
-                        self.regs.write16(Reg16::AX, count as u8 as u16);
-                    },
-                    _ => panic!("Unimplmented int 13"),
+                        (_, result) => result.map(|_| ()),
+                    };
+                    if let Err(err) = result {
+                        status = err.bios_status();
+                        break;
+                    }
+                    done += 1;
+                    buf_off = buf_off.wrapping_add(size as u16);
+                    sector += 1;
+                    if sector > geometry.sectors_per_track {
+                        sector = 1;
+                        head += 1;
+                        if head >= geometry.heads {
+                            head = 0;
+                            cylinder = cylinder.wrapping_add(1);
+                        }
+                    }
                 }
-            }
-            _ => panic!("Unimplemented interrupt {}", intr),
+                self.regs.write8(Reg8::AL, done);
+                status
+            }
+            (0x05, Some(_)) => {
+                let mut status = 0;
+                for i in 0..count as u16 {
+                    let id_off = buf_off.wrapping_add(i << 2);
+                    let sector = self.mem_read_byte(ctx, buf_seg, id_off.wrapping_add(2));
+                    let image = ctx.floppy(drive).unwrap();
+                    if let Err(err) = image.write_sector(cylinder, head, sector, &[0xf6; 512]) {
+                        status = err.bios_status();
+                        break;
+                    }
+                }
+                status
+            }
+            (0x08, Some(geometry)) => {
+                let max_cylinder = geometry.cylinders - 1;
+                let drives = (0..4).filter(|&drive| ctx.floppy(drive).is_some()).count();
+                self.regs.write8(Reg8::AL, 0);
+                self.regs.write8(Reg8::BL, geometry.drive_type());
+                self.regs.write8(Reg8::CH, max_cylinder);
+                self.regs.write8(Reg8::CL, geometry.sectors_per_track);
+                self.regs.write8(Reg8::DH, geometry.heads - 1);
+                self.regs.write8(Reg8::DL, drives as u8);
+                0
+            }
+            (0x08, None) => 0x01,
+            (0x15, geometry) => {
+                // Diskette drive without change line support, or no drive.
+                self.regs.write8(Reg8::AH, geometry.is_some() as u8);
+                self.regs.flags.set(Flags::CARRY, false);
+                return true;
+            }
+            _ => return false,
+        };
+        self.regs.write8(Reg8::AH, status);
+        self.regs.flags.set(Flags::CARRY, status != 0);
+        self.mem_write_byte(ctx, 0x40, 0x41, status);
+        true
+    }
+    /// INT 13h for hard disks attached through the context, leaving drives
+    /// without an image to the BIOS. Status goes to 0040:0074.
+    fn hard_disk_service<T: Cpu8086Context>(&mut self, ctx: &mut T, drive: u8) -> bool {
+        let geometry = match ctx.hard_disk(drive) {
+            Some(image) => image.geometry,
+            None => return false,
+        };
+        let count = self.regs.read8(Reg8::AL);
+        let cl = self.regs.read8(Reg8::CL);
+        let cylinder = self.regs.read8(Reg8::CH) as u16 | (((cl & 0xc0) as u16) << 2);
+        let head = self.regs.read8(Reg8::DH);
+        let buf_seg = self.regs.readseg16(SegReg::ES);
+        let mut buf_off = self.regs.read16(Reg16::BX);
+        let status = match self.regs.read8(Reg8::AH) {
+            0x00 | 0x0c | 0x0d | 0x10 | 0x11 => 0,
+            0x01 => {
+                let status = self.mem_read_byte(ctx, 0x40, 0x74);
+                self.regs.write8(Reg8::AL, status);
+                status
+            }
+            function @ 0x02..=0x04 => {
+                let mut status = 0;
+                let mut done = 0;
+                let start = geometry.lba(cylinder, head, cl & 0x3f);
+                while done < count {
+                    let lba = match start {
+                        Some(lba) if lba + (done as usize) < geometry.total_sectors() => {
+                            lba + done as usize
+                        }
+                        _ => {
+                            status = 0x04;
+                            break;
+                        }
+                    };
+                    match function {
+                        0x02 => {
+                            let data = ctx.hard_disk(drive).unwrap().read_sector(lba).unwrap();
+                            let data = data.to_vec();
+                            for (i, &byte) in data.iter().enumerate() {
+                                self.mem_write_byte(
+                                    ctx,
+                                    buf_seg,
+                                    buf_off.wrapping_add(i as u16),
+                                    byte,
+                                );
+                            }
+                        }
+                        0x03 => {
+                            let mut data = [0u8; 512];
+                            for (i, byte) in data.iter_mut().enumerate() {
+                                *byte = self.mem_read_byte(
+                                    ctx,
+                                    buf_seg,
+                                    buf_off.wrapping_add(i as u16),
+                                );
+                            }
+                            let image = ctx.hard_disk(drive).unwrap();
+                            if image.write_sector(lba, &data).is_err() {
+                                // Write fault.
+                                status = 0xcc;
+                                break;
+                            }
+                        }
+                        _ => {}
+                    }
+                    done += 1;
+                    buf_off = buf_off.wrapping_add(512);
+                }
+                self.regs.write8(Reg8::AL, done);
+                status
+            }
+            0x08 => {
+                let max_cylinder = geometry.cylinders.saturating_sub(1).min(1023);
+                let drives = (0..2).filter(|&drive| ctx.hard_disk(drive).is_some()).count();
+                self.regs.write8(Reg8::CH, max_cylinder as u8);
+                self.regs.write8(
+                    Reg8::CL,
+                    ((max_cylinder >> 2) as u8 & 0xc0) | geometry.sectors_per_track,
+                );
+                self.regs.write8(Reg8::DH, geometry.heads - 1);
+                self.regs.write8(Reg8::DL, drives as u8);
+                0
+            }
+            0x15 => {
+                let sectors = geometry.total_sectors() as u32;
+                self.regs.write8(Reg8::AH, 0x03);
+                self.regs.write16(Reg16::CX, (sectors >> 16) as u16);
+                self.regs.write16(Reg16::DX, sectors as u16);
+                self.regs.flags.set(Flags::CARRY, false);
+                return true;
+            }
+            _ => 0x01,
+        };
+        self.regs.write8(Reg8::AH, status);
+        self.regs.flags.set(Flags::CARRY, status != 0);
+        self.mem_write_byte(ctx, 0x40, 0x74, status);
+        true
+    }
+    /// Enters an interrupt handler through the vector table at 0000:0000,
+    /// pushing FLAGS, CS and IP and clearing IF and TF.
+    pub fn interrupt<T: Cpu8086Context>(&mut self, ctx: &mut T, vector: u8) {
+        self.push16(ctx, self.regs.read16(Reg16::FLAGS));
+        self.regs.flags.set(Flags::INTERRUPT, false);
+        self.regs.flags.set(Flags::TRAP, false);
+        self.push16(ctx, self.regs.readseg16(SegReg::CS));
+        self.push16(ctx, self.regs.ip);
+        let offset = self.mem_read_word(ctx, 0, (vector as u16) << 2);
+        let segment = self.mem_read_word(ctx, 0, ((vector as u16) << 2).wrapping_add(2));
+        self.regs.writeseg16(SegReg::CS, segment);
+        self.regs.ip = offset;
+    }
+    /// Raises INT n, INT 3, INTO or a divide error, giving any HLE hook for
+    /// the vector the first chance to service it.
+    pub fn software_interrupt<T: Cpu8086Context>(&mut self, ctx: &mut T, intr: u8) {
+        if self.hle_vectors.contains(&intr) && self.interrupt_hook(ctx, intr) {
+            return;
         }
+        self.interrupt(ctx, intr);
     }
     pub fn mem_read_byte<T: Cpu8086Context>(&mut self, ctx: &mut T, seg: u16, addr: u16) -> u8 {
-        let masked_addr = (((seg as u32) << 4) | addr as u32) & 0xf_ffff;
+        let masked_addr = (((seg as u32) << 4) + addr as u32) & 0xf_ffff;
         ctx.mem_read_byte(masked_addr)
     }
     pub fn mem_write_byte<T: Cpu8086Context>(
@@ -97,7 +353,7 @@ impl Cpu8086 {
         addr: u16,
         value: u8,
     ) {
-        let masked_addr = (((seg as u32) << 4) | addr as u32) & 0xf_ffff;
+        let masked_addr = (((seg as u32) << 4) + addr as u32) & 0xf_ffff;
         ctx.mem_write_byte(masked_addr, value)
     }
 
@@ -109,10 +365,20 @@ impl Cpu8086 {
         ctx.io_write_byte(addr, value)
     }
 
+    pub fn io_read_word<T: Cpu8086Context>(&mut self, ctx: &mut T, addr: u16) -> u16 {
+        let lo = ctx.io_read_byte(addr);
+        let hi = ctx.io_read_byte(addr.wrapping_add(1));
+        u16::from_le_bytes([lo, hi])
+    }
+
+    pub fn io_write_word<T: Cpu8086Context>(&mut self, ctx: &mut T, addr: u16, value: u16) {
+        ctx.io_write_byte(addr, value as u8);
+        ctx.io_write_byte(addr.wrapping_add(1), (value >> 8) as u8);
+    }
+
     pub fn mem_read_word<T: Cpu8086Context>(&mut self, ctx: &mut T, seg: u16, addr: u16) -> u16 {
-        let masked_addr = (((seg as u32) << 4) | addr as u32) & 0xf_ffff;
-        let lo = ctx.mem_read_byte(masked_addr);
-        let hi = ctx.mem_read_byte(masked_addr.wrapping_add(1) & 0xf_ffff);
+        let lo = self.mem_read_byte(ctx, seg, addr);
+        let hi = self.mem_read_byte(ctx, seg, addr.wrapping_add(1));
         u16::from_le_bytes([lo, hi])
     }
 
@@ -123,18 +389,92 @@ impl Cpu8086 {
         addr: u16,
         value: u16,
     ) {
-        let masked_addr = (((seg as u32) << 4) | addr as u32) & 0xf_ffff;
-        ctx.mem_write_byte(masked_addr, value as u8);
-        ctx.mem_write_byte(masked_addr + 1, (value >> 8) as u8);
+        self.mem_write_byte(ctx, seg, addr, value as u8);
+        self.mem_write_byte(ctx, seg, addr.wrapping_add(1), (value >> 8) as u8);
+    }
+
+    pub fn fetch8<T: Cpu8086Context>(&mut self, ctx: &mut T) -> u8 {
+        let value = self.mem_read_byte(ctx, self.regs.readseg16(SegReg::CS), self.regs.ip);
+        self.regs.ip = self.regs.ip.wrapping_add(1);
+        value
+    }
+
+    pub fn fetch16<T: Cpu8086Context>(&mut self, ctx: &mut T) -> u16 {
+        let value = self.mem_read_word(ctx, self.regs.readseg16(SegReg::CS), self.regs.ip);
+        self.regs.ip = self.regs.ip.wrapping_add(2);
+        value
+    }
+
+    pub fn fetch_modrm<T: Cpu8086Context>(&mut self, ctx: &mut T) -> OpcodeParams {
+        let modrm = self.fetch8(ctx);
+        self.get_opcode_params_from_modrm(ctx, modrm)
+    }
+
+    pub fn read_rm8<T: Cpu8086Context>(&mut self, ctx: &mut T, rm: Operand) -> u8 {
+        match rm {
+            Operand::Register(reg_num) => self.regs.read8(Reg8::from_num(reg_num).unwrap()),
+            Operand::Address(segment, offset) => {
+                self.mem_read_byte(ctx, self.regs.readseg16(segment), offset)
+            }
+        }
+    }
+
+    pub fn write_rm8<T: Cpu8086Context>(&mut self, ctx: &mut T, rm: Operand, value: u8) {
+        match rm {
+            Operand::Register(reg_num) => {
+                self.regs.write8(Reg8::from_num(reg_num).unwrap(), value)
+            }
+            Operand::Address(segment, offset) => {
+                self.mem_write_byte(ctx, self.regs.readseg16(segment), offset, value)
+            }
+        }
+    }
+
+    pub fn read_rm16<T: Cpu8086Context>(&mut self, ctx: &mut T, rm: Operand) -> u16 {
+        match rm {
+            Operand::Register(reg_num) => self.regs.read16(Reg16::from_num(reg_num).unwrap()),
+            Operand::Address(segment, offset) => {
+                self.mem_read_word(ctx, self.regs.readseg16(segment), offset)
+            }
+        }
+    }
+
+    pub fn write_rm16<T: Cpu8086Context>(&mut self, ctx: &mut T, rm: Operand, value: u16) {
+        match rm {
+            Operand::Register(reg_num) => {
+                self.regs.write16(Reg16::from_num(reg_num).unwrap(), value)
+            }
+            Operand::Address(segment, offset) => {
+                self.mem_write_word(ctx, self.regs.readseg16(segment), offset, value)
+            }
+        }
     }
 
-    pub fn set_parity_flag(&mut self, mut data: u16) {
-        let mut parity = 0;
-        while data != 0 {
-            parity ^= data & 1;
-            data >>= 1;
+    /// Returns the memory operand of LEA/LES/LDS and far JMP/CALL. The 8086
+    /// does not reject a register operand for these; it reuses the effective
+    /// address most recently computed by a ModR/M byte instead.
+    pub fn memory_operand(&self, rm: Operand) -> (SegReg, u16) {
+        match rm {
+            Operand::Address(segment, offset) => (segment, offset),
+            Operand::Register(_) => match self.last_ea {
+                Some(Operand::Address(segment, offset)) => (segment, offset),
+                _ => (SegReg::DS, 0),
+            },
         }
-        self.regs.flags.set(Flags::PARITY, parity != 0);
+    }
+
+    pub fn read_far_pointer<T: Cpu8086Context>(&mut self, ctx: &mut T, rm: Operand) -> (u16, u16) {
+        let (segment, offset) = self.memory_operand(rm);
+        let base = self.regs.readseg16(segment);
+        let addr = self.mem_read_word(ctx, base, offset);
+        let selector = self.mem_read_word(ctx, base, offset.wrapping_add(2));
+        (selector, addr)
+    }
+
+    pub fn set_parity_flag(&mut self, data: u16) {
+        self.regs
+            .flags
+            .set(Flags::PARITY, (data as u8).count_ones().is_multiple_of(2));
     }
 
     pub fn set_pzs8(&mut self, data: u8) {
@@ -149,2169 +489,1251 @@ impl Cpu8086 {
         self.regs.flags.set(Flags::SIGN, (data & 0x8000) == 0x8000);
     }
 
+    pub fn set_pzs(&mut self, data: u16, width: RegisterType) {
+        match width {
+            RegisterType::Bits8 => self.set_pzs8(data as u8),
+            RegisterType::Bits16 => self.set_pzs16(data),
+        }
+    }
+
+    /// Performs one of the eight arithmetic/logic operations selected by the
+    /// `reg` field of opcodes 0x00-0x3f and group 0x80-0x83
+    /// (ADD, OR, ADC, SBB, AND, SUB, XOR, CMP) and updates the flags.
+    /// The caller is responsible for discarding the result of CMP.
+    pub fn alu(&mut self, op: u8, dst: u16, src: u16, width: RegisterType) -> u16 {
+        let (mask, sign) = width_masks(width);
+        let dst = dst as u32 & mask;
+        let src = src as u32 & mask;
+        let carry_in = self.regs.flags.contains(Flags::CARRY) as u32;
+        let result = match op & 7 {
+            0 | 2 => {
+                let carry = if op == 2 { carry_in } else { 0 };
+                let result = dst + src + carry;
+                self.regs.flags.set(Flags::CARRY, result > mask);
+                self.regs.flags.set(
+                    Flags::OVERFLOW,
+                    ((result ^ dst) & (result ^ src) & sign) != 0,
+                );
+                self.regs
+                    .flags
+                    .set(Flags::ADJUST, ((result ^ dst ^ src) & 0x10) != 0);
+                result
+            }
+            3 | 5 | 7 => {
+                let borrow = if op == 3 { carry_in } else { 0 };
+                let result = dst.wrapping_sub(src).wrapping_sub(borrow);
+                self.regs.flags.set(Flags::CARRY, src + borrow > dst);
+                self.regs.flags.set(
+                    Flags::OVERFLOW,
+                    ((dst ^ src) & (dst ^ result) & sign) != 0,
+                );
+                self.regs
+                    .flags
+                    .set(Flags::ADJUST, ((result ^ dst ^ src) & 0x10) != 0);
+                result
+            }
+            1 | 4 | 6 => {
+                let result = match op {
+                    1 => dst | src,
+                    4 => dst & src,
+                    _ => dst ^ src,
+                };
+                self.regs.flags.set(Flags::CARRY, false);
+                self.regs.flags.set(Flags::OVERFLOW, false);
+                self.regs.flags.set(Flags::ADJUST, false);
+                result
+            }
+            _ => unreachable!(),
+        } & mask;
+        self.set_pzs(result as u16, width);
+        result as u16
+    }
+
+    pub fn inc(&mut self, dst: u16, width: RegisterType) -> u16 {
+        let carry = self.regs.flags.contains(Flags::CARRY);
+        let result = self.alu(0, dst, 1, width);
+        self.regs.flags.set(Flags::CARRY, carry);
+        result
+    }
+
+    pub fn dec(&mut self, dst: u16, width: RegisterType) -> u16 {
+        let carry = self.regs.flags.contains(Flags::CARRY);
+        let result = self.alu(5, dst, 1, width);
+        self.regs.flags.set(Flags::CARRY, carry);
+        result
+    }
+
+    /// Performs the rotate/shift selected by the `reg` field of group 0xd0-0xd3
+    /// (ROL, ROR, RCL, RCR, SHL, SHR, SETMO, SAR). A count of zero leaves the
+    /// flags untouched.
+    pub fn shift(&mut self, op: u8, value: u16, count: u8, width: RegisterType) -> u16 {
+        let (mask, sign) = width_masks(width);
+        let mut value = value as u32 & mask;
+        if count == 0 {
+            return value as u16;
+        }
+        let mut carry = self.regs.flags.contains(Flags::CARRY);
+        for _ in 0..count {
+            match op & 7 {
+                0 => {
+                    carry = (value & sign) != 0;
+                    value = ((value << 1) | carry as u32) & mask;
+                }
+                1 => {
+                    carry = (value & 1) != 0;
+                    value = (value >> 1) | if carry { sign } else { 0 };
+                }
+                2 => {
+                    let carry_out = (value & sign) != 0;
+                    value = ((value << 1) | carry as u32) & mask;
+                    carry = carry_out;
+                }
+                3 => {
+                    let carry_out = (value & 1) != 0;
+                    value = (value >> 1) | if carry { sign } else { 0 };
+                    carry = carry_out;
+                }
+                4 => {
+                    carry = (value & sign) != 0;
+                    value = (value << 1) & mask;
+                }
+                5 => {
+                    carry = (value & 1) != 0;
+                    value >>= 1;
+                }
+                6 => {
+                    // Undocumented SETMO: the 8086 sets the operand to all ones.
+                    carry = false;
+                    value = mask;
+                }
+                _ => {
+                    carry = (value & 1) != 0;
+                    value = (value >> 1) | (value & sign);
+                }
+            }
+        }
+        self.regs.flags.set(Flags::CARRY, carry);
+        let msb = (value & sign) != 0;
+        let overflow = match op & 7 {
+            0 | 2 | 4 => msb != carry,
+            1 | 3 | 5 => msb != ((value & (sign >> 1)) != 0),
+            _ => false,
+        };
+        if op & 7 == 6 {
+            self.regs.flags.set(Flags::ADJUST, false);
+        }
+        self.regs.flags.set(Flags::OVERFLOW, overflow);
+        if op & 7 >= 4 {
+            self.set_pzs(value as u16, width);
+        }
+        value as u16
+    }
+
+    pub fn push16<T: Cpu8086Context>(&mut self, ctx: &mut T, value: u16) {
+        let stack_pointer = self.regs.read16(Reg16::SP).wrapping_sub(2);
+        self.regs.write16(Reg16::SP, stack_pointer);
+        self.mem_write_word(ctx, self.regs.readseg16(SegReg::SS), stack_pointer, value);
+    }
+
     pub fn pop16<T: Cpu8086Context>(&mut self, ctx: &mut T) -> u16 {
         let stack_pointer = self.regs.read16(Reg16::SP);
         self.regs.write16(Reg16::SP, stack_pointer.wrapping_add(2));
         self.mem_read_word(ctx, self.regs.readseg16(SegReg::SS), stack_pointer)
     }
 
+    /// Evaluates the condition encoded in the low nibble of a Jcc opcode.
+    pub fn condition(&self, cc: u8) -> bool {
+        let flags = self.regs.flags;
+        let result = match (cc >> 1) & 7 {
+            0 => flags.contains(Flags::OVERFLOW),
+            1 => flags.contains(Flags::CARRY),
+            2 => flags.contains(Flags::ZERO),
+            3 => flags.contains(Flags::CARRY) || flags.contains(Flags::ZERO),
+            4 => flags.contains(Flags::SIGN),
+            5 => flags.contains(Flags::PARITY),
+            6 => flags.contains(Flags::SIGN) != flags.contains(Flags::OVERFLOW),
+            _ => {
+                flags.contains(Flags::ZERO)
+                    || (flags.contains(Flags::SIGN) != flags.contains(Flags::OVERFLOW))
+            }
+        };
+        result != ((cc & 1) == 1)
+    }
+
+    pub fn jump_short(&mut self, offset: u8, taken: bool) {
+        if taken {
+            self.regs.ip = self.regs.ip.wrapping_add(offset as i8 as u16);
+        }
+    }
+
+    fn data_seg(&self) -> SegReg {
+        self.seg_override.unwrap_or(SegReg::DS)
+    }
+
+    fn string_delta(&self, width: RegisterType) -> u16 {
+        let size = match width {
+            RegisterType::Bits8 => 1u16,
+            RegisterType::Bits16 => 2u16,
+        };
+        if self.regs.flags.contains(Flags::DIRECTION) {
+            size.wrapping_neg()
+        } else {
+            size
+        }
+    }
+
+    /// Executes a single iteration of the string instruction in `self.opcode`.
+    fn string_op<T: Cpu8086Context>(&mut self, ctx: &mut T) {
+        let width = self.opcode_width();
+        let delta = self.string_delta(width);
+        let src_seg = self.regs.readseg16(self.data_seg());
+        let dst_seg = self.regs.readseg16(SegReg::ES);
+        let si = self.regs.read16(Reg16::SI);
+        let di = self.regs.read16(Reg16::DI);
+        match self.opcode {
+            0xa4 | 0xa5 => {
+                match width {
+                    RegisterType::Bits8 => {
+                        let data = self.mem_read_byte(ctx, src_seg, si);
+                        self.mem_write_byte(ctx, dst_seg, di, data);
+                    }
+                    RegisterType::Bits16 => {
+                        let data = self.mem_read_word(ctx, src_seg, si);
+                        self.mem_write_word(ctx, dst_seg, di, data);
+                    }
+                }
+                self.regs.write16(Reg16::SI, si.wrapping_add(delta));
+                self.regs.write16(Reg16::DI, di.wrapping_add(delta));
+            }
+            0xa6 | 0xa7 => {
+                let (src, dst) = match width {
+                    RegisterType::Bits8 => (
+                        self.mem_read_byte(ctx, src_seg, si) as u16,
+                        self.mem_read_byte(ctx, dst_seg, di) as u16,
+                    ),
+                    RegisterType::Bits16 => (
+                        self.mem_read_word(ctx, src_seg, si),
+                        self.mem_read_word(ctx, dst_seg, di),
+                    ),
+                };
+                self.alu(7, src, dst, width);
+                self.regs.write16(Reg16::SI, si.wrapping_add(delta));
+                self.regs.write16(Reg16::DI, di.wrapping_add(delta));
+            }
+            0xaa => {
+                self.mem_write_byte(ctx, dst_seg, di, self.regs.read8(Reg8::AL));
+                self.regs.write16(Reg16::DI, di.wrapping_add(delta));
+            }
+            0xab => {
+                self.mem_write_word(ctx, dst_seg, di, self.regs.read16(Reg16::AX));
+                self.regs.write16(Reg16::DI, di.wrapping_add(delta));
+            }
+            0xac => {
+                let data = self.mem_read_byte(ctx, src_seg, si);
+                self.regs.write8(Reg8::AL, data);
+                self.regs.write16(Reg16::SI, si.wrapping_add(delta));
+            }
+            0xad => {
+                let data = self.mem_read_word(ctx, src_seg, si);
+                self.regs.write16(Reg16::AX, data);
+                self.regs.write16(Reg16::SI, si.wrapping_add(delta));
+            }
+            0xae => {
+                let dst = self.mem_read_byte(ctx, dst_seg, di) as u16;
+                self.alu(7, self.regs.read8(Reg8::AL) as u16, dst, width);
+                self.regs.write16(Reg16::DI, di.wrapping_add(delta));
+            }
+            0xaf => {
+                let dst = self.mem_read_word(ctx, dst_seg, di);
+                self.alu(7, self.regs.read16(Reg16::AX), dst, width);
+                self.regs.write16(Reg16::DI, di.wrapping_add(delta));
+            }
+            _ => panic!("Invalid string opcode!"),
+        }
+    }
+
+    /// Executes the string instruction in `self.opcode`, honouring any REP prefix.
+    /// A repeated instruction runs one iteration per tick and rewinds IP to its
+    /// prefix until it is done, so interrupts and the rest of the machine get a
+    /// look in between iterations.
+    pub fn string_instruction<T: Cpu8086Context>(&mut self, ctx: &mut T) {
+        match self.rep_state {
+            None => self.string_op(ctx),
+            Some(rep) => {
+                if self.regs.read16(Reg16::CX) == 0 {
+                    return;
+                }
+                self.string_op(ctx);
+                let cx = self.regs.read16(Reg16::CX).wrapping_sub(1);
+                self.regs.write16(Reg16::CX, cx);
+                let compares = matches!(self.opcode, 0xa6 | 0xa7 | 0xae | 0xaf);
+                let terminated =
+                    compares && self.regs.flags.contains(Flags::ZERO) != (rep == RepType::REPE);
+                if cx != 0 && !terminated {
+                    self.regs.ip = self.instruction_ip;
+                }
+            }
+        }
+    }
+
+    pub fn mul8(&mut self, src: u8, signed: bool) {
+        let al = self.regs.read8(Reg8::AL);
+        let result = if signed {
+            (al as i8 as i16).wrapping_mul(src as i8 as i16) as u16
+        } else {
+            (al as u16) * (src as u16)
+        };
+        self.regs.write16(Reg16::AX, result);
+        let overflow = if signed {
+            result != (result as u8 as i8 as i16 as u16)
+        } else {
+            (result & 0xff00) != 0
+        };
+        self.regs.flags.set(Flags::CARRY, overflow);
+        self.regs.flags.set(Flags::OVERFLOW, overflow);
+    }
+
+    pub fn mul16(&mut self, src: u16, signed: bool) {
+        let ax = self.regs.read16(Reg16::AX);
+        let result = if signed {
+            (ax as i16 as i32).wrapping_mul(src as i16 as i32) as u32
+        } else {
+            (ax as u32) * (src as u32)
+        };
+        self.regs.write16(Reg16::AX, result as u16);
+        self.regs.write16(Reg16::DX, (result >> 16) as u16);
+        let overflow = if signed {
+            result != (result as u16 as i16 as i32 as u32)
+        } else {
+            (result & 0xffff_0000) != 0
+        };
+        self.regs.flags.set(Flags::CARRY, overflow);
+        self.regs.flags.set(Flags::OVERFLOW, overflow);
+    }
+
+    /// Divides AX by `src`, returning false if a divide error must be raised.
+    pub fn div8(&mut self, src: u8, signed: bool) -> bool {
+        if src == 0 {
+            return false;
+        }
+        let ax = self.regs.read16(Reg16::AX);
+        let (quotient, remainder) = if signed {
+            let dividend = ax as i16 as i32;
+            let divisor = src as i8 as i32;
+            let quotient = dividend / divisor;
+            if !(-127..=127).contains(&quotient) {
+                return false;
+            }
+            (quotient as u8, (dividend % divisor) as u8)
+        } else {
+            let quotient = ax / (src as u16);
+            if quotient > 0xff {
+                return false;
+            }
+            (quotient as u8, (ax % (src as u16)) as u8)
+        };
+        self.regs.write8(Reg8::AL, quotient);
+        self.regs.write8(Reg8::AH, remainder);
+        true
+    }
+
+    /// Divides DX:AX by `src`, returning false if a divide error must be raised.
+    pub fn div16(&mut self, src: u16, signed: bool) -> bool {
+        if src == 0 {
+            return false;
+        }
+        let dividend =
+            ((self.regs.read16(Reg16::DX) as u32) << 16) | self.regs.read16(Reg16::AX) as u32;
+        let (quotient, remainder) = if signed {
+            let dividend = dividend as i32 as i64;
+            let divisor = src as i16 as i64;
+            let quotient = dividend / divisor;
+            if !(-32767..=32767).contains(&quotient) {
+                return false;
+            }
+            (quotient as u16, (dividend % divisor) as u16)
+        } else {
+            let quotient = dividend / (src as u32);
+            if quotient > 0xffff {
+                return false;
+            }
+            (quotient as u16, (dividend % (src as u32)) as u16)
+        };
+        self.regs.write16(Reg16::AX, quotient);
+        self.regs.write16(Reg16::DX, remainder);
+        true
+    }
+
+    /// Operand size selected by the low bit of the opcode.
+    fn opcode_width(&self) -> RegisterType {
+        if self.opcode & 1 == 0 {
+            RegisterType::Bits8
+        } else {
+            RegisterType::Bits16
+        }
+    }
+
+    fn read_rm<T: Cpu8086Context>(&mut self, ctx: &mut T, rm: Operand, width: RegisterType) -> u16 {
+        match width {
+            RegisterType::Bits8 => self.read_rm8(ctx, rm) as u16,
+            RegisterType::Bits16 => self.read_rm16(ctx, rm),
+        }
+    }
+
+    fn write_rm<T: Cpu8086Context>(
+        &mut self,
+        ctx: &mut T,
+        rm: Operand,
+        value: u16,
+        width: RegisterType,
+    ) {
+        match width {
+            RegisterType::Bits8 => self.write_rm8(ctx, rm, value as u8),
+            RegisterType::Bits16 => self.write_rm16(ctx, rm, value),
+        }
+    }
+
+    /// Executes group 0xfe/0xff. Only INC and DEC are documented for 0xfe, but
+    /// the 8086 decodes the remaining operations for it too, with the byte
+    /// operand forming the low half of a word whose high half is all ones.
+    fn group_ff<T: Cpu8086Context>(
+        &mut self,
+        ctx: &mut T,
+        opcode_params: OpcodeParams,
+        width: RegisterType,
+    ) {
+        let word_operand = |cpu: &mut Cpu8086, ctx: &mut T| -> u16 {
+            match width {
+                RegisterType::Bits8 => cpu.read_rm8(ctx, opcode_params.rm) as u16 | 0xff00,
+                RegisterType::Bits16 => cpu.read_rm16(ctx, opcode_params.rm),
+            }
+        };
+        match opcode_params.reg {
+            0 => {
+                trace!("inc rm");
+                let rm = self.read_rm(ctx, opcode_params.rm, width);
+                let result = self.inc(rm, width);
+                self.write_rm(ctx, opcode_params.rm, result, width);
+            }
+            1 => {
+                trace!("dec rm");
+                let rm = self.read_rm(ctx, opcode_params.rm, width);
+                let result = self.dec(rm, width);
+                self.write_rm(ctx, opcode_params.rm, result, width);
+            }
+            2 => {
+                trace!("call near rm");
+                let target = word_operand(self, ctx);
+                self.push16(ctx, self.regs.ip);
+                self.regs.ip = target;
+            }
+            3 => {
+                trace!("call far");
+                let (segment, offset) = self.read_far_pointer(ctx, opcode_params.rm);
+                self.push16(ctx, self.regs.readseg16(SegReg::CS));
+                self.push16(ctx, self.regs.ip);
+                self.regs.writeseg16(SegReg::CS, segment);
+                self.regs.ip = offset;
+            }
+            4 => {
+                trace!("jmp near rm");
+                self.regs.ip = word_operand(self, ctx);
+            }
+            5 => {
+                trace!("jmp far");
+                let (segment, offset) = self.read_far_pointer(ctx, opcode_params.rm);
+                self.regs.writeseg16(SegReg::CS, segment);
+                self.regs.ip = offset;
+            }
+            _ => {
+                trace!("push rm");
+                let value = word_operand(self, ctx);
+                self.push16(ctx, value);
+            }
+        }
+    }
+
+    /// Samples NMI and INTR at an instruction boundary, entering the handler
+    /// and leaving the halt state if an interrupt is accepted.
+    pub fn check_interrupts<T: Cpu8086Context>(&mut self, ctx: &mut T) -> bool {
+        if self.inhibit_interrupts {
+            self.inhibit_interrupts = false;
+            return false;
+        }
+        if ctx.nmi_pending() {
+            self.halted = false;
+            self.interrupt(ctx, 2);
+            return true;
+        }
+        if self.regs.flags.contains(Flags::INTERRUPT) && ctx.intr_pending() {
+            self.halted = false;
+            let vector = ctx.intr_acknowledge();
+            self.interrupt(ctx, vector);
+            return true;
+        }
+        false
+    }
+
     pub fn tick<T: Cpu8086Context>(&mut self, ctx: &mut T) -> usize {
-        self.opcode = self.mem_read_byte(ctx, self.regs.readseg16(SegReg::CS), self.regs.ip);
-        println!(
+        if self.check_interrupts(ctx) || self.halted {
+            return 4;
+        }
+        let trap = self.regs.flags.contains(Flags::TRAP);
+        self.instruction_ip = self.regs.ip;
+        let cycles = self.execute(ctx);
+        if trap {
+            self.interrupt(ctx, 1);
+        }
+        cycles
+    }
+
+    /// Executes one instruction, including any prefixes in front of it.
+    pub fn execute<T: Cpu8086Context>(&mut self, ctx: &mut T) -> usize {
+        self.opcode = self.fetch8(ctx);
+        trace!(
             "Opcode {:#02x} CS {:#04x} IP {:#04x}\nGPRs {:x?} Segments {:x?}\nFLAGS {:#04x}",
             self.opcode,
             self.regs.readseg16(SegReg::CS),
-            self.regs.ip,
+            self.regs.ip.wrapping_sub(1),
             self.regs.gprs,
             self.regs.seg_regs,
             self.regs.flags.bits()
         );
         match self.opcode {
-            0x00 => {
-                println!("add rm8, reg8");
-                let modrm = self.mem_read_byte(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                );
-                self.regs.ip = self.regs.ip.wrapping_add(2);
-                let opcode_params = self.get_opcode_params_from_modrm(ctx, modrm);
-                let reg_num = (modrm & 0x38) >> 3;
-                let reg = self.regs.read8(Reg8::from_num(reg_num).unwrap());
-                let mut rm: u8 = 0;
-                let mut segment_long: SegReg = SegReg::DS;
-                let mut opcode_rm_long: u16 = 0;
-                if let Operand::Register(opcode_rm) = opcode_params.rm {
-                    rm = self.regs.read8(Reg8::from_num(opcode_rm).unwrap());
-
-                    let result = reg.wrapping_sub(rm);
-                    self.set_pzs8(result);
-                    self.regs.flags.set(
-                        Flags::OVERFLOW,
-                        ((reg ^ rm) & (result ^ reg) & 0x80) == 0x80,
-                    );
-                    self.regs
-                        .flags
-                        .set(Flags::ADJUST, ((result ^ reg ^ rm) & 0x10) == 0x10);
-                    self.regs
-                        .flags
-                        .set(Flags::CARRY, (rm & 0x80) > (reg & 0x80));
-                    self.regs.write8(Reg8::from_num(opcode_rm).unwrap(), result);
-                } else if let Operand::Address(segment, opcode_rm) = opcode_params.rm {
-                    rm = self.mem_read_byte(ctx, self.regs.readseg16(segment), opcode_rm);
-                    opcode_rm_long = opcode_rm;
-                    segment_long = segment;
-
-                    let result = reg.wrapping_sub(rm);
-                    self.set_pzs8(result);
-                    self.regs.flags.set(
-                        Flags::OVERFLOW,
-                        ((reg ^ rm) & (result ^ reg) & 0x80) == 0x80,
-                    );
-                    self.regs
-                        .flags
-                        .set(Flags::ADJUST, ((result ^ reg ^ rm) & 0x10) == 0x10);
-                    self.regs
-                        .flags
-                        .set(Flags::CARRY, (rm & 0x80) > (reg & 0x80));
-                    self.mem_write_byte(
-                        ctx,
-                        self.regs.readseg16(segment_long),
-                        opcode_rm_long,
-                        result,
-                    );
+            0x00..=0x3f if self.opcode & 7 < 6 => {
+                let op = self.opcode >> 3;
+                match self.opcode & 7 {
+                    0 => {
+                        trace!("alu{} rm8, reg8", op);
+                        let opcode_params = self.fetch_modrm(ctx);
+                        let reg = self.regs.read8(Reg8::from_num(opcode_params.reg).unwrap());
+                        let rm = self.read_rm8(ctx, opcode_params.rm);
+                        let result = self.alu(op, rm as u16, reg as u16, RegisterType::Bits8);
+                        if op != 7 {
+                            self.write_rm8(ctx, opcode_params.rm, result as u8);
+                        }
+                    }
+                    1 => {
+                        trace!("alu{} rm16, reg16", op);
+                        let opcode_params = self.fetch_modrm(ctx);
+                        let reg = self.regs.read16(Reg16::from_num(opcode_params.reg).unwrap());
+                        let rm = self.read_rm16(ctx, opcode_params.rm);
+                        let result = self.alu(op, rm, reg, RegisterType::Bits16);
+                        if op != 7 {
+                            self.write_rm16(ctx, opcode_params.rm, result);
+                        }
+                    }
+                    2 => {
+                        trace!("alu{} reg8, rm8", op);
+                        let opcode_params = self.fetch_modrm(ctx);
+                        let reg_num = Reg8::from_num(opcode_params.reg).unwrap();
+                        let reg = self.regs.read8(reg_num);
+                        let rm = self.read_rm8(ctx, opcode_params.rm);
+                        let result = self.alu(op, reg as u16, rm as u16, RegisterType::Bits8);
+                        if op != 7 {
+                            self.regs.write8(reg_num, result as u8);
+                        }
+                    }
+                    3 => {
+                        trace!("alu{} reg16, rm16", op);
+                        let opcode_params = self.fetch_modrm(ctx);
+                        let reg_num = Reg16::from_num(opcode_params.reg).unwrap();
+                        let reg = self.regs.read16(reg_num);
+                        let rm = self.read_rm16(ctx, opcode_params.rm);
+                        let result = self.alu(op, reg, rm, RegisterType::Bits16);
+                        if op != 7 {
+                            self.regs.write16(reg_num, result);
+                        }
+                    }
+                    4 => {
+                        trace!("alu{} al, imm8", op);
+                        let imm = self.fetch8(ctx);
+                        let al = self.regs.read8(Reg8::AL);
+                        let result = self.alu(op, al as u16, imm as u16, RegisterType::Bits8);
+                        if op != 7 {
+                            self.regs.write8(Reg8::AL, result as u8);
+                        }
+                    }
+                    _ => {
+                        trace!("alu{} ax, imm16", op);
+                        let imm = self.fetch16(ctx);
+                        let ax = self.regs.read16(Reg16::AX);
+                        let result = self.alu(op, ax, imm, RegisterType::Bits16);
+                        if op != 7 {
+                            self.regs.write16(Reg16::AX, result);
+                        }
+                    }
                 }
             }
-            0x02 => {
-                println!("add reg8, rm8");
-                let modrm = self.mem_read_byte(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                );
-                self.regs.ip = self.regs.ip.wrapping_add(2);
-                let opcode_params = self.get_opcode_params_from_modrm(ctx, modrm);
-                let reg_num = (modrm & 0x38) >> 3;
-                let reg = self.regs.read8(Reg8::from_num(reg_num).unwrap());
-                let mut rm: u8 = 0;
-                if let Operand::Register(opcode_rm) = opcode_params.rm {
-                    rm = self.regs.read8(Reg8::from_num(opcode_rm).unwrap());
-                } else if let Operand::Address(segment, opcode_rm) = opcode_params.rm {
-                    rm = self.mem_read_byte(ctx, self.regs.readseg16(segment), opcode_rm);
+            0x06 | 0x0e | 0x16 | 0x1e => {
+                let seg = SegReg::from_num(self.opcode >> 3).unwrap();
+                trace!("push {:?}", seg);
+                self.push16(ctx, self.regs.readseg16(seg));
+            }
+            0x07 | 0x0f | 0x17 | 0x1f => {
+                // 0x0f is POP CS on the 8086; later CPUs reuse it as a prefix.
+                let seg = SegReg::from_num(self.opcode >> 3).unwrap();
+                trace!("pop {:?}", seg);
+                let value = self.pop16(ctx);
+                self.regs.writeseg16(seg, value);
+                // The 8086 holds off interrupts after any segment register load.
+                self.inhibit_interrupts = true;
+            }
+            0x26 | 0x2e | 0x36 | 0x3e => {
+                let seg = SegReg::from_num(self.opcode >> 3).unwrap();
+                trace!("{:?}:", seg);
+                self.seg_override = Some(seg);
+                return self.execute(ctx);
+            }
+            0x27 => {
+                trace!("daa");
+                let old_al = self.regs.read8(Reg8::AL);
+                let old_carry = self.regs.flags.contains(Flags::CARRY);
+                let mut al = old_al;
+                if (al & 0x0f) > 9 || self.regs.flags.contains(Flags::ADJUST) {
+                    al = al.wrapping_add(6);
+                    self.regs.flags.set(Flags::ADJUST, true);
+                } else {
+                    self.regs.flags.set(Flags::ADJUST, false);
                 }
-                let result = reg.wrapping_add(rm);
-                self.set_pzs8(result);
-                self.regs.flags.set(
-                    Flags::OVERFLOW,
-                    ((result ^ rm) & (result ^ reg) & 0x80) == 0x80,
-                );
-                self.regs
-                    .flags
-                    .set(Flags::ADJUST, ((result ^ reg ^ rm) & 0x10) == 0x10);
-                self.regs
-                    .flags
-                    .set(Flags::CARRY, (rm & 0x80) > (result & 0x80));
-                self.regs.write8(Reg8::from_num(reg_num).unwrap(), result);
-            }
-            0x03 => {
-                println!("add reg16, rm16");
-                let modrm = self.mem_read_byte(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                );
-                self.regs.ip = self.regs.ip.wrapping_add(2);
-                let opcode_params = self.get_opcode_params_from_modrm(ctx, modrm);
-                let reg_num = (modrm & 0x38) >> 3;
-                let reg = self.regs.read16(Reg16::from_num(reg_num).unwrap());
-                let mut rm: u16 = 0;
-                if let Operand::Register(opcode_rm) = opcode_params.rm {
-                    rm = self.regs.read16(Reg16::from_num(opcode_rm).unwrap());
-                } else if let Operand::Address(segment, opcode_rm) = opcode_params.rm {
-                    rm = self.mem_read_word(ctx, self.regs.readseg16(segment), opcode_rm);
+                if old_al > 0x99 || old_carry {
+                    al = al.wrapping_add(0x60);
+                    self.regs.flags.set(Flags::CARRY, true);
+                } else {
+                    self.regs.flags.set(Flags::CARRY, false);
                 }
-                let result = reg.wrapping_add(rm);
-                self.set_pzs16(result);
-                self.regs.flags.set(
-                    Flags::OVERFLOW,
-                    ((result ^ rm) & (result ^ reg) & 0x80) == 0x80,
-                );
-                self.regs
-                    .flags
-                    .set(Flags::ADJUST, ((result ^ reg ^ rm) & 0x10) == 0x10);
-                self.regs
-                    .flags
-                    .set(Flags::CARRY, (rm & 0x8000) > (result & 0x8000));
-                self.regs.write16(Reg16::from_num(reg_num).unwrap(), result);
-            }
-            0x06 => {
-                println!("push es");
-                self.regs
-                    .write16(Reg16::SP, self.regs.read16(Reg16::SP).wrapping_sub(2));
-                self.mem_write_word(
-                    ctx,
-                    self.regs.readseg16(SegReg::SS),
-                    self.regs.read16(Reg16::SP),
-                    self.regs.readseg16(SegReg::ES),
-                );
-                self.regs.ip = self.regs.ip.wrapping_add(1);
-            }
-            0x07 => {
-                println!("pop es");
-                let es = self.pop16(ctx);
-                self.regs.writeseg16(SegReg::ES, es);
-                self.regs.ip = self.regs.ip.wrapping_add(1);
-            }
-            0x0a => {
-                println!("or reg8, rm8");
-                let modrm = self.mem_read_byte(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                );
-                self.regs.ip = self.regs.ip.wrapping_add(2);
-                let opcode_params = self.get_opcode_params_from_modrm(ctx, modrm);
-                self.regs.flags.set(Flags::OVERFLOW, false);
-                self.regs.flags.set(Flags::CARRY, false);
-                let reg_num = (modrm & 0x38) >> 3;
-                let reg = self.regs.read8(Reg8::from_num(reg_num).unwrap());
-                let mut rm: u8 = 0;
-                if let Operand::Register(opcode_rm) = opcode_params.rm {
-                    rm = self.regs.read8(Reg8::from_num(opcode_rm).unwrap());
-                } else if let Operand::Address(segment, opcode_rm) = opcode_params.rm {
-                    rm = self.mem_read_byte(ctx, self.regs.readseg16(segment), opcode_rm);
+                self.regs.write8(Reg8::AL, al);
+                self.set_pzs8(al);
+            }
+            0x2f => {
+                trace!("das");
+                let old_al = self.regs.read8(Reg8::AL);
+                let old_carry = self.regs.flags.contains(Flags::CARRY);
+                let mut al = old_al;
+                if (al & 0x0f) > 9 || self.regs.flags.contains(Flags::ADJUST) {
+                    al = al.wrapping_sub(6);
+                    self.regs.flags.set(Flags::ADJUST, true);
+                } else {
+                    self.regs.flags.set(Flags::ADJUST, false);
                 }
-                let result = reg | rm;
-                self.set_pzs8(result);
-                self.regs.write8(Reg8::from_num(reg_num).unwrap(), result);
-            }
-            0x0b => {
-                println!("or reg16, rm16");
-                let modrm = self.mem_read_byte(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                );
-                self.regs.ip = self.regs.ip.wrapping_add(2);
-                let opcode_params = self.get_opcode_params_from_modrm(ctx, modrm);
-                self.regs.flags.set(Flags::OVERFLOW, false);
-                self.regs.flags.set(Flags::CARRY, false);
-                let reg_num = (modrm & 0x38) >> 3;
-                let reg = self.regs.read16(Reg16::from_num(reg_num).unwrap());
-                let mut rm: u16 = 0;
-                if let Operand::Register(opcode_rm_reg) = opcode_params.rm {
-                    rm = self.regs.read16(Reg16::from_num(opcode_rm_reg).unwrap());
-                } else if let Operand::Address(segment, opcode_rm_addr) = opcode_params.rm {
-                    rm = self.mem_read_word(ctx, self.regs.readseg16(segment), opcode_rm_addr);
+                if old_al > 0x99 || old_carry {
+                    al = al.wrapping_sub(0x60);
+                    self.regs.flags.set(Flags::CARRY, true);
+                } else {
+                    self.regs.flags.set(Flags::CARRY, false);
                 }
-                let result = reg | rm;
-                self.set_pzs16(result);
-                self.regs.write16(Reg16::from_num(reg_num).unwrap(), result);
-            }
-            0x13 => {
-                println!("adc reg16, rm16");
-                let modrm = self.mem_read_byte(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                );
-                self.regs.ip = self.regs.ip.wrapping_add(2);
-                let opcode_params = self.get_opcode_params_from_modrm(ctx, modrm);
-                let reg_num = (modrm & 0x38) >> 3;
-                let reg = self.regs.read16(Reg16::from_num(reg_num).unwrap());
-                let mut rm: u16 = 0;
-                if let Operand::Register(opcode_rm) = opcode_params.rm {
-                    rm = self.regs.read16(Reg16::from_num(opcode_rm).unwrap());
-                } else if let Operand::Address(segment, opcode_rm) = opcode_params.rm {
-                    rm = self.mem_read_word(ctx, self.regs.readseg16(segment), opcode_rm);
+                self.regs.write8(Reg8::AL, al);
+                self.set_pzs8(al);
+            }
+            0x37 | 0x3f => {
+                let adjust = (self.regs.read8(Reg8::AL) & 0x0f) > 9
+                    || self.regs.flags.contains(Flags::ADJUST);
+                if adjust {
+                    if self.opcode == 0x37 {
+                        trace!("aaa");
+                        self.regs
+                            .write8(Reg8::AL, self.regs.read8(Reg8::AL).wrapping_add(6));
+                        self.regs
+                            .write8(Reg8::AH, self.regs.read8(Reg8::AH).wrapping_add(1));
+                    } else {
+                        trace!("aas");
+                        self.regs
+                            .write8(Reg8::AL, self.regs.read8(Reg8::AL).wrapping_sub(6));
+                        self.regs
+                            .write8(Reg8::AH, self.regs.read8(Reg8::AH).wrapping_sub(1));
+                    }
                 }
-                let carry: u16 = if self.regs.flags.contains(Flags::CARRY) { 1 } else { 0 };
-                let result = reg.wrapping_add(rm).wrapping_add(carry);
-                self.set_pzs16(result);
-                self.regs.flags.set(
-                    Flags::OVERFLOW,
-                    ((result ^ rm) & (result ^ reg) & 0x80) == 0x80,
-                );
+                self.regs.flags.set(Flags::ADJUST, adjust);
+                self.regs.flags.set(Flags::CARRY, adjust);
+                self.regs.write8(Reg8::AL, self.regs.read8(Reg8::AL) & 0x0f);
+            }
+            0x40..=0x47 => {
+                let reg = Reg16::from_num(self.opcode).unwrap();
+                trace!("inc {:?}", reg);
+                let result = self.inc(self.regs.read16(reg), RegisterType::Bits16);
+                self.regs.write16(reg, result);
+            }
+            0x48..=0x4f => {
+                let reg = Reg16::from_num(self.opcode).unwrap();
+                trace!("dec {:?}", reg);
+                let result = self.dec(self.regs.read16(reg), RegisterType::Bits16);
+                self.regs.write16(reg, result);
+            }
+            0x50..=0x57 => {
+                let reg = Reg16::from_num(self.opcode).unwrap();
+                trace!("push {:?}", reg);
+                // The 8086 stores SP after it has been decremented.
+                let stack_pointer = self.regs.read16(Reg16::SP).wrapping_sub(2);
+                self.regs.write16(Reg16::SP, stack_pointer);
+                let value = self.regs.read16(reg);
+                self.mem_write_word(ctx, self.regs.readseg16(SegReg::SS), stack_pointer, value);
+            }
+            0x58..=0x5f => {
+                let reg = Reg16::from_num(self.opcode).unwrap();
+                trace!("pop {:?}", reg);
+                let value = self.pop16(ctx);
+                self.regs.write16(reg, value);
+            }
+            0x60..=0x7f => {
+                // 0x60-0x6f decode as aliases of 0x70-0x7f on the 8086.
+                trace!("j{:x} rel8", self.opcode & 0x0f);
+                let offset = self.fetch8(ctx);
+                let taken = self.condition(self.opcode & 0x0f);
+                self.jump_short(offset, taken);
+            }
+            0x80..=0x83 => {
+                let opcode_params = self.fetch_modrm(ctx);
+                let op = opcode_params.reg;
+                let width = self.opcode_width();
+                trace!("alu{} rm, imm", op);
+                let rm = self.read_rm(ctx, opcode_params.rm, width);
+                let imm = match self.opcode {
+                    0x81 => self.fetch16(ctx),
+                    0x83 => self.fetch8(ctx) as i8 as u16,
+                    _ => self.fetch8(ctx) as u16,
+                };
+                let result = self.alu(op, rm, imm, width);
+                if op != 7 {
+                    self.write_rm(ctx, opcode_params.rm, result, width);
+                }
+            }
+            0x84 => {
+                trace!("test rm8, reg8");
+                let opcode_params = self.fetch_modrm(ctx);
+                let reg = self.regs.read8(Reg8::from_num(opcode_params.reg).unwrap());
+                let rm = self.read_rm8(ctx, opcode_params.rm);
+                self.alu(4, rm as u16, reg as u16, RegisterType::Bits8);
+            }
+            0x85 => {
+                trace!("test rm16, reg16");
+                let opcode_params = self.fetch_modrm(ctx);
+                let reg = self.regs.read16(Reg16::from_num(opcode_params.reg).unwrap());
+                let rm = self.read_rm16(ctx, opcode_params.rm);
+                self.alu(4, rm, reg, RegisterType::Bits16);
+            }
+            0x86 => {
+                trace!("xchg rm8, reg8");
+                let opcode_params = self.fetch_modrm(ctx);
+                let reg_num = Reg8::from_num(opcode_params.reg).unwrap();
+                let reg = self.regs.read8(reg_num);
+                let rm = self.read_rm8(ctx, opcode_params.rm);
+                self.write_rm8(ctx, opcode_params.rm, reg);
+                self.regs.write8(reg_num, rm);
+            }
+            0x87 => {
+                trace!("xchg rm16, reg16");
+                let opcode_params = self.fetch_modrm(ctx);
+                let reg_num = Reg16::from_num(opcode_params.reg).unwrap();
+                let reg = self.regs.read16(reg_num);
+                let rm = self.read_rm16(ctx, opcode_params.rm);
+                self.write_rm16(ctx, opcode_params.rm, reg);
+                self.regs.write16(reg_num, rm);
+            }
+            0x88 => {
+                trace!("mov rm8, reg8");
+                let opcode_params = self.fetch_modrm(ctx);
+                let reg = self.regs.read8(Reg8::from_num(opcode_params.reg).unwrap());
+                self.write_rm8(ctx, opcode_params.rm, reg);
+            }
+            0x89 => {
+                trace!("mov rm16, reg16");
+                let opcode_params = self.fetch_modrm(ctx);
+                let reg = self.regs.read16(Reg16::from_num(opcode_params.reg).unwrap());
+                self.write_rm16(ctx, opcode_params.rm, reg);
+            }
+            0x8a => {
+                trace!("mov reg8, rm8");
+                let opcode_params = self.fetch_modrm(ctx);
+                let rm = self.read_rm8(ctx, opcode_params.rm);
                 self.regs
-                    .flags
-                    .set(Flags::ADJUST, ((result ^ reg ^ rm) & 0x10) == 0x10);
+                    .write8(Reg8::from_num(opcode_params.reg).unwrap(), rm);
+            }
+            0x8b => {
+                trace!("mov reg16, rm16");
+                let opcode_params = self.fetch_modrm(ctx);
+                let rm = self.read_rm16(ctx, opcode_params.rm);
                 self.regs
-                    .flags
-                    .set(Flags::CARRY, (rm & 0x8000) > (result & 0x8000));
-                self.regs.write16(Reg16::from_num(reg_num).unwrap(), result);
+                    .write16(Reg16::from_num(opcode_params.reg).unwrap(), rm);
             }
-            0x16 => {
-                println!("push ss");
+            0x8c => {
+                trace!("mov rm, seg");
+                let opcode_params = self.fetch_modrm(ctx);
+                let seg = self
+                    .regs
+                    .readseg16(SegReg::from_num(opcode_params.reg).unwrap());
+                self.write_rm16(ctx, opcode_params.rm, seg);
+            }
+            0x8d => {
+                trace!("lea");
+                let opcode_params = self.fetch_modrm(ctx);
+                let (_, offset) = self.memory_operand(opcode_params.rm);
                 self.regs
-                    .write16(Reg16::SP, self.regs.read16(Reg16::SP).wrapping_sub(2));
-                self.mem_write_word(
-                    ctx,
-                    self.regs.readseg16(SegReg::SS),
-                    self.regs.read16(Reg16::SP),
-                    self.regs.readseg16(SegReg::SS),
-                );
-                self.regs.ip = self.regs.ip.wrapping_add(1);
+                    .write16(Reg16::from_num(opcode_params.reg).unwrap(), offset);
             }
-            0x1e => {
-                println!("push ds");
+            0x8e => {
+                trace!("mov seg, rm");
+                let opcode_params = self.fetch_modrm(ctx);
+                let rm = self.read_rm16(ctx, opcode_params.rm);
                 self.regs
-                    .write16(Reg16::SP, self.regs.read16(Reg16::SP).wrapping_sub(2));
-                self.mem_write_word(
-                    ctx,
-                    self.regs.readseg16(SegReg::SS),
-                    self.regs.read16(Reg16::SP),
-                    self.regs.readseg16(SegReg::DS),
-                );
-                self.regs.ip = self.regs.ip.wrapping_add(1);
-            }
-            0x1f => {
-                println!("pop ds");
-                let ds = self.pop16(ctx);
-                self.regs.writeseg16(SegReg::DS, ds);
-                self.regs.ip = self.regs.ip.wrapping_add(1);
-            }
-            0x22 => {
-                println!("and reg8, rm8");
-                let modrm = self.mem_read_byte(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
+                    .writeseg16(SegReg::from_num(opcode_params.reg).unwrap(), rm);
+                self.inhibit_interrupts = true;
+            }
+            0x8f => {
+                trace!("pop rm16");
+                let opcode_params = self.fetch_modrm(ctx);
+                let value = self.pop16(ctx);
+                self.write_rm16(ctx, opcode_params.rm, value);
+            }
+            0x90 => {
+                trace!("nop");
+            }
+            0x91..=0x97 => {
+                let reg = Reg16::from_num(self.opcode).unwrap();
+                trace!("xchg {:?}, ax", reg);
+                let value = self.regs.read16(reg);
+                self.regs.write16(reg, self.regs.read16(Reg16::AX));
+                self.regs.write16(Reg16::AX, value);
+            }
+            0x98 => {
+                trace!("cbw");
+                let al = self.regs.read8(Reg8::AL);
+                self.regs.write16(Reg16::AX, al as i8 as u16);
+            }
+            0x99 => {
+                trace!("cwd");
+                let sign = (self.regs.read16(Reg16::AX) & 0x8000) != 0;
+                self.regs.write16(Reg16::DX, if sign { 0xffff } else { 0 });
+            }
+            0x9a => {
+                trace!("call far");
+                let offset = self.fetch16(ctx);
+                let segment = self.fetch16(ctx);
+                self.push16(ctx, self.regs.readseg16(SegReg::CS));
+                self.push16(ctx, self.regs.ip);
+                self.regs.writeseg16(SegReg::CS, segment);
+                self.regs.ip = offset;
+            }
+            0x9b => {
+                trace!("wait");
+            }
+            0x9c => {
+                trace!("pushf");
+                self.push16(ctx, self.regs.read16(Reg16::FLAGS));
+            }
+            0x9d => {
+                trace!("popf");
+                let flags = self.pop16(ctx);
+                self.regs.write16(Reg16::FLAGS, flags);
+            }
+            0x9e => {
+                trace!("sahf");
+                self.regs.write16(
+                    Reg16::FLAGS,
+                    (self.regs.read16(Reg16::FLAGS) & 0xff00)
+                        | (self.regs.read8(Reg8::AH) as u16 & 0xd5),
                 );
-                self.regs.ip = self.regs.ip.wrapping_add(2);
-                let opcode_params = self.get_opcode_params_from_modrm(ctx, modrm);
-                self.regs.flags.set(Flags::OVERFLOW, false);
-                self.regs.flags.set(Flags::CARRY, false);
-                let reg_num = (modrm & 0x38) >> 3;
-                let reg = self.regs.read8(Reg8::from_num(reg_num).unwrap());
-                let mut rm: u8 = 0;
-                if let Operand::Register(opcode_rm) = opcode_params.rm {
-                    rm = self.regs.read8(Reg8::from_num(opcode_rm).unwrap());
-                } else if let Operand::Address(segment, opcode_rm) = opcode_params.rm {
-                    rm = self.mem_read_byte(ctx, self.regs.readseg16(segment), opcode_rm);
-                }
-                let result = reg | rm;
-                self.set_pzs8(result);
-                self.regs.write8(Reg8::from_num(reg_num).unwrap(), result);
-            }
-            0x23 => {
-                println!("and reg16, rm16");
-                let modrm = self.mem_read_byte(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                );
-                self.regs.ip = self.regs.ip.wrapping_add(2);
-                let opcode_params = self.get_opcode_params_from_modrm(ctx, modrm);
-                self.regs.flags.set(Flags::OVERFLOW, false);
-                self.regs.flags.set(Flags::CARRY, false);
-                let reg_num = (modrm & 0x38) >> 3;
-                let reg = self.regs.read16(Reg16::from_num(reg_num).unwrap());
-                let mut rm: u16 = 0;
-                if let Operand::Register(opcode_rm) = opcode_params.rm {
-                    rm = self.regs.read16(Reg16::from_num(opcode_rm).unwrap());
-                } else if let Operand::Address(segment, opcode_rm) = opcode_params.rm {
-                    rm = self.mem_read_word(ctx, self.regs.readseg16(segment), opcode_rm);
-                }
-                let result = reg & rm;
-                self.set_pzs16(result);
-                self.regs.write16(Reg16::from_num(reg_num).unwrap(), result);
-            }
-            0x24 => {
-                println!("and al, imm");
-                let imm_value = self.mem_read_byte(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                );
-                self.regs.write8(Reg8::AL, self.regs.read8(Reg8::AL) & imm_value);
-                self.set_pzs8(self.regs.read8(Reg8::AL));
-                self.regs.flags.set(Flags::OVERFLOW, false);
-                self.regs.flags.set(Flags::CARRY, false);
-                self.regs.ip = self.regs.ip.wrapping_add(2);
-            }
-            0x26 => {
-                println!("es:");
-                self.seg_override = Some(SegReg::ES);
-                self.regs.ip = self.regs.ip.wrapping_add(1);
-                self.tick(ctx);
-            }
-            0x28 => {
-                println!("sub rm8, reg8");
-                let modrm = self.mem_read_byte(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                );
-                self.regs.ip = self.regs.ip.wrapping_add(2);
-                let opcode_params = self.get_opcode_params_from_modrm(ctx, modrm);
-                let reg_num = (modrm & 0x38) >> 3;
-                let reg = self.regs.read8(Reg8::from_num(reg_num).unwrap());
-                let mut rm: u8 = 0;
-                let mut segment_long: SegReg = SegReg::DS;
-                let mut opcode_rm_long: u16 = 0;
-                if let Operand::Register(opcode_rm) = opcode_params.rm {
-                    rm = self.regs.read8(Reg8::from_num(opcode_rm).unwrap());
-
-                    let result = reg.wrapping_sub(rm);
-                    self.set_pzs8(result);
-                    self.regs.flags.set(
-                        Flags::OVERFLOW,
-                        ((reg ^ rm) & (result ^ reg) & 0x80) == 0x80,
-                    );
-                    self.regs
-                        .flags
-                        .set(Flags::ADJUST, ((result ^ reg ^ rm) & 0x10) == 0x10);
-                    self.regs
-                        .flags
-                        .set(Flags::CARRY, (rm & 0x80) > (reg & 0x80));
-                    self.regs.write8(Reg8::from_num(opcode_rm).unwrap(), result);
-                } else if let Operand::Address(segment, opcode_rm) = opcode_params.rm {
-                    rm = self.mem_read_byte(ctx, self.regs.readseg16(segment), opcode_rm);
-                    opcode_rm_long = opcode_rm;
-                    segment_long = segment;
-
-                    let result = reg.wrapping_sub(rm);
-                    self.set_pzs8(result);
-                    self.regs.flags.set(
-                        Flags::OVERFLOW,
-                        ((reg ^ rm) & (result ^ reg) & 0x80) == 0x80,
-                    );
-                    self.regs
-                        .flags
-                        .set(Flags::ADJUST, ((result ^ reg ^ rm) & 0x10) == 0x10);
-                    self.regs
-                        .flags
-                        .set(Flags::CARRY, rm > reg);
-                    self.mem_write_byte(
-                        ctx,
-                        self.regs.readseg16(segment_long),
-                        opcode_rm_long,
-                        result,
-                    );
-                }
-            }
-            0x2a => {
-                println!("sub reg8, rm8");
-                let modrm = self.mem_read_byte(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                );
-                self.regs.ip = self.regs.ip.wrapping_add(2);
-                let opcode_params = self.get_opcode_params_from_modrm(ctx, modrm);
-                let reg_num = (modrm & 0x38) >> 3;
-                let reg = self.regs.read8(Reg8::from_num(reg_num).unwrap());
-                let mut rm: u8 = 0;
-                if let Operand::Register(opcode_rm) = opcode_params.rm {
-                    rm = self.regs.read8(Reg8::from_num(opcode_rm).unwrap());
-                } else if let Operand::Address(segment, opcode_rm) = opcode_params.rm {
-                    rm = self.mem_read_byte(ctx, self.regs.readseg16(segment), opcode_rm);
-                }
-                let result = reg.wrapping_sub(rm);
-                self.set_pzs8(result);
-                self.regs.flags.set(
-                    Flags::OVERFLOW,
-                    ((reg ^ rm) & (result ^ reg) & 0x80) == 0x80,
-                );
-                self.regs
-                    .flags
-                    .set(Flags::ADJUST, ((result ^ reg ^ rm) & 0x10) == 0x10);
-                self.regs
-                    .flags
-                    .set(Flags::CARRY, rm > reg);
-                self.regs.write8(Reg8::from_num(reg_num).unwrap(), result);
-            }
-            0x2b => {
-                println!("sub reg16, rm16");
-                let modrm = self.mem_read_byte(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                );
-                self.regs.ip = self.regs.ip.wrapping_add(2);
-                let opcode_params = self.get_opcode_params_from_modrm(ctx, modrm);
-                let reg_num = (modrm & 0x38) >> 3;
-                let reg = self.regs.read16(Reg16::from_num(reg_num).unwrap());
-                let mut rm: u16 = 0;
-                if let Operand::Register(opcode_rm) = opcode_params.rm {
-                    rm = self.regs.read16(Reg16::from_num(opcode_rm).unwrap());
-                } else if let Operand::Address(segment, opcode_rm) = opcode_params.rm {
-                    rm = self.mem_read_word(ctx, self.regs.readseg16(segment), opcode_rm);
-                }
-                let result = reg.wrapping_sub(rm);
-                self.set_pzs16(result);
-                self.regs.flags.set(
-                    Flags::OVERFLOW,
-                    ((reg ^ rm) & (result ^ reg) & 0x8000) == 0x8000,
-                );
-                self.regs
-                    .flags
-                    .set(Flags::ADJUST, ((result ^ reg ^ rm) & 0x10) == 0x10);
-                self.regs
-                    .flags
-                    .set(Flags::CARRY, rm > reg);
-                self.regs.write16(Reg16::from_num(reg_num).unwrap(), result);
-            }
-            0x2e => {
-                println!("cs:");
-                self.seg_override = Some(SegReg::CS);
-                self.regs.ip = self.regs.ip.wrapping_add(1);
-                self.tick(ctx);
-            }
-            0x30 => {
-                println!("xor rm8, reg8");
-                let modrm = self.mem_read_byte(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                );
-                self.regs.ip = self.regs.ip.wrapping_add(2);
-                let opcode_params = self.get_opcode_params_from_modrm(ctx, modrm);
-                let reg_num = (modrm & 0x38) >> 3;
-                let reg = self.regs.read8(Reg8::from_num(reg_num).unwrap());
-                let mut rm: u8 = 0;
-                let mut segment_long: SegReg = SegReg::DS;
-                let mut opcode_rm_long: u16 = 0;
-                if let Operand::Register(opcode_rm) = opcode_params.rm {
-                    rm = self.regs.read8(Reg8::from_num(opcode_rm).unwrap());
-
-                    let result = reg ^ rm;
-                    self.set_pzs8(result);
-                    self.regs.write8(Reg8::from_num(opcode_rm).unwrap(), result);
-                } else if let Operand::Address(segment, opcode_rm) = opcode_params.rm {
-                    rm = self.mem_read_byte(ctx, self.regs.readseg16(segment), opcode_rm);
-                    opcode_rm_long = opcode_rm;
-                    segment_long = segment;
-
-                    let result = reg ^ rm;
-                    self.set_pzs8(result);
-                    self.mem_write_byte(
-                        ctx,
-                        self.regs.readseg16(segment_long),
-                        opcode_rm_long,
-                        result,
-                    );
-                }
-            }
-            0x32 => {
-                println!("xor reg8, rm8");
-                let modrm = self.mem_read_byte(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                );
-                self.regs.ip = self.regs.ip.wrapping_add(2);
-                let opcode_params = self.get_opcode_params_from_modrm(ctx, modrm);
-                self.regs.flags.set(Flags::OVERFLOW, false);
-                self.regs.flags.set(Flags::CARRY, false);
-                let reg_num = (modrm & 0x38) >> 3;
-                let reg = self.regs.read8(Reg8::from_num(reg_num).unwrap());
-                let mut rm: u8 = 0;
-                if let Operand::Register(opcode_rm) = opcode_params.rm {
-                    rm = self.regs.read8(Reg8::from_num(opcode_rm).unwrap());
-                } else if let Operand::Address(segment, opcode_rm) = opcode_params.rm {
-                    rm = self.mem_read_byte(ctx, self.regs.readseg16(segment), opcode_rm);
-                }
-                let result = reg ^ rm;
-                self.set_pzs8(result);
-                self.regs.write8(Reg8::from_num(reg_num).unwrap(), result);
-            }
-            0x33 => {
-                println!("xor reg16, rm16");
-                let modrm = self.mem_read_byte(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                );
-                self.regs.ip = self.regs.ip.wrapping_add(2);
-                let opcode_params = self.get_opcode_params_from_modrm(ctx, modrm);
-                self.regs.flags.set(Flags::OVERFLOW, false);
-                self.regs.flags.set(Flags::CARRY, false);
-                let reg_num = (modrm & 0x38) >> 3;
-                let reg = self.regs.read16(Reg16::from_num(reg_num).unwrap());
-                let mut rm: u16 = 0;
-                if let Operand::Register(opcode_rm) = opcode_params.rm {
-                    rm = self.regs.read16(Reg16::from_num(opcode_rm).unwrap());
-                } else if let Operand::Address(segment, opcode_rm) = opcode_params.rm {
-                    rm = self.mem_read_word(ctx, self.regs.readseg16(segment), opcode_rm);
-                }
-                let result = reg ^ rm;
-                self.set_pzs16(result);
-                self.regs.write16(Reg16::from_num(reg_num).unwrap(), result);
-            }
-            0x36 => {
-                println!("ss:");
-                self.seg_override = Some(SegReg::SS);
-                self.regs.ip = self.regs.ip.wrapping_add(1);
-                self.tick(ctx);
-            }
-            0x39 => {
-                println!("cmp rm16, reg16");
-                let modrm = self.mem_read_byte(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                );
-                self.regs.ip = self.regs.ip.wrapping_add(2);
-                let opcode_params = self.get_opcode_params_from_modrm(ctx, modrm);
-                let reg_num = (modrm & 0x38) >> 3;
-                let reg = self.regs.read16(Reg16::from_num(reg_num).unwrap());
-                let mut rm: u16 = 0;
-                let mut segment_long: SegReg = SegReg::DS;
-                let mut opcode_rm_long: u16 = 0;
-                if let Operand::Register(opcode_rm) = opcode_params.rm {
-                    rm = self.regs.read16(Reg16::from_num(opcode_rm).unwrap());
-                } else if let Operand::Address(segment, opcode_rm) = opcode_params.rm {
-                    rm = self.mem_read_word(ctx, self.regs.readseg16(segment), opcode_rm);
-                    opcode_rm_long = opcode_rm;
-                    segment_long = segment;
-                }
-                let result = reg.wrapping_sub(rm);
-                self.set_pzs16(result);
-                self.regs.flags.set(
-                    Flags::OVERFLOW,
-                    ((result ^ rm) & (result ^ reg) & 0x80) == 0x80,
-                );
-                self.regs
-                    .flags
-                    .set(Flags::ADJUST, ((result ^ reg ^ rm) & 0x10) == 0x10);
-                self.regs
-                    .flags
-                    .set(Flags::CARRY, rm > result);
-            }
-            0x3a => {
-                println!("cmp reg8, rm8");
-                let modrm = self.mem_read_byte(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                );
-                self.regs.ip = self.regs.ip.wrapping_add(2);
-                let opcode_params = self.get_opcode_params_from_modrm(ctx, modrm);
-                let reg_num = (modrm & 0x38) >> 3;
-                let reg = self.regs.read8(Reg8::from_num(reg_num).unwrap());
-                let mut rm: u8 = 0;
-                if let Operand::Register(opcode_rm) = opcode_params.rm {
-                    rm = self.regs.read8(Reg8::from_num(opcode_rm).unwrap());
-                } else if let Operand::Address(segment, opcode_rm) = opcode_params.rm {
-                    rm = self.mem_read_byte(ctx, self.regs.readseg16(segment), opcode_rm);
-                }
-                let result = reg.wrapping_sub(rm);
-                self.set_pzs8(result);
-                self.regs.flags.set(
-                    Flags::OVERFLOW,
-                    ((reg ^ rm) & (result ^ reg) & 0x80) == 0x80,
-                );
-                self.regs
-                    .flags
-                    .set(Flags::ADJUST, ((result ^ reg ^ rm) & 0x10) == 0x10);
-                self.regs
-                    .flags
-                    .set(Flags::CARRY, rm > reg);
-            }
-            0x3b => {
-                println!("cmp reg16, rm16");
-                let modrm = self.mem_read_byte(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                );
-                self.regs.ip = self.regs.ip.wrapping_add(2);
-                let opcode_params = self.get_opcode_params_from_modrm(ctx, modrm);
-                let reg_num = (modrm & 0x38) >> 3;
-                let reg = self.regs.read16(Reg16::from_num(reg_num).unwrap());
-                let mut rm: u16 = 0;
-                if let Operand::Register(opcode_rm) = opcode_params.rm {
-                    rm = self.regs.read16(Reg16::from_num(opcode_rm).unwrap());
-                } else if let Operand::Address(segment, opcode_rm) = opcode_params.rm {
-                    rm = self.mem_read_word(ctx, self.regs.readseg16(segment), opcode_rm);
-                }
-                let result = reg.wrapping_sub(rm);
-                self.set_pzs16(result);
-                self.regs.flags.set(
-                    Flags::OVERFLOW,
-                    ((reg ^ rm) & (result ^ reg) & 0x8000) == 0x8000,
-                );
-                self.regs
-                    .flags
-                    .set(Flags::ADJUST, ((result ^ reg ^ rm) & 0x10) == 0x10);
-                self.regs
-                    .flags
-                    .set(Flags::CARRY, rm > reg);
-            }
-            0x3e => {
-                println!("ds:");
-                self.seg_override = Some(SegReg::DS);
-                self.regs.ip = self.regs.ip.wrapping_add(1);
-                self.tick(ctx);
-            }
-            0x40 => {
-                println!("inc ax");
-                let reg: u16 = self.regs.read16(Reg16::AX);
-                let result = reg.wrapping_add(1);
-                self.set_pzs16(result);
-                self.regs.flags.set(
-                    Flags::OVERFLOW,
-                    ((result ^ 1) & (result ^ reg) & 0x8000) == 0x8000,
-                );
-                self.regs
-                    .flags
-                    .set(Flags::ADJUST, ((result ^ reg ^ 1) & 0x10) == 0x10);
-                self.regs.write16(Reg16::AX, result);
-                self.regs.ip = self.regs.ip.wrapping_add(1);
-            }
-            0x41 => {
-                println!("inc cx");
-                let reg: u16 = self.regs.read16(Reg16::CX);
-                let result = reg.wrapping_add(1);
-                self.set_pzs16(result);
-                self.regs.flags.set(
-                    Flags::OVERFLOW,
-                    ((result ^ 1) & (result ^ reg) & 0x8000) == 0x8000,
-                );
-                self.regs
-                    .flags
-                    .set(Flags::ADJUST, ((result ^ reg ^ 1) & 0x10) == 0x10);
-                self.regs.write16(Reg16::CX, result);
-                self.regs.ip = self.regs.ip.wrapping_add(1);
-            }
-            0x42 => {
-                println!("inc dx");
-                let reg: u16 = self.regs.read16(Reg16::DX);
-                let result = reg.wrapping_add(1);
-                self.set_pzs16(result);
-                self.regs.flags.set(
-                    Flags::OVERFLOW,
-                    ((result ^ 1) & (result ^ reg) & 0x8000) == 0x8000,
-                );
-                self.regs
-                    .flags
-                    .set(Flags::ADJUST, ((result ^ reg ^ 1) & 0x10) == 0x10);
-                self.regs.write16(Reg16::DX, result);
-                self.regs.ip = self.regs.ip.wrapping_add(1);
-            }
-            0x43 => {
-                println!("inc bx");
-                let reg: u16 = self.regs.read16(Reg16::BX);
-                let result = reg.wrapping_add(1);
-                self.set_pzs16(result);
-                self.regs.flags.set(
-                    Flags::OVERFLOW,
-                    ((result ^ 1) & (result ^ reg) & 0x8000) == 0x8000,
-                );
-                self.regs
-                    .flags
-                    .set(Flags::ADJUST, ((result ^ reg ^ 1) & 0x10) == 0x10);
-                self.regs.write16(Reg16::BX, result);
-                self.regs.ip = self.regs.ip.wrapping_add(1);
-            }
-            0x44 => {
-                println!("inc sp");
-                let reg: u16 = self.regs.read16(Reg16::SP);
-                let result = reg.wrapping_add(1);
-                self.set_pzs16(result);
-                self.regs.flags.set(
-                    Flags::OVERFLOW,
-                    ((result ^ 1) & (result ^ reg) & 0x8000) == 0x8000,
-                );
-                self.regs
-                    .flags
-                    .set(Flags::ADJUST, ((result ^ reg ^ 1) & 0x10) == 0x10);
-                self.regs.write16(Reg16::SP, result);
-                self.regs.ip = self.regs.ip.wrapping_add(1);
-            }
-            0x45 => {
-                println!("inc bp");
-                let reg: u16 = self.regs.read16(Reg16::BP);
-                let result = reg.wrapping_add(1);
-                self.set_pzs16(result);
-                self.regs.flags.set(
-                    Flags::OVERFLOW,
-                    ((result ^ 1) & (result ^ reg) & 0x8000) == 0x8000,
-                );
-                self.regs
-                    .flags
-                    .set(Flags::ADJUST, ((result ^ reg ^ 1) & 0x10) == 0x10);
-                self.regs.write16(Reg16::BP, result);
-                self.regs.ip = self.regs.ip.wrapping_add(1);
-            }
-            0x46 => {
-                println!("inc si");
-                let reg: u16 = self.regs.read16(Reg16::SI);
-                let result = reg.wrapping_add(1);
-                self.set_pzs16(result);
-                self.regs.flags.set(
-                    Flags::OVERFLOW,
-                    ((result ^ 1) & (result ^ reg) & 0x8000) == 0x8000,
-                );
-                self.regs
-                    .flags
-                    .set(Flags::ADJUST, ((result ^ reg ^ 1) & 0x10) == 0x10);
-                self.regs.write16(Reg16::SI, result);
-                self.regs.ip = self.regs.ip.wrapping_add(1);
-            }
-            0x47 => {
-                println!("inc di");
-                let reg: u16 = self.regs.read16(Reg16::DI);
-                let result = reg.wrapping_add(1);
-                self.set_pzs16(result);
-                self.regs.flags.set(
-                    Flags::OVERFLOW,
-                    ((result ^ 1) & (result ^ reg) & 0x8000) == 0x8000,
-                );
-                self.regs
-                    .flags
-                    .set(Flags::ADJUST, ((result ^ reg ^ 1) & 0x10) == 0x10);
-                self.regs.write16(Reg16::DI, result);
-                self.regs.ip = self.regs.ip.wrapping_add(1);
-            }
-            0x48 => {
-                println!("dec ax");
-                let reg: u16 = self.regs.read16(Reg16::AX);
-                let result = reg.wrapping_sub(1);
-                self.set_pzs16(result);
-                self.regs.flags.set(
-                    Flags::OVERFLOW,
-                    ((reg ^ 1) & (result ^ reg) & 0x8000) == 0x8000,
-                );
-                self.regs
-                    .flags
-                    .set(Flags::ADJUST, ((result ^ reg ^ 1) & 0x10) == 0x10);
-                self.regs.write16(Reg16::AX, result);
-                self.regs.ip = self.regs.ip.wrapping_add(1);
-            }
-            0x49 => {
-                println!("dec cx");
-                let reg: u16 = self.regs.read16(Reg16::CX);
-                let result = reg.wrapping_sub(1);
-                self.set_pzs16(result);
-                self.regs.flags.set(
-                    Flags::OVERFLOW,
-                    ((reg ^ 1) & (result ^ reg) & 0x8000) == 0x8000,
-                );
-                self.regs
-                    .flags
-                    .set(Flags::ADJUST, ((result ^ reg ^ 1) & 0x10) == 0x10);
-                self.regs.write16(Reg16::CX, result);
-                self.regs.ip = self.regs.ip.wrapping_add(1);
-            }
-            0x4a => {
-                println!("dec dx");
-                let reg: u16 = self.regs.read16(Reg16::DX);
-                let result = reg.wrapping_sub(1);
-                self.set_pzs16(result);
-                self.regs.flags.set(
-                    Flags::OVERFLOW,
-                    ((reg ^ 1) & (result ^ reg) & 0x8000) == 0x8000,
-                );
-                self.regs
-                    .flags
-                    .set(Flags::ADJUST, ((result ^ reg ^ 1) & 0x10) == 0x10);
-                self.regs.write16(Reg16::DX, result);
-                self.regs.ip = self.regs.ip.wrapping_add(1);
-            }
-            0x4b => {
-                println!("dec bx");
-                let reg: u16 = self.regs.read16(Reg16::BX);
-                let result = reg.wrapping_sub(1);
-                self.set_pzs16(result);
-                self.regs.flags.set(
-                    Flags::OVERFLOW,
-                    ((reg ^ 1) & (result ^ reg) & 0x8000) == 0x8000,
-                );
-                self.regs
-                    .flags
-                    .set(Flags::ADJUST, ((result ^ reg ^ 1) & 0x10) == 0x10);
-                self.regs.write16(Reg16::BX, result);
-                self.regs.ip = self.regs.ip.wrapping_add(1);
-            }
-            0x4c => {
-                println!("dec sp");
-                let reg: u16 = self.regs.read16(Reg16::SP);
-                let result = reg.wrapping_sub(1);
-                self.set_pzs16(result);
-                self.regs.flags.set(
-                    Flags::OVERFLOW,
-                    ((reg ^ 1) & (result ^ reg) & 0x8000) == 0x8000,
-                );
-                self.regs
-                    .flags
-                    .set(Flags::ADJUST, ((result ^ reg ^ 1) & 0x10) == 0x10);
-                self.regs.write16(Reg16::SP, result);
-                self.regs.ip = self.regs.ip.wrapping_add(1);
-            }
-            0x4d => {
-                println!("dec bp");
-                let reg: u16 = self.regs.read16(Reg16::BP);
-                let result = reg.wrapping_sub(1);
-                self.set_pzs16(result);
-                self.regs.flags.set(
-                    Flags::OVERFLOW,
-                    ((reg ^ 1) & (result ^ reg) & 0x8000) == 0x8000,
-                );
-                self.regs
-                    .flags
-                    .set(Flags::ADJUST, ((result ^ reg ^ 1) & 0x10) == 0x10);
-                self.regs.write16(Reg16::BP, result);
-                self.regs.ip = self.regs.ip.wrapping_add(1);
-            }
-            0x4e => {
-                println!("dec si");
-                let reg: u16 = self.regs.read16(Reg16::SI);
-                let result = reg.wrapping_sub(1);
-                self.set_pzs16(result);
-                self.regs.flags.set(
-                    Flags::OVERFLOW,
-                    ((reg ^ 1) & (result ^ reg) & 0x8000) == 0x8000,
-                );
-                self.regs
-                    .flags
-                    .set(Flags::ADJUST, ((result ^ reg ^ 1) & 0x10) == 0x10);
-                self.regs.write16(Reg16::SI, result);
-                self.regs.ip = self.regs.ip.wrapping_add(1);
-            }
-            0x4f => {
-                println!("dec di");
-                let reg: u16 = self.regs.read16(Reg16::DI);
-                let result = reg.wrapping_sub(1);
-                self.set_pzs16(result);
-                self.regs.flags.set(
-                    Flags::OVERFLOW,
-                    ((reg ^ 1) & (result ^ reg) & 0x8000) == 0x8000,
-                );
-                self.regs
-                    .flags
-                    .set(Flags::ADJUST, ((result ^ reg ^ 1) & 0x10) == 0x10);
-                self.regs.write16(Reg16::DI, result);
-                self.regs.ip = self.regs.ip.wrapping_add(1);
             }
-            0x50 => {
-                println!("push ax");
-                self.regs
-                    .write16(Reg16::SP, self.regs.read16(Reg16::SP).wrapping_sub(2));
-                self.mem_write_word(
-                    ctx,
-                    self.regs.readseg16(SegReg::SS),
-                    self.regs.read16(Reg16::SP),
-                    self.regs.read16(Reg16::AX),
-                );
-                self.regs.ip = self.regs.ip.wrapping_add(1);
-            }
-            0x51 => {
-                println!("push cx");
-                self.regs
-                    .write16(Reg16::SP, self.regs.read16(Reg16::SP).wrapping_sub(2));
-                self.mem_write_word(
-                    ctx,
-                    self.regs.readseg16(SegReg::SS),
-                    self.regs.read16(Reg16::SP),
-                    self.regs.read16(Reg16::CX),
-                );
-                self.regs.ip = self.regs.ip.wrapping_add(1);
-            }
-            0x52 => {
-                println!("push dx");
-                self.regs
-                    .write16(Reg16::SP, self.regs.read16(Reg16::SP).wrapping_sub(2));
-                self.mem_write_word(
-                    ctx,
-                    self.regs.readseg16(SegReg::SS),
-                    self.regs.read16(Reg16::SP),
-                    self.regs.read16(Reg16::DX),
-                );
-                self.regs.ip = self.regs.ip.wrapping_add(1);
-            }
-            0x53 => {
-                println!("push bx");
+            0x9f => {
+                trace!("lahf");
                 self.regs
-                    .write16(Reg16::SP, self.regs.read16(Reg16::SP).wrapping_sub(2));
-                self.mem_write_word(
-                    ctx,
-                    self.regs.readseg16(SegReg::SS),
-                    self.regs.read16(Reg16::SP),
-                    self.regs.read16(Reg16::BX),
-                );
-                self.regs.ip = self.regs.ip.wrapping_add(1);
+                    .write8(Reg8::AH, (self.regs.read16(Reg16::FLAGS) & 0xd7) as u8);
             }
-            0x54 => {
-                println!("push sp");
-                let sp = self.regs.read16(Reg16::SP);
-                self.regs
-                    .write16(Reg16::SP, self.regs.read16(Reg16::SP).wrapping_sub(2));
-                self.mem_write_word(
-                    ctx,
-                    self.regs.readseg16(SegReg::SS),
-                    self.regs.read16(Reg16::SP),
-                    sp,
-                );
-                self.regs.ip = self.regs.ip.wrapping_add(1);
+            0xa0 => {
+                trace!("mov al, [imm]");
+                let offset = self.fetch16(ctx);
+                let seg = self.regs.readseg16(self.data_seg());
+                let value = self.mem_read_byte(ctx, seg, offset);
+                self.regs.write8(Reg8::AL, value);
             }
-            0x55 => {
-                println!("push bp");
+            0xa1 => {
+                trace!("mov ax, [imm]");
+                let offset = self.fetch16(ctx);
+                let seg = self.regs.readseg16(self.data_seg());
+                let value = self.mem_read_word(ctx, seg, offset);
+                self.regs.write16(Reg16::AX, value);
+            }
+            0xa2 => {
+                trace!("mov [imm], al");
+                let offset = self.fetch16(ctx);
+                let seg = self.regs.readseg16(self.data_seg());
+                self.mem_write_byte(ctx, seg, offset, self.regs.read8(Reg8::AL));
+            }
+            0xa3 => {
+                trace!("mov [imm], ax");
+                let offset = self.fetch16(ctx);
+                let seg = self.regs.readseg16(self.data_seg());
+                self.mem_write_word(ctx, seg, offset, self.regs.read16(Reg16::AX));
+            }
+            0xa4..=0xa7 | 0xaa..=0xaf => {
+                trace!("string op {:#02x}", self.opcode);
+                self.string_instruction(ctx);
+            }
+            0xa8 => {
+                trace!("test al, imm8");
+                let imm = self.fetch8(ctx);
+                self.alu(
+                    4,
+                    self.regs.read8(Reg8::AL) as u16,
+                    imm as u16,
+                    RegisterType::Bits8,
+                );
+            }
+            0xa9 => {
+                trace!("test ax, imm16");
+                let imm = self.fetch16(ctx);
+                self.alu(4, self.regs.read16(Reg16::AX), imm, RegisterType::Bits16);
+            }
+            0xb0..=0xb7 => {
+                let reg = Reg8::from_num(self.opcode).unwrap();
+                trace!("mov {:?}, imm", reg);
+                let imm = self.fetch8(ctx);
+                self.regs.write8(reg, imm);
+            }
+            0xb8..=0xbf => {
+                let reg = Reg16::from_num(self.opcode).unwrap();
+                trace!("mov {:?}, imm", reg);
+                let imm = self.fetch16(ctx);
+                self.regs.write16(reg, imm);
+            }
+            0xc0 | 0xc2 => {
+                // 0xc0 decodes as an alias of 0xc2 on the 8086.
+                trace!("ret imm");
+                let imm = self.fetch16(ctx);
+                self.regs.ip = self.pop16(ctx);
                 self.regs
-                    .write16(Reg16::SP, self.regs.read16(Reg16::SP).wrapping_sub(2));
-                self.mem_write_word(
-                    ctx,
-                    self.regs.readseg16(SegReg::SS),
-                    self.regs.read16(Reg16::SP),
-                    self.regs.read16(Reg16::BP),
-                );
-                self.regs.ip = self.regs.ip.wrapping_add(1);
+                    .write16(Reg16::SP, self.regs.read16(Reg16::SP).wrapping_add(imm));
             }
-            0x56 => {
-                println!("push si");
-                self.regs
-                    .write16(Reg16::SP, self.regs.read16(Reg16::SP).wrapping_sub(2));
-                self.mem_write_word(
-                    ctx,
-                    self.regs.readseg16(SegReg::SS),
-                    self.regs.read16(Reg16::SP),
-                    self.regs.read16(Reg16::SI),
-                );
-                self.regs.ip = self.regs.ip.wrapping_add(1);
+            0xc1 | 0xc3 => {
+                trace!("ret");
+                self.regs.ip = self.pop16(ctx);
             }
-            0x57 => {
-                println!("push di");
+            0xc4 | 0xc5 => {
+                let seg = if self.opcode == 0xc4 {
+                    trace!("les");
+                    SegReg::ES
+                } else {
+                    trace!("lds");
+                    SegReg::DS
+                };
+                let opcode_params = self.fetch_modrm(ctx);
+                let (selector, addr) = self.read_far_pointer(ctx, opcode_params.rm);
+                self.regs.writeseg16(seg, selector);
                 self.regs
-                    .write16(Reg16::SP, self.regs.read16(Reg16::SP).wrapping_sub(2));
-                self.mem_write_word(
-                    ctx,
-                    self.regs.readseg16(SegReg::SS),
-                    self.regs.read16(Reg16::SP),
-                    self.regs.read16(Reg16::DI),
-                );
-                self.regs.ip = self.regs.ip.wrapping_add(1);
-            }
-            0x58 => {
-                println!("pop ax");
-                let tmp = self.pop16(ctx);
-                self.regs.write16(Reg16::AX, tmp);
-                self.regs.ip = self.regs.ip.wrapping_add(1);
-            }
-            0x59 => {
-                println!("pop cx");
-                let tmp = self.pop16(ctx);
-                self.regs.write16(Reg16::CX, tmp);
-                self.regs.ip = self.regs.ip.wrapping_add(1);
-            }
-            0x5a => {
-                println!("pop dx");
-                let tmp = self.pop16(ctx);
-                self.regs.write16(Reg16::DX, tmp);
-                self.regs.ip = self.regs.ip.wrapping_add(1);
-            }
-            0x5b => {
-                println!("pop bx");
-                let tmp = self.pop16(ctx);
-                self.regs.write16(Reg16::BX, tmp);
-                self.regs.ip = self.regs.ip.wrapping_add(1);
-            }
-            0x5c => {
-                println!("pop sp");
-                let tmp = self.pop16(ctx);
-                self.regs.write16(Reg16::SP, tmp);
-                self.regs.ip = self.regs.ip.wrapping_add(1);
-            }
-            0x5d => {
-                println!("pop bp");
-                let tmp = self.pop16(ctx);
-                self.regs.write16(Reg16::BP, tmp);
-                self.regs.ip = self.regs.ip.wrapping_add(1);
-            }
-            0x5e => {
-                println!("pop si");
-                let tmp = self.pop16(ctx);
-                self.regs.write16(Reg16::SI, tmp);
-                self.regs.ip = self.regs.ip.wrapping_add(1);
-            }
-            0x5f => {
-                println!("pop di");
-                let tmp = self.pop16(ctx);
-                self.regs.write16(Reg16::DI, tmp);
-                self.regs.ip = self.regs.ip.wrapping_add(1);
-            }
-            0x70 => {
-                println!("jo");
-                let offset: i16 = self.mem_read_byte(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                ) as i8 as i16;
-                self.regs.ip = self.regs.ip.wrapping_add(2);
-                if self.regs.flags.contains(Flags::OVERFLOW) {
-                    self.regs.ip = self.regs.ip.wrapping_add(offset as u16);
-                }
-            }
-            0x71 => {
-                println!("jno");
-                let offset: i16 = self.mem_read_byte(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                ) as i8 as i16;
-                self.regs.ip = self.regs.ip.wrapping_add(2);
-                if !self.regs.flags.contains(Flags::OVERFLOW) {
-                    self.regs.ip = self.regs.ip.wrapping_add(offset as u16);
-                }
-            }
-            0x72 => {
-                println!("jc");
-                let offset: i16 = self.mem_read_byte(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                ) as i8 as i16;
-                self.regs.ip = self.regs.ip.wrapping_add(2);
-                if self.regs.flags.contains(Flags::CARRY) {
-                    self.regs.ip = self.regs.ip.wrapping_add(offset as u16);
-                }
-            }
-            0x73 => {
-                println!("jnc");
-                let offset: i16 = self.mem_read_byte(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                ) as i8 as i16;
-                self.regs.ip = self.regs.ip.wrapping_add(2);
-                if !self.regs.flags.contains(Flags::CARRY) {
-                    self.regs.ip = self.regs.ip.wrapping_add(offset as u16);
-                }
-            }
-            0x74 => {
-                println!("jz");
-                let offset: i16 = self.mem_read_byte(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                ) as i8 as i16;
-                self.regs.ip = self.regs.ip.wrapping_add(2);
-                if self.regs.flags.contains(Flags::ZERO) {
-                    self.regs.ip = self.regs.ip.wrapping_add(offset as u16);
-                }
-            }
-            0x75 => {
-                println!("jnz");
-                let offset: i16 = self.mem_read_byte(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                ) as i8 as i16;
-                self.regs.ip = self.regs.ip.wrapping_add(2);
-                if !self.regs.flags.contains(Flags::ZERO) {
-                    self.regs.ip = self.regs.ip.wrapping_add(offset as u16);
-                }
-            }
-            0x78 => {
-                println!("js");
-                let offset: i16 = self.mem_read_byte(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                ) as i8 as i16;
-                self.regs.ip = self.regs.ip.wrapping_add(2);
-                if self.regs.flags.contains(Flags::SIGN) {
-                    self.regs.ip = self.regs.ip.wrapping_add(offset as u16);
-                }
-            }
-            0x79 => {
-                println!("jns");
-                let offset: i16 = self.mem_read_byte(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                ) as i8 as i16;
-                self.regs.ip = self.regs.ip.wrapping_add(2);
-                if !self.regs.flags.contains(Flags::SIGN) {
-                    self.regs.ip = self.regs.ip.wrapping_add(offset as u16);
-                }
-            }
-            0x7a => {
-                println!("jp");
-                let offset: i16 = self.mem_read_byte(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                ) as i8 as i16;
-                self.regs.ip = self.regs.ip.wrapping_add(2);
-                if self.regs.flags.contains(Flags::PARITY) {
-                    self.regs.ip = self.regs.ip.wrapping_add(offset as u16);
-                }
-            }
-            0x7b => {
-                println!("jnp");
-                let offset: i16 = self.mem_read_byte(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                ) as i8 as i16;
-                self.regs.ip = self.regs.ip.wrapping_add(2);
-                if !self.regs.flags.contains(Flags::PARITY) {
-                    self.regs.ip = self.regs.ip.wrapping_add(offset as u16);
-                }
-            }
-            0x80 => {
-                let modrm = self.mem_read_byte(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                );
-                self.regs.ip = self.regs.ip.wrapping_add(2);
-                let opcode_params = self.get_opcode_params_from_modrm(ctx, modrm);
-                let imm: u8 = self.mem_read_byte(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip,
-                );
-                self.regs.ip = self.regs.ip.wrapping_add(1);
-                let group_op = (modrm & 0x38) >> 3;
-                match group_op {
-                    1 => {
-                        println!("or rm8, imm8");
-                        if let Operand::Register(reg_num) = opcode_params.rm {
-                            let reg: u8 = self.regs.read8(Reg8::from_num(reg_num).unwrap());
-                            let result = reg | imm;
-                            self.set_pzs8(result);
-                            self.regs.write8(Reg8::from_num(reg_num).unwrap(), result);
-                        }
-                        if let Operand::Address(segment, opcode_rm) = opcode_params.rm {
-                            let src = self.mem_read_byte(ctx, self.regs.readseg16(segment), opcode_rm);
-                            let result = src | imm;
-                            self.set_pzs8(result);
-                            self.mem_write_byte(ctx, self.regs.readseg16(segment), opcode_rm, result);
-                        }
-                    }
-                    7 => {
-                        println!("cmp rm8, imm8");
-                        if let Operand::Register(reg_num) = opcode_params.rm {
-                            let reg: u8 = self.regs.read8(Reg8::from_num(reg_num).unwrap());
-                            let result = reg.wrapping_sub(imm);
-                            self.set_pzs8(result);
-                            self.regs.flags.set(
-                                Flags::OVERFLOW,
-                                ((reg ^ imm) & (result ^ reg) & 0x80) == 0x80,
-                            );
-                            self.regs
-                                .flags
-                                .set(Flags::ADJUST, ((result ^ reg ^ imm) & 0x10) == 0x10);
-                            self.regs
-                                .flags
-                                .set(Flags::CARRY, (imm & 0x80) > (reg & 0x80));
-                        }
-                        if let Operand::Address(segment, opcode_rm) = opcode_params.rm {
-                            let src = self.mem_read_byte(ctx, self.regs.readseg16(segment), opcode_rm);
-                            let result = src.wrapping_sub(imm);
-                            self.set_pzs8(result);
-                            self.regs.flags.set(
-                                Flags::OVERFLOW,
-                                ((src ^ imm) & (result ^ src) & 0x80) == 0x80,
-                            );
-                            self.regs
-                                .flags
-                                .set(Flags::ADJUST, ((result ^ src ^ imm) & 0x10) == 0x10);
-                            self.regs
-                                .flags
-                                .set(Flags::CARRY, (imm & 0x80) > (src & 0x80));
-                        }
-                    }
-                    _ => panic!("Unimplemented group opcode!"),
-                }
-            }
-            0x88 => {
-                println!("mov rm8, reg8");
-                let modrm = self.mem_read_byte(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                );
-                self.regs.ip = self.regs.ip.wrapping_add(2);
-                let opcode_params = self.get_opcode_params_from_modrm(ctx, modrm);
-                let reg_num = (modrm & 0x38) >> 3;
-                if let Operand::Register(opcode_rm) = opcode_params.rm {
-                    self.regs.write8(
-                        Reg8::from_num(opcode_rm).unwrap(),
-                        self.regs.read8(Reg8::from_num(reg_num).unwrap()),
-                    );
-                } else if let Operand::Address(segment, opcode_rm) = opcode_params.rm {
-                    self.mem_write_byte(
-                        ctx,
-                        self.regs.readseg16(segment),
-                        opcode_rm,
-                        self.regs.read8(Reg8::from_num(reg_num).unwrap()),
-                    );
-                }
-            }
-            0x89 => {
-                println!("mov rm16, reg16");
-                let modrm = self.mem_read_byte(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                );
-                self.regs.ip = self.regs.ip.wrapping_add(2);
-                let opcode_params = self.get_opcode_params_from_modrm(ctx, modrm);
-                let reg_num = (modrm & 0x38) >> 3;
-                if let Operand::Register(opcode_rm) = opcode_params.rm {
-                    self.regs.write16(
-                        Reg16::from_num(opcode_rm).unwrap(),
-                        self.regs.read16(Reg16::from_num(reg_num).unwrap()),
-                    );
-                } else if let Operand::Address(segment, opcode_rm) = opcode_params.rm {
-                    self.mem_write_word(
-                        ctx,
-                        self.regs.readseg16(segment),
-                        opcode_rm,
-                        self.regs.read16(Reg16::from_num(reg_num).unwrap()),
-                    );
-                }
-            }
-            0x8a => {
-                println!("mov reg8, rm8");
-                let modrm = self.mem_read_byte(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                );
-                self.regs.ip = self.regs.ip.wrapping_add(2);
-                let opcode_params = self.get_opcode_params_from_modrm(ctx, modrm);
-                let reg_num = (modrm & 0x38) >> 3;
-                if let Operand::Register(opcode_rm) = opcode_params.rm {
-                    self.regs.write8(
-                        Reg8::from_num(reg_num).unwrap(),
-                        self.regs.read8(Reg8::from_num(opcode_rm).unwrap()),
-                    );
-                } else if let Operand::Address(segment, opcode_rm) = opcode_params.rm {
-                    let rm = self.mem_read_byte(ctx, self.regs.readseg16(segment), opcode_rm);
-                    self.regs.write8(Reg8::from_num(reg_num).unwrap(), rm);
-                }
-            }
-            0x8b => {
-                println!("mov reg16, rm16");
-                let modrm = self.mem_read_byte(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                );
-                self.regs.ip = self.regs.ip.wrapping_add(2);
-                let opcode_params = self.get_opcode_params_from_modrm(ctx, modrm);
-                let reg_num = (modrm & 0x38) >> 3;
-                if let Operand::Register(opcode_rm) = opcode_params.rm {
-                    self.regs.write16(
-                        Reg16::from_num(reg_num).unwrap(),
-                        self.regs.read16(Reg16::from_num(opcode_rm).unwrap()),
-                    );
-                } else if let Operand::Address(segment, opcode_rm) = opcode_params.rm {
-                    let rm = self.mem_read_word(ctx, self.regs.readseg16(segment), opcode_rm);
-                    self.regs.write16(Reg16::from_num(reg_num).unwrap(), rm);
-                }
-            }
-            0x8c => {
-                println!("mov rm, seg");
-                let modrm = self.mem_read_byte(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                );
-                self.regs.ip = self.regs.ip.wrapping_add(2);
-                let opcode_params = self.get_opcode_params_from_modrm(ctx, modrm);
-                let reg_num = (modrm & 0x38) >> 3;
-                if let Operand::Register(opcode_rm) = opcode_params.rm {
-                    self.regs.write16(
-                        Reg16::from_num(opcode_rm).unwrap(),
-                        self.regs.readseg16(SegReg::from_num(reg_num).unwrap()),
-                    );
-                } else if let Operand::Address(segment, opcode_rm) = opcode_params.rm {
-                    self.mem_write_word(
-                        ctx,
-                        self.regs.readseg16(segment),
-                        opcode_rm,
-                        self.regs.readseg16(SegReg::from_num(reg_num).unwrap()),
-                    );
-                }
+                    .write16(Reg16::from_num(opcode_params.reg).unwrap(), addr);
             }
-            0x8e => {
-                println!("mov seg, rm");
-                let modrm = self.mem_read_byte(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                );
-                self.regs.ip = self.regs.ip.wrapping_add(2);
-                let opcode_params = self.get_opcode_params_from_modrm(ctx, modrm);
-                let reg_num = (modrm & 0x38) >> 3;
-                if let Operand::Register(opcode_rm) = opcode_params.rm {
-                    self.regs.writeseg16(
-                        SegReg::from_num(reg_num).unwrap(),
-                        self.regs.read16(Reg16::from_num(opcode_rm).unwrap()),
-                    );
-                } else if let Operand::Address(segment, opcode_rm) = opcode_params.rm {
-                    let rm = self.mem_read_word(ctx, self.regs.readseg16(segment), opcode_rm);
-                    self.regs.writeseg16(SegReg::from_num(reg_num).unwrap(), rm);
-                }
-            }
-            0x96 => {
-                println!("xchg si,ax");
-                self.regs.ip = self.regs.ip.wrapping_add(1);
-                let oldsi = self.regs.read16(Reg16::SI);
-                let oldax = self.regs.read16(Reg16::AX);
-                self.regs.write16(Reg16::SI, oldax);
-                self.regs.write16(Reg16::AX, oldsi);
-            }
-            0x9e => {
-                println!("sahf");
-                self.regs.flags = Flags::from_bits(
-                    (self.regs.flags.bits() & 0xff02) | (self.regs.read8(Reg8::AH) as u16),
-                )
-                .unwrap();
-                self.regs.ip = self.regs.ip.wrapping_add(1);
+            0xc6 => {
+                trace!("mov rm8, imm");
+                let opcode_params = self.fetch_modrm(ctx);
+                let imm = self.fetch8(ctx);
+                self.write_rm8(ctx, opcode_params.rm, imm);
             }
-            0x9f => {
-                println!("lahf");
+            0xc7 => {
+                trace!("mov rm16, imm");
+                let opcode_params = self.fetch_modrm(ctx);
+                let imm = self.fetch16(ctx);
+                self.write_rm16(ctx, opcode_params.rm, imm);
+            }
+            0xc8 | 0xca => {
+                // 0xc8 decodes as an alias of 0xca on the 8086.
+                trace!("retf imm");
+                let imm = self.fetch16(ctx);
+                self.regs.ip = self.pop16(ctx);
+                let segment = self.pop16(ctx);
+                self.regs.writeseg16(SegReg::CS, segment);
                 self.regs
-                    .write8(Reg8::AH, (self.regs.flags.bits() & 0xd5) as u8);
-                self.regs.ip = self.regs.ip.wrapping_add(1);
-            }
-            0xa0 => {
-                println!("mov al, [imm]");
-                let imm_value = self.mem_read_word(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                );
-                self.regs.ip = self.regs.ip.wrapping_add(3);
-                let mut result: u8 = 0;
-                if let Some(segment) = self.seg_override {
-                    result = self.mem_read_byte(ctx, self.regs.readseg16(segment), imm_value);
-                }
-                else {
-                    result = self.mem_read_byte(ctx, self.regs.readseg16(SegReg::DS), imm_value);
-                }
-                self.regs.write8(Reg8::AL, result);
-            }
-            0xa1 => {
-                println!("mov ax, [imm]");
-                let imm_value = self.mem_read_word(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                );
-                self.regs.ip = self.regs.ip.wrapping_add(3);
-                let mut result: u16 = 0;
-                if let Some(segment) = self.seg_override {
-                    result = self.mem_read_word(ctx, self.regs.readseg16(segment), imm_value);
-                }
-                else {
-                    result = self.mem_read_word(ctx, self.regs.readseg16(SegReg::DS), imm_value);
-                }
-                self.regs.write16(Reg16::AX, result);
-            }
-            0xa4 => {
-                println!("movsb");
-                if self.rep_state == None {
-                    let data = self.mem_read_byte(ctx, self.regs.readseg16(SegReg::DS), self.regs.read16(Reg16::SI));
-                    self.mem_write_byte(ctx, self.regs.readseg16(SegReg::ES), self.regs.read16(Reg16::DI), data);
-                    let mut offset: i16 = 1;
-                    if self.regs.flags.contains(Flags::DIRECTION) {
-                        offset = -1;
-                    }
-                    self.regs.write16(Reg16::SI, self.regs.read16(Reg16::SI).wrapping_add(offset as u16));
-                    self.regs.write16(Reg16::DI, self.regs.read16(Reg16::DI).wrapping_add(offset as u16));
-                }
-                else {
-                    loop {
-                        let data = self.mem_read_byte(ctx, self.regs.readseg16(SegReg::DS), self.regs.read16(Reg16::SI));
-                        self.mem_write_byte(ctx, self.regs.readseg16(SegReg::ES), self.regs.read16(Reg16::DI), data);
-                        let mut offset: i16 = 1;
-                        if self.regs.flags.contains(Flags::DIRECTION) {
-                            offset = -1;
-                        }
-                        self.regs.write16(Reg16::SI, self.regs.read16(Reg16::SI).wrapping_add(offset as u16));
-                        self.regs.write16(Reg16::DI, self.regs.read16(Reg16::DI).wrapping_add(offset as u16));
-                        self.regs.write16(Reg16::CX, self.regs.read16(Reg16::CX).wrapping_sub(1));
-                        if self.regs.read16(Reg16::CX) == 0 { break; }
-                    }
-                }
-                self.regs.ip = self.regs.ip.wrapping_add(1);
-            }
-            0xa6 => {
-                println!("cmpsb");
-                match self.rep_state {
-                    None => {
-                        let src = self.mem_read_byte(ctx, self.regs.readseg16(SegReg::DS), self.regs.read16(Reg16::SI));
-                        let dst = self.mem_read_byte(ctx, self.regs.readseg16(SegReg::ES), self.regs.read16(Reg16::DI));
-                        let result = dst.wrapping_sub(src);
-                        let mut offset: i16 = 1;
-                        self.set_pzs8(result);
-                        self.regs.flags.set(
-                            Flags::OVERFLOW,
-                            ((result ^ src) & (result ^ dst) & 0x80) == 0x80,
-                        );
-                        self.regs
-                            .flags
-                            .set(Flags::ADJUST, ((result ^ src ^ dst) & 0x10) == 0x10);
-                        self.regs
-                            .flags
-                            .set(Flags::CARRY, (dst & 0x80) > (result & 0x80));
-                        if self.regs.flags.contains(Flags::DIRECTION) {
-                            offset = -1;
-                        }
-                        self.regs.write16(Reg16::SI, self.regs.read16(Reg16::SI).wrapping_add(offset as u16));
-                        self.regs.write16(Reg16::DI, self.regs.read16(Reg16::DI).wrapping_add(offset as u16));
-                    }   
-                    Some(RepType::REPE) => {
-                        loop {
-                            let src = self.mem_read_byte(ctx, self.regs.readseg16(SegReg::DS), self.regs.read16(Reg16::SI));
-                            let dst = self.mem_read_byte(ctx, self.regs.readseg16(SegReg::ES), self.regs.read16(Reg16::DI));
-                            let result = dst.wrapping_sub(src);
-                            let mut offset: i16 = 1;
-                            self.set_pzs8(result);
-                            self.regs
-                                .flags
-                                .set(Flags::CARRY, (dst & 0x80) > (src & 0x80));
-                            if self.regs.flags.contains(Flags::DIRECTION) {
-                                offset = -1;
-                            }
-                            self.regs.write16(Reg16::SI, self.regs.read16(Reg16::SI).wrapping_add(offset as u16));
-                            self.regs.write16(Reg16::DI, self.regs.read16(Reg16::DI).wrapping_add(offset as u16));
-                            self.regs.write16(Reg16::CX, self.regs.read16(Reg16::CX).wrapping_sub(1));
-                            if self.regs.read16(Reg16::CX) == 0 { break; }
-                            if !self.regs.flags.contains(Flags::ZERO) { break; }
-                        }
-                    }
-                    Some(RepType::REPNE) => {
-                        loop {
-                            let src = self.mem_read_byte(ctx, self.regs.readseg16(SegReg::DS), self.regs.read16(Reg16::SI));
-                            let dst = self.mem_read_byte(ctx, self.regs.readseg16(SegReg::ES), self.regs.read16(Reg16::DI));
-                            let result = dst.wrapping_sub(src);
-                            let mut offset: i16 = 1;
-                            self.set_pzs8(result);
-                            self.regs.flags.set(
-                                Flags::OVERFLOW,
-                                ((result ^ src) & (result ^ dst) & 0x80) == 0x80,
-                            );
-                            self.regs
-                                .flags
-                                .set(Flags::ADJUST, ((result ^ src ^ dst) & 0x10) == 0x10);
-                            self.regs
-                                .flags
-                                .set(Flags::CARRY, (dst & 0x80) > (result & 0x80));
-                            if self.regs.flags.contains(Flags::DIRECTION) {
-                                offset = -1;
-                            }
-                            self.regs.write16(Reg16::SI, self.regs.read16(Reg16::SI).wrapping_add(offset as u16));
-                            self.regs.write16(Reg16::DI, self.regs.read16(Reg16::DI).wrapping_add(offset as u16));
-                            self.regs.write16(Reg16::CX, self.regs.read16(Reg16::CX).wrapping_sub(1));
-                            if self.regs.read16(Reg16::CX) == 0 { break; }
-                            if self.regs.flags.contains(Flags::ZERO) { break; }
-                        }
-                    }
-                }
-                self.regs.ip = self.regs.ip.wrapping_add(1);
-            }
-            0xaa => {
-                println!("stosb");
-                if self.rep_state == None {
-                    self.mem_write_byte(ctx, self.regs.readseg16(SegReg::ES), self.regs.read16(Reg16::DI), self.regs.read8(Reg8::AL));
-                    let mut offset: i16 = 1;
-                    if self.regs.flags.contains(Flags::DIRECTION) {
-                        offset = -1;
-                    }
-                    self.regs.write16(Reg16::SI, self.regs.read16(Reg16::DI).wrapping_add(offset as u16));
-                }
-                else {
-                    loop {
-                        self.mem_write_byte(ctx, self.regs.readseg16(SegReg::ES), self.regs.read16(Reg16::DI), self.regs.read8(Reg8::AL));
-                        let mut offset: i16 = 1;
-                        if self.regs.flags.contains(Flags::DIRECTION) {
-                            offset = -1;
-                        }
-                        self.regs.write16(Reg16::SI, self.regs.read16(Reg16::SI).wrapping_add(offset as u16));
-                        self.regs.write16(Reg16::CX, self.regs.read16(Reg16::CX).wrapping_sub(1));
-                        if self.regs.read16(Reg16::CX) == 0 { break; }
-                    }
-                }
-                self.regs.ip = self.regs.ip.wrapping_add(1);
-            }
-            0xac => {
-                println!("lodsb");
-                if self.rep_state == None {
-                    let data = self.mem_read_byte(ctx, self.regs.readseg16(SegReg::DS), self.regs.read16(Reg16::SI));
-                    self.regs.write8(Reg8::AL, data);
-                    let mut offset: i16 = 1;
-                    if self.regs.flags.contains(Flags::DIRECTION) {
-                        offset = -1;
-                    }
-                    self.regs.write16(Reg16::SI, self.regs.read16(Reg16::SI).wrapping_add(offset as u16));
-                }
-                else {
-                    loop {
-                        let data = self.mem_read_byte(ctx, self.regs.readseg16(SegReg::DS), self.regs.read16(Reg16::SI));
-                        self.regs.write8(Reg8::AL, data);
-                        let mut offset: i16 = 1;
-                        if self.regs.flags.contains(Flags::DIRECTION) {
-                            offset = -1;
-                        }
-                        self.regs.write16(Reg16::SI, self.regs.read16(Reg16::SI).wrapping_add(offset as u16));
-                        self.regs.write16(Reg16::CX, self.regs.read16(Reg16::CX).wrapping_sub(1));
-                        if self.regs.read16(Reg16::CX) == 0 { break; }
-                    }
-                }
-                self.regs.ip = self.regs.ip.wrapping_add(1);
-            }
-            0xb0 => {
-                println!("mov al, imm");
-                let imm_value = self.mem_read_byte(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                );
-                self.regs.write8(Reg8::AL, imm_value);
-                self.regs.ip = self.regs.ip.wrapping_add(2);
-            }
-            0xb1 => {
-                println!("mov cl, imm");
-                let imm_value = self.mem_read_byte(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                );
-                self.regs.write8(Reg8::CL, imm_value);
-                self.regs.ip = self.regs.ip.wrapping_add(2);
-            }
-            0xb2 => {
-                println!("mov dl, imm");
-                let imm_value = self.mem_read_byte(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                );
-                self.regs.write8(Reg8::DL, imm_value);
-                self.regs.ip = self.regs.ip.wrapping_add(2);
-            }
-            0xb3 => {
-                println!("mov bl, imm");
-                let imm_value = self.mem_read_byte(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                );
-                self.regs.write8(Reg8::BL, imm_value);
-                self.regs.ip = self.regs.ip.wrapping_add(2);
-            }
-            0xb4 => {
-                println!("mov ah, imm");
-                let imm_value = self.mem_read_byte(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                );
-                self.regs.write8(Reg8::AH, imm_value);
-                self.regs.ip = self.regs.ip.wrapping_add(2);
-            }
-            0xb5 => {
-                println!("mov ch, imm");
-                let imm_value = self.mem_read_byte(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                );
-                self.regs.write8(Reg8::CH, imm_value);
-                self.regs.ip = self.regs.ip.wrapping_add(2);
-            }
-            0xb6 => {
-                println!("mov dh, imm");
-                let imm_value = self.mem_read_byte(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                );
-                self.regs.write8(Reg8::DH, imm_value);
-                self.regs.ip = self.regs.ip.wrapping_add(2);
-            }
-            0xb7 => {
-                println!("mov bh, imm");
-                let imm_value = self.mem_read_byte(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                );
-                self.regs.write8(Reg8::BH, imm_value);
-                self.regs.ip = self.regs.ip.wrapping_add(2);
-            }
-            0xb8 => {
-                println!("mov ax, imm");
-                let imm_value = self.mem_read_word(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                );
-                self.regs.write16(Reg16::AX, imm_value);
-                self.regs.ip = self.regs.ip.wrapping_add(3);
-            }
-            0xb9 => {
-                println!("mov cx, imm");
-                let imm_value = self.mem_read_word(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                );
-                self.regs.write16(Reg16::CX, imm_value);
-                self.regs.ip = self.regs.ip.wrapping_add(3);
-            }
-            0xba => {
-                println!("mov dx, imm");
-                let imm_value = self.mem_read_word(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                );
-                self.regs.write16(Reg16::DX, imm_value);
-                self.regs.ip = self.regs.ip.wrapping_add(3);
-            }
-            0xbb => {
-                println!("mov bx, imm");
-                let imm_value = self.mem_read_word(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                );
-                self.regs.write16(Reg16::BX, imm_value);
-                self.regs.ip = self.regs.ip.wrapping_add(3);
-            }
-            0xbc => {
-                println!("mov sp, imm");
-                let imm_value = self.mem_read_word(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                );
-                self.regs.write16(Reg16::SP, imm_value);
-                self.regs.ip = self.regs.ip.wrapping_add(3);
-            }
-            0xbd => {
-                println!("mov bp, imm");
-                let imm_value = self.mem_read_word(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                );
-                self.regs.write16(Reg16::BP, imm_value);
-                self.regs.ip = self.regs.ip.wrapping_add(3);
-            }
-            0xbe => {
-                println!("mov si, imm");
-                let imm_value = self.mem_read_word(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                );
-                self.regs.write16(Reg16::SI, imm_value);
-                self.regs.ip = self.regs.ip.wrapping_add(3);
-            }
-            0xbf => {
-                println!("mov di, imm");
-                let imm_value = self.mem_read_word(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                );
-                self.regs.write16(Reg16::DI, imm_value);
-                self.regs.ip = self.regs.ip.wrapping_add(3);
+                    .write16(Reg16::SP, self.regs.read16(Reg16::SP).wrapping_add(imm));
             }
-            0xc3 => {
-                println!("ret");
+            0xc9 | 0xcb => {
+                trace!("retf");
                 self.regs.ip = self.pop16(ctx);
+                let segment = self.pop16(ctx);
+                self.regs.writeseg16(SegReg::CS, segment);
             }
-            0xc4 => {
-                println!("les");
-                let modrm = self.mem_read_byte(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                );
-                self.regs.ip = self.regs.ip.wrapping_add(2);
-                let opcode_params = self.get_opcode_params_from_modrm(ctx, modrm);
-                let reg = (modrm & 0x38) >> 3;
-                if let Operand::Address(segment, opcode_rm) = opcode_params.rm {
-                    let addr = self.mem_read_word(ctx, self.regs.readseg16(segment), opcode_rm);
-                    let seg = self.mem_read_word(ctx, self.regs.readseg16(segment), opcode_rm + 2);
-                    self.regs.writeseg16(SegReg::ES, seg);
-                    self.regs.write16(Reg16::from_num(reg).unwrap(), addr);
-                }
-            }
-            0xc5 => {
-                println!("lds");
-                let modrm = self.mem_read_byte(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                );
-                self.regs.ip = self.regs.ip.wrapping_add(2);
-                let opcode_params = self.get_opcode_params_from_modrm(ctx, modrm);
-                let reg = (modrm & 0x38) >> 3;
-                if let Operand::Address(segment, opcode_rm) = opcode_params.rm {
-                    let addr = self.mem_read_word(ctx, self.regs.readseg16(segment), opcode_rm);
-                    let seg = self.mem_read_word(ctx, self.regs.readseg16(segment), opcode_rm + 2);
-                    self.regs.writeseg16(SegReg::DS, seg);
-                    self.regs.write16(Reg16::from_num(reg).unwrap(), addr);
-                }
+            0xcc => {
+                trace!("int3");
+                self.software_interrupt(ctx, 3);
             }
-            0xc6 => {
-                println!("mov rm8, imm");
-                let modrm = self.mem_read_byte(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                );
-                self.regs.ip = self.regs.ip.wrapping_add(2);
-                let opcode_params = self.get_opcode_params_from_modrm(ctx, modrm);
-                match opcode_params.rm {
-                    Operand::Address(_, _) => (),
-                    _ => panic!("Opcode doesn't support register operands!"),
-                }
-                let imm = self.mem_read_byte(ctx, self.regs.readseg16(SegReg::CS), self.regs.ip.wrapping_add(1));
-                if let Operand::Address(easeg, ea) = opcode_params.rm {
-                    self.mem_write_byte(ctx, self.regs.readseg16(easeg), ea, imm);
-                }
-                self.regs.ip = self.regs.ip.wrapping_add(1);
+            0xcd => {
+                let intr = self.fetch8(ctx);
+                trace!("int {:x}", intr);
+                self.software_interrupt(ctx, intr);
             }
-            0xc7 => {
-                println!("mov rm16, imm");
-                let modrm = self.mem_read_byte(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                );
-                self.regs.ip = self.regs.ip.wrapping_add(2);
-                let opcode_params = self.get_opcode_params_from_modrm(ctx, modrm);
-                match opcode_params.rm {
-                    Operand::Address(_, _) => (),
-                    _ => panic!("Opcode doesn't support register operands!"),
-                }
-                let imm = self.mem_read_word(ctx, self.regs.readseg16(SegReg::CS), self.regs.ip.wrapping_add(1));
-                if let Operand::Address(easeg, ea) = opcode_params.rm {
-                    self.mem_write_word(ctx, self.regs.readseg16(easeg), ea, imm);
+            0xce => {
+                trace!("into");
+                if self.regs.flags.contains(Flags::OVERFLOW) {
+                    self.software_interrupt(ctx, 4);
                 }
-                self.regs.ip = self.regs.ip.wrapping_add(2);
             }
-            0xcd => {
-                let intr = self.mem_read_byte(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                );
-                println!("int {:x}", intr);
-                self.interrupt_hook(ctx, intr);
-                self.regs.ip = self.regs.ip.wrapping_add(2);
-            }
-            0xd0 => {
-                let modrm = self.mem_read_byte(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                );
-                self.regs.ip = self.regs.ip.wrapping_add(2);
-                let opcode_params = self.get_opcode_params_from_modrm(ctx, modrm);
-                match opcode_params.rm {
-                    Operand::Register(_) => (),
-                    _ => panic!("Opcode doesn't support memory operands!"),
-                }
-                let group_op = (modrm & 0x38) >> 3;
-                match group_op {
-                    4 => {
-                        println!("shl reg, 1");
-                        if let Operand::Register(reg_num) = opcode_params.rm {
-                            let mut reg: u8 = self.regs.read8(Reg8::from_num(reg_num).unwrap());
-                            self.regs.flags.set(Flags::CARRY, (reg & 1) == 1);
-                            let overflow_calc = ((reg >> 7) & 1) ^ ((reg >> 6) & 1);
-                            self.regs.flags.set(Flags::OVERFLOW, overflow_calc == 1);
-                            reg = reg.wrapping_shl(1);
-                            self.set_pzs8(reg);
-                            self.regs.write8(Reg8::from_num(reg_num).unwrap(), reg);
-                        }
-                    }
-                    5 => {
-                        println!("shr reg, 1");
-                        if let Operand::Register(reg_num) = opcode_params.rm {
-                            let mut reg: u8 = self.regs.read8(Reg8::from_num(reg_num).unwrap());
-                            self.regs.flags.set(Flags::CARRY, (reg & 1) == 1);
-                            self.regs.flags.set(Flags::OVERFLOW, (reg & 0x80) == 0x80);
-                            reg = reg.wrapping_shr(1);
-                            self.set_pzs8(reg);
-                            self.regs.write8(Reg8::from_num(reg_num).unwrap(), reg);
-                        }
+            0xcf => {
+                trace!("iret");
+                self.regs.ip = self.pop16(ctx);
+                let segment = self.pop16(ctx);
+                self.regs.writeseg16(SegReg::CS, segment);
+                let flags = self.pop16(ctx);
+                self.regs.write16(Reg16::FLAGS, flags);
+            }
+            0xd0..=0xd3 => {
+                let opcode_params = self.fetch_modrm(ctx);
+                let op = opcode_params.reg;
+                let count = if self.opcode & 2 == 0 {
+                    1
+                } else {
+                    self.regs.read8(Reg8::CL)
+                };
+                trace!("shift{} rm, {}", op, count);
+                let width = self.opcode_width();
+                let rm = self.read_rm(ctx, opcode_params.rm, width);
+                let result = self.shift(op, rm, count, width);
+                self.write_rm(ctx, opcode_params.rm, result, width);
+            }
+            0xd4 => {
+                trace!("aam");
+                let base = self.fetch8(ctx);
+                let al = self.regs.read8(Reg8::AL);
+                match al.checked_div(base) {
+                    Some(quotient) => {
+                        self.regs.write8(Reg8::AH, quotient);
+                        self.regs.write8(Reg8::AL, al % base);
+                        self.set_pzs8(al % base);
                     }
-                    _ => panic!("Unimplemented group opcode!"),
+                    None => self.software_interrupt(ctx, 0),
                 }
             }
-            0xd2 => {
-                let modrm = self.mem_read_byte(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                );
-                self.regs.ip = self.regs.ip.wrapping_add(2);
-                let opcode_params = self.get_opcode_params_from_modrm(ctx, modrm);
-                match opcode_params.rm {
-                    Operand::Register(_) => (),
-                    _ => panic!("Opcode doesn't support memory operands!"),
-                }
-                let group_op = (modrm & 0x38) >> 3;
-                match group_op {
-                    4 => {
-                        println!("shl reg, cl");
-                        let mut count = self.regs.read8(Reg8::CL);
-                        if let Operand::Register(reg_num) = opcode_params.rm {
-                            let mut reg: u8 = self.regs.read8(Reg8::from_num(reg_num).unwrap());
-                            while count != 0 {
-                                self.regs.flags.set(Flags::CARRY, (reg & 0x80) == 0x80);
-                                reg = reg.wrapping_shl(1);
-                                count = count.wrapping_sub(1);
-                            }
-                            self.set_pzs8(reg);
-                            self.regs.write8(Reg8::from_num(reg_num).unwrap(), reg);
-                        }
+            0xd5 => {
+                trace!("aad");
+                let base = self.fetch8(ctx);
+                let al = self
+                    .regs
+                    .read8(Reg8::AH)
+                    .wrapping_mul(base)
+                    .wrapping_add(self.regs.read8(Reg8::AL));
+                self.regs.write16(Reg16::AX, al as u16);
+                self.set_pzs8(al);
+            }
+            0xd6 => {
+                // Undocumented SALC: sets AL from the carry flag.
+                trace!("salc");
+                let carry = self.regs.flags.contains(Flags::CARRY);
+                self.regs.write8(Reg8::AL, if carry { 0xff } else { 0 });
+            }
+            0xd7 => {
+                trace!("xlat");
+                let offset = self
+                    .regs
+                    .read16(Reg16::BX)
+                    .wrapping_add(self.regs.read8(Reg8::AL) as u16);
+                let seg = self.regs.readseg16(self.data_seg());
+                let value = self.mem_read_byte(ctx, seg, offset);
+                self.regs.write8(Reg8::AL, value);
+            }
+            0xd8..=0xdf => {
+                // ESC: there is no coprocessor, so only the operand is decoded.
+                trace!("esc");
+                self.fetch_modrm(ctx);
+            }
+            0xe0..=0xe2 => {
+                let offset = self.fetch8(ctx);
+                let cx = self.regs.read16(Reg16::CX).wrapping_sub(1);
+                self.regs.write16(Reg16::CX, cx);
+                let zero = self.regs.flags.contains(Flags::ZERO);
+                let taken = match self.opcode {
+                    0xe0 => {
+                        trace!("loopne");
+                        cx != 0 && !zero
                     }
-                    5 => {
-                        println!("shr reg, cl");
-                        let mut count = self.regs.read8(Reg8::CL);
-                        if let Operand::Register(reg_num) = opcode_params.rm {
-                            let mut reg: u8 = self.regs.read8(Reg8::from_num(reg_num).unwrap());
-                            while count != 0 {
-                                self.regs.flags.set(Flags::CARRY, (reg & 1) == 1);
-                                reg = reg.wrapping_shr(1);
-                                count = count.wrapping_sub(1);
-                            }
-                            self.set_pzs8(reg);
-                            self.regs.write8(Reg8::from_num(reg_num).unwrap(), reg);
-                        }
+                    0xe1 => {
+                        trace!("loope");
+                        cx != 0 && zero
                     }
-                    _ => panic!("Unimplemented group opcode!"),
-                }
+                    _ => {
+                        trace!("loop");
+                        cx != 0
+                    }
+                };
+                self.jump_short(offset, taken);
             }
-            0xe2 => {
-                println!("loop");
-                let offset: i16 = self.mem_read_byte(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                ) as i8 as i16;
-                self.regs
-                    .write16(Reg16::CX, self.regs.read16(Reg16::CX).wrapping_sub(1));
-                self.regs.ip = self.regs.ip.wrapping_add(2);
-                if self.regs.read16(Reg16::CX) != 0 {
-                    self.regs.ip = self.regs.ip.wrapping_add(offset as u16);
-                }
+            0xe3 => {
+                trace!("jcxz");
+                let offset = self.fetch8(ctx);
+                let taken = self.regs.read16(Reg16::CX) == 0;
+                self.jump_short(offset, taken);
             }
             0xe4 => {
-                println!("in al, imm");
-                let imm_value = self.mem_read_byte(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                );
-                let result = self.io_read_byte(ctx, imm_value as u16);
-                self.regs.write8(Reg8::AL, result);
-                self.regs.ip = self.regs.ip.wrapping_add(2);
+                trace!("in al, imm");
+                let port = self.fetch8(ctx) as u16;
+                let value = self.io_read_byte(ctx, port);
+                self.regs.write8(Reg8::AL, value);
+            }
+            0xe5 => {
+                trace!("in ax, imm");
+                let port = self.fetch8(ctx) as u16;
+                let value = self.io_read_word(ctx, port);
+                self.regs.write16(Reg16::AX, value);
             }
             0xe6 => {
-                println!("out imm, al");
-                let imm_value = self.mem_read_byte(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                );
-                self.io_write_byte(ctx, imm_value as u16, self.regs.read8(Reg8::AL));
-                self.regs.ip = self.regs.ip.wrapping_add(2);
+                trace!("out imm, al");
+                let port = self.fetch8(ctx) as u16;
+                self.io_write_byte(ctx, port, self.regs.read8(Reg8::AL));
+            }
+            0xe7 => {
+                trace!("out imm, ax");
+                let port = self.fetch8(ctx) as u16;
+                self.io_write_word(ctx, port, self.regs.read16(Reg16::AX));
             }
             0xe8 => {
-                println!("call near");
-                let offset = self.mem_read_word(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                );
-                self.regs.ip = self.regs.ip.wrapping_add(3);
-                self.regs.write16(Reg16::SP, self.regs.read16(Reg16::SP).wrapping_sub(2));
-                self.mem_write_word(ctx, self.regs.readseg16(SegReg::SS), self.regs.read16(Reg16::SP), self.regs.ip);
+                trace!("call near");
+                let offset = self.fetch16(ctx);
+                self.push16(ctx, self.regs.ip);
                 self.regs.ip = self.regs.ip.wrapping_add(offset);
             }
             0xe9 => {
-                println!("jmp near");
-                let offset = self.mem_read_word(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                );
-                self.regs.ip = self.regs.ip.wrapping_add(offset + 3);
+                trace!("jmp near");
+                let offset = self.fetch16(ctx);
+                self.regs.ip = self.regs.ip.wrapping_add(offset);
             }
             0xea => {
-                println!("jmp far");
-                let offset = self.mem_read_word(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                );
-                let segment = self.mem_read_word(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(3),
-                );
+                trace!("jmp far");
+                let offset = self.fetch16(ctx);
+                let segment = self.fetch16(ctx);
                 self.regs.writeseg16(SegReg::CS, segment);
                 self.regs.ip = offset;
             }
             0xeb => {
-                println!("jmp rel8");
-                let offset = self.mem_read_byte(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                );
-                self.regs.ip = self.regs.ip.wrapping_add((offset as i8 as u16) + 2u16);
+                trace!("jmp rel8");
+                let offset = self.fetch8(ctx);
+                self.jump_short(offset, true);
+            }
+            0xec => {
+                trace!("in al, dx");
+                let value = self.io_read_byte(ctx, self.regs.read16(Reg16::DX));
+                self.regs.write8(Reg8::AL, value);
+            }
+            0xed => {
+                trace!("in ax, dx");
+                let value = self.io_read_word(ctx, self.regs.read16(Reg16::DX));
+                self.regs.write16(Reg16::AX, value);
             }
             0xee => {
-                println!("out dx, al");
+                trace!("out dx, al");
                 self.io_write_byte(ctx, self.regs.read16(Reg16::DX), self.regs.read8(Reg8::AL));
-                self.regs.ip = self.regs.ip.wrapping_add(1);
+            }
+            0xef => {
+                trace!("out dx, ax");
+                self.io_write_word(ctx, self.regs.read16(Reg16::DX), self.regs.read16(Reg16::AX));
+            }
+            0xf0 | 0xf1 => {
+                // 0xf1 decodes as an alias of LOCK on the 8086.
+                trace!("lock:");
+                return self.execute(ctx);
             }
             0xf2 => {
-                println!("repne:");
+                trace!("repne:");
                 self.rep_state = Some(RepType::REPNE);
-                self.regs.ip = self.regs.ip.wrapping_add(1);
-                self.tick(ctx);
+                return self.execute(ctx);
             }
             0xf3 => {
-                println!("repe:");
+                trace!("repe:");
                 self.rep_state = Some(RepType::REPE);
-                self.regs.ip = self.regs.ip.wrapping_add(1);
-                self.tick(ctx);
+                return self.execute(ctx);
+            }
+            0xf4 => {
+                trace!("hlt");
+                self.halted = true;
+            }
+            0xf5 => {
+                trace!("cmc");
+                self.regs.flags.toggle(Flags::CARRY);
+            }
+            0xf6 => {
+                let opcode_params = self.fetch_modrm(ctx);
+                let rm = self.read_rm8(ctx, opcode_params.rm);
+                match opcode_params.reg {
+                    0 | 1 => {
+                        trace!("test rm8, imm8");
+                        let imm = self.fetch8(ctx);
+                        self.alu(4, rm as u16, imm as u16, RegisterType::Bits8);
+                    }
+                    2 => {
+                        trace!("not rm8");
+                        self.write_rm8(ctx, opcode_params.rm, !rm);
+                    }
+                    3 => {
+                        trace!("neg rm8");
+                        let result = self.alu(5, 0, rm as u16, RegisterType::Bits8);
+                        self.write_rm8(ctx, opcode_params.rm, result as u8);
+                    }
+                    4 | 5 => {
+                        trace!("mul rm8");
+                        self.mul8(rm, opcode_params.reg == 5);
+                    }
+                    _ => {
+                        trace!("div rm8");
+                        if !self.div8(rm, opcode_params.reg == 7) {
+                            self.software_interrupt(ctx, 0);
+                        }
+                    }
+                }
             }
             0xf7 => {
-                let modrm = self.mem_read_byte(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                );
-                self.regs.ip = self.regs.ip.wrapping_add(2);
-                let opcode_params = self.get_opcode_params_from_modrm(ctx, modrm);
-                let group_op = (modrm & 0x38) >> 3;
-                match group_op {
-                    4 => {
-                        println!("mul rm16");
-                        if let Operand::Address(easeg, ea) = opcode_params.rm {
-                            let reg: u16 = self.regs.read16(Reg16::AX);
-                            let dst: u16 = self.mem_read_word(ctx, self.regs.readseg16(easeg), ea);
-                            let result: u32 = (reg as u32) * (dst as u32);
-                            self.regs.flags.set(
-                                Flags::OVERFLOW,
-                                (result & 0xffff0000u32) != 0,
-                            );
-                            self.regs
-                                .flags
-                                .set(Flags::CARRY, (result & 0xffff0000u32) != 0);
-                            self.regs.write16(Reg16::AX, result as u16);
-                            self.regs.write16(Reg16::DX, (result >> 16) as u16);
-                        }
-                        else if let Operand::Register(rm) = opcode_params.rm {
-                            let reg: u16 = self.regs.read16(Reg16::AX);
-                            let dst: u16 = self.regs.read16(Reg16::from_num(rm).unwrap());
-                            let result: u32 = (reg as u32) * (dst as u32);
-                            self.regs.flags.set(
-                                Flags::OVERFLOW,
-                                (result & 0xffff0000u32) != 0,
-                            );
-                            self.regs
-                                .flags
-                                .set(Flags::CARRY, (result & 0xffff0000u32) != 0);
-                            self.regs.write16(Reg16::AX, result as u16);
-                            self.regs.write16(Reg16::DX, (result >> 16) as u16);
+                let opcode_params = self.fetch_modrm(ctx);
+                let rm = self.read_rm16(ctx, opcode_params.rm);
+                match opcode_params.reg {
+                    0 | 1 => {
+                        trace!("test rm16, imm16");
+                        let imm = self.fetch16(ctx);
+                        self.alu(4, rm, imm, RegisterType::Bits16);
+                    }
+                    2 => {
+                        trace!("not rm16");
+                        self.write_rm16(ctx, opcode_params.rm, !rm);
+                    }
+                    3 => {
+                        trace!("neg rm16");
+                        let result = self.alu(5, 0, rm, RegisterType::Bits16);
+                        self.write_rm16(ctx, opcode_params.rm, result);
+                    }
+                    4 | 5 => {
+                        trace!("mul rm16");
+                        self.mul16(rm, opcode_params.reg == 5);
+                    }
+                    _ => {
+                        trace!("div rm16");
+                        if !self.div16(rm, opcode_params.reg == 7) {
+                            self.software_interrupt(ctx, 0);
                         }
                     }
-                    _ => panic!("Unimplemented group opcode!"),
                 }
             }
             0xf8 => {
-                println!("clc");
+                trace!("clc");
                 self.regs.flags.set(Flags::CARRY, false);
-                self.regs.ip = self.regs.ip.wrapping_add(1);
             }
             0xf9 => {
-                println!("stc");
+                trace!("stc");
                 self.regs.flags.set(Flags::CARRY, true);
-                self.regs.ip = self.regs.ip.wrapping_add(1);
             }
             0xfa => {
-                println!("cli");
+                trace!("cli");
                 self.regs.flags.set(Flags::INTERRUPT, false);
-                self.regs.ip = self.regs.ip.wrapping_add(1);
             }
             0xfb => {
-                println!("sti");
+                trace!("sti");
+                if !self.regs.flags.contains(Flags::INTERRUPT) {
+                    self.inhibit_interrupts = true;
+                }
                 self.regs.flags.set(Flags::INTERRUPT, true);
-                self.regs.ip = self.regs.ip.wrapping_add(1);
             }
             0xfc => {
-                println!("cld");
+                trace!("cld");
                 self.regs.flags.set(Flags::DIRECTION, false);
-                self.regs.ip = self.regs.ip.wrapping_add(1);
             }
             0xfd => {
-                println!("std");
+                trace!("std");
                 self.regs.flags.set(Flags::DIRECTION, true);
-                self.regs.ip = self.regs.ip.wrapping_add(1);
             }
-            0xfe => {
-                let modrm = self.mem_read_byte(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                );
-                self.regs.ip = self.regs.ip.wrapping_add(2);
-                let opcode_params = self.get_opcode_params_from_modrm(ctx, modrm);
-                match opcode_params.rm {
-                    Operand::Register(_) => (),
-                    _ => panic!("Memory operands not supported yet!"),
-                }
-                let group_op = (modrm & 0x38) >> 3;
-                match group_op {
-                    0 => {
-                        println!("inc rm8");
-                        if let Operand::Register(reg_num) = opcode_params.rm {
-                            let reg: u8 = self.regs.read8(Reg8::from_num(reg_num).unwrap());
-                            let result = reg.wrapping_add(1);
-                            self.set_pzs8(result);
-                            self.regs.flags.set(
-                                Flags::OVERFLOW,
-                                ((result ^ 1) & (result ^ reg) & 0x80) == 0x80,
-                            );
-                            self.regs
-                                .flags
-                                .set(Flags::ADJUST, ((result ^ reg ^ 1) & 0x10) == 0x10);
-                            self.regs.write8(Reg8::from_num(reg_num).unwrap(), result);
-                        }
-                    }
-                    1 => {
-                        println!("dec rm8");
-                        if let Operand::Register(reg_num) = opcode_params.rm {
-                            let reg: u8 = self.regs.read8(Reg8::from_num(reg_num).unwrap());
-                            let result = reg.wrapping_sub(1);
-                            self.set_pzs8(result);
-                            self.regs
-                                .flags
-                                .set(Flags::OVERFLOW, ((reg ^ 1) & (result ^ reg) & 0x80) == 0x80);
-                            self.regs
-                                .flags
-                                .set(Flags::ADJUST, ((result ^ reg ^ 1) & 0x10) == 0x10);
-                            self.regs.write8(Reg8::from_num(reg_num).unwrap(), result);
-                        }
-                    }
-                    _ => panic!("Unimplemented group opcode!"),
-                }
-            }
-            0xff => {
-                let modrm = self.mem_read_byte(
-                    ctx,
-                    self.regs.readseg16(SegReg::CS),
-                    self.regs.ip.wrapping_add(1),
-                );
-                self.regs.ip = self.regs.ip.wrapping_add(2);
-                let opcode_params = self.get_opcode_params_from_modrm(ctx, modrm);
-                let group_op = (modrm & 0x38) >> 3;
-                match group_op {
-                    0 => {
-                        println!("inc rm16");
-                        match opcode_params.rm {
-                            Operand::Register(_) => (),
-                            _ => panic!("Memory operands not supported yet!"),
-                        }
-                        if let Operand::Register(reg_num) = opcode_params.rm {
-                            let reg: u16 = self.regs.read16(Reg16::from_num(reg_num).unwrap());
-                            let result = reg.wrapping_add(1);
-                            self.set_pzs16(result);
-                            self.regs.flags.set(
-                                Flags::OVERFLOW,
-                                ((result ^ 1) & (result ^ reg) & 0x8000) == 0x8000,
-                            );
-                            self.regs
-                                .flags
-                                .set(Flags::ADJUST, ((result ^ reg ^ 1) & 0x10) == 0x10);
-                            self.regs.write16(Reg16::from_num(reg_num).unwrap(), result);
-                        }
-                    }
-                    1 => {
-                        println!("dec rm16");
-                        match opcode_params.rm {
-                            Operand::Register(_) => (),
-                            _ => panic!("Memory operands not supported yet!"),
-                        }
-                        if let Operand::Register(reg_num) = opcode_params.rm {
-                            let reg: u16 = self.regs.read16(Reg16::from_num(reg_num).unwrap());
-                            let result = reg.wrapping_sub(1);
-                            self.set_pzs16(result);
-                            self.regs
-                                .flags
-                                .set(Flags::OVERFLOW, ((reg ^ 1) & (result ^ reg) & 0x80) == 0x80);
-                            self.regs
-                                .flags
-                                .set(Flags::ADJUST, ((result ^ reg ^ 1) & 0x10) == 0x10);
-                            self.regs.write16(Reg16::from_num(reg_num).unwrap(), result);
-                        }
-                    }
-                    5 => {
-                        println!("jmp far");
-                        match opcode_params.rm {
-                            Operand::Address(_, _) => (),
-                            _ => panic!("Register operands not supported yet!"),
-                        }
-                        if let Operand::Address(easeg, ea) = opcode_params.rm {
-                            let offset = self.mem_read_word(ctx, self.regs.readseg16(easeg), ea);
-                            let segment = self.mem_read_word(ctx, self.regs.readseg16(easeg), ea.wrapping_add(2));
-                            self.regs.ip = offset;
-                            self.regs.writeseg16(SegReg::CS, segment);
-                        }
-                    }
-                    _ => panic!("Unimplemented group opcode!"),
-                }
+            0xfe | 0xff => {
+                let opcode_params = self.fetch_modrm(ctx);
+                self.group_ff(ctx, opcode_params, self.opcode_width());
             }
             _ => panic!("Unhandled opcode!"),
         }
@@ -2320,3 +1742,442 @@ impl Cpu8086 {
         4
     }
 }
+
+fn width_masks(width: RegisterType) -> (u32, u32) {
+    match width {
+        RegisterType::Bits8 => (0xff, 0x80),
+        RegisterType::Bits16 => (0xffff, 0x8000),
+    }
+}
+
+#[cfg(test)]
+struct TestHardware {
+    ram: Vec<u8>,
+    ports: Vec<u8>,
+    intr: Option<u8>,
+    nmi: bool,
+    floppies: [Option<FloppyImage>; 2],
+    hard_disks: [Option<HardDiskImage>; 2],
+}
+
+#[cfg(test)]
+impl Cpu8086Context for TestHardware {
+    fn mem_read_byte(&mut self, addr: u32) -> u8 {
+        self.ram[addr as usize]
+    }
+    fn mem_write_byte(&mut self, addr: u32, value: u8) {
+        self.ram[addr as usize] = value;
+    }
+    fn io_read_byte(&mut self, addr: u16) -> u8 {
+        self.ports[addr as usize]
+    }
+    fn io_write_byte(&mut self, addr: u16, value: u8) {
+        self.ports[addr as usize] = value;
+    }
+    fn intr_pending(&mut self) -> bool {
+        self.intr.is_some()
+    }
+    fn intr_acknowledge(&mut self) -> u8 {
+        self.intr.take().unwrap()
+    }
+    fn nmi_pending(&mut self) -> bool {
+        std::mem::take(&mut self.nmi)
+    }
+    fn floppy(&mut self, drive: u8) -> Option<&mut FloppyImage> {
+        self.floppies.get_mut(drive as usize)?.as_mut()
+    }
+    fn hard_disk(&mut self, drive: u8) -> Option<&mut HardDiskImage> {
+        self.hard_disks.get_mut(drive as usize)?.as_mut()
+    }
+}
+
+#[cfg(test)]
+struct TestMachine {
+    cpu: Cpu8086,
+    hardware: TestHardware,
+}
+
+#[cfg(test)]
+impl TestMachine {
+    /// Creates a machine with flat RAM whose code segment starts at 0x1000:0000.
+    fn new() -> TestMachine {
+        let mut cpu = Cpu8086::new();
+        cpu.regs.writeseg16(SegReg::CS, 0x1000);
+        cpu.regs.writeseg16(SegReg::SS, 0x2000);
+        cpu.regs.write16(Reg16::SP, 0x1000);
+        TestMachine {
+            cpu,
+            hardware: TestHardware {
+                ram: vec![0; 0x10_0000],
+                ports: vec![0; 0x1_0000],
+                intr: None,
+                nmi: false,
+                floppies: [None, None],
+                hard_disks: [None, None],
+            },
+        }
+    }
+    fn load(code: &[u8]) -> TestMachine {
+        let mut machine = TestMachine::new();
+        machine.hardware.ram[0x1_0000..0x1_0000 + code.len()].copy_from_slice(code);
+        machine
+    }
+    fn step(&mut self, count: usize) {
+        for _ in 0..count {
+            self.cpu.tick(&mut self.hardware);
+        }
+    }
+}
+
+#[cfg(test)]
+impl Default for TestMachine {
+    fn default() -> TestMachine {
+        TestMachine::new()
+    }
+}
+
+#[test]
+fn test_add_adc_sbb_flags() {
+    // mov al, 0xff; add al, 1; adc al, 0; sbb al, 1
+    let mut machine = TestMachine::load(&[0xb0, 0xff, 0x04, 0x01, 0x14, 0x00, 0x1c, 0x01]);
+    machine.step(2);
+    assert_eq!(machine.cpu.regs.read8(Reg8::AL), 0);
+    assert!(machine.cpu.regs.flags.contains(Flags::CARRY | Flags::ZERO | Flags::ADJUST));
+    machine.step(1);
+    assert_eq!(machine.cpu.regs.read8(Reg8::AL), 1);
+    assert!(!machine.cpu.regs.flags.contains(Flags::CARRY));
+    machine.step(1);
+    assert_eq!(machine.cpu.regs.read8(Reg8::AL), 0);
+    assert!(machine.cpu.regs.flags.contains(Flags::ZERO));
+}
+
+#[test]
+fn test_cmp_and_conditional_jumps() {
+    // mov ax, 0x7fff; cmp ax, -1; jg +2; mov bl, 1; jle +2; mov bh, 1
+    let mut machine = TestMachine::load(&[
+        0xb8, 0xff, 0x7f, 0x3d, 0xff, 0xff, 0x7f, 0x02, 0xb3, 0x01, 0x7e, 0x02, 0xb7, 0x01,
+    ]);
+    machine.step(5);
+    assert_eq!(machine.cpu.regs.read16(Reg16::AX), 0x7fff);
+    assert_eq!(machine.cpu.regs.read16(Reg16::BX), 0x0100);
+    assert!(machine.cpu.regs.flags.contains(Flags::OVERFLOW));
+}
+
+#[test]
+fn test_call_ret_and_stack() {
+    // call +3; hlt; nop; nop; pushf; popf; push ax; pop bx; ret 0
+    let mut machine = TestMachine::load(&[
+        0xe8, 0x03, 0x00, 0xf4, 0x90, 0x90, 0x9c, 0x9d, 0x50, 0x5b, 0xc2, 0x00, 0x00,
+    ]);
+    machine.cpu.regs.write16(Reg16::AX, 0x1234);
+    machine.step(6);
+    assert_eq!(machine.cpu.regs.read16(Reg16::BX), 0x1234);
+    assert_eq!(machine.cpu.regs.read16(Reg16::SP), 0x1000);
+    assert_eq!(machine.cpu.regs.ip, 3);
+    machine.step(1);
+    assert!(machine.cpu.halted);
+}
+
+#[test]
+fn test_rep_movsb_and_repne_scasb() {
+    // mov cx, 4; rep movsb; mov al, 0x33; mov cx, 4; repne scasb
+    let mut machine = TestMachine::load(&[
+        0xb9, 0x04, 0x00, 0xf3, 0xa4, 0xb0, 0x33, 0xb9, 0x04, 0x00, 0xf2, 0xae,
+    ]);
+    machine.cpu.regs.writeseg16(SegReg::DS, 0x3000);
+    machine.cpu.regs.writeseg16(SegReg::ES, 0x4000);
+    machine.hardware.ram[0x3_0000..0x3_0004].copy_from_slice(&[0x11, 0x22, 0x33, 0x44]);
+    machine.step(4);
+    // One iteration per tick, with IP back on the prefix until CX runs out.
+    assert_eq!(machine.cpu.regs.ip, 3);
+    assert_eq!(machine.cpu.regs.read16(Reg16::CX), 1);
+    machine.step(1);
+    assert_eq!(machine.cpu.regs.ip, 5);
+    assert_eq!(&machine.hardware.ram[0x4_0000..0x4_0004], &[0x11, 0x22, 0x33, 0x44]);
+    assert_eq!(machine.cpu.regs.read16(Reg16::CX), 0);
+    machine.cpu.regs.write16(Reg16::DI, 0);
+    machine.step(5);
+    assert_eq!(machine.cpu.regs.ip, 12);
+    assert_eq!(machine.cpu.regs.read16(Reg16::DI), 3);
+    assert_eq!(machine.cpu.regs.read16(Reg16::CX), 1);
+    assert!(machine.cpu.regs.flags.contains(Flags::ZERO));
+}
+
+#[test]
+fn test_mul_div_and_bcd() {
+    // mov ax, 0x0010; mov bl, 0x20; mul bl; mov bl, 0x30; div bl;
+    // mov al, 0x15; add al, 0x27; daa; cwd; cbw
+    let mut machine = TestMachine::load(&[
+        0xb8, 0x10, 0x00, 0xb3, 0x20, 0xf6, 0xe3, 0xb3, 0x30, 0xf6, 0xf3, 0xb0, 0x15, 0x04,
+        0x27, 0x27, 0x99, 0x98,
+    ]);
+    machine.step(3);
+    assert_eq!(machine.cpu.regs.read16(Reg16::AX), 0x0200);
+    assert!(machine.cpu.regs.flags.contains(Flags::CARRY | Flags::OVERFLOW));
+    machine.step(2);
+    assert_eq!(machine.cpu.regs.read16(Reg16::AX), 0x200a);
+    machine.step(3);
+    assert_eq!(machine.cpu.regs.read8(Reg8::AL), 0x42);
+    machine.step(2);
+    assert_eq!(machine.cpu.regs.read16(Reg16::DX), 0);
+    assert_eq!(machine.cpu.regs.read16(Reg16::AX), 0x0042);
+}
+
+#[test]
+fn test_shifts_and_rotates() {
+    // mov al, 0x81; rol al, 1; mov cl, 4; shr al, cl; sar ax, 1
+    let mut machine = TestMachine::load(&[0xb0, 0x81, 0xd0, 0xc0, 0xb1, 0x04, 0xd2, 0xe8, 0xd1, 0xf8]);
+    machine.step(2);
+    assert_eq!(machine.cpu.regs.read8(Reg8::AL), 0x03);
+    assert!(machine.cpu.regs.flags.contains(Flags::CARRY));
+    machine.step(2);
+    assert_eq!(machine.cpu.regs.read8(Reg8::AL), 0x00);
+    assert!(machine.cpu.regs.flags.contains(Flags::ZERO));
+    machine.cpu.regs.write16(Reg16::AX, 0x8002);
+    machine.step(1);
+    assert_eq!(machine.cpu.regs.read16(Reg16::AX), 0xc001);
+}
+
+#[test]
+fn test_lea_xchg_xlat() {
+    // mov bx, 0x100; lea si, [bx+si+0x10]; xchg si, ax; mov al, 2; xlat
+    let mut machine = TestMachine::load(&[
+        0xbb, 0x00, 0x01, 0x8d, 0x70, 0x10, 0x96, 0xb0, 0x02, 0xd7,
+    ]);
+    machine.cpu.regs.write16(Reg16::SI, 0x5);
+    machine.hardware.ram[0x102] = 0x77;
+    machine.step(3);
+    assert_eq!(machine.cpu.regs.read16(Reg16::AX), 0x115);
+    machine.step(2);
+    assert_eq!(machine.cpu.regs.read8(Reg8::AL), 0x77);
+}
+
+#[test]
+fn test_group_memory_operands() {
+    // add byte [0x100], 5; sub word [0x102], -1; shl word [0x102], 1;
+    // ror byte [0x100], cl; neg word [0x104]; not byte [0x106];
+    // inc byte [0x106]; dec word [0x104]
+    let mut machine = TestMachine::load(&[
+        0x80, 0x06, 0x00, 0x01, 0x05, 0x83, 0x2e, 0x02, 0x01, 0xff, 0xd1, 0x26, 0x02, 0x01,
+        0xd2, 0x0e, 0x00, 0x01, 0xf7, 0x1e, 0x04, 0x01, 0xf6, 0x16, 0x06, 0x01, 0xfe, 0x06,
+        0x06, 0x01, 0xff, 0x0e, 0x04, 0x01,
+    ]);
+    machine.cpu.regs.write8(Reg8::CL, 4);
+    machine.hardware.ram[0x100..0x108].copy_from_slice(&[0x10, 0, 0x00, 0x40, 0x01, 0, 0x0f, 0]);
+    machine.step(8);
+    assert_eq!(
+        &machine.hardware.ram[0x100..0x108],
+        &[0x51, 0, 0x02, 0x80, 0xfe, 0xff, 0xf1, 0]
+    );
+}
+
+#[test]
+fn test_group_mul_div_memory_operands() {
+    // mov ax, 300; imul word [0x100]; idiv byte [0x102]
+    let mut machine = TestMachine::load(&[
+        0xb8, 0x2c, 0x01, 0xf7, 0x2e, 0x00, 0x01, 0xf6, 0x3e, 0x02, 0x01,
+    ]);
+    machine.hardware.ram[0x100..0x103].copy_from_slice(&[0xfe, 0xff, 0x07]);
+    machine.step(2);
+    assert_eq!(machine.cpu.regs.read16(Reg16::AX), (-600i16) as u16);
+    assert_eq!(machine.cpu.regs.read16(Reg16::DX), 0xffff);
+    assert!(!machine.cpu.regs.flags.contains(Flags::CARRY));
+    machine.step(1);
+    assert_eq!(machine.cpu.regs.read8(Reg8::AL), (-85i8) as u8);
+    assert_eq!(machine.cpu.regs.read8(Reg8::AH), (-5i8) as u8);
+}
+
+#[test]
+fn test_far_jump_through_register_operand() {
+    // mov ax, [0x100]; jmp far ax
+    let mut machine = TestMachine::load(&[0x8b, 0x06, 0x00, 0x01, 0xff, 0xe8]);
+    machine.hardware.ram[0x100..0x104].copy_from_slice(&[0x34, 0x12, 0x00, 0x50]);
+    machine.step(2);
+    assert_eq!(machine.cpu.regs.readseg16(SegReg::CS), 0x5000);
+    assert_eq!(machine.cpu.regs.ip, 0x1234);
+}
+
+#[test]
+fn test_software_interrupt_through_ivt() {
+    // sti; int 0x21; (handler at 0x1000:0x0010) iret
+    let mut machine = TestMachine::load(&[0xfb, 0xcd, 0x21]);
+    machine.hardware.ram[0x1_0010] = 0xcf;
+    machine.hardware.ram[0x84..0x88].copy_from_slice(&[0x10, 0x00, 0x00, 0x10]);
+    machine.step(2);
+    assert_eq!(machine.cpu.regs.readseg16(SegReg::CS), 0x1000);
+    assert_eq!(machine.cpu.regs.ip, 0x0010);
+    assert!(!machine.cpu.regs.flags.contains(Flags::INTERRUPT));
+    assert_eq!(machine.cpu.regs.read16(Reg16::SP), 0x0ffa);
+    assert_eq!(&machine.hardware.ram[0x2_0ffa..0x2_0ffe], &[0x03, 0x00, 0x00, 0x10]);
+    machine.step(1);
+    assert_eq!(machine.cpu.regs.ip, 0x0003);
+    assert_eq!(machine.cpu.regs.read16(Reg16::SP), 0x1000);
+    assert!(machine.cpu.regs.flags.contains(Flags::INTERRUPT));
+}
+
+#[test]
+fn test_divide_error_and_hle_hook() {
+    // div bl (bl = 0); mov ah, 0x0e; int 0x10
+    let mut machine = TestMachine::load(&[0xf6, 0xf3, 0xb4, 0x0e, 0xcd, 0x10]);
+    machine.hardware.ram[0..4].copy_from_slice(&[0x02, 0x00, 0x00, 0x10]);
+    machine.cpu.set_hle_hook(0x10, true);
+    machine.step(1);
+    assert_eq!(machine.cpu.regs.ip, 0x0002);
+    assert_eq!(machine.cpu.regs.read16(Reg16::SP), 0x0ffa);
+    machine.step(2);
+    assert_eq!(machine.cpu.regs.ip, 0x0006);
+    assert_eq!(machine.cpu.regs.read16(Reg16::SP), 0x0ffa);
+}
+
+#[test]
+fn test_intr_sti_shadow_and_hlt_wakeup() {
+    // sti; nop; hlt
+    let mut machine = TestMachine::load(&[0xfb, 0x90, 0xf4]);
+    machine.hardware.ram[0x20..0x24].copy_from_slice(&[0x00, 0x01, 0x00, 0x10]);
+    machine.hardware.intr = Some(0x08);
+    machine.step(2);
+    assert_eq!(machine.cpu.regs.ip, 0x0002);
+    assert!(machine.hardware.intr.is_some());
+    machine.step(1);
+    assert_eq!(machine.cpu.regs.ip, 0x0100);
+    assert!(machine.hardware.intr.is_none());
+    machine.cpu.regs.ip = 0x0002;
+    machine.cpu.regs.flags.set(Flags::INTERRUPT, true);
+    machine.step(2);
+    assert!(machine.cpu.halted);
+    machine.hardware.intr = Some(0x08);
+    machine.step(1);
+    assert!(!machine.cpu.halted);
+    assert_eq!(machine.cpu.regs.ip, 0x0100);
+}
+
+#[test]
+fn test_intr_between_rep_iterations() {
+    // sti; mov cx, 8; rep es: stosb
+    let mut machine = TestMachine::load(&[0xfb, 0xb9, 0x08, 0x00, 0xf3, 0x26, 0xaa]);
+    machine.hardware.ram[0x20..0x24].copy_from_slice(&[0x00, 0x01, 0x00, 0x10]);
+    machine.step(4);
+    assert_eq!(machine.cpu.regs.read16(Reg16::CX), 6);
+    machine.hardware.intr = Some(0x08);
+    machine.step(1);
+    assert_eq!(machine.cpu.regs.ip, 0x0100);
+    // The return address is the first prefix, so IRET resumes the REP.
+    assert_eq!(machine.hardware.ram[0x2_0ffa..0x2_0ffc], [0x04, 0x00]);
+    assert_eq!(machine.cpu.regs.read16(Reg16::CX), 6);
+}
+
+#[test]
+fn test_nmi_ignores_interrupt_flag() {
+    // cli; nop
+    let mut machine = TestMachine::load(&[0xfa, 0x90]);
+    machine.hardware.ram[0x08..0x0c].copy_from_slice(&[0x00, 0x02, 0x00, 0x10]);
+    machine.step(1);
+    machine.hardware.intr = Some(0x08);
+    machine.step(1);
+    assert_eq!(machine.cpu.regs.ip, 0x0002);
+    machine.hardware.nmi = true;
+    machine.step(1);
+    assert_eq!(machine.cpu.regs.ip, 0x0200);
+}
+
+#[test]
+fn test_hle_int13_floppy_services() {
+    // int 0x13 (read 3 sectors from C0 H0 S8 into 3000:0000); int 0x13 (write); int 0x13 (get parameters)
+    let mut machine = TestMachine::load(&[0xcd, 0x13, 0xcd, 0x13, 0xcd, 0x13]);
+    let mut data = vec![0u8; 360 * 1024];
+    for (i, byte) in data.iter_mut().enumerate() {
+        *byte = (i >> 9) as u8;
+    }
+    machine.hardware.floppies[1] = FloppyImage::from_bytes(data);
+    machine.cpu.set_hle_hook(0x13, true);
+    machine.cpu.regs.writeseg16(SegReg::ES, 0x3000);
+    machine.cpu.regs.write16(Reg16::AX, 0x0203);
+    machine.cpu.regs.write16(Reg16::CX, 0x0008);
+    machine.cpu.regs.write16(Reg16::DX, 0x0001);
+    machine.step(1);
+    assert!(!machine.cpu.regs.flags.contains(Flags::CARRY));
+    assert_eq!(machine.cpu.regs.read16(Reg16::AX), 0x0003);
+    assert_eq!(machine.hardware.ram[0x30000], 7);
+    assert_eq!(machine.hardware.ram[0x30200], 8);
+    assert_eq!(machine.hardware.ram[0x30400], 9);
+    machine.hardware.floppies[1].as_mut().unwrap().write_protected = true;
+    machine.cpu.regs.write16(Reg16::AX, 0x0301);
+    machine.step(1);
+    assert!(machine.cpu.regs.flags.contains(Flags::CARRY));
+    assert_eq!(machine.cpu.regs.read8(Reg8::AH), 0x03);
+    assert_eq!(machine.hardware.ram[0x441], 0x03);
+    machine.cpu.regs.write16(Reg16::AX, 0x0800);
+    machine.step(1);
+    assert_eq!(machine.cpu.regs.read8(Reg8::BL), 1);
+    assert_eq!(machine.cpu.regs.read16(Reg16::CX), 0x2709);
+    assert_eq!(machine.cpu.regs.read16(Reg16::DX), 0x0101);
+}
+
+#[test]
+fn test_hle_int13_floppy_sector_sizes() {
+    use crate::hardware::floppy::{FloppyFormat, Sector, SectorId};
+
+    // int 0x13 (read 2 sectors from C0 H0 S1); int 0x13 (write 2 sectors to S3)
+    let mut machine = TestMachine::load(&[0xcd, 0x13, 0xcd, 0x13]);
+    let sectors = (1..=4)
+        .map(|sector| Sector {
+            id: SectorId::new(0, 0, sector, 1),
+            data: vec![sector; 256],
+            deleted: false,
+            crc_error: false,
+        })
+        .collect();
+    let image = FloppyImage::from_tracks(vec![(0, 0, sectors)], FloppyFormat::Imd).unwrap();
+    machine.hardware.floppies[0] = Some(image);
+    machine.cpu.set_hle_hook(0x13, true);
+    machine.cpu.regs.writeseg16(SegReg::ES, 0x3000);
+    machine.cpu.regs.write16(Reg16::AX, 0x0202);
+    machine.cpu.regs.write16(Reg16::CX, 0x0001);
+    machine.cpu.regs.write16(Reg16::DX, 0x0000);
+    machine.step(1);
+    assert_eq!(machine.cpu.regs.read16(Reg16::AX), 0x0002);
+    assert_eq!(machine.hardware.ram[0x300ff], 1);
+    assert_eq!(machine.hardware.ram[0x30100], 2);
+    assert_eq!(machine.hardware.ram[0x30200], 0);
+    machine.hardware.ram[0x30100..0x30200].fill(0xaa);
+    machine.cpu.regs.write16(Reg16::AX, 0x0302);
+    machine.cpu.regs.write16(Reg16::CX, 0x0003);
+    machine.step(1);
+    assert_eq!(machine.cpu.regs.read16(Reg16::AX), 0x0002);
+    let image = machine.hardware.floppies[0].as_ref().unwrap();
+    assert_eq!(image.read_sector(0, 0, 3).unwrap(), &[1; 256][..]);
+    assert_eq!(image.read_sector(0, 0, 4).unwrap(), &[0xaa; 256][..]);
+}
+
+#[test]
+fn test_hle_int13_hard_disk_through_overlay() {
+    use crate::hardware::harddisk::HardDiskGeometry;
+    use crate::hardware::overlay::DiskOverlay;
+    // int 0x13 (write C1 H2 S3); int 0x13 (read it back); int 0x13 (get parameters)
+    let mut machine = TestMachine::load(&[0xcd, 0x13, 0xcd, 0x13, 0xcd, 0x13]);
+    let geometry = HardDiskGeometry::new(306, 4, 17);
+    let mut image = HardDiskImage::new(geometry);
+    image.write_protected = true;
+    image.attach_overlay(DiskOverlay::new());
+    machine.hardware.hard_disks[0] = Some(image);
+    machine.cpu.set_hle_hook(0x13, true);
+    machine.hardware.ram[0x30000..0x30200].fill(0x5a);
+    machine.cpu.regs.writeseg16(SegReg::ES, 0x3000);
+    machine.cpu.regs.write16(Reg16::AX, 0x0301);
+    machine.cpu.regs.write16(Reg16::CX, 0x0103);
+    machine.cpu.regs.write16(Reg16::DX, 0x0280);
+    machine.step(1);
+    assert!(!machine.cpu.regs.flags.contains(Flags::CARRY));
+    let image = machine.hardware.hard_disks[0].as_ref().unwrap();
+    assert_eq!(image.overlay.as_ref().unwrap().sectors.len(), 1);
+    assert_eq!(image.read_sector((4 + 2) * 17 + 2).unwrap()[0], 0x5a);
+    machine.cpu.regs.write16(Reg16::AX, 0x0201);
+    machine.cpu.regs.write16(Reg16::BX, 0x1000);
+    machine.step(1);
+    assert_eq!(machine.cpu.regs.read16(Reg16::AX), 0x0001);
+    assert_eq!(machine.hardware.ram[0x311ff], 0x5a);
+    machine.cpu.regs.write16(Reg16::AX, 0x0800);
+    machine.step(1);
+    assert_eq!(machine.cpu.regs.read16(Reg16::CX), 0x3151);
+    assert_eq!(machine.cpu.regs.read16(Reg16::DX), 0x0301);
+}
@@ -20,7 +20,7 @@ pub enum DisplacementType {
     Word,
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone, Copy)]
 pub enum Operand {
     Register(u8),
     Address(SegReg, u16),
@@ -29,7 +29,7 @@ pub enum Operand {
 pub type ByteOperand = Operand;
 pub type WordOperand = Operand;
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone, Copy)]
 pub struct OpcodeParams {
     pub reg: u8,
     pub rm: Operand,
@@ -75,34 +75,28 @@ impl Cpu8086 {
     }
     pub fn get_offset(&self, addr_type: AddrType, offset: u16) -> u16 {
         let base = match addr_type {
-            AddrType::BxSi => self.regs.read16(Reg16::BX) + self.regs.read16(Reg16::SI),
-            AddrType::BxDi => self.regs.read16(Reg16::BX) + self.regs.read16(Reg16::DI),
-            AddrType::BpSi => self.regs.read16(Reg16::BP) + self.regs.read16(Reg16::SI),
-            AddrType::BpDi => self.regs.read16(Reg16::BP) + self.regs.read16(Reg16::DI),
+            AddrType::BxSi => self.regs.read16(Reg16::BX).wrapping_add(self.regs.read16(Reg16::SI)),
+            AddrType::BxDi => self.regs.read16(Reg16::BX).wrapping_add(self.regs.read16(Reg16::DI)),
+            AddrType::BpSi => self.regs.read16(Reg16::BP).wrapping_add(self.regs.read16(Reg16::SI)),
+            AddrType::BpDi => self.regs.read16(Reg16::BP).wrapping_add(self.regs.read16(Reg16::DI)),
             AddrType::Si => self.regs.read16(Reg16::SI),
             AddrType::Di => self.regs.read16(Reg16::DI),
             AddrType::Bp => self.regs.read16(Reg16::BP),
             AddrType::Bx => self.regs.read16(Reg16::BX),
         };
-        base + offset
+        base.wrapping_add(offset)
     }
     pub fn get_operand_seg(
         &self,
         addr_type: Option<AddrType>,
-        disp_type: Option<DisplacementType>,
+        _disp_type: Option<DisplacementType>,
     ) -> SegReg {
         match self.seg_override {
             Some(segment) => segment,
             None => match addr_type {
                 Some(AddrType::BpSi) => SegReg::SS,
                 Some(AddrType::BpDi) => SegReg::SS,
-                Some(AddrType::Bp) => {
-                    if disp_type == Some(DisplacementType::Word) {
-                        SegReg::SS
-                    } else {
-                        SegReg::DS
-                    }
-                }
+                Some(AddrType::Bp) => SegReg::SS,
                 _ => SegReg::DS,
             },
         }
@@ -121,7 +115,6 @@ impl Cpu8086 {
             0 => {
                 let addr_type = Cpu8086::get_addr_type_from_modrm(modrm);
                 let disp_type = Cpu8086::get_disp_type_from_modrm(modrm);
-                let addr: u16;
                 let displacement: u16;
                 let segment: SegReg = self.get_operand_seg(addr_type, disp_type);
                 match disp_type {
@@ -140,15 +133,12 @@ impl Cpu8086 {
                         self.regs.ip = self.regs.ip.wrapping_add(2);
                     }
                 }
-                match addr_type {
-                    None => {
-                        addr = displacement;
-                    }
-                    Some(_) => {
-                        addr = self.get_offset(addr_type.unwrap(), displacement);
-                    }
-                }
+                let addr = match addr_type {
+                    None => displacement,
+                    Some(addr_type) => self.get_offset(addr_type, displacement),
+                };
                 let operand_rm = Operand::Address(segment, addr);
+                self.last_ea = Some(operand_rm);
                 let operand_reg = reg;
                 OpcodeParams {
                     reg: operand_reg,
@@ -157,18 +147,17 @@ impl Cpu8086 {
             }
             1 => {
                 let addr_type = Cpu8086::get_addr_type_from_modrm(modrm);
-                let addr: u16;
                 let displacement: u16 =
-                    self.mem_read_byte(ctx, self.regs.readseg16(SegReg::CS), self.regs.ip) as u16;
+                    self.mem_read_byte(ctx, self.regs.readseg16(SegReg::CS), self.regs.ip) as i8
+                        as u16;
                 let segment: SegReg = self.get_operand_seg(addr_type, Some(DisplacementType::Byte));
                 self.regs.ip = self.regs.ip.wrapping_add(1);
-                match addr_type {
+                let addr = match addr_type {
                     None => panic!("Invalid address type for this ModR/M type!"),
-                    Some(_) => {
-                        addr = self.get_offset(addr_type.unwrap(), displacement);
-                    }
-                }
+                    Some(addr_type) => self.get_offset(addr_type, displacement),
+                };
                 let operand_rm = Operand::Address(segment, addr);
+                self.last_ea = Some(operand_rm);
                 let operand_reg = reg;
                 OpcodeParams {
                     reg: operand_reg,
@@ -177,18 +166,16 @@ impl Cpu8086 {
             }
             2 => {
                 let addr_type = Cpu8086::get_addr_type_from_modrm(modrm);
-                let addr: u16;
                 let displacement: u16 =
                     self.mem_read_word(ctx, self.regs.readseg16(SegReg::CS), self.regs.ip);
                 let segment: SegReg = self.get_operand_seg(addr_type, Some(DisplacementType::Word));
                 self.regs.ip = self.regs.ip.wrapping_add(2);
-                match addr_type {
+                let addr = match addr_type {
                     None => panic!("Invalid address type for this ModR/M type!"),
-                    Some(_) => {
-                        addr = self.get_offset(addr_type.unwrap(), displacement);
-                    }
-                }
+                    Some(addr_type) => self.get_offset(addr_type, displacement),
+                };
                 let operand_rm = Operand::Address(segment, addr);
+                self.last_ea = Some(operand_rm);
                 let operand_reg = reg;
                 OpcodeParams {
                     reg: operand_reg,
@@ -210,7 +197,7 @@ impl Cpu8086 {
 
 #[test]
 fn test_modrm() {
-    let mut machine = IbmPc5150Machine::new();
+    let mut machine = super::TestMachine::new();
     for modrm in 0..=0xffu8 {
         machine
             .cpu
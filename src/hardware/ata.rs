@@ -0,0 +1,513 @@
+use crate::hardware::harddisk::*;
+
+pub const ATA_IRQ: u8 = 14;
+
+/// Status register bits.
+const STATUS_BSY: u8 = 0x80;
+const STATUS_DRDY: u8 = 0x40;
+const STATUS_DSC: u8 = 0x10;
+const STATUS_DRQ: u8 = 0x08;
+const STATUS_ERR: u8 = 0x01;
+
+/// Error register bits.
+const ERROR_IDNF: u8 = 0x10;
+const ERROR_ABRT: u8 = 0x04;
+
+/// Largest block READ/WRITE MULTIPLE may be set up for.
+const MAX_MULTIPLE: u8 = 16;
+
+#[derive(Debug, Clone, Default)]
+pub struct AtaDrive {
+    pub image: Option<HardDiskImage>,
+    /// Geometry set by INITIALIZE DEVICE PARAMETERS for CHS addressing.
+    pub logical: HardDiskGeometry,
+    /// Sectors per block for READ/WRITE MULTIPLE, 0 if disabled.
+    pub multiple: u8,
+}
+
+/// An ATA controller with up to two drives on the primary channel, task
+/// file at 0x1F0-0x1F7 and device control at 0x3F6.
+#[derive(Debug, Clone)]
+pub struct AtaController {
+    pub drives: [AtaDrive; 2],
+    pub features: u8,
+    pub error: u8,
+    pub sector_count: u8,
+    pub sector_number: u8,
+    pub cylinder: u16,
+    pub drive_head: u8,
+    pub status: u8,
+    /// Interrupt disable (bit 1) and software reset (bit 2).
+    pub device_control: u8,
+    /// Sector data being moved through the data port.
+    pub buffer: Vec<u8>,
+    pub buffer_pos: usize,
+    pub command: u8,
+    /// Sectors of the command still to be buffered.
+    pub remaining: usize,
+    pub lba: usize,
+    /// Data port moves single bytes, enabled through SET FEATURES.
+    pub eight_bit: bool,
+    pub irq: bool,
+}
+
+impl AtaController {
+    pub fn new() -> Self {
+        Self {
+            drives: Default::default(),
+            features: 0,
+            error: 0x01,
+            sector_count: 1,
+            sector_number: 1,
+            cylinder: 0,
+            drive_head: 0,
+            status: STATUS_DRDY | STATUS_DSC,
+            device_control: 0,
+            buffer: Vec::new(),
+            buffer_pos: 0,
+            command: 0,
+            remaining: 0,
+            lba: 0,
+            eight_bit: false,
+            irq: false,
+        }
+    }
+
+    pub fn attach(&mut self, drive: usize, image: HardDiskImage) {
+        self.drives[drive] = AtaDrive {
+            logical: image.geometry,
+            image: Some(image),
+            multiple: 0,
+        };
+    }
+
+    /// Level of IRQ14, masked by the interrupt disable bit.
+    pub fn irq(&self) -> bool {
+        self.irq && (self.device_control & 0x02) == 0
+    }
+
+    fn selected(&self) -> usize {
+        ((self.drive_head >> 4) & 1) as usize
+    }
+
+    fn reset(&mut self) {
+        self.error = 0x01;
+        self.sector_count = 1;
+        self.sector_number = 1;
+        self.cylinder = 0;
+        self.drive_head = 0;
+        self.status = STATUS_DRDY | STATUS_DSC;
+        self.buffer.clear();
+        self.buffer_pos = 0;
+        self.remaining = 0;
+        self.eight_bit = false;
+        self.irq = false;
+    }
+
+    fn abort(&mut self, error: u8) {
+        self.error = error;
+        self.status = STATUS_DRDY | STATUS_DSC | STATUS_ERR;
+        self.buffer.clear();
+        self.remaining = 0;
+        self.irq = true;
+    }
+
+    fn complete(&mut self) {
+        self.error = 0;
+        self.status = STATUS_DRDY | STATUS_DSC;
+        self.irq = true;
+    }
+
+    /// Block address in the task file, by LBA or logical CHS.
+    fn task_file_lba(&self) -> Option<usize> {
+        let drive = &self.drives[self.selected()];
+        let image = drive.image.as_ref()?;
+        let lba = if (self.drive_head & 0x40) != 0 {
+            ((self.drive_head as usize & 0x0f) << 24)
+                | ((self.cylinder as usize) << 8)
+                | self.sector_number as usize
+        } else {
+            drive
+                .logical
+                .lba(self.cylinder, self.drive_head & 0x0f, self.sector_number)?
+        };
+        (lba < image.geometry.total_sectors()).then_some(lba)
+    }
+
+    /// Points the task file at a block address, as left after a transfer.
+    fn set_task_file_lba(&mut self, lba: usize) {
+        if (self.drive_head & 0x40) != 0 {
+            self.sector_number = lba as u8;
+            self.cylinder = (lba >> 8) as u16;
+            self.drive_head = (self.drive_head & 0xf0) | ((lba >> 24) as u8 & 0x0f);
+        } else {
+            let (cylinder, head, sector) = self.drives[self.selected()].logical.chs(lba);
+            self.cylinder = cylinder;
+            self.drive_head = (self.drive_head & 0xf0) | head;
+            self.sector_number = sector;
+        }
+    }
+
+    fn sector_count(&self) -> usize {
+        match self.sector_count {
+            0 => 256,
+            count => count as usize,
+        }
+    }
+
+    /// Sectors moved per DRQ block by the current command.
+    fn block_sectors(&self) -> usize {
+        match self.command {
+            0xc4 | 0xc5 => self.drives[self.selected()].multiple as usize,
+            _ => 1,
+        }
+        .min(self.remaining)
+    }
+
+    /// Buffers the next block of a read and interrupts for it.
+    fn next_read_block(&mut self) {
+        let count = self.block_sectors();
+        let drive = self.selected();
+        let image = self.drives[drive].image.as_ref().unwrap();
+        let mut buffer = Vec::with_capacity(count << 9);
+        for lba in self.lba..self.lba + count {
+            match image.read_sector(lba) {
+                Some(data) => buffer.extend_from_slice(data),
+                None => return self.abort(ERROR_IDNF),
+            }
+        }
+        self.buffer = buffer;
+        self.buffer_pos = 0;
+        self.set_task_file_lba(self.lba + count - 1);
+        self.lba += count;
+        self.remaining -= count;
+        self.status = STATUS_DRDY | STATUS_DSC | STATUS_DRQ;
+        self.irq = true;
+    }
+
+    /// Opens the buffer for the next block of a write.
+    fn next_write_block(&mut self) {
+        self.buffer = vec![0; self.block_sectors() << 9];
+        self.buffer_pos = 0;
+        self.status = STATUS_DRDY | STATUS_DSC | STATUS_DRQ;
+    }
+
+    /// Writes a filled buffer to the disk.
+    fn flush_write_block(&mut self) {
+        let drive = self.selected();
+        let image = self.drives[drive].image.as_mut().unwrap();
+        let count = self.buffer.len() >> 9;
+        for (i, data) in self.buffer.chunks(512).enumerate() {
+            if image.write_sector(self.lba + i, data).is_err() {
+                return self.abort(ERROR_ABRT);
+            }
+        }
+        self.set_task_file_lba(self.lba + count - 1);
+        self.lba += count;
+        self.remaining -= count;
+        if self.remaining == 0 {
+            self.buffer.clear();
+            self.complete();
+        } else {
+            self.next_write_block();
+            self.irq = true;
+        }
+    }
+
+    /// Called once the host has emptied or filled the buffer.
+    fn buffer_done(&mut self) {
+        match self.command {
+            0x20 | 0x21 | 0xc4 if self.remaining > 0 => self.next_read_block(),
+            0x30 | 0x31 | 0xc5 => self.flush_write_block(),
+            _ => {
+                self.buffer.clear();
+                self.status = STATUS_DRDY | STATUS_DSC;
+            }
+        }
+    }
+
+    fn identify(&self) -> Vec<u8> {
+        let drive = &self.drives[self.selected()];
+        let geometry = drive.image.as_ref().unwrap().geometry;
+        let logical = drive.logical;
+        let total = geometry.total_sectors() as u32;
+        let logical_total = logical.total_sectors().min(total as usize) as u32;
+        let mut words = [0u16; 256];
+        words[0] = 0x0040;
+        words[1] = geometry.cylinders;
+        words[3] = geometry.heads as u16;
+        words[4] = geometry.sectors_per_track as u16 * 512;
+        words[5] = 512;
+        words[6] = geometry.sectors_per_track as u16;
+        let mut string = |first: usize, text: &str, len: usize| {
+            let mut bytes = text.as_bytes().to_vec();
+            bytes.resize(len, b' ');
+            for (i, pair) in bytes.chunks(2).enumerate() {
+                words[first + i] = ((pair[0] as u16) << 8) | pair[1] as u16;
+            }
+        };
+        string(10, "EMUPC0000001", 20);
+        string(23, "1.0", 8);
+        string(27, "EMUPC HARD DISK", 40);
+        words[47] = 0x8000 | MAX_MULTIPLE as u16;
+        words[49] = 0x0200;
+        words[51] = 0x0200;
+        words[53] = 0x0001;
+        words[54] = logical.cylinders;
+        words[55] = logical.heads as u16;
+        words[56] = logical.sectors_per_track as u16;
+        words[57] = logical_total as u16;
+        words[58] = (logical_total >> 16) as u16;
+        if drive.multiple != 0 {
+            words[59] = 0x0100 | drive.multiple as u16;
+        }
+        words[60] = total as u16;
+        words[61] = (total >> 16) as u16;
+        words.iter().flat_map(|word| word.to_le_bytes()).collect()
+    }
+
+    fn execute(&mut self, command: u8) {
+        self.command = command;
+        self.irq = false;
+        let drive = self.selected();
+        if self.drives[drive].image.is_none() {
+            return;
+        }
+        match command {
+            0x10..=0x1f => {
+                self.cylinder = 0;
+                self.complete();
+            }
+            0x20 | 0x21 | 0x30 | 0x31 | 0xc4 | 0xc5 | 0x40 | 0x41 | 0x70 => {
+                if matches!(command, 0xc4 | 0xc5) && self.drives[drive].multiple == 0 {
+                    return self.abort(ERROR_ABRT);
+                }
+                let lba = match self.task_file_lba() {
+                    Some(lba) => lba,
+                    None => return self.abort(ERROR_IDNF),
+                };
+                let total = self.drives[drive]
+                    .image
+                    .as_ref()
+                    .unwrap()
+                    .geometry
+                    .total_sectors();
+                self.lba = lba;
+                self.remaining = self.sector_count();
+                if command != 0x70 && lba + self.remaining > total {
+                    return self.abort(ERROR_IDNF);
+                }
+                match command {
+                    0x20 | 0x21 | 0xc4 => self.next_read_block(),
+                    0x30 | 0x31 | 0xc5 => self.next_write_block(),
+                    _ => {
+                        self.remaining = 0;
+                        self.complete();
+                    }
+                }
+            }
+            0x90 => {
+                self.reset();
+                self.irq = true;
+            }
+            0x91 => {
+                let logical = &mut self.drives[drive].logical;
+                logical.heads = (self.drive_head & 0x0f) + 1;
+                logical.sectors_per_track = self.sector_count;
+                let image = self.drives[drive].image.as_ref().unwrap();
+                let per_cylinder = logical.heads as usize * logical.sectors_per_track as usize;
+                logical.cylinders = match per_cylinder {
+                    0 => 0,
+                    _ => (image.geometry.total_sectors() / per_cylinder).min(65535) as u16,
+                };
+                self.complete();
+            }
+            0xc6 => {
+                let count = self.sector_count;
+                if count > MAX_MULTIPLE || !(count == 0 || count.is_power_of_two()) {
+                    return self.abort(ERROR_ABRT);
+                }
+                self.drives[drive].multiple = count;
+                self.complete();
+            }
+            0xec => {
+                self.buffer = self.identify();
+                self.buffer_pos = 0;
+                self.status = STATUS_DRDY | STATUS_DSC | STATUS_DRQ;
+                self.irq = true;
+            }
+            0xef => match self.features {
+                0x01 | 0x81 => {
+                    self.eight_bit = self.features == 0x01;
+                    self.complete();
+                }
+                // Transfer mode, write cache and read look-ahead settings
+                // have no effect here.
+                0x02 | 0x03 | 0x55 | 0x66 | 0x82 | 0xaa | 0xcc => self.complete(),
+                _ => self.abort(ERROR_ABRT),
+            },
+            _ => self.abort(ERROR_ABRT),
+        }
+    }
+
+    fn read_data_byte(&mut self) -> u8 {
+        if (self.status & STATUS_DRQ) == 0 {
+            return 0xff;
+        }
+        let value = self.buffer[self.buffer_pos];
+        self.buffer_pos += 1;
+        if self.buffer_pos >= self.buffer.len() {
+            self.buffer_done();
+        }
+        value
+    }
+
+    fn write_data_byte(&mut self, data: u8) {
+        if (self.status & STATUS_DRQ) == 0 || !matches!(self.command, 0x30 | 0x31 | 0xc5) {
+            return;
+        }
+        self.buffer[self.buffer_pos] = data;
+        self.buffer_pos += 1;
+        if self.buffer_pos >= self.buffer.len() {
+            self.buffer_done();
+        }
+    }
+
+    /// 16-bit access to the data port.
+    pub fn read_data(&mut self) -> u16 {
+        if self.eight_bit {
+            return self.read_data_byte() as u16;
+        }
+        let lo = self.read_data_byte();
+        let hi = self.read_data_byte();
+        u16::from_le_bytes([lo, hi])
+    }
+
+    pub fn write_data(&mut self, data: u16) {
+        if self.eight_bit {
+            return self.write_data_byte(data as u8);
+        }
+        self.write_data_byte(data as u8);
+        self.write_data_byte((data >> 8) as u8);
+    }
+
+    fn read_status(&self) -> u8 {
+        if self.drives[self.selected()].image.is_none() {
+            return 0;
+        }
+        self.status
+    }
+
+    pub fn rb(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x1f0 => self.read_data_byte(),
+            0x1f1 => self.error,
+            0x1f2 => self.sector_count,
+            0x1f3 => self.sector_number,
+            0x1f4 => self.cylinder as u8,
+            0x1f5 => (self.cylinder >> 8) as u8,
+            0x1f6 => self.drive_head | 0xa0,
+            0x1f7 => {
+                self.irq = false;
+                self.read_status()
+            }
+            0x3f6 => self.read_status(),
+            _ => 0xff,
+        }
+    }
+
+    pub fn wb(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x1f0 => self.write_data_byte(data),
+            0x1f1 => self.features = data,
+            0x1f2 => self.sector_count = data,
+            0x1f3 => self.sector_number = data,
+            0x1f4 => self.cylinder = (self.cylinder & 0xff00) | data as u16,
+            0x1f5 => self.cylinder = (self.cylinder & 0x00ff) | ((data as u16) << 8),
+            0x1f6 => self.drive_head = data,
+            0x1f7 if (self.status & STATUS_BSY) == 0 => self.execute(data),
+            0x3f6 => {
+                if (data & 0x04) != 0 {
+                    self.reset();
+                    self.status = STATUS_BSY;
+                } else if (self.device_control & 0x04) != 0 {
+                    self.status = STATUS_DRDY | STATUS_DSC;
+                }
+                self.device_control = data;
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Default for AtaController {
+    fn default() -> AtaController {
+        AtaController::new()
+    }
+}
+
+#[test]
+fn test_ata_identify_and_pio_transfers() {
+    let geometry = HardDiskGeometry::new(100, 4, 17);
+    let mut data = vec![0u8; geometry.total_sectors() << 9];
+    for (i, byte) in data.iter_mut().enumerate() {
+        *byte = (i >> 9) as u8;
+    }
+    let mut ata = AtaController::new();
+    ata.attach(0, HardDiskImage::from_bytes(data, geometry));
+    ata.wb(0x1f6, 0xa0);
+    ata.wb(0x1f7, 0xec);
+    assert!(ata.irq());
+    assert_eq!(ata.rb(0x1f7), 0x58);
+    assert!(!ata.irq());
+    let identify: Vec<u16> = (0..256).map(|_| ata.read_data()).collect();
+    assert_eq!(identify[1], 100);
+    assert_eq!(identify[3], 4);
+    assert_eq!(identify[6], 17);
+    assert_eq!(identify[27], u16::from_be_bytes(*b"EM"));
+    assert_eq!(identify[60], 6800);
+    assert_eq!(ata.rb(0x1f7), 0x50);
+    // READ SECTORS by CHS: cylinder 2 head 1 sector 5 is block 4 + 2 * 68 + 17.
+    ata.wb(0x1f2, 2);
+    ata.wb(0x1f3, 5);
+    ata.wb(0x1f4, 2);
+    ata.wb(0x1f5, 0);
+    ata.wb(0x1f6, 0xa1);
+    ata.wb(0x1f7, 0x20);
+    let first: Vec<u16> = (0..256).map(|_| ata.read_data()).collect();
+    assert_eq!(first[0], 157 * 0x101);
+    assert!(ata.irq());
+    assert_eq!(ata.rb(0x1f7), 0x58);
+    let second: Vec<u16> = (0..256).map(|_| ata.read_data()).collect();
+    assert_eq!(second[255], 158 * 0x101);
+    assert_eq!(ata.rb(0x1f7), 0x50);
+    assert_eq!(ata.rb(0x1f3), 6);
+    // WRITE MULTIPLE by LBA in one block of four sectors.
+    ata.wb(0x1f2, 4);
+    ata.wb(0x1f7, 0xc6);
+    assert_eq!(ata.rb(0x1f7), 0x50);
+    ata.wb(0x1f2, 4);
+    ata.wb(0x1f3, 0x34);
+    ata.wb(0x1f4, 0x12);
+    ata.wb(0x1f5, 0);
+    ata.wb(0x1f6, 0xe0);
+    ata.wb(0x1f7, 0xc5);
+    assert_eq!(ata.rb(0x3f6), 0x58);
+    for _ in 0..1024 {
+        ata.write_data(0xbeef);
+    }
+    assert_eq!(ata.rb(0x1f7), 0x50);
+    let image = ata.drives[0].image.as_ref().unwrap();
+    assert_eq!(image.read_sector(0x1237).unwrap()[511], 0xbe);
+    assert_eq!(image.read_sector(0x1238).unwrap()[0], 0x38);
+    // Beyond the end of the disk.
+    ata.wb(0x1f5, 0x10);
+    ata.wb(0x1f7, 0x20);
+    assert_eq!(ata.rb(0x1f7), 0x51);
+    assert_eq!(ata.rb(0x1f1), ERROR_IDNF);
+    // Unsupported commands and the absent slave.
+    ata.wb(0x1f7, 0xff);
+    assert_eq!(ata.rb(0x1f1), ERROR_ABRT);
+    ata.wb(0x1f6, 0xb0);
+    assert_eq!(ata.rb(0x1f7), 0);
+}
@@ -0,0 +1,323 @@
+#[cfg(test)]
+use crate::hardware::crtc::run_frames;
+use crate::hardware::crtc::Crtc6845;
+use crate::hardware::framebuffer::Framebuffer;
+use crate::hardware::textscreen::TextScreen;
+
+/// Character generator ROM of the IBM Color/Graphics Adapter.
+pub const CGA_FONT_PATH: &str = "roms/video/cga/cga.rom";
+
+pub const CGA_WIDTH: usize = 640;
+pub const CGA_HEIGHT: usize = 200;
+
+/// 14.318 MHz dot clock; a character is eight dots in 80 column text
+/// and sixteen otherwise.
+pub const CGA_DOT_CLOCK: u64 = 14_318_181;
+
+const MODE_HIRES_TEXT: u8 = 0x01;
+const MODE_GRAPHICS: u8 = 0x02;
+const MODE_BW: u8 = 0x04;
+const MODE_VIDEO_ENABLE: u8 = 0x08;
+const MODE_HIRES_GRAPHICS: u8 = 0x10;
+const MODE_BLINK: u8 = 0x20;
+
+/// The 16 RGBI colours as 0x00RRGGBB, with colour 6 darkened to brown
+/// as on the IBM 5153 monitor.
+pub const CGA_PALETTE: [u32; 16] = [
+    0x00_0000, 0x00_00aa, 0x00_aa00, 0x00_aaaa, 0xaa_0000, 0xaa_00aa, 0xaa_5500, 0xaa_aaaa,
+    0x55_5555, 0x55_55ff, 0x55_ff55, 0x55_ffff, 0xff_5555, 0xff_55ff, 0xff_ff55, 0xff_ffff,
+];
+
+/// IBM Color/Graphics Adapter: 16K of memory at B800:0, a 6845 CRTC at
+/// 0x3D4/0x3D5, mode control at 0x3D8, colour select at 0x3D9, status
+/// at 0x3DA and the light pen latch at 0x3DB/0x3DC, drawn into a 640x200
+/// RGB framebuffer. Modes with 40 columns or 320 pixels are doubled
+/// horizontally.
+#[derive(Debug, Clone)]
+pub struct CGA {
+    pub vram: Vec<u8>,
+    /// 8x8 glyphs, eight bytes each.
+    pub font: Vec<u8>,
+    pub crtc: Crtc6845,
+    pub mode: u8,
+    pub color: u8,
+    pub light_pen_latched: bool,
+    /// Dot clocks owed, scaled by the CPU clock.
+    pub phase: u64,
+    /// The last completed frame.
+    pub frame: Framebuffer,
+    /// The frame being drawn.
+    pub back: Framebuffer,
+}
+
+impl CGA {
+    pub fn new() -> Self {
+        let mut crtc = Crtc6845::new();
+        // The BIOS mode 3 parameters.
+        crtc.load(&[
+            0x71, 0x50, 0x5a, 0x0a, 0x1f, 0x06, 0x19, 0x1c, 0x02, 0x07, 0x06, 0x07,
+        ]);
+        Self {
+            vram: vec![0; 0x4000],
+            font: Vec::new(),
+            crtc,
+            mode: 0,
+            color: 0,
+            light_pen_latched: false,
+            phase: 0,
+            frame: Framebuffer::new(CGA_WIDTH, CGA_HEIGHT),
+            back: Framebuffer::new(CGA_WIDTH, CGA_HEIGHT),
+        }
+    }
+
+    /// Takes the normal-weight font out of a full 8K character ROM, or a
+    /// bare 2K font as is.
+    pub fn load_font(&mut self, rom: &[u8]) {
+        self.font = if rom.len() >= 0x2000 {
+            rom[0x1800..0x2000].to_vec()
+        } else {
+            rom.to_vec()
+        };
+    }
+
+    pub fn read_vram(&self, addr: u32) -> u8 {
+        self.vram[(addr & 0x3fff) as usize]
+    }
+
+    pub fn write_vram(&mut self, addr: u32, data: u8) {
+        self.vram[(addr & 0x3fff) as usize] = data;
+    }
+
+    fn char_width(&self) -> usize {
+        if (self.mode & MODE_HIRES_TEXT) != 0 {
+            8
+        } else {
+            16
+        }
+    }
+
+    /// Runs the adapter for `cycles` clocks of a CPU running at
+    /// `cpu_clock` Hz, one character clock at a time.
+    pub fn tick(&mut self, cycles: usize, cpu_clock: u64) {
+        self.phase += cycles as u64 * CGA_DOT_CLOCK;
+        loop {
+            let period = cpu_clock * self.char_width() as u64;
+            if self.phase < period {
+                break;
+            }
+            self.phase -= period;
+            if self.crtc.display_enable() {
+                self.draw_char();
+            }
+            if self.crtc.clock() {
+                std::mem::swap(&mut self.frame, &mut self.back);
+                self.back.fill(0);
+            }
+        }
+    }
+
+    /// The displayed text page, unless the card is in a graphics mode.
+    pub fn text_screen(&self) -> Option<TextScreen> {
+        if (self.mode & MODE_GRAPHICS) != 0 {
+            return None;
+        }
+        let columns = self.crtc.regs[1] as usize;
+        let start = self.crtc.start_address() as usize;
+        let mut screen = TextScreen::read(columns, self.crtc.regs[6] as usize, |row, column| {
+            let addr = (start + row * columns + column) * 2;
+            (self.vram[addr & 0x3fff], self.vram[(addr + 1) & 0x3fff])
+        });
+        if (self.crtc.regs[10] & 0x60) != 0x20 {
+            let cursor = self.crtc.cursor_address() as usize;
+            screen.place_cursor(cursor.wrapping_sub(start) & 0x3fff, columns);
+        }
+        Some(screen)
+    }
+
+    /// Colours 0-3 of the 320x200 mode.
+    fn graphics_palette(&self) -> [u32; 4] {
+        let intensity = (self.color & 0x10) >> 1;
+        let colors = if (self.mode & MODE_BW) != 0 {
+            [3, 4, 7]
+        } else if (self.color & 0x20) != 0 {
+            [3, 5, 7]
+        } else {
+            [2, 4, 6]
+        };
+        [
+            CGA_PALETTE[(self.color & 0x0f) as usize],
+            CGA_PALETTE[(colors[0] | intensity) as usize],
+            CGA_PALETTE[(colors[1] | intensity) as usize],
+            CGA_PALETTE[(colors[2] | intensity) as usize],
+        ]
+    }
+
+    /// Draws the character or graphics cell under the beam into the back
+    /// buffer.
+    fn draw_char(&mut self) {
+        let char_width = self.char_width();
+        let x = self.crtc.hcount as usize * char_width;
+        let y = self.crtc.line as usize;
+        if x + char_width > CGA_WIDTH || y >= CGA_HEIGHT {
+            return;
+        }
+        if (self.mode & MODE_VIDEO_ENABLE) == 0 {
+            return;
+        }
+        let addr = self.crtc.ma as usize * 2;
+        let line = self.crtc.ra as usize;
+        let palette = self.graphics_palette();
+        let pixels = &mut self.back.row_mut(y)[x..][..char_width];
+        if (self.mode & MODE_GRAPHICS) != 0 {
+            // Odd scanlines of each row come from the second 8K bank.
+            let offset = (addr & 0x1fff) | ((line & 1) << 13);
+            let data = u16::from_be_bytes([self.vram[offset], self.vram[(offset + 1) & 0x3fff]]);
+            if (self.mode & MODE_HIRES_GRAPHICS) != 0 {
+                let fg = CGA_PALETTE[(self.color & 0x0f) as usize];
+                for (x, pixel) in pixels.iter_mut().enumerate() {
+                    *pixel = if (data & (0x8000 >> x)) != 0 { fg } else { 0 };
+                }
+            } else {
+                for (x, pixel) in pixels.iter_mut().enumerate() {
+                    let shift = 14 - (x / 2) * 2;
+                    *pixel = palette[((data >> shift) & 3) as usize];
+                }
+            }
+            return;
+        }
+        let ch = self.vram[addr & 0x3fff];
+        let attr = self.vram[(addr + 1) & 0x3fff];
+        let mut fg = CGA_PALETTE[(attr & 0x0f) as usize];
+        let mut bg = CGA_PALETTE[((attr >> 4) & 0x07) as usize];
+        if (attr & 0x80) != 0 {
+            if (self.mode & MODE_BLINK) == 0 {
+                bg = CGA_PALETTE[(attr >> 4) as usize];
+            } else if (self.crtc.frame_count & 0x10) == 0 {
+                fg = bg;
+            }
+        }
+        let mut bits = self.font.get(ch as usize * 8 + line).copied().unwrap_or(0);
+        if self.crtc.cursor() {
+            bits = 0xff;
+            fg = CGA_PALETTE[(attr & 0x0f) as usize];
+        }
+        let dot_width = char_width / 8;
+        for (x, pixel) in pixels.iter_mut().enumerate() {
+            let lit = (bits & (0x80 >> (x / dot_width))) != 0;
+            *pixel = if lit { fg } else { bg };
+        }
+    }
+
+    /// Bit 0 is set outside the displayed area, bit 1 once the light pen
+    /// has triggered and bit 2 while its switch is open.
+    fn status(&self) -> u8 {
+        0xf4 | (!self.crtc.display_enable() as u8)
+            | ((self.light_pen_latched as u8) << 1)
+            | ((self.crtc.vsync as u8) << 3)
+    }
+
+    pub fn rb(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x3d0..=0x3d7 => self.crtc.rb(addr),
+            0x3da => self.status(),
+            _ => 0xff,
+        }
+    }
+
+    pub fn wb(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x3d0..=0x3d7 => self.crtc.wb(addr, data),
+            0x3d8 => self.mode = data,
+            0x3d9 => self.color = data,
+            0x3db => self.light_pen_latched = false,
+            0x3dc if !self.light_pen_latched => {
+                self.crtc.strobe_light_pen();
+                self.light_pen_latched = true;
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Default for CGA {
+    fn default() -> CGA {
+        CGA::new()
+    }
+}
+
+#[cfg(test)]
+fn cga_set_mode(cga: &mut CGA, crtc: &[u8], mode: u8) {
+    for (index, &value) in crtc.iter().enumerate() {
+        cga.wb(0x3d4, index as u8);
+        cga.wb(0x3d5, value);
+    }
+    cga.wb(0x3d8, mode);
+}
+
+#[test]
+fn test_cga_text_and_graphics() {
+    let pixel = |cga: &CGA, x: usize, y: usize| cga.frame.pixel(x, y);
+    let mut cga = CGA::new();
+    cga.font = vec![0x81; 0x800];
+    // Mode 3: 80x25 text.
+    let text80 = [
+        0x71, 0x50, 0x5a, 0x0a, 0x1f, 0x06, 0x19, 0x1c, 0x02, 0x07, 0x26, 0x07,
+    ];
+    cga_set_mode(&mut cga, &text80, 0x29);
+    cga.write_vram(0xb_8000, b'A');
+    cga.write_vram(0xb_8001, 0x1e);
+    let mut inactive = false;
+    let mut vsync = false;
+    while cga.crtc.frame_count == 0 {
+        cga.tick(50, 4_772_727);
+        let status = cga.rb(0x3da);
+        inactive |= (status & 0x01) != 0;
+        vsync |= (status & 0x08) != 0;
+    }
+    assert!(inactive && vsync);
+    assert_eq!(pixel(&cga, 0, 0), CGA_PALETTE[14]);
+    assert_eq!(pixel(&cga, 1, 0), CGA_PALETTE[1]);
+    assert_eq!(pixel(&cga, 7, 7), CGA_PALETTE[14]);
+    assert_eq!(pixel(&cga, 8, 0), CGA_PALETTE[0]);
+    // Mode 1: 40x25 text doubles each dot.
+    let text40 = [
+        0x38, 0x28, 0x2d, 0x0a, 0x1f, 0x06, 0x19, 0x1c, 0x02, 0x07, 0x26, 0x07,
+    ];
+    cga_set_mode(&mut cga, &text40, 0x28);
+    run_frames(&mut cga, |cga| cga.crtc.frame_count, CGA::tick);
+    assert_eq!(pixel(&cga, 1, 0), CGA_PALETTE[14]);
+    assert_eq!(pixel(&cga, 2, 0), CGA_PALETTE[1]);
+    assert_eq!(pixel(&cga, 15, 0), CGA_PALETTE[14]);
+    // Mode 4: 320x200 in four colours, odd lines in the second bank.
+    let graphics = [
+        0x38, 0x28, 0x2d, 0x0a, 0x7f, 0x06, 0x64, 0x70, 0x02, 0x01, 0x06, 0x07,
+    ];
+    cga_set_mode(&mut cga, &graphics, 0x0a);
+    cga.wb(0x3d9, 0x31);
+    cga.write_vram(0xb_8000, 0x1b);
+    cga.write_vram(0xb_a000, 0xc0);
+    run_frames(&mut cga, |cga| cga.crtc.frame_count, CGA::tick);
+    assert_eq!(pixel(&cga, 0, 0), CGA_PALETTE[1]);
+    assert_eq!(pixel(&cga, 2, 0), CGA_PALETTE[11]);
+    assert_eq!(pixel(&cga, 5, 0), CGA_PALETTE[13]);
+    assert_eq!(pixel(&cga, 7, 0), CGA_PALETTE[15]);
+    assert_eq!(pixel(&cga, 0, 1), CGA_PALETTE[15]);
+    assert_eq!(pixel(&cga, 2, 1), CGA_PALETTE[1]);
+    // Mode 6: 640x200 in two colours.
+    cga_set_mode(&mut cga, &graphics, 0x1a);
+    cga.wb(0x3d9, 0x0f);
+    run_frames(&mut cga, |cga| cga.crtc.frame_count, CGA::tick);
+    assert_eq!(pixel(&cga, 3, 0), CGA_PALETTE[15]);
+    assert_eq!(pixel(&cga, 2, 0), CGA_PALETTE[0]);
+    assert_eq!(pixel(&cga, 0, 1), CGA_PALETTE[15]);
+    assert_eq!(pixel(&cga, 2, 1), CGA_PALETTE[0]);
+    // The light pen latches the address under the beam once until cleared.
+    cga.wb(0x3dc, 0);
+    assert_eq!(cga.rb(0x3da) & 0x02, 0x02);
+    let latched = cga.crtc.regs[17];
+    cga.tick(100, 4_772_727);
+    cga.wb(0x3dc, 0);
+    assert_eq!(cga.crtc.regs[17], latched);
+    cga.wb(0x3db, 0);
+    assert_eq!(cga.rb(0x3da) & 0x02, 0);
+}
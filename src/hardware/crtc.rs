@@ -0,0 +1,311 @@
+/// Write masks of the 6845 registers; bits outside them do not exist.
+const REGISTER_MASKS: [u8; 18] = [
+    0xff, 0xff, 0xff, 0xff, 0x7f, 0x1f, 0x7f, 0x7f, 0x03, 0x1f, 0x7f, 0x1f, 0x3f, 0xff, 0x3f, 0xff,
+    0x3f, 0xff,
+];
+
+/// Motorola 6845 CRT controller, stepped one character clock at a time.
+/// The card owning it maps the index register at even and the data
+/// register at odd addresses, draws the character at `ma`/`ra` whenever
+/// `display_enable` is set and samples the sync outputs for its status
+/// port.
+#[derive(Debug, Clone)]
+pub struct Crtc6845 {
+    pub index: u8,
+    pub regs: [u8; 18],
+    /// Character within the scanline, 0..=R0.
+    pub hcount: u8,
+    /// Scanline within the character row, 0..=R9: the row address.
+    pub ra: u8,
+    /// Character row, 0..=R4.
+    pub vcount: u8,
+    /// Set while counting the R5 vertical adjust scanlines.
+    pub in_adjust: bool,
+    pub adjust_count: u8,
+    /// Scanline since the start of the frame.
+    pub line: u16,
+    /// Memory address of the current character.
+    pub ma: u16,
+    /// Memory address of the first character of the current row.
+    pub ma_row: u16,
+    pub hdisplay: bool,
+    pub vdisplay: bool,
+    pub hsync: bool,
+    pub vsync: bool,
+    hsync_count: u8,
+    vsync_count: u8,
+    pub frame_count: u64,
+}
+
+impl Crtc6845 {
+    pub fn new() -> Self {
+        Self {
+            index: 0,
+            regs: [0; 18],
+            hcount: 0,
+            ra: 0,
+            vcount: 0,
+            in_adjust: false,
+            adjust_count: 0,
+            line: 0,
+            ma: 0,
+            ma_row: 0,
+            hdisplay: true,
+            vdisplay: true,
+            hsync: false,
+            vsync: false,
+            hsync_count: 0,
+            vsync_count: 0,
+            frame_count: 0,
+        }
+    }
+
+    /// Loads registers R0 onwards, as the BIOS does on a mode set.
+    pub fn load(&mut self, values: &[u8]) {
+        for (index, &value) in values.iter().enumerate().take(16) {
+            self.regs[index] = value & REGISTER_MASKS[index];
+        }
+    }
+
+    pub fn display_enable(&self) -> bool {
+        self.hdisplay && self.vdisplay
+    }
+
+    pub fn start_address(&self) -> u16 {
+        ((self.regs[12] as u16) << 8) | self.regs[13] as u16
+    }
+
+    pub fn cursor_address(&self) -> u16 {
+        ((self.regs[14] as u16) << 8) | self.regs[15] as u16
+    }
+
+    /// Scanlines per character row, R9+1.
+    pub fn row_height(&self) -> u8 {
+        self.regs[9] + 1
+    }
+
+    /// Whether the cursor covers the current character and scanline. R10
+    /// bits 5-6 select a steady, hidden, or 16 or 32 frame blinking
+    /// cursor; a start line past the end line wraps around the row.
+    pub fn cursor(&self) -> bool {
+        if self.ma != self.cursor_address() {
+            return false;
+        }
+        let visible = match self.regs[10] & 0x60 {
+            0x00 => true,
+            0x20 => false,
+            0x40 => (self.frame_count & 0x08) != 0,
+            _ => (self.frame_count & 0x10) != 0,
+        };
+        let start = self.regs[10] & 0x1f;
+        let end = self.regs[11];
+        let in_range = if start <= end {
+            self.ra >= start && self.ra <= end
+        } else {
+            self.ra >= start || self.ra <= end
+        };
+        visible && in_range
+    }
+
+    /// Latches the current address into R16/R17, as a light pen strobe
+    /// does.
+    pub fn strobe_light_pen(&mut self) {
+        self.regs[16] = ((self.ma >> 8) & 0x3f) as u8;
+        self.regs[17] = self.ma as u8;
+    }
+
+    /// Advances one character clock. Returns true when a new frame
+    /// starts.
+    pub fn clock(&mut self) -> bool {
+        if self.hsync {
+            self.hsync_count = self.hsync_count.wrapping_add(1);
+            // A zero width gives sixteen characters.
+            if self.hsync_count == (self.regs[3].wrapping_sub(1) & 0x0f) + 1 {
+                self.hsync = false;
+            }
+        }
+        let mut new_frame = false;
+        if self.hcount == self.regs[0] {
+            self.hcount = 0;
+            new_frame = self.end_of_line();
+        } else {
+            self.hcount = self.hcount.wrapping_add(1);
+        }
+        if self.hcount == self.regs[1] {
+            self.hdisplay = false;
+        }
+        if self.hcount == 0 {
+            self.hdisplay = true;
+        }
+        if self.hcount == self.regs[2] && !self.hsync {
+            self.hsync = true;
+            self.hsync_count = 0;
+        }
+        self.ma = self.ma_row.wrapping_add(self.hcount as u16) & 0x3fff;
+        new_frame
+    }
+
+    fn end_of_line(&mut self) -> bool {
+        self.line += 1;
+        // Vertical sync is a fixed sixteen scanlines on the 6845.
+        if self.vsync {
+            self.vsync_count += 1;
+            if self.vsync_count == 16 {
+                self.vsync = false;
+            }
+        }
+        if self.in_adjust {
+            self.adjust_count += 1;
+            if self.adjust_count >= self.regs[5] {
+                self.new_frame();
+                return true;
+            }
+            return false;
+        }
+        if self.ra == self.regs[9] {
+            self.ra = 0;
+            if self.vcount == self.regs[4] {
+                if self.regs[5] == 0 {
+                    self.new_frame();
+                    return true;
+                }
+                self.in_adjust = true;
+                self.adjust_count = 0;
+                return false;
+            }
+            self.vcount = (self.vcount + 1) & 0x7f;
+            self.ma_row = self.ma_row.wrapping_add(self.regs[1] as u16);
+            if self.vcount == self.regs[6] {
+                self.vdisplay = false;
+            }
+            if self.vcount == self.regs[7] && !self.vsync {
+                self.vsync = true;
+                self.vsync_count = 0;
+            }
+        } else {
+            self.ra = (self.ra + 1) & 0x1f;
+        }
+        false
+    }
+
+    fn new_frame(&mut self) {
+        self.vcount = 0;
+        self.ra = 0;
+        self.in_adjust = false;
+        self.line = 0;
+        self.ma_row = self.start_address();
+        self.vdisplay = self.regs[6] != 0;
+        if self.regs[7] == 0 && !self.vsync {
+            self.vsync = true;
+            self.vsync_count = 0;
+        }
+        self.frame_count += 1;
+    }
+
+    pub fn rb(&mut self, addr: u16) -> u8 {
+        if (addr & 1) == 0 {
+            return 0xff;
+        }
+        // Only the cursor and light pen registers read back.
+        match self.index {
+            14..=17 => self.regs[self.index as usize],
+            _ => 0,
+        }
+    }
+
+    pub fn wb(&mut self, addr: u16, data: u8) {
+        if (addr & 1) == 0 {
+            self.index = data & 0x1f;
+        } else if self.index < 16 {
+            self.regs[self.index as usize] = data & REGISTER_MASKS[self.index as usize];
+        }
+    }
+}
+
+impl Default for Crtc6845 {
+    fn default() -> Crtc6845 {
+        Crtc6845::new()
+    }
+}
+
+/// Ticks an adapter through two frames of its CRTC, so the latest is drawn
+/// entirely with the current settings.
+#[cfg(test)]
+pub fn run_frames<T>(card: &mut T, frame_count: fn(&T) -> u64, tick: fn(&mut T, usize, u64)) {
+    let frame = frame_count(card);
+    while frame_count(card) < frame + 2 {
+        tick(card, 1000, 4_772_727);
+    }
+}
+
+#[test]
+fn test_crtc_counters_and_outputs() {
+    let mut crtc = Crtc6845::new();
+    // The CGA's 80x25 text timings: 114 characters by 262 scanlines.
+    crtc.load(&[
+        0x71, 0x50, 0x5a, 0x0a, 0x1f, 0x06, 0x19, 0x1c, 0x02, 0x07, 0x06, 0x07, 0x00, 0x10, 0x00,
+        0x15,
+    ]);
+    // Run to the start of a frame so the start address is latched.
+    while !crtc.clock() {}
+    let (mut clocks, mut displayed, mut hsyncs, mut vsync_clocks) = (0, 0, 0, 0);
+    let mut hsync = crtc.hsync;
+    let mut cursor = None;
+    loop {
+        if crtc.display_enable() {
+            displayed += 1;
+            if crtc.cursor() {
+                cursor = cursor.or(Some((crtc.ma, crtc.ra)));
+            }
+        }
+        vsync_clocks += crtc.vsync as u32;
+        if crtc.hsync && !hsync {
+            hsyncs += 1;
+        }
+        hsync = crtc.hsync;
+        clocks += 1;
+        if crtc.clock() {
+            break;
+        }
+    }
+    assert_eq!(clocks, 114 * 262);
+    assert_eq!(displayed, 80 * 200);
+    assert_eq!(hsyncs, 262);
+    assert_eq!(vsync_clocks, 114 * 16);
+    // The cursor is at 0x15 from the start address 0x10, on lines 6-7.
+    assert_eq!(cursor, Some((0x15, 6)));
+    // Light pen strobes latch the address and read back through R16/R17.
+    while crtc.ma != 0x10 + 80 * 2 + 3 {
+        crtc.clock();
+    }
+    crtc.strobe_light_pen();
+    crtc.wb(0x3d4, 16);
+    assert_eq!(crtc.rb(0x3d5), 0x00);
+    crtc.wb(0x3d4, 17);
+    assert_eq!(crtc.rb(0x3d5), 0xb3);
+    crtc.wb(0x3d4, 1);
+    assert_eq!(crtc.rb(0x3d5), 0);
+}
+
+#[test]
+fn test_crtc_total_lowered_mid_line() {
+    let mut crtc = Crtc6845::new();
+    crtc.load(&[
+        0x71, 0x50, 0x5a, 0x0a, 0x1f, 0x06, 0x19, 0x1c, 0x02, 0x07, 0x06, 0x07, 0x00, 0x10, 0x00,
+        0x15,
+    ]);
+    while crtc.hcount != 0x60 {
+        crtc.clock();
+    }
+    // Going to 40 columns past the new total: the 8-bit counter wraps and
+    // the line ends when it comes round to R0 again.
+    let line = crtc.line;
+    crtc.wb(0x3d4, 0);
+    crtc.wb(0x3d5, 0x38);
+    for _ in 0..0x100 - 0x60 + 0x38 {
+        crtc.clock();
+    }
+    assert_eq!((crtc.hcount, crtc.line), (0x38, line));
+    crtc.clock();
+    assert_eq!((crtc.hcount, crtc.line), (0, line + 1));
+}
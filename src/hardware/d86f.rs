@@ -0,0 +1,190 @@
+use crate::hardware::floppy::*;
+use crate::hardware::mfm::MfmTrack;
+use std::io;
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("86F: {}", msg))
+}
+
+const VERSION: u16 = 0x020c;
+
+/// Disk flags: surface description present, two sides, write protected
+/// and per-track extra bit cell counts.
+const DISK_SURFACE: u16 = 0x0001;
+const DISK_SIDES: u16 = 0x0008;
+const DISK_WRITE_PROTECT: u16 = 0x0010;
+const DISK_EXTRA_CELLS: u16 = 0x0080;
+
+/// Track flag bits 3-4 select the encoding.
+const TRACK_ENCODING_MASK: u16 = 0x0018;
+const TRACK_MFM: u16 = 0x0008;
+
+/// Data rates in kbit/s indexed by track flag bits 0-2.
+const DATA_RATES: [u16; 4] = [500, 300, 250, 1000];
+
+/// Reads cells stored as little-endian 16-bit words, first cell in bit 15.
+fn read_words(data: &[u8], len: usize) -> Vec<u8> {
+    let mut cells: Vec<u8> = data.chunks(2).flat_map(|word| [word[1], word[0]]).collect();
+    cells.truncate(len.div_ceil(8));
+    cells
+}
+
+fn write_words(data: &mut Vec<u8>, cells: &[u8]) {
+    let mut cells = cells.to_vec();
+    cells.resize(cells.len().div_ceil(2) * 2, 0);
+    for word in cells.chunks(2) {
+        data.extend_from_slice(&[word[1], word[0]]);
+    }
+}
+
+impl FloppyImage {
+    /// Parses an 86Box 86F image: a header with disk flags, a table of
+    /// track offsets, and per track its flags, bit cell count adjustment,
+    /// index hole position, bit cells and optional surface description,
+    /// whose set bits mark weak cells.
+    pub fn from_86f(data: &[u8]) -> io::Result<FloppyImage> {
+        if data.len() < 8 || !data.starts_with(b"86BF") {
+            return Err(invalid("bad signature"));
+        }
+        if data[5] != 2 {
+            return Err(invalid("only version 2 images are supported"));
+        }
+        let flags = u16::from_le_bytes([data[6], data[7]]);
+        let sides = if (flags & DISK_SIDES) != 0 { 2 } else { 1 };
+        let slice = |offset: usize, len: usize| {
+            data.get(offset..offset + len)
+                .ok_or_else(|| invalid("unexpected end of file"))
+        };
+        let dword = |offset: usize| -> io::Result<u32> {
+            let bytes = slice(offset, 4)?;
+            Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        };
+        let mut tracks = Vec::new();
+        for entry in 0..256 * sides {
+            let mut offset = dword(8 + entry * 4)? as usize;
+            if offset == 0 {
+                continue;
+            }
+            let track_flags = u16::from_le_bytes(slice(offset, 2)?.try_into().unwrap());
+            offset += 2;
+            if (track_flags & TRACK_ENCODING_MASK) != TRACK_MFM {
+                return Err(invalid("only MFM tracks are supported"));
+            }
+            let data_rate = *DATA_RATES
+                .get((track_flags & 7) as usize)
+                .ok_or_else(|| invalid("bad data rate"))?;
+            let mut len = MfmTrack::nominal_len(data_rate);
+            if (flags & DISK_EXTRA_CELLS) != 0 {
+                len = len.saturating_add_signed(dword(offset)? as i32 as isize);
+                offset += 4;
+            }
+            let index_pos = dword(offset)? as usize;
+            offset += 4;
+            let bytes = len.div_ceil(16) * 2;
+            let mut track = MfmTrack::new(len, data_rate);
+            track.cells = read_words(slice(offset, bytes)?, len);
+            if (flags & DISK_SURFACE) != 0 {
+                track.weak = read_words(slice(offset + bytes, bytes)?, len);
+            }
+            if index_pos != 0 && len != 0 {
+                // Start the revolution at the index hole.
+                let mut rotated = MfmTrack::new(len, data_rate);
+                for pos in 0..len {
+                    rotated.set_cell(pos, track.cell(pos + index_pos));
+                    rotated.set_weak(pos, track.is_weak(pos + index_pos));
+                }
+                track = rotated;
+            }
+            tracks.push(((entry / sides) as u8, (entry % sides) as u8, track));
+        }
+        let mut image = FloppyImage::from_flux(tracks, FloppyFormat::D86f)?;
+        image.write_protected = (flags & DISK_WRITE_PROTECT) != 0;
+        Ok(image)
+    }
+
+    /// Writes the image as an 86F, keeping weak cells in a surface
+    /// description and each track's exact length as extra bit cells.
+    pub fn to_86f(&self) -> Vec<u8> {
+        let geometry = self.geometry;
+        let sides = geometry.heads.clamp(1, 2) as usize;
+        let tracks: Vec<MfmTrack> = (0..geometry.cylinders as usize * sides)
+            .map(|entry| self.flux_track((entry / sides) as u8, (entry % sides) as u8))
+            .collect();
+        let surface = tracks
+            .iter()
+            .any(|track| track.weak.iter().any(|&b| b != 0));
+        let mut flags = DISK_EXTRA_CELLS;
+        if surface {
+            flags |= DISK_SURFACE;
+        }
+        if sides == 2 {
+            flags |= DISK_SIDES;
+        }
+        if self.write_protected {
+            flags |= DISK_WRITE_PROTECT;
+        }
+        // Hole: DD, HD or ED from the fastest data rate.
+        flags |= match tracks.iter().map(|track| track.data_rate).max() {
+            Some(1000) => 2 << 1,
+            Some(500) => 1 << 1,
+            _ => 0,
+        };
+        let mut data = b"86BF".to_vec();
+        data.extend_from_slice(&VERSION.to_le_bytes());
+        data.extend_from_slice(&flags.to_le_bytes());
+        data.resize(8 + 256 * sides * 4, 0);
+        for (entry, track) in tracks.iter().enumerate() {
+            let offset = data.len() as u32;
+            data[8 + entry * 4..12 + entry * 4].copy_from_slice(&offset.to_le_bytes());
+            let rate = DATA_RATES
+                .iter()
+                .position(|&rate| rate == track.data_rate)
+                .unwrap_or(2) as u16;
+            data.extend_from_slice(&(rate | TRACK_MFM).to_le_bytes());
+            let data_rate = DATA_RATES[rate as usize];
+            let extra = track.len as i32 - MfmTrack::nominal_len(data_rate) as i32;
+            data.extend_from_slice(&extra.to_le_bytes());
+            data.extend_from_slice(&0u32.to_le_bytes());
+            write_words(&mut data, &track.cells);
+            if surface {
+                let mut weak = track.weak.clone();
+                weak.resize(track.cells.len(), 0);
+                write_words(&mut data, &weak);
+            }
+        }
+        data
+    }
+}
+
+#[test]
+fn test_86f_round_trip_keeps_weak_cells_and_track_length() {
+    let sectors: Vec<Sector> = (1..=11)
+        .map(|r| Sector {
+            id: SectorId::new(0, 0, r, 2),
+            data: vec![r; 512],
+            deleted: false,
+            crc_error: r == 2,
+        })
+        .collect();
+    // A long track with eleven sectors, one with a weak data field.
+    let mut track = MfmTrack::from_sectors(&sectors, 250);
+    assert!(track.len > MfmTrack::nominal_len(250));
+    let data_pos = track.sectors(false)[6].data_pos.unwrap();
+    for pos in data_pos + 64..data_pos + 64 + 32 {
+        track.set_weak(pos, true);
+    }
+    let image = FloppyImage::from_flux(vec![(0, 0, track.clone())], FloppyFormat::D86f).unwrap();
+    let mut image = FloppyImage::from_86f(&image.to_86f()).unwrap();
+    assert_eq!(image.flux[0], track);
+    assert_eq!(image.track(0, 0).len(), 11);
+    assert!(image.track(0, 0)[1].crc_error);
+    let first = image.read_sector_at(0, 0, 6).unwrap();
+    assert!(first.crc_error);
+    let differs = (0..8).any(|_| image.read_sector_at(0, 0, 6).unwrap().data != first.data);
+    assert!(differs);
+    // Writing the sector lays down clean cells again.
+    image.write_sector(0, 0, 7, &[0x77; 512]).unwrap();
+    let sector = image.read_sector_at(0, 0, 6).unwrap();
+    assert_eq!(sector.data, vec![0x77; 512]);
+    assert!(!sector.crc_error);
+}
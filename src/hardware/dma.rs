@@ -0,0 +1,449 @@
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DmaTransferType {
+    Verify = 0,
+    /// Device to memory.
+    Write = 1,
+    /// Memory to device.
+    Read = 2,
+    Illegal = 3,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DmaChannel {
+    pub base_address: u16,
+    pub base_count: u16,
+    pub address: u16,
+    pub count: u16,
+    pub mode: u8,
+    pub page: u8,
+    pub masked: bool,
+    /// Software request bit set through the request register.
+    pub request: bool,
+}
+
+/// Page register port for each of DMA channels 0-7.
+pub const DMA_PAGE_PORTS: [u16; 8] = [0x87, 0x83, 0x81, 0x82, 0x8f, 0x8b, 0x89, 0x8a];
+
+#[derive(Debug, Clone, Copy)]
+pub struct DMA {
+    pub channels: [DmaChannel; 4],
+    pub command: u8,
+    /// Terminal count bits, cleared when the status register is read.
+    pub terminal_count: u8,
+    pub temp: u8,
+    pub flip_flop: bool,
+    /// Set for the AT's second controller, which moves words and whose
+    /// address registers count words.
+    pub word_mode: bool,
+}
+
+/// Device side of a DMA controller, used by peripherals to move data.
+/// Devices hand over a whole buffer at a time, so demand, single and block
+/// mode channels all move it in one go.
+pub trait DmaTransfer {
+    /// True if `channel` will service a request from its device.
+    fn is_ready(&self, channel: usize) -> bool;
+    /// Moves `data` from a device into `ram` through `channel`, stopping at
+    /// terminal count or when the channel is masked. Returns the number of
+    /// bytes consumed and whether terminal count was reached.
+    fn transfer_to_memory(&mut self, channel: usize, ram: &mut [u8], data: &[u8]) -> (usize, bool);
+    /// Moves up to `len` bytes from `ram` to a device through `channel`,
+    /// stopping at terminal count.
+    fn transfer_from_memory(&mut self, channel: usize, ram: &[u8], len: usize) -> (Vec<u8>, bool);
+}
+
+impl DmaChannel {
+    pub fn new() -> Self {
+        Self {
+            base_address: 0,
+            base_count: 0,
+            address: 0,
+            count: 0,
+            mode: 0,
+            page: 0,
+            masked: true,
+            request: false,
+        }
+    }
+
+    pub fn transfer_type(&self) -> DmaTransferType {
+        match (self.mode >> 2) & 3 {
+            0 => DmaTransferType::Verify,
+            1 => DmaTransferType::Write,
+            2 => DmaTransferType::Read,
+            _ => DmaTransferType::Illegal,
+        }
+    }
+
+    pub fn auto_init(&self) -> bool {
+        (self.mode & 0x10) != 0
+    }
+
+    pub fn decrement(&self) -> bool {
+        (self.mode & 0x20) != 0
+    }
+}
+
+impl Default for DmaChannel {
+    fn default() -> DmaChannel {
+        DmaChannel::new()
+    }
+}
+
+impl DMA {
+    pub fn new(word_mode: bool) -> Self {
+        Self {
+            channels: [DmaChannel::new(); 4],
+            command: 0,
+            terminal_count: 0,
+            temp: 0,
+            flip_flop: false,
+            word_mode,
+        }
+    }
+
+    fn master_clear(&mut self) {
+        self.command = 0;
+        self.terminal_count = 0;
+        self.temp = 0;
+        self.flip_flop = false;
+        for channel in self.channels.iter_mut() {
+            channel.masked = true;
+            channel.request = false;
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        (self.command & 0x04) == 0
+    }
+
+    /// Bytes moved per transfer cycle.
+    pub fn unit_size(&self) -> usize {
+        if self.word_mode {
+            2
+        } else {
+            1
+        }
+    }
+
+    /// Physical address of the next transfer on `channel`.
+    pub fn physical_address(&self, channel: usize) -> u32 {
+        let channel = &self.channels[channel];
+        if self.word_mode {
+            (((channel.page & 0xfe) as u32) << 16) | ((channel.address as u32) << 1)
+        } else {
+            ((channel.page as u32) << 16) | channel.address as u32
+        }
+    }
+
+    /// Advances `channel` by one transfer, returning true at terminal count.
+    fn advance(&mut self, channel: usize) -> bool {
+        let ch = &mut self.channels[channel];
+        ch.address = if ch.decrement() {
+            ch.address.wrapping_sub(1)
+        } else {
+            ch.address.wrapping_add(1)
+        };
+        ch.count = ch.count.wrapping_sub(1);
+        if ch.count != 0xffff {
+            return false;
+        }
+        self.terminal_count |= 1 << channel;
+        ch.request = false;
+        if ch.auto_init() {
+            ch.address = ch.base_address;
+            ch.count = ch.base_count;
+        } else {
+            ch.masked = true;
+        }
+        true
+    }
+
+    fn status(&self) -> u8 {
+        let mut requests = 0;
+        for (i, channel) in self.channels.iter().enumerate() {
+            if channel.request {
+                requests |= 0x10 << i;
+            }
+        }
+        requests | self.terminal_count
+    }
+
+    fn mask(&self) -> u8 {
+        let mut mask = 0xf0;
+        for (i, channel) in self.channels.iter().enumerate() {
+            if channel.masked {
+                mask |= 1 << i;
+            }
+        }
+        mask
+    }
+
+    /// Reads register `addr & 0xf`.
+    pub fn rb(&mut self, addr: u16) -> u8 {
+        match addr & 0xf {
+            reg @ 0..=7 => {
+                let channel = &self.channels[(reg >> 1) as usize];
+                let value = if (reg & 1) == 0 {
+                    channel.address
+                } else {
+                    channel.count
+                };
+                self.flip_flop = !self.flip_flop;
+                if self.flip_flop {
+                    value as u8
+                } else {
+                    (value >> 8) as u8
+                }
+            }
+            8 => {
+                let status = self.status();
+                self.terminal_count = 0;
+                status
+            }
+            0xd => self.temp,
+            0xf => self.mask(),
+            _ => 0xff,
+        }
+    }
+
+    /// Writes register `addr & 0xf`.
+    pub fn wb(&mut self, addr: u16, data: u8) {
+        match addr & 0xf {
+            reg @ 0..=7 => {
+                let low = !self.flip_flop;
+                self.flip_flop = !self.flip_flop;
+                let channel = &mut self.channels[(reg >> 1) as usize];
+                let (base, current) = if (reg & 1) == 0 {
+                    (&mut channel.base_address, &mut channel.address)
+                } else {
+                    (&mut channel.base_count, &mut channel.count)
+                };
+                *base = if low {
+                    (*base & 0xff00) | data as u16
+                } else {
+                    (*base & 0x00ff) | ((data as u16) << 8)
+                };
+                *current = *base;
+            }
+            8 => self.command = data,
+            9 => self.channels[(data & 3) as usize].request = (data & 4) != 0,
+            0xa => self.channels[(data & 3) as usize].masked = (data & 4) != 0,
+            0xb => self.channels[(data & 3) as usize].mode = data & 0xfc,
+            0xc => self.flip_flop = false,
+            0xd => self.master_clear(),
+            0xe => {
+                for channel in self.channels.iter_mut() {
+                    channel.masked = false;
+                }
+            }
+            _ => {
+                for (i, channel) in self.channels.iter_mut().enumerate() {
+                    channel.masked = (data & (1 << i)) != 0;
+                }
+            }
+        }
+    }
+}
+
+impl DmaTransfer for DMA {
+    fn is_ready(&self, channel: usize) -> bool {
+        self.enabled() && !self.channels[channel].masked
+    }
+
+    fn transfer_to_memory(&mut self, channel: usize, ram: &mut [u8], data: &[u8]) -> (usize, bool) {
+        let mut done = 0;
+        while done < data.len() && self.is_ready(channel) {
+            let addr = self.physical_address(channel) as usize;
+            let unit = self.unit_size().min(data.len() - done);
+            if self.channels[channel].transfer_type() == DmaTransferType::Write {
+                for i in 0..unit {
+                    if let Some(byte) = ram.get_mut(addr + i) {
+                        *byte = data[done + i];
+                    }
+                }
+            }
+            done += unit;
+            if self.advance(channel) {
+                return (done, true);
+            }
+        }
+        (done, false)
+    }
+
+    fn transfer_from_memory(&mut self, channel: usize, ram: &[u8], len: usize) -> (Vec<u8>, bool) {
+        let mut data = Vec::new();
+        while data.len() < len && self.is_ready(channel) {
+            let addr = self.physical_address(channel) as usize;
+            let unit = self.unit_size().min(len - data.len());
+            let read = self.channels[channel].transfer_type() == DmaTransferType::Read;
+            for i in 0..unit {
+                data.push(if read {
+                    ram.get(addr + i).copied().unwrap_or(0xff)
+                } else {
+                    0xff
+                });
+            }
+            if self.advance(channel) {
+                return (data, true);
+            }
+        }
+        (data, false)
+    }
+}
+
+impl Default for DMA {
+    fn default() -> DMA {
+        DMA::new(false)
+    }
+}
+
+/// The AT's two 8237s: channels 0-3 on the 8-bit controller at 0x00-0x0F,
+/// cascaded into channel 4 of the 16-bit controller at 0xC0-0xDF.
+#[derive(Debug, Clone, Copy)]
+pub struct DmaPair {
+    pub dma8: DMA,
+    pub dma16: DMA,
+    /// Page registers at 0x80-0x8F, including the unused scratch ones.
+    pub pages: [u8; 16],
+}
+
+impl DmaPair {
+    pub fn new() -> Self {
+        Self {
+            dma8: DMA::new(false),
+            dma16: DMA::new(true),
+            pages: [0; 16],
+        }
+    }
+
+    fn controller(&mut self, channel: usize) -> &mut DMA {
+        if channel < 4 {
+            &mut self.dma8
+        } else {
+            &mut self.dma16
+        }
+    }
+
+    pub fn rb(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x00..=0x1f => self.dma8.rb(addr),
+            0xc0..=0xdf => self.dma16.rb(addr >> 1),
+            _ => self.pages[(addr & 0xf) as usize],
+        }
+    }
+
+    pub fn wb(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x00..=0x1f => self.dma8.wb(addr, data),
+            0xc0..=0xdf => self.dma16.wb(addr >> 1, data),
+            _ => {
+                self.pages[(addr & 0xf) as usize] = data;
+                if let Some(channel) = DMA_PAGE_PORTS
+                    .iter()
+                    .position(|&port| port == (addr | 0x80))
+                {
+                    self.controller(channel).channels[channel & 3].page = data;
+                }
+            }
+        }
+    }
+}
+
+impl DmaTransfer for DmaPair {
+    fn is_ready(&self, channel: usize) -> bool {
+        if channel < 4 {
+            self.dma8.is_ready(channel) && self.dma16.is_ready(0)
+        } else {
+            self.dma16.is_ready(channel & 3)
+        }
+    }
+
+    fn transfer_to_memory(&mut self, channel: usize, ram: &mut [u8], data: &[u8]) -> (usize, bool) {
+        if !self.is_ready(channel) {
+            return (0, false);
+        }
+        self.controller(channel)
+            .transfer_to_memory(channel & 3, ram, data)
+    }
+
+    fn transfer_from_memory(&mut self, channel: usize, ram: &[u8], len: usize) -> (Vec<u8>, bool) {
+        if !self.is_ready(channel) {
+            return (Vec::new(), false);
+        }
+        self.controller(channel)
+            .transfer_from_memory(channel & 3, ram, len)
+    }
+}
+
+impl Default for DmaPair {
+    fn default() -> DmaPair {
+        DmaPair::new()
+    }
+}
+
+#[cfg(test)]
+fn program_channel(dma: &mut DMA, channel: u8, mode: u8, address: u16, count: u16) {
+    dma.wb(0x0a, 0x04 | channel);
+    dma.wb(0x0c, 0);
+    dma.wb(0x0b, mode | channel);
+    dma.wb((channel as u16) << 1, address as u8);
+    dma.wb((channel as u16) << 1, (address >> 8) as u8);
+    dma.wb(((channel as u16) << 1) + 1, count as u8);
+    dma.wb(((channel as u16) << 1) + 1, (count >> 8) as u8);
+    dma.wb(0x0a, channel);
+}
+
+#[test]
+fn test_dma_registers_and_flip_flop() {
+    let mut dma = DMA::new(false);
+    program_channel(&mut dma, 2, 0x44, 0x1234, 0x01ff);
+    dma.wb(0x0c, 0);
+    assert_eq!(dma.rb(0x04), 0x34);
+    assert_eq!(dma.rb(0x04), 0x12);
+    assert_eq!(dma.rb(0x05), 0xff);
+    assert_eq!(dma.rb(0x05), 0x01);
+    assert_eq!(dma.rb(0x0f), 0xfb);
+    dma.wb(0x0d, 0);
+    assert_eq!(dma.rb(0x0f), 0xff);
+}
+
+#[test]
+fn test_dma_block_transfer_and_terminal_count() {
+    let mut dma = DMA::new(false);
+    let mut ram = vec![0u8; 0x20000];
+    program_channel(&mut dma, 2, 0x44, 0x0100, 3);
+    dma.channels[2].page = 1;
+    let data = [1, 2, 3, 4, 5, 6];
+    assert_eq!(dma.transfer_to_memory(2, &mut ram, &data), (4, true));
+    assert_eq!(&ram[0x10100..0x10105], &[1, 2, 3, 4, 0]);
+    assert_eq!(dma.rb(0x08) & 0x0f, 0x04);
+    assert!(!dma.is_ready(2));
+    // Auto-initialise reloads the base registers instead of masking.
+    program_channel(&mut dma, 1, 0x58, 0x0000, 1);
+    assert_eq!(dma.transfer_from_memory(1, &ram, 2), (vec![0, 0], true));
+    assert!(dma.is_ready(1));
+    assert_eq!(dma.channels[1].count, 1);
+}
+
+#[test]
+fn test_dma_pair_word_transfer() {
+    let mut dmas = DmaPair::new();
+    let mut ram = vec![0u8; 0x40000];
+    dmas.wb(0xd6, 0xc0);
+    dmas.wb(0xd4, 0x00);
+    dmas.wb(0xd6, 0x45);
+    dmas.wb(0xd8, 0);
+    dmas.wb(0xc4, 0x80);
+    dmas.wb(0xc4, 0x00);
+    dmas.wb(0xc6, 0x01);
+    dmas.wb(0xc6, 0x00);
+    dmas.wb(0x8b, 0x02);
+    dmas.wb(0xd4, 0x01);
+    assert_eq!(
+        dmas.transfer_to_memory(5, &mut ram, &[0xaa, 0xbb, 0xcc, 0xdd]),
+        (4, true)
+    );
+    assert_eq!(&ram[0x20100..0x20104], &[0xaa, 0xbb, 0xcc, 0xdd]);
+    assert_eq!(dmas.rb(0x8b), 0x02);
+}
@@ -0,0 +1,695 @@
+use crate::hardware::cga::CGA_PALETTE;
+use crate::hardware::framebuffer::Framebuffer;
+use crate::hardware::textscreen::TextScreen;
+
+/// BIOS option ROM of the IBM Enhanced Graphics Adapter, mapped at
+/// C000:0.
+pub const EGA_ROM_PATH: &str = "roms/video/ega/ibm_6277356_ega_card_u44_27128.bin";
+
+/// Largest frame the CRTC may describe; anything bigger is clipped.
+const MAX_WIDTH: usize = 1024;
+const MAX_HEIGHT: usize = 768;
+
+const EGA_DOT_CLOCKS: [u64; 2] = [14_318_181, 16_257_000];
+
+/// Switch settings for an Enhanced Color Display in high resolution mode.
+pub const EGA_SWITCHES_ECD: u8 = 0x09;
+
+/// Converts a 6-bit rgbRGB colour to 0x00RRGGBB.
+pub fn ega_color(color: u8) -> u32 {
+    let level = |high: u8, low: u8| {
+        (((color >> high) & 1) as u32 * 0x55) + (((color >> low) & 1) as u32 * 0xaa)
+    };
+    (level(5, 2) << 16) | (level(4, 1) << 8) | level(3, 0)
+}
+
+/// IBM Enhanced Graphics Adapter with 256K of memory in four 64K planes.
+/// The CPU reaches the planes through the sequencer's map mask and the
+/// graphics controller's latches, read modes and write modes; the CRTC
+/// scans them out through the attribute controller's palette. Frames are
+/// drawn whole each time the CRTC wraps, at the size it is programmed
+/// for, with dots doubled when the sequencer halves the dot clock.
+#[derive(Debug, Clone)]
+pub struct EGA {
+    pub planes: [Vec<u8>; 4],
+    pub latch: [u8; 4],
+    pub rom: Vec<u8>,
+    pub misc_output: u8,
+    /// Configuration switches read back through input status 0.
+    pub switches: u8,
+    pub seq_index: u8,
+    pub seq: [u8; 5],
+    pub gc_index: u8,
+    pub gc: [u8; 9],
+    pub crtc_index: u8,
+    pub crtc: [u8; 25],
+    /// Set when the next write to 0x3C0 is data rather than an index.
+    pub attr_flip_flop: bool,
+    pub attr_index: u8,
+    pub attr: [u8; 21],
+    /// Character clocks since the start of the current frame.
+    pub position: u64,
+    /// Dot clocks owed, scaled by the CPU clock.
+    pub phase: u64,
+    pub frame_count: u64,
+    /// The last completed frame.
+    pub frame: Framebuffer,
+}
+
+impl EGA {
+    pub fn new() -> Self {
+        Self {
+            planes: [
+                vec![0; 0x10000],
+                vec![0; 0x10000],
+                vec![0; 0x10000],
+                vec![0; 0x10000],
+            ],
+            latch: [0; 4],
+            rom: Vec::new(),
+            misc_output: 0,
+            switches: EGA_SWITCHES_ECD,
+            seq_index: 0,
+            seq: [0; 5],
+            gc_index: 0,
+            gc: [0, 0, 0, 0, 0, 0, 0, 0x0f, 0xff],
+            crtc_index: 0,
+            crtc: [0; 25],
+            attr_flip_flop: false,
+            attr_index: 0,
+            attr: [0; 21],
+            position: 0,
+            phase: 0,
+            frame_count: 0,
+            frame: Framebuffer::default(),
+        }
+    }
+
+    /// Whether an I/O port belongs to the card: the attribute, sequencer
+    /// and graphics controller ports, and the CRTC and status ports at
+    /// 0x3Bx or 0x3Dx as the miscellaneous output register selects.
+    pub fn decodes(&self, addr: u16) -> bool {
+        let crtc_base = if self.color_mode() { 0x3d0 } else { 0x3b0 };
+        (0x3c0..=0x3cf).contains(&addr) || (addr & 0xfff0) == crtc_base
+    }
+
+    /// Offset into the planes of a CPU address, if the graphics
+    /// controller maps it.
+    pub fn window(&self, addr: u32) -> Option<usize> {
+        if (self.misc_output & 0x02) == 0 {
+            return None;
+        }
+        let (base, size) = match (self.gc[6] >> 2) & 3 {
+            0 => (0xa_0000, 0x2_0000),
+            1 => (0xa_0000, 0x1_0000),
+            2 => (0xb_0000, 0x8000),
+            _ => (0xb_8000, 0x8000),
+        };
+        if addr >= base && addr < base + size {
+            Some((addr - base) as usize)
+        } else {
+            None
+        }
+    }
+
+    /// Reads through the graphics controller, loading the latches.
+    pub fn read_vram(&mut self, offset: usize) -> u8 {
+        let mut offset = offset;
+        let mut plane = (self.gc[4] & 3) as usize;
+        if (self.gc[5] & 0x10) != 0 {
+            // Odd/even: address bit 0 picks between a pair of planes.
+            plane = (plane & 2) | (offset & 1);
+            offset &= !1;
+        }
+        self.read_planes(offset, plane)
+    }
+
+    /// Loads the latches from `offset` in every plane and returns what the
+    /// read mode makes of them, with `plane` as the one read mode 0
+    /// returns.
+    pub fn read_planes(&mut self, offset: usize, plane: usize) -> u8 {
+        for (latch, data) in self.latch.iter_mut().zip(&self.planes) {
+            *latch = data[offset & 0xffff];
+        }
+        if (self.gc[5] & 0x08) == 0 {
+            return self.latch[plane];
+        }
+        // Read mode 1: set bits mark pixels matching the colour compare
+        // register in the planes that are not "don't care".
+        let mut result = 0xff;
+        for i in 0..4 {
+            if (self.gc[7] & (1 << i)) != 0 {
+                let compare = if (self.gc[2] & (1 << i)) != 0 {
+                    0xff
+                } else {
+                    0
+                };
+                result &= !(self.latch[i] ^ compare);
+            }
+        }
+        result
+    }
+
+    /// Writes through the graphics controller's write mode, logical
+    /// operation and bit mask into the planes enabled by the map mask.
+    pub fn write_vram(&mut self, offset: usize, data: u8) {
+        let mut offset = offset;
+        let mut map_mask = self.seq[2] & 0x0f;
+        if (self.seq[4] & 0x04) == 0 {
+            // Odd/even: even addresses reach planes 0 and 2, odd ones 1
+            // and 3.
+            map_mask &= if (offset & 1) != 0 { 0x0a } else { 0x05 };
+            offset &= !1;
+        }
+        self.write_planes(offset, data, map_mask);
+    }
+
+    /// Writes `data` at `offset` in the planes set in `map_mask`. Write
+    /// mode 3 only exists on the VGA: the rotated data ANDed with the bit
+    /// mask selects between the set/reset colour and the latches.
+    pub fn write_planes(&mut self, offset: usize, data: u8, map_mask: u8) {
+        let rotated = data.rotate_right((self.gc[3] & 7) as u32);
+        let bit_mask = if (self.gc[5] & 3) == 3 {
+            rotated & self.gc[8]
+        } else {
+            self.gc[8]
+        };
+        for plane in 0..4 {
+            if (map_mask & (1 << plane)) == 0 {
+                continue;
+            }
+            let latch = self.latch[plane];
+            let set_reset = if (self.gc[0] & (1 << plane)) != 0 {
+                0xff
+            } else {
+                0
+            };
+            let value = match self.gc[5] & 3 {
+                1 => {
+                    self.planes[plane][offset & 0xffff] = latch;
+                    continue;
+                }
+                2 => {
+                    if (data & (1 << plane)) != 0 {
+                        0xff
+                    } else {
+                        0
+                    }
+                }
+                3 => set_reset,
+                _ => {
+                    if (self.gc[1] & (1 << plane)) != 0 {
+                        set_reset
+                    } else {
+                        rotated
+                    }
+                }
+            };
+            let value = match (self.gc[3] >> 3) & 3 {
+                1 => value & latch,
+                2 => value | latch,
+                3 => value ^ latch,
+                _ => value,
+            };
+            self.planes[plane][offset & 0xffff] = (value & bit_mask) | (latch & !bit_mask);
+        }
+    }
+
+    pub fn color_mode(&self) -> bool {
+        (self.misc_output & 0x01) != 0
+    }
+
+    fn dot_clock(&self) -> u64 {
+        EGA_DOT_CLOCKS[((self.misc_output >> 2) & 1) as usize]
+    }
+
+    /// Dots per character clock.
+    fn char_width(&self) -> u64 {
+        8 << ((self.seq[1] >> 3) & 1)
+    }
+
+    pub fn horizontal_total(&self) -> u64 {
+        self.crtc[0] as u64 + 2
+    }
+
+    fn vertical_total(&self) -> u64 {
+        (self.crtc[6] as u64 | ((self.crtc[7] as u64 & 0x01) << 8)) + 1
+    }
+
+    fn vertical_display_end(&self) -> u64 {
+        (self.crtc[0x12] as u64 | ((self.crtc[7] as u64 & 0x02) << 7)) + 1
+    }
+
+    fn vertical_retrace_start(&self) -> u64 {
+        self.crtc[0x10] as u64 | ((self.crtc[7] as u64 & 0x04) << 6)
+    }
+
+    fn line_compare(&self) -> u64 {
+        self.crtc[0x18] as u64 | ((self.crtc[7] as u64 & 0x10) << 4)
+    }
+
+    /// Vertical retrace runs until the low four bits of the scanline
+    /// counter match the retrace end register.
+    fn vertical_retrace(&self) -> bool {
+        let line = self.position / self.horizontal_total();
+        let start = self.vertical_retrace_start();
+        let length = (self.crtc[0x11] as u64).wrapping_sub(start) & 0x0f;
+        line >= start && line < start + length
+    }
+
+    fn display_enable(&self) -> bool {
+        let column = self.position % self.horizontal_total();
+        let line = self.position / self.horizontal_total();
+        column <= self.crtc[1] as u64 && line < self.vertical_display_end()
+    }
+
+    /// Runs the adapter for `cycles` clocks of a CPU running at
+    /// `cpu_clock` Hz, drawing a frame each time the CRTC wraps.
+    pub fn tick(&mut self, cycles: usize, cpu_clock: u64) {
+        let period = cpu_clock * self.char_width();
+        self.phase += cycles as u64 * self.dot_clock();
+        self.position += self.phase / period;
+        self.phase %= period;
+        let frame_len = self.horizontal_total() * self.vertical_total();
+        while self.position >= frame_len {
+            self.position -= frame_len;
+            self.render();
+        }
+    }
+
+    /// Final colour of an attribute controller input. Monitors in
+    /// 200-line modes take bit 4 as intensity and ignore bits 3 and 5.
+    fn palette_color(&self, index: u8) -> u32 {
+        let color = self.attr[(index & self.attr[0x12] & 0x0f) as usize];
+        if (self.misc_output & 0x80) == 0 {
+            CGA_PALETTE[((color & 0x07) | ((color & 0x10) >> 1)) as usize]
+        } else {
+            ega_color(color)
+        }
+    }
+
+    /// Memory address of character clock `ma` on row scan `scan`,
+    /// following the CRTC's byte/word mode, or the VGA's doubleword mode,
+    /// and CGA-style bank interleaving.
+    pub fn scan_address(&self, ma: usize, scan: usize) -> usize {
+        let mode = self.crtc[0x17];
+        let mut addr = if (self.crtc[0x14] & 0x40) != 0 {
+            ma << 2
+        } else if (mode & 0x40) != 0 {
+            ma
+        } else {
+            ma << 1
+        };
+        if (mode & 0x01) == 0 {
+            addr = (addr & !0x2000) | ((scan & 1) << 13);
+        }
+        if (mode & 0x02) == 0 {
+            addr = (addr & !0x4000) | ((scan & 2) << 13);
+        }
+        addr & 0xffff
+    }
+
+    /// Eight attribute controller inputs for one character clock of a
+    /// graphics mode.
+    pub fn graphics_pixels(&self, addr: usize) -> [u8; 8] {
+        let mut pixels = [0u8; 8];
+        if (self.gc[5] & 0x20) != 0 {
+            // CGA-compatible shift: two bits per pixel, even byte first.
+            let data = [self.planes[0][addr], self.planes[1][addr]];
+            for (x, pixel) in pixels.iter_mut().enumerate() {
+                *pixel = (data[x / 4] >> (6 - (x % 4) * 2)) & 3;
+            }
+        } else {
+            for (x, pixel) in pixels.iter_mut().enumerate() {
+                for plane in 0..4 {
+                    *pixel |= ((self.planes[plane][addr] >> (7 - x)) & 1) << plane;
+                }
+            }
+        }
+        pixels
+    }
+
+    /// Glyph row, foreground and background of one character cell of a
+    /// text mode: character in plane 0, attribute in plane 1 and glyphs
+    /// in plane 2 at the font selected by attribute bit 3. Bits 4 and 5 of
+    /// the character map select only exist on the VGA, where they pick
+    /// the upper 8K of each 16K font block.
+    pub fn text_cell(&self, addr: usize, ma: usize, scan: usize) -> (u8, u8, u8) {
+        let ch = self.planes[0][addr] as usize;
+        let attr = self.planes[1][addr];
+        let map = self.seq[3] as usize;
+        let font = if (attr & 0x08) != 0 {
+            ((map >> 2) & 3) | ((map >> 3) & 4)
+        } else {
+            (map & 3) | ((map >> 2) & 4)
+        };
+        let base = (font & 3) * 0x4000 + (font >> 2) * 0x2000;
+        let mut bits = self.planes[2][(base + ch * 32 + scan) & 0xffff];
+        let mut fg = attr & 0x0f;
+        let mut bg = attr >> 4;
+        if (self.attr[0x10] & 0x08) != 0 {
+            bg &= 7;
+            if (attr & 0x80) != 0 && (self.frame_count & 0x20) == 0 {
+                fg = bg;
+            }
+        }
+        let cursor = ((self.crtc[0x0e] as usize) << 8) | self.crtc[0x0f] as usize;
+        let cursor_start = (self.crtc[0x0a] & 0x1f) as usize;
+        let cursor_end = (self.crtc[0x0b] & 0x1f) as usize;
+        if ma == cursor
+            && scan >= cursor_start
+            && scan <= cursor_end
+            && (self.frame_count & 0x10) != 0
+        {
+            bits = 0xff;
+        }
+        (bits, fg, bg)
+    }
+
+    /// The displayed text page, unless the attribute controller is in a
+    /// graphics mode.
+    pub fn text_screen(&self) -> Option<TextScreen> {
+        self.text_screen_lines(self.vertical_display_end() as usize)
+    }
+
+    /// The text page shown on `lines` scanlines: characters in plane 0
+    /// and attributes in plane 1, rows as far apart as the CRTC offset
+    /// register puts them.
+    pub fn text_screen_lines(&self, lines: usize) -> Option<TextScreen> {
+        if (self.attr[0x10] & 0x01) != 0 {
+            return None;
+        }
+        let columns = self.crtc[1] as usize + 1;
+        let rows = lines / ((self.crtc[9] & 0x1f) as usize + 1);
+        let start = ((self.crtc[0x0c] as usize) << 8) | self.crtc[0x0d] as usize;
+        let offset = self.crtc[0x13] as usize * 2;
+        let mut screen = TextScreen::read(columns, rows, |row, column| {
+            let addr = self.scan_address(start + row * offset + column, 0);
+            (self.planes[0][addr], self.planes[1][addr])
+        });
+        if (self.crtc[0x0a] & 0x20) == 0 {
+            let cursor = ((self.crtc[0x0e] as usize) << 8) | self.crtc[0x0f] as usize;
+            screen.place_cursor(cursor.wrapping_sub(start) & 0xffff, offset);
+        }
+        Some(screen)
+    }
+
+    /// Draws the whole frame from video memory into `frame`.
+    pub fn render(&mut self) {
+        self.frame_count += 1;
+        let double = (self.seq[1] & 0x08) != 0;
+        let columns = self.crtc[1] as usize + 1;
+        let width = ((columns * 8) << double as usize).min(MAX_WIDTH);
+        let height = (self.vertical_display_end() as usize).min(MAX_HEIGHT);
+        self.frame.resize(width, height);
+        // With the palette address source cleared the screen shows only
+        // the overscan colour.
+        if (self.attr_index & 0x20) == 0 || (self.seq[0] & 0x03) != 0x03 {
+            return;
+        }
+        let graphics = (self.attr[0x10] & 0x01) != 0;
+        let row_height = (self.crtc[9] & 0x1f) as usize + 1;
+        let offset = self.crtc[0x13] as usize * 2;
+        let line_compare = self.line_compare() as usize;
+        let mut row_start = ((self.crtc[0x0c] as usize) << 8) | self.crtc[0x0d] as usize;
+        let mut scan = (self.crtc[8] & 0x1f) as usize;
+        for y in 0..height {
+            if y == line_compare {
+                row_start = 0;
+                scan = 0;
+            }
+            for column in 0..columns {
+                let ma = row_start + column;
+                let addr = self.scan_address(ma, scan);
+                let pixels = if graphics {
+                    self.graphics_pixels(addr)
+                } else {
+                    let (bits, fg, bg) = self.text_cell(addr, ma & 0xffff, scan);
+                    let mut pixels = [0u8; 8];
+                    for (x, pixel) in pixels.iter_mut().enumerate() {
+                        *pixel = if (bits & (0x80 >> x)) != 0 { fg } else { bg };
+                    }
+                    pixels
+                };
+                for (x, &pixel) in pixels.iter().enumerate() {
+                    let color = self.palette_color(pixel);
+                    let x = (column * 8 + x) << double as usize;
+                    if x < width {
+                        self.frame.pixels[y * width + x] = color;
+                        if double {
+                            self.frame.pixels[y * width + x + 1] = color;
+                        }
+                    }
+                }
+            }
+            scan += 1;
+            if scan == row_height {
+                scan = 0;
+                row_start += offset;
+            }
+        }
+    }
+
+    /// Input status 1: bit 0 is set outside the displayed area and bit 3
+    /// during vertical retrace. Reading it resets the attribute
+    /// controller flip-flop.
+    fn input_status_1(&mut self) -> u8 {
+        self.attr_flip_flop = false;
+        (!self.display_enable() as u8) | ((self.vertical_retrace() as u8) << 3)
+    }
+
+    pub fn rb(&mut self, addr: u16) -> u8 {
+        let crtc_base = if self.color_mode() { 0x3d0 } else { 0x3b0 };
+        match addr {
+            0x3c0 => self.attr_index,
+            0x3c1 => self
+                .attr
+                .get(self.attr_index as usize & 0x1f)
+                .copied()
+                .unwrap_or(0),
+            // Input status 0: bit 4 senses the switch the clock select
+            // bits of the miscellaneous output register point at.
+            0x3c2 => {
+                if (self.switches & (1 << ((self.misc_output >> 2) & 3))) != 0 {
+                    0
+                } else {
+                    0x10
+                }
+            }
+            0x3c4 => self.seq_index,
+            0x3c5 => self
+                .seq
+                .get(self.seq_index as usize)
+                .copied()
+                .unwrap_or(0xff),
+            0x3ce => self.gc_index,
+            0x3cf => self.gc.get(self.gc_index as usize).copied().unwrap_or(0xff),
+            _ if addr == crtc_base + 4 => self.crtc_index,
+            _ if addr == crtc_base + 5 => self
+                .crtc
+                .get(self.crtc_index as usize)
+                .copied()
+                .unwrap_or(0xff),
+            _ if addr == crtc_base + 0x0a => self.input_status_1(),
+            _ => 0xff,
+        }
+    }
+
+    pub fn wb(&mut self, addr: u16, data: u8) {
+        let crtc_base = if self.color_mode() { 0x3d0 } else { 0x3b0 };
+        match addr {
+            0x3c0 => {
+                if self.attr_flip_flop {
+                    if let Some(reg) = self.attr.get_mut(self.attr_index as usize & 0x1f) {
+                        *reg = data;
+                    }
+                } else {
+                    self.attr_index = data & 0x3f;
+                }
+                self.attr_flip_flop = !self.attr_flip_flop;
+            }
+            0x3c2 => self.misc_output = data,
+            0x3c4 => self.seq_index = data,
+            0x3c5 => {
+                if let Some(reg) = self.seq.get_mut(self.seq_index as usize) {
+                    *reg = data;
+                }
+            }
+            0x3ce => self.gc_index = data,
+            0x3cf => {
+                if let Some(reg) = self.gc.get_mut(self.gc_index as usize) {
+                    *reg = data;
+                }
+            }
+            _ if addr == crtc_base + 4 => self.crtc_index = data,
+            _ if addr == crtc_base + 5 => {
+                if let Some(reg) = self.crtc.get_mut(self.crtc_index as usize) {
+                    *reg = data;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Default for EGA {
+    fn default() -> EGA {
+        EGA::new()
+    }
+}
+
+/// Programs a mode's registers the way the BIOS does. The VGA passes
+/// all of these ports through to its EGA, so its tests share this.
+#[cfg(test)]
+pub fn ega_set_mode(ega: &mut EGA, misc: u8, seq: &[u8], crtc: &[u8], attr: &[u8], gc: &[u8]) {
+    ega.wb(0x3c2, misc);
+    for (index, &value) in seq.iter().enumerate() {
+        ega.wb(0x3c4, index as u8 + 1);
+        ega.wb(0x3c5, value);
+    }
+    ega.wb(0x3c4, 0);
+    ega.wb(0x3c5, 0x03);
+    for (index, &value) in crtc.iter().enumerate() {
+        ega.wb(0x3d4, index as u8);
+        ega.wb(0x3d5, value);
+    }
+    ega.rb(0x3da);
+    for (index, &value) in attr.iter().enumerate() {
+        ega.wb(0x3c0, index as u8);
+        ega.wb(0x3c0, value);
+    }
+    ega.wb(0x3c0, 0x20);
+    for (index, &value) in gc.iter().enumerate() {
+        ega.wb(0x3ce, index as u8);
+        ega.wb(0x3cf, value);
+    }
+}
+
+#[test]
+fn test_ega_planar_graphics() {
+    let mut ega = EGA::new();
+    // The BIOS parameters of mode 10h, 640x350 in 16 colours.
+    ega_set_mode(
+        &mut ega,
+        0xa7,
+        &[0x01, 0x0f, 0x00, 0x06],
+        &[
+            0x5b, 0x4f, 0x53, 0x37, 0x52, 0x00, 0x6c, 0x1f, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x5e, 0x2b, 0x5d, 0x28, 0x0f, 0x5f, 0x0a, 0xe3, 0xff,
+        ],
+        &[
+            0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x14, 0x07, 0x38, 0x39, 0x3a, 0x3b, 0x3c, 0x3d,
+            0x3e, 0x3f, 0x01, 0x00, 0x0f, 0x00,
+        ],
+        &[0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x05, 0x0f, 0xff],
+    );
+    assert_eq!(ega.window(0xa_0000), Some(0));
+    assert_eq!(ega.window(0xb_8000), None);
+    // Write mode 2 fills the left half of the first byte with colour 9
+    // through the bit mask.
+    ega.wb(0x3ce, 5);
+    ega.wb(0x3cf, 0x02);
+    ega.wb(0x3ce, 8);
+    ega.wb(0x3cf, 0xf0);
+    ega.read_vram(0);
+    ega.write_vram(0, 0x09);
+    assert_eq!(ega.planes[0][0], 0xf0);
+    assert_eq!(ega.planes[1][0], 0x00);
+    assert_eq!(ega.planes[3][0], 0xf0);
+    // Write mode 0 with set/reset on plane 1 and XOR against the latches.
+    ega.wb(0x3ce, 5);
+    ega.wb(0x3cf, 0x00);
+    ega.wb(0x3ce, 8);
+    ega.wb(0x3cf, 0xff);
+    ega.wb(0x3ce, 0);
+    ega.wb(0x3cf, 0x02);
+    ega.wb(0x3ce, 1);
+    ega.wb(0x3cf, 0x02);
+    ega.wb(0x3ce, 3);
+    ega.wb(0x3cf, 0x18);
+    ega.read_vram(0);
+    ega.write_vram(0, 0x0f);
+    assert_eq!(ega.planes[0][0], 0xff);
+    assert_eq!(ega.planes[1][0], 0xff);
+    assert_eq!(ega.planes[2][0], 0x0f);
+    assert_eq!(ega.planes[3][0], 0xff);
+    // Write mode 1 copies the latches.
+    ega.wb(0x3ce, 3);
+    ega.wb(0x3cf, 0x00);
+    ega.wb(0x3ce, 5);
+    ega.wb(0x3cf, 0x01);
+    ega.read_vram(0);
+    ega.write_vram(80, 0);
+    assert_eq!(ega.planes[2][80], 0x0f);
+    // Read mode 1 finds the pixels of colour 11: the left four.
+    ega.wb(0x3ce, 5);
+    ega.wb(0x3cf, 0x08);
+    ega.wb(0x3ce, 2);
+    ega.wb(0x3cf, 0x0b);
+    assert_eq!(ega.read_vram(0), 0xf0);
+    // Read mode 0 returns the plane chosen by the read map select.
+    ega.wb(0x3ce, 5);
+    ega.wb(0x3cf, 0x00);
+    ega.wb(0x3ce, 4);
+    ega.wb(0x3cf, 2);
+    assert_eq!(ega.read_vram(0), 0x0f);
+    let mut retrace = false;
+    while ega.frame_count < 2 {
+        ega.tick(100, 6_000_000);
+        retrace |= (ega.rb(0x3da) & 0x08) != 0;
+    }
+    assert!(retrace);
+    assert_eq!((ega.frame.width, ega.frame.height), (640, 350));
+    // Colour 11 then colour 15, through the palette to bright cyan and
+    // bright white.
+    assert_eq!(ega.frame.pixels[0], 0x55_ffff);
+    assert_eq!(ega.frame.pixels[4], 0xff_ffff);
+    assert_eq!(ega.frame.pixels[640], 0x55_ffff);
+    assert_eq!(ega.frame.pixels[640 + 4], 0xff_ffff);
+    assert_eq!(ega.frame.pixels[640 * 2], 0);
+}
+
+#[test]
+fn test_ega_text_mode() {
+    let mut ega = EGA::new();
+    // The BIOS parameters of mode 3 on an enhanced display: 80x25 with
+    // 8x14 characters.
+    ega_set_mode(
+        &mut ega,
+        0xa7,
+        &[0x01, 0x03, 0x00, 0x02],
+        &[
+            0x5b, 0x4f, 0x53, 0x37, 0x51, 0x5b, 0x6c, 0x1f, 0x00, 0x0d, 0x0b, 0x0c, 0x00, 0x00,
+            0x00, 0x00, 0x5e, 0x2b, 0x5d, 0x28, 0x0f, 0x5e, 0x0a, 0xa3, 0xff,
+        ],
+        &[
+            0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x14, 0x07, 0x38, 0x39, 0x3a, 0x3b, 0x3c, 0x3d,
+            0x3e, 0x3f, 0x08, 0x00, 0x0f, 0x00,
+        ],
+        &[0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x0e, 0x00, 0xff],
+    );
+    assert_eq!(ega.window(0xb_8000), Some(0));
+    // Odd/even writes put characters in plane 0 and attributes in plane 1.
+    ega.write_vram(2, b'A');
+    ega.write_vram(3, 0x1e);
+    assert_eq!(ega.planes[0][2], b'A');
+    assert_eq!(ega.planes[1][2], 0x1e);
+    assert_eq!(ega.read_vram(3), 0x1e);
+    // A glyph for 'A' in plane 2, written as the BIOS does.
+    ega.wb(0x3c4, 2);
+    ega.wb(0x3c5, 0x04);
+    ega.wb(0x3c4, 4);
+    ega.wb(0x3c5, 0x06);
+    ega.wb(0x3ce, 5);
+    ega.wb(0x3cf, 0x00);
+    ega.wb(0x3ce, 6);
+    ega.wb(0x3cf, 0x04);
+    ega.write_vram(b'A' as usize * 32 + 1, 0x81);
+    assert_eq!(ega.planes[2][b'A' as usize * 32 + 1], 0x81);
+    ega.render();
+    assert_eq!((ega.frame.width, ega.frame.height), (640, 350));
+    assert_eq!(ega.frame.pixels[640 + 8], 0xff_ff55);
+    assert_eq!(ega.frame.pixels[640 + 9], 0x00_00aa);
+    assert_eq!(ega.frame.pixels[640 + 15], 0xff_ff55);
+    assert_eq!(ega.frame.pixels[8], 0x00_00aa);
+}
@@ -0,0 +1,646 @@
+use crate::hardware::dma::*;
+use crate::hardware::floppy::*;
+use std::cmp::Ordering;
+use std::collections::VecDeque;
+
+/// DMA channel used by the floppy controller.
+pub const FDC_DMA_CHANNEL: usize = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FdcPhase {
+    Command,
+    Execution,
+    Result,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct FloppyDrive {
+    pub image: Option<FloppyImage>,
+    /// Cylinder the head is positioned over.
+    pub cylinder: u8,
+    /// Position on the track of the sector whose ID passes the head next.
+    pub next_index: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct FDC {
+    /// Digital output register: drive select, reset, DMA/IRQ enable, motors.
+    pub dor: u8,
+    pub phase: FdcPhase,
+    pub command: Vec<u8>,
+    pub result: VecDeque<u8>,
+    pub drives: [FloppyDrive; 4],
+    /// ST0 and present cylinder of seeks and resets awaiting SENSE
+    /// INTERRUPT STATUS.
+    pub interrupts: VecDeque<(u8, u8)>,
+    pub irq: bool,
+}
+
+/// Length of a command including its first byte. Invalid opcodes are a
+/// single byte, answered with ST0 0x80.
+fn command_length(command: u8) -> usize {
+    match command & 0x1f {
+        0x02 => 9,
+        0x03 => 3,
+        0x04 => 2,
+        0x05 | 0x06 | 0x09 | 0x0c => 9,
+        0x07 => 2,
+        0x08 => 1,
+        0x0a => 2,
+        0x0d => 6,
+        0x0f => 3,
+        0x11 | 0x19 | 0x1d => 9,
+        _ => 1,
+    }
+}
+
+/// Compares sector data from the disk against data from memory for the
+/// SCAN commands, where 0xFF in memory matches anything. Returns whether
+/// the scan condition holds and whether the data was equal.
+fn scan_compare(function: u8, disk: &[u8], memory: &[u8]) -> (bool, bool) {
+    let ordering = disk
+        .iter()
+        .zip(memory)
+        .filter(|(_, &m)| m != 0xff)
+        .map(|(d, m)| d.cmp(m))
+        .find(|ordering| ordering.is_ne())
+        .unwrap_or(Ordering::Equal);
+    let satisfied = match function {
+        0x11 => ordering.is_eq(),
+        0x19 => ordering.is_le(),
+        _ => ordering.is_ge(),
+    };
+    (satisfied, ordering.is_eq())
+}
+
+impl FDC {
+    pub fn new() -> Self {
+        Self {
+            dor: 0,
+            phase: FdcPhase::Command,
+            command: Vec::new(),
+            result: VecDeque::new(),
+            drives: Default::default(),
+            interrupts: VecDeque::new(),
+            irq: false,
+        }
+    }
+
+    pub fn insert_disk(&mut self, drive: usize, image: FloppyImage) {
+        let drive = &mut self.drives[drive];
+        drive.image = Some(image);
+        drive.next_index = 0;
+    }
+
+    pub fn eject_disk(&mut self, drive: usize) -> Option<FloppyImage> {
+        self.drives[drive].image.take()
+    }
+
+    /// Level of IRQ6, gated by the DMA/IRQ enable bit of the DOR.
+    pub fn irq(&self) -> bool {
+        self.irq && (self.dor & 0x08) != 0
+    }
+
+    fn reset(&mut self) {
+        self.phase = FdcPhase::Command;
+        self.command.clear();
+        self.result.clear();
+        self.interrupts.clear();
+        self.irq = false;
+    }
+
+    fn msr(&self) -> u8 {
+        if (self.dor & 0x04) == 0 {
+            return 0;
+        }
+        match self.phase {
+            FdcPhase::Command if self.command.is_empty() => 0x80,
+            FdcPhase::Command => 0x90,
+            FdcPhase::Execution => 0x10,
+            FdcPhase::Result => 0xd0,
+        }
+    }
+
+    fn finish(&mut self, result: &[u8], interrupt: bool) {
+        self.command.clear();
+        self.result = result.iter().copied().collect();
+        self.phase = if self.result.is_empty() {
+            FdcPhase::Command
+        } else {
+            FdcPhase::Result
+        };
+        if interrupt {
+            self.irq = true;
+        }
+    }
+
+    /// Runs a command whose parameters have all been written, moving data
+    /// through DMA channel 2.
+    pub fn tick<D: DmaTransfer>(&mut self, dma: &mut D, ram: &mut [u8]) {
+        if self.phase != FdcPhase::Execution {
+            return;
+        }
+        let cmd = self.command.clone();
+        let drive = (cmd.get(1).copied().unwrap_or(0) & 3) as usize;
+        let head = (cmd.get(1).copied().unwrap_or(0) >> 2) & 1;
+        let st0 = (head << 2) | drive as u8;
+        match cmd[0] & 0x1f {
+            0x03 => self.finish(&[], false),
+            0x04 => {
+                let d = &self.drives[drive];
+                let mut st3 = 0x20 | st0;
+                if d.cylinder == 0 {
+                    st3 |= 0x10;
+                }
+                if let Some(image) = &d.image {
+                    if image.geometry.heads > 1 {
+                        st3 |= 0x08;
+                    }
+                    if image.is_write_protected() {
+                        st3 |= 0x40;
+                    }
+                }
+                self.finish(&[st3], false);
+            }
+            0x02 | 0x05 | 0x06 | 0x09 | 0x0c | 0x11 | 0x19 | 0x1d => {
+                self.read_write(dma, ram, &cmd)
+            }
+            0x07 => {
+                self.drives[drive].cylinder = 0;
+                self.interrupts.push_back((0x20 | drive as u8, 0));
+                self.finish(&[], true);
+            }
+            0x08 => {
+                self.irq = false;
+                match self.interrupts.pop_front() {
+                    Some((st0, pcn)) => self.finish(&[st0, pcn], false),
+                    None => self.finish(&[0x80], false),
+                }
+            }
+            0x0a => {
+                let d = &mut self.drives[drive];
+                let track = match &d.image {
+                    Some(image) => image.track(d.cylinder, head),
+                    None => &[],
+                };
+                if track.is_empty() {
+                    self.finish(&[0x40 | st0, 0x01, 0, 0, head, 0, 2], true);
+                } else {
+                    let id = track[d.next_index % track.len()].id;
+                    d.next_index = (d.next_index + 1) % track.len();
+                    let result = [st0, 0, 0, id.cylinder, id.head, id.sector, id.size_code];
+                    self.finish(&result, true);
+                }
+            }
+            0x0d => self.format_track(dma, ram, &cmd),
+            0x0f => {
+                self.drives[drive].cylinder = cmd[2];
+                self.interrupts.push_back((0x20 | st0, cmd[2]));
+                self.finish(&[], true);
+            }
+            _ => self.finish(&[0x80], false),
+        }
+    }
+
+    fn read_write<D: DmaTransfer>(&mut self, dma: &mut D, ram: &mut [u8], cmd: &[u8]) {
+        let function = cmd[0] & 0x1f;
+        let write = matches!(function, 0x05 | 0x09);
+        let read_track = function == 0x02;
+        let scan = matches!(function, 0x11 | 0x19 | 0x1d);
+        let want_deleted = matches!(function, 0x09 | 0x0c);
+        let multi_track = (cmd[0] & 0x80) != 0;
+        let skip = (cmd[0] & 0x20) != 0;
+        let drive = (cmd[1] & 3) as usize;
+        let mut head = (cmd[1] >> 2) & 1;
+        let (mut c, mut h, mut r, n) = (cmd[2], cmd[3], cmd[4], cmd[5]);
+        let (eot, dtl) = (cmd[6], cmd[8]);
+        let length = if n == 0 && !scan {
+            dtl as usize
+        } else {
+            128 << n.min(7)
+        };
+        // The SCAN commands step R by STP, which takes the place of DTL.
+        let step = if scan { dtl.max(1) } else { 1 };
+        // READ TRACK takes sectors in the order they pass the head,
+        // flagging any whose ID differs from the one expected.
+        let mut position = 0;
+        let mut no_data = 0;
+        let (st0, st1, st2) = loop {
+            let d = &mut self.drives[drive];
+            let st0 = (head << 2) | drive as u8;
+            let cylinder = d.cylinder;
+            let image = match &mut d.image {
+                Some(image) => image,
+                None => break (0x48 | st0, 0, 0),
+            };
+            if write && image.is_write_protected() {
+                break (0x40 | st0, 0x02, 0);
+            }
+            let id = SectorId::new(c, h, r, n);
+            let found = if read_track {
+                let track = image.track(cylinder, head);
+                let index = (position < track.len()).then_some(position);
+                if index.is_some_and(|index| track[index].id != id) {
+                    no_data = 0x04;
+                }
+                position += 1;
+                index
+            } else {
+                image.find_sector(cylinder, head, id)
+            };
+            let index = match found {
+                Some(index) => index,
+                None => {
+                    let track = image.track(cylinder, head);
+                    let st2 = match track.iter().find(|s| s.id.sector == r) {
+                        Some(s) if s.id.cylinder == 0xff => 0x02,
+                        Some(s) if s.id.cylinder != c => 0x10,
+                        _ => 0,
+                    };
+                    let st1 = if track.is_empty() { 0x01 } else { 0x04 };
+                    break (0x40 | st0, st1, st2);
+                }
+            };
+            let mut control_mark = false;
+            let (done, tc) = if write {
+                let (data, tc) = dma.transfer_from_memory(FDC_DMA_CHANNEL, ram, length);
+                if image
+                    .write_sector_at(cylinder, head, index, &data, want_deleted)
+                    .is_err()
+                {
+                    // A failed write to the image file is a drive fault.
+                    break (0x50 | st0, 0, 0);
+                }
+                (data.len(), tc)
+            } else if scan {
+                let sector = image.read_sector_at(cylinder, head, index).unwrap();
+                let (data, tc) = dma.transfer_from_memory(FDC_DMA_CHANNEL, ram, length);
+                let (satisfied, equal) = scan_compare(function, &sector.data, &data);
+                if satisfied {
+                    break (st0, 0, if equal { 0x08 } else { 0 });
+                }
+                if r >= eot || tc {
+                    // Scan not satisfied.
+                    break (st0, 0, 0x04);
+                }
+                (data.len(), tc)
+            } else {
+                let sector = image.read_sector_at(cylinder, head, index).unwrap();
+                if sector.data.is_empty() {
+                    break (0x40 | st0, 0x01, 0x01);
+                }
+                control_mark = sector.deleted != want_deleted;
+                if control_mark && skip {
+                    (length, false)
+                } else {
+                    let mut data = sector.data;
+                    data.resize(length, 0);
+                    let (done, tc) = dma.transfer_to_memory(FDC_DMA_CHANNEL, ram, &data);
+                    if sector.crc_error {
+                        break (0x40 | st0, 0x20, 0x20);
+                    }
+                    if control_mark {
+                        break (0x40 | st0, 0, 0x40);
+                    }
+                    (done, tc)
+                }
+            };
+            if done < length && !tc {
+                // The DMA controller stopped servicing requests.
+                break (0x40 | st0, 0x10, 0);
+            }
+            if r == eot {
+                r = 1;
+                if multi_track && head == 0 {
+                    head = 1;
+                    h ^= 1;
+                } else {
+                    if multi_track {
+                        head = 0;
+                        h ^= 1;
+                    }
+                    c = c.wrapping_add(1);
+                    if !tc {
+                        break (0x40 | st0, 0x80 | no_data, 0);
+                    }
+                }
+            } else {
+                r = r.wrapping_add(step);
+            }
+            if tc {
+                let st0 = if no_data != 0 { 0x40 | st0 } else { st0 };
+                break (st0, no_data, if control_mark { 0x40 } else { 0 });
+            }
+        };
+        self.finish(&[st0, st1, st2, c, h, r, n], true);
+    }
+
+    fn format_track<D: DmaTransfer>(&mut self, dma: &mut D, ram: &mut [u8], cmd: &[u8]) {
+        let drive = (cmd[1] & 3) as usize;
+        let head = (cmd[1] >> 2) & 1;
+        let st0 = (head << 2) | drive as u8;
+        let (n, sectors, fill) = (cmd[2], cmd[3], cmd[5]);
+        let d = &mut self.drives[drive];
+        let image = match &mut d.image {
+            Some(image) => image,
+            None => return self.finish(&[0x48 | st0, 0, 0, 0, head, 0, n], true),
+        };
+        if image.is_write_protected() {
+            return self.finish(&[0x40 | st0, 0x02, 0, 0, head, 0, n], true);
+        }
+        let mut ids = Vec::new();
+        for _ in 0..sectors {
+            let (bytes, _) = dma.transfer_from_memory(FDC_DMA_CHANNEL, ram, 4);
+            if bytes.len() < 4 {
+                break;
+            }
+            ids.push(SectorId::new(bytes[0], bytes[1], bytes[2], bytes[3]));
+        }
+        if image.format_track(d.cylinder, head, &ids, fill).is_err() {
+            return self.finish(&[0x50 | st0, 0, 0, 0, head, 0, n], true);
+        }
+        let last = ids.last().copied().unwrap_or(SectorId::new(0, head, 0, n));
+        let st1 = if ids.len() < sectors as usize {
+            0x10
+        } else {
+            0
+        };
+        let st0 = if st1 != 0 { 0x40 | st0 } else { st0 };
+        let result = [
+            st0,
+            st1,
+            0,
+            last.cylinder,
+            last.head,
+            last.sector,
+            last.size_code,
+        ];
+        self.finish(&result, true);
+    }
+
+    pub fn rb(&mut self, addr: u16) -> u8 {
+        match addr & 7 {
+            4 => self.msr(),
+            5 => {
+                if self.phase != FdcPhase::Result {
+                    return 0xff;
+                }
+                self.irq = false;
+                let value = self.result.pop_front().unwrap_or(0xff);
+                if self.result.is_empty() {
+                    self.phase = FdcPhase::Command;
+                }
+                value
+            }
+            _ => 0xff,
+        }
+    }
+
+    pub fn wb(&mut self, addr: u16, data: u8) {
+        match addr & 7 {
+            2 => {
+                let old = self.dor;
+                self.dor = data;
+                if (data & 0x04) == 0 {
+                    self.reset();
+                } else if (old & 0x04) == 0 {
+                    // Leaving reset reports a ready change on every drive.
+                    for drive in 0..4 {
+                        let pcn = self.drives[drive].cylinder;
+                        self.interrupts.push_back((0xc0 | drive as u8, pcn));
+                    }
+                    self.irq = true;
+                }
+            }
+            5 => {
+                if self.phase != FdcPhase::Command || (self.dor & 0x04) == 0 {
+                    return;
+                }
+                self.command.push(data);
+                if self.command.len() >= command_length(self.command[0]) {
+                    self.phase = FdcPhase::Execution;
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+impl Default for FDC {
+    fn default() -> FDC {
+        FDC::new()
+    }
+}
+
+#[cfg(test)]
+fn fdc_with_disk() -> FDC {
+    let mut fdc = FDC::new();
+    let mut data = vec![0u8; 40 * 2 * 9 * 512];
+    for (i, byte) in data.iter_mut().enumerate() {
+        *byte = (i >> 9) as u8;
+    }
+    fdc.insert_disk(0, FloppyImage::from_bytes(data).unwrap());
+    fdc.wb(0x3f2, 0x1c);
+    fdc
+}
+
+#[cfg(test)]
+fn run_command<D: DmaTransfer>(
+    fdc: &mut FDC,
+    dma: &mut D,
+    ram: &mut [u8],
+    bytes: &[u8],
+) -> Vec<u8> {
+    for &byte in bytes {
+        assert_eq!(fdc.rb(0x3f4) & 0xc0, 0x80);
+        fdc.wb(0x3f5, byte);
+    }
+    fdc.tick(dma, ram);
+    let mut result = Vec::new();
+    while fdc.rb(0x3f4) == 0xd0 {
+        result.push(fdc.rb(0x3f5));
+    }
+    result
+}
+
+#[test]
+fn test_fdc_reset_seek_and_sense() {
+    let mut fdc = fdc_with_disk();
+    let mut dma = DMA::new(false);
+    let mut ram = vec![0u8; 0x10000];
+    assert!(fdc.irq());
+    assert_eq!(
+        run_command(&mut fdc, &mut dma, &mut ram, &[0x08]),
+        vec![0xc0, 0]
+    );
+    assert!(!fdc.irq());
+    fdc.interrupts.clear();
+    assert_eq!(
+        run_command(&mut fdc, &mut dma, &mut ram, &[0x0f, 0x04, 10]),
+        vec![]
+    );
+    assert!(fdc.irq());
+    assert_eq!(
+        run_command(&mut fdc, &mut dma, &mut ram, &[0x08]),
+        vec![0x24, 10]
+    );
+    assert_eq!(
+        run_command(&mut fdc, &mut dma, &mut ram, &[0x08]),
+        vec![0x80]
+    );
+    assert_eq!(
+        run_command(&mut fdc, &mut dma, &mut ram, &[0x07, 0x00]),
+        vec![]
+    );
+    assert_eq!(
+        run_command(&mut fdc, &mut dma, &mut ram, &[0x08]),
+        vec![0x20, 0]
+    );
+    let result = run_command(&mut fdc, &mut dma, &mut ram, &[0x0a, 0x00]);
+    assert_eq!(result, vec![0x00, 0, 0, 0, 0, 1, 2]);
+}
+
+#[test]
+fn test_fdc_read_and_write_data_through_dma() {
+    let mut fdc = fdc_with_disk();
+    let mut dma = DMA::new(false);
+    let mut ram = vec![0u8; 0x10000];
+    fdc.interrupts.clear();
+    run_command(&mut fdc, &mut dma, &mut ram, &[0x0f, 0x00, 1]);
+    run_command(&mut fdc, &mut dma, &mut ram, &[0x08]);
+    // Read the last two sectors of cylinder 1 with multi-track set.
+    dma.wb(0x0b, 0x46);
+    dma.wb(0x0c, 0);
+    dma.wb(0x04, 0x00);
+    dma.wb(0x04, 0x10);
+    dma.wb(0x05, 0xff);
+    dma.wb(0x05, 0x03);
+    dma.wb(0x0a, 0x02);
+    let cmd = [0xe6, 0x04, 1, 1, 8, 2, 9, 0x2a, 0xff];
+    let result = run_command(&mut fdc, &mut dma, &mut ram, &cmd);
+    assert_eq!(result, vec![0x04, 0, 0, 2, 0, 1, 2]);
+    assert_eq!(ram[0x1000], 34);
+    assert_eq!(ram[0x11ff], 34);
+    assert_eq!(ram[0x1200], 35);
+    // Write one sector back from memory.
+    ram[0x2000..0x2200].fill(0x5a);
+    dma.wb(0x0b, 0x4a);
+    dma.wb(0x0c, 0);
+    dma.wb(0x04, 0x00);
+    dma.wb(0x04, 0x20);
+    dma.wb(0x05, 0xff);
+    dma.wb(0x05, 0x01);
+    dma.wb(0x0a, 0x02);
+    let cmd = [0x45, 0x00, 1, 0, 3, 2, 9, 0x2a, 0xff];
+    let result = run_command(&mut fdc, &mut dma, &mut ram, &cmd);
+    assert_eq!(result, vec![0x00, 0, 0, 1, 0, 4, 2]);
+    assert_eq!(
+        fdc.drives[0]
+            .image
+            .as_ref()
+            .unwrap()
+            .read_sector(1, 0, 3)
+            .unwrap()[0],
+        0x5a
+    );
+    // A sector that is not on the track is reported as not found.
+    dma.wb(0x0a, 0x02);
+    let cmd = [0x46, 0x00, 1, 0, 10, 2, 10, 0x2a, 0xff];
+    let result = run_command(&mut fdc, &mut dma, &mut ram, &cmd);
+    assert_eq!(result[0..2], [0x40, 0x04]);
+}
+
+#[test]
+fn test_fdc_sector_ids_and_deleted_data() {
+    let mut fdc = FDC::new();
+    let sectors = vec![
+        Sector {
+            id: SectorId::new(0, 0, 0x41, 3),
+            data: vec![0x11; 1024],
+            deleted: false,
+            crc_error: false,
+        },
+        Sector {
+            id: SectorId::new(0, 0, 0x42, 3),
+            data: vec![0x22; 1024],
+            deleted: true,
+            crc_error: false,
+        },
+    ];
+    let image = FloppyImage::from_tracks(vec![(0, 0, sectors)], FloppyFormat::Imd).unwrap();
+    fdc.insert_disk(0, image);
+    fdc.wb(0x3f2, 0x1c);
+    fdc.interrupts.clear();
+    let mut dma = DMA::new(false);
+    let mut ram = vec![0u8; 0x10000];
+    let result = run_command(&mut fdc, &mut dma, &mut ram, &[0x4a, 0x00]);
+    assert_eq!(result, vec![0x00, 0, 0, 0, 0, 0x41, 3]);
+    // READ DATA stops at the deleted sector with the control mark set.
+    dma.wb(0x0b, 0x46);
+    dma.wb(0x05, 0xff);
+    dma.wb(0x05, 0x07);
+    dma.wb(0x0a, 0x02);
+    let cmd = [0x46, 0x00, 0, 0, 0x41, 3, 0x42, 0x2a, 0xff];
+    let result = run_command(&mut fdc, &mut dma, &mut ram, &cmd);
+    assert_eq!(result[0..3], [0x40, 0x00, 0x40]);
+    assert_eq!(ram[0x3ff], 0x11);
+    assert_eq!(ram[0x400], 0x22);
+    // READ DELETED DATA reads it normally.
+    dma.wb(0x0a, 0x02);
+    let cmd = [0x4c, 0x00, 0, 0, 0x42, 3, 0x42, 0x2a, 0xff];
+    let result = run_command(&mut fdc, &mut dma, &mut ram, &cmd);
+    assert_eq!(result, vec![0x40, 0x80, 0x00, 1, 0, 1, 3]);
+}
+
+#[test]
+fn test_fdc_read_track_scan_and_invalid_commands() {
+    let mut fdc = fdc_with_disk();
+    let mut dma = DMA::new(false);
+    let mut ram = vec![0u8; 0x10000];
+    fdc.interrupts.clear();
+    assert_eq!(
+        run_command(&mut fdc, &mut dma, &mut ram, &[0x00]),
+        vec![0x80]
+    );
+    assert_eq!(
+        run_command(&mut fdc, &mut dma, &mut ram, &[0x1f]),
+        vec![0x80]
+    );
+    // READ TRACK reads from the index hole on, flagging mismatched IDs.
+    let program = |dma: &mut DMA, mode: u8, count: u16| {
+        dma.wb(0x0b, mode);
+        dma.wb(0x0c, 0);
+        dma.wb(0x04, 0x00);
+        dma.wb(0x04, 0x00);
+        dma.wb(0x05, count as u8);
+        dma.wb(0x05, (count >> 8) as u8);
+        dma.wb(0x0a, 0x02);
+    };
+    program(&mut dma, 0x46, 0x3ff);
+    let cmd = [0x02, 0x00, 0, 0, 1, 2, 9, 0x2a, 0xff];
+    let result = run_command(&mut fdc, &mut dma, &mut ram, &cmd);
+    assert_eq!(result, vec![0x00, 0, 0, 0, 0, 3, 2]);
+    assert_eq!((ram[0], ram[0x200]), (0, 1));
+    program(&mut dma, 0x46, 0x1ff);
+    let cmd = [0x02, 0x00, 0, 0, 5, 2, 9, 0x2a, 0xff];
+    let result = run_command(&mut fdc, &mut dma, &mut ram, &cmd);
+    assert_eq!(result[0..2], [0x40, 0x04]);
+    // The SCANs compare each sector with the next block of memory, where
+    // 0xFF matches anything. Each sector is filled with its LBA.
+    ram.fill(0xff);
+    for chunk in ram[..0x1200].chunks_mut(0x200) {
+        chunk[0] = 3;
+    }
+    program(&mut dma, 0x4a, 0x11ff);
+    let cmd = [0x11, 0x00, 0, 0, 1, 2, 9, 0x2a, 1];
+    let result = run_command(&mut fdc, &mut dma, &mut ram, &cmd);
+    assert_eq!(result, vec![0x00, 0, 0x08, 0, 0, 4, 2]);
+    program(&mut dma, 0x4a, 0x11ff);
+    let cmd = [0x19, 0x00, 0, 0, 1, 2, 9, 0x2a, 1];
+    let result = run_command(&mut fdc, &mut dma, &mut ram, &cmd);
+    assert_eq!(result, vec![0x00, 0, 0x00, 0, 0, 1, 2]);
+    program(&mut dma, 0x4a, 0x11ff);
+    let cmd = [0x11, 0x00, 0, 0, 1, 2, 2, 0x2a, 1];
+    let result = run_command(&mut fdc, &mut dma, &mut ram, &cmd);
+    assert_eq!(result, vec![0x00, 0, 0x04, 0, 0, 2, 2]);
+}
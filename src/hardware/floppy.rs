@@ -0,0 +1,672 @@
+use crate::hardware::mfm::MfmTrack;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct FloppyGeometry {
+    pub cylinders: u8,
+    pub heads: u8,
+    pub sectors_per_track: u8,
+}
+
+/// Standard PC formats, smallest first.
+const STANDARD_GEOMETRIES: [FloppyGeometry; 8] = [
+    FloppyGeometry::new(40, 1, 8),
+    FloppyGeometry::new(40, 1, 9),
+    FloppyGeometry::new(40, 2, 8),
+    FloppyGeometry::new(40, 2, 9),
+    FloppyGeometry::new(80, 2, 9),
+    FloppyGeometry::new(80, 2, 15),
+    FloppyGeometry::new(80, 2, 18),
+    FloppyGeometry::new(80, 2, 36),
+];
+
+impl FloppyGeometry {
+    pub const fn new(cylinders: u8, heads: u8, sectors_per_track: u8) -> Self {
+        Self {
+            cylinders,
+            heads,
+            sectors_per_track,
+        }
+    }
+
+    pub fn total_sectors(&self) -> usize {
+        self.cylinders as usize * self.heads as usize * self.sectors_per_track as usize
+    }
+
+    /// Matches a raw image size against the standard formats from 160K to
+    /// 2.88M.
+    pub fn from_size(size: usize) -> Option<Self> {
+        STANDARD_GEOMETRIES
+            .iter()
+            .copied()
+            .find(|geometry| geometry.total_sectors() << 9 == size)
+    }
+
+    /// Reads the geometry from the BIOS parameter block of a DOS 2.0+ boot
+    /// sector.
+    pub fn from_bpb(boot_sector: &[u8]) -> Option<Self> {
+        if boot_sector.len() < 0x24 {
+            return None;
+        }
+        let word =
+            |offset: usize| u16::from_le_bytes([boot_sector[offset], boot_sector[offset + 1]]);
+        let bytes_per_sector = word(0x0b);
+        let sectors_per_track = word(0x18);
+        let heads = word(0x1a);
+        let mut total = word(0x13) as u32;
+        if total == 0 {
+            total = u32::from_le_bytes([
+                boot_sector[0x20],
+                boot_sector[0x21],
+                boot_sector[0x22],
+                boot_sector[0x23],
+            ]);
+        }
+        if bytes_per_sector != 512
+            || !(1..=63).contains(&sectors_per_track)
+            || !(1..=2).contains(&heads)
+        {
+            return None;
+        }
+        let cylinders = total / (sectors_per_track * heads) as u32;
+        if cylinders == 0 || cylinders > 255 {
+            return None;
+        }
+        Some(Self::new(
+            cylinders as u8,
+            heads as u8,
+            sectors_per_track as u8,
+        ))
+    }
+
+    /// BIOS drive type reported by INT 13h AH=08h.
+    pub fn drive_type(&self) -> u8 {
+        match (self.cylinders, self.sectors_per_track) {
+            (0..=42, _) => 1,
+            (_, 0..=9) => 3,
+            (_, 10..=15) => 2,
+            (_, 16..=18) => 4,
+            _ => 6,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FloppyError {
+    SectorNotFound,
+    WriteProtected,
+    /// Writing the change through to the image file failed.
+    Io(io::ErrorKind),
+}
+
+impl FloppyError {
+    /// Status code returned in AH by INT 13h.
+    pub fn bios_status(&self) -> u8 {
+        match self {
+            FloppyError::SectorNotFound => 0x04,
+            FloppyError::WriteProtected => 0x03,
+            FloppyError::Io(_) => 0x20,
+        }
+    }
+}
+
+impl From<io::Error> for FloppyError {
+    fn from(err: io::Error) -> FloppyError {
+        FloppyError::Io(err.kind())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct SectorId {
+    pub cylinder: u8,
+    pub head: u8,
+    pub sector: u8,
+    /// Size code N; the sector holds 128 << N bytes.
+    pub size_code: u8,
+}
+
+impl SectorId {
+    pub fn new(cylinder: u8, head: u8, sector: u8, size_code: u8) -> Self {
+        Self {
+            cylinder,
+            head,
+            sector,
+            size_code,
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        128 << self.size_code.min(7)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Sector {
+    pub id: SectorId,
+    /// Contents of the data field, empty if the sector has only an ID field.
+    pub data: Vec<u8>,
+    /// Data field was written with a deleted data address mark.
+    pub deleted: bool,
+    pub crc_error: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum FloppyFormat {
+    /// Flat sector dump, written back to its file sector by sector.
+    #[default]
+    Raw,
+    Imd,
+    Td0,
+    /// Bit cell images, rewritten whole on every write.
+    Hfe,
+    D86f,
+}
+
+/// A floppy disk as the sectors found on each physical track, optionally
+/// backed by a file that sector writes are written back to.
+#[derive(Debug, Clone, Default)]
+pub struct FloppyImage {
+    /// Sectors in rotational order, indexed by `cylinder * heads + head`.
+    pub tracks: Vec<Vec<Sector>>,
+    /// Bit cells of each track for images loaded at the bit level, empty
+    /// otherwise. `tracks` then holds what decodes from them cleanly.
+    pub flux: Vec<MfmTrack>,
+    pub geometry: FloppyGeometry,
+    pub format: FloppyFormat,
+    pub path: Option<PathBuf>,
+    pub write_protected: bool,
+    /// Tracks as they were when a copy-on-write session began. While set,
+    /// writes stay in memory and nothing reaches the image file.
+    pub overlay_base: Option<(Vec<Vec<Sector>>, Vec<MfmTrack>)>,
+}
+
+impl FloppyImage {
+    /// Wraps a raw image, detecting its geometry from the size or the BPB.
+    pub fn from_bytes(data: Vec<u8>) -> Option<Self> {
+        let geometry =
+            FloppyGeometry::from_size(data.len()).or_else(|| FloppyGeometry::from_bpb(&data))?;
+        let mut tracks = Vec::new();
+        let mut chunks = data.chunks(512);
+        for cylinder in 0..geometry.cylinders {
+            for head in 0..geometry.heads {
+                let mut track = Vec::new();
+                for sector in 1..=geometry.sectors_per_track {
+                    // A truncated image ends the track early.
+                    match chunks.next() {
+                        Some(chunk) if chunk.len() == 512 => track.push(Sector {
+                            id: SectorId::new(cylinder, head, sector, 2),
+                            data: chunk.to_vec(),
+                            deleted: false,
+                            crc_error: false,
+                        }),
+                        _ => break,
+                    }
+                }
+                tracks.push(track);
+            }
+        }
+        Some(Self {
+            tracks,
+            flux: Vec::new(),
+            geometry,
+            format: FloppyFormat::Raw,
+            path: None,
+            write_protected: false,
+            overlay_base: None,
+        })
+    }
+
+    /// Builds an image from tracks tagged with their physical cylinder and
+    /// head, taking the geometry from the largest values seen. Cylinder or
+    /// head 255 leaves no room to count the tracks and is refused.
+    pub fn from_tracks(
+        tracks: Vec<(u8, u8, Vec<Sector>)>,
+        format: FloppyFormat,
+    ) -> io::Result<Self> {
+        let mut geometry = FloppyGeometry::new(0, 1, 0);
+        for (cylinder, head, sectors) in &tracks {
+            let (cylinders, heads) = cylinder
+                .checked_add(1)
+                .zip(head.checked_add(1))
+                .ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidData, "track number out of range")
+                })?;
+            geometry.cylinders = geometry.cylinders.max(cylinders);
+            geometry.heads = geometry.heads.max(heads);
+            geometry.sectors_per_track = geometry.sectors_per_track.max(sectors.len() as u8);
+        }
+        let mut image = Self {
+            tracks: vec![Vec::new(); geometry.cylinders as usize * geometry.heads as usize],
+            flux: Vec::new(),
+            geometry,
+            format,
+            path: None,
+            write_protected: false,
+            overlay_base: None,
+        };
+        for (cylinder, head, sectors) in tracks {
+            let index = image.track_index(cylinder, head).unwrap();
+            image.tracks[index] = sectors;
+        }
+        Ok(image)
+    }
+
+    /// Builds an image from bit cell tracks tagged with their physical
+    /// cylinder and head, decoding their sectors.
+    pub fn from_flux(tracks: Vec<(u8, u8, MfmTrack)>, format: FloppyFormat) -> io::Result<Self> {
+        let decoded = tracks
+            .iter()
+            .map(|(cylinder, head, track)| {
+                let sectors = track.clone().sectors(false);
+                let sectors = sectors.into_iter().map(|found| found.sector).collect();
+                (*cylinder, *head, sectors)
+            })
+            .collect();
+        let mut image = Self::from_tracks(decoded, format)?;
+        image.flux = vec![MfmTrack::default(); image.tracks.len()];
+        for (cylinder, head, track) in tracks {
+            let index = image.track_index(cylinder, head).unwrap();
+            image.flux[index] = track;
+        }
+        Ok(image)
+    }
+
+    /// Loads an IMD, TD0, HFE, 86F or raw image file. Read-only files are mounted
+    /// write protected.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let data = fs::read(path)?;
+        let mut image = if data.starts_with(b"IMD ") {
+            Self::from_imd(&data)?
+        } else if data.starts_with(b"HXCPICFE") {
+            Self::from_hfe(&data)?
+        } else if data.starts_with(b"86BF") {
+            Self::from_86f(&data)?
+        } else if data.starts_with(b"TD") || data.starts_with(b"td") {
+            Self::from_td0(&data)?
+        } else {
+            Self::from_bytes(data).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "unrecognised floppy image geometry",
+                )
+            })?
+        };
+        image.write_protected |= fs::metadata(path)?.permissions().readonly();
+        image.path = Some(path.to_path_buf());
+        Ok(image)
+    }
+
+    fn track_index(&self, cylinder: u8, head: u8) -> Option<usize> {
+        if cylinder >= self.geometry.cylinders || head >= self.geometry.heads {
+            return None;
+        }
+        Some(cylinder as usize * self.geometry.heads as usize + head as usize)
+    }
+
+    /// Sectors on a physical track, empty if the track is not in the image.
+    pub fn track(&self, cylinder: u8, head: u8) -> &[Sector] {
+        match self.track_index(cylinder, head) {
+            Some(index) => &self.tracks[index],
+            None => &[],
+        }
+    }
+
+    /// Position on a physical track of the sector with the given ID.
+    pub fn find_sector(&self, cylinder: u8, head: u8, id: SectorId) -> Option<usize> {
+        self.track(cylinder, head)
+            .iter()
+            .position(|sector| sector.id == id)
+    }
+
+    /// Reads the `index`th sector of a physical track as the controller
+    /// sees it. On bit cell images the data field is decoded afresh, so
+    /// weak cells can read differently each time.
+    pub fn read_sector_at(&mut self, cylinder: u8, head: u8, index: usize) -> Option<Sector> {
+        let track = self.track_index(cylinder, head)?;
+        let mut sector = self.tracks[track].get(index)?.clone();
+        if let Some(flux) = self.flux.get_mut(track) {
+            let data_pos = flux.sectors(false).get(index).and_then(|found| found.data_pos);
+            if let Some(data_pos) = data_pos {
+                let size = sector.id.size();
+                if let Some((data, deleted, crc_error)) =
+                    flux.read_data_field(data_pos, size, true)
+                {
+                    sector.data = data;
+                    sector.deleted = deleted;
+                    sector.crc_error = crc_error;
+                }
+            }
+        }
+        Some(sector)
+    }
+
+    /// Data rate a sector image's tracks are laid down at.
+    fn default_data_rate(&self) -> u16 {
+        match self.geometry.sectors_per_track {
+            0..=10 => 250,
+            11..=21 => 500,
+            _ => 1000,
+        }
+    }
+
+    /// Bit cells of a physical track, encoded from its sectors unless the
+    /// image was loaded at the bit level.
+    pub fn flux_track(&self, cylinder: u8, head: u8) -> MfmTrack {
+        match self.track_index(cylinder, head) {
+            Some(index) if index < self.flux.len() => self.flux[index].clone(),
+            _ => MfmTrack::from_sectors(self.track(cylinder, head), self.default_data_rate()),
+        }
+    }
+
+    /// Decodes a bit cell track again after it was written.
+    fn refresh_track(&mut self, track: usize) {
+        let sectors = self.flux[track].sectors(false);
+        self.tracks[track] = sectors.into_iter().map(|found| found.sector).collect();
+    }
+
+    /// Reads the first sector numbered `sector` on a physical track.
+    pub fn read_sector(&self, cylinder: u8, head: u8, sector: u8) -> Result<&[u8], FloppyError> {
+        match self
+            .track(cylinder, head)
+            .iter()
+            .find(|s| s.id.sector == sector && !s.data.is_empty())
+        {
+            Some(s) => Ok(&s.data),
+            None => Err(FloppyError::SectorNotFound),
+        }
+    }
+
+    /// Size from the ID field of the first sector numbered `sector` on a
+    /// physical track.
+    pub fn sector_size(&self, cylinder: u8, head: u8, sector: u8) -> Option<usize> {
+        self.track(cylinder, head)
+            .iter()
+            .find(|s| s.id.sector == sector)
+            .map(|s| s.id.size())
+    }
+
+    /// Writes to the first sector numbered `sector` on a physical track.
+    pub fn write_sector(
+        &mut self,
+        cylinder: u8,
+        head: u8,
+        sector: u8,
+        data: &[u8],
+    ) -> Result<(), FloppyError> {
+        let index = self
+            .track(cylinder, head)
+            .iter()
+            .position(|s| s.id.sector == sector)
+            .ok_or(FloppyError::SectorNotFound)?;
+        self.write_sector_at(cylinder, head, index, data, false)
+    }
+
+    /// Replaces the data field of the `index`th sector of a physical track,
+    /// keeping its size, and writes it through to the backing file.
+    pub fn write_sector_at(
+        &mut self,
+        cylinder: u8,
+        head: u8,
+        index: usize,
+        data: &[u8],
+        deleted: bool,
+    ) -> Result<(), FloppyError> {
+        if self.is_write_protected() {
+            return Err(FloppyError::WriteProtected);
+        }
+        let track = self
+            .track_index(cylinder, head)
+            .ok_or(FloppyError::SectorNotFound)?;
+        if let Some(flux) = self.flux.get_mut(track) {
+            let found = flux.sectors(false);
+            let found = found.get(index).ok_or(FloppyError::SectorNotFound)?;
+            let data_pos = found.data_pos.ok_or(FloppyError::SectorNotFound)?;
+            let mut data = data.to_vec();
+            data.resize(found.sector.id.size(), 0);
+            flux.write_data_field(data_pos, &data, deleted);
+            self.refresh_track(track);
+            self.write_back(cylinder, head, index)?;
+            return Ok(());
+        }
+        let sector = self.tracks[track]
+            .get_mut(index)
+            .ok_or(FloppyError::SectorNotFound)?;
+        let size = sector.id.size();
+        sector.data.resize(size, 0);
+        let len = data.len().min(size);
+        sector.data[..len].copy_from_slice(&data[..len]);
+        sector.deleted = deleted;
+        sector.crc_error = false;
+        self.write_back(cylinder, head, index)?;
+        Ok(())
+    }
+
+    /// Lays down a new track with every sector filled with `fill`.
+    pub fn format_track(
+        &mut self,
+        cylinder: u8,
+        head: u8,
+        ids: &[SectorId],
+        fill: u8,
+    ) -> Result<(), FloppyError> {
+        if self.is_write_protected() {
+            return Err(FloppyError::WriteProtected);
+        }
+        if self.format == FloppyFormat::Raw {
+            // A flat image can only hold its own layout.
+            for id in ids {
+                self.write_sector(cylinder, head, id.sector, &[fill; 512])?;
+            }
+            return Ok(());
+        }
+        let track = self
+            .track_index(cylinder, head)
+            .ok_or(FloppyError::SectorNotFound)?;
+        self.tracks[track] = ids
+            .iter()
+            .map(|&id| Sector {
+                id,
+                data: vec![fill; id.size()],
+                deleted: false,
+                crc_error: false,
+            })
+            .collect();
+        if let Some(flux) = self.flux.get(track) {
+            self.flux[track] = MfmTrack::from_sectors(&self.tracks[track], flux.data_rate);
+            self.refresh_track(track);
+            self.write_back(cylinder, head, 0)?;
+        }
+        Ok(())
+    }
+
+    /// Whether writes are refused. A copy-on-write session makes even a
+    /// protected disk writable, since the base image is never touched.
+    pub fn is_write_protected(&self) -> bool {
+        self.write_protected && self.overlay_base.is_none()
+    }
+
+    /// Starts a copy-on-write session: writes from now on are kept in
+    /// memory until committed or discarded.
+    pub fn begin_overlay(&mut self) {
+        if self.overlay_base.is_none() {
+            self.overlay_base = Some((self.tracks.clone(), self.flux.clone()));
+        }
+    }
+
+    /// Writes the session's changes to the image file and starts a fresh
+    /// session on top of them. A failed commit keeps the session, so its
+    /// changes can still be discarded.
+    pub fn commit_overlay(&mut self) -> io::Result<()> {
+        if self.overlay_base.is_none() {
+            return Ok(());
+        }
+        if self.path.is_some() && matches!(self.format, FloppyFormat::Imd | FloppyFormat::Td0) {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "IMD and TD0 images cannot be written back",
+            ));
+        }
+        if self.write_protected {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "floppy image is write protected",
+            ));
+        }
+        if self.format == FloppyFormat::Raw {
+            for cylinder in 0..self.geometry.cylinders {
+                for head in 0..self.geometry.heads {
+                    for index in 0..self.track(cylinder, head).len() {
+                        self.write_to_file(cylinder, head, index)?;
+                    }
+                }
+            }
+        } else {
+            self.write_to_file(0, 0, 0)?;
+        }
+        self.overlay_base = Some((self.tracks.clone(), self.flux.clone()));
+        Ok(())
+    }
+
+    /// Throws away every write made since the session began or was last
+    /// committed.
+    pub fn discard_overlay(&mut self) {
+        if let Some((tracks, flux)) = &self.overlay_base {
+            self.tracks = tracks.clone();
+            self.flux = flux.clone();
+        }
+    }
+
+    /// Ends the session, keeping its changes in memory only.
+    pub fn end_overlay(&mut self) {
+        self.overlay_base = None;
+    }
+
+    /// Writes a sector back to the image file unless a copy-on-write
+    /// session is holding the changes in memory.
+    fn write_back(&self, cylinder: u8, head: u8, index: usize) -> io::Result<()> {
+        if self.overlay_base.is_some() {
+            return Ok(());
+        }
+        self.write_to_file(cylinder, head, index)
+    }
+
+    /// Writes a sector to a raw image file, or the whole file for bit cell
+    /// images. IMD and TD0 files cannot be written and are refused.
+    fn write_to_file(&self, cylinder: u8, head: u8, index: usize) -> io::Result<()> {
+        let path = match (&self.path, self.format) {
+            (Some(path), FloppyFormat::Hfe) => return fs::write(path, self.to_hfe()),
+            (Some(path), FloppyFormat::D86f) => return fs::write(path, self.to_86f()),
+            (Some(path), FloppyFormat::Raw) => path,
+            (Some(_), _) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "IMD and TD0 images cannot be written back",
+                ))
+            }
+            (None, _) => return Ok(()),
+        };
+        let sector = &self.track(cylinder, head)[index];
+        let geometry = &self.geometry;
+        let lba = (cylinder as usize * geometry.heads as usize + head as usize)
+            * geometry.sectors_per_track as usize
+            + (sector.id.sector as usize).saturating_sub(1);
+        let mut file: File = OpenOptions::new().write(true).open(path)?;
+        file.seek(SeekFrom::Start((lba << 9) as u64))?;
+        file.write_all(&sector.data)
+    }
+}
+
+#[test]
+fn test_floppy_geometry_detection() {
+    assert_eq!(
+        FloppyGeometry::from_size(160 * 1024),
+        Some(FloppyGeometry::new(40, 1, 8))
+    );
+    assert_eq!(
+        FloppyGeometry::from_size(1440 * 1024),
+        Some(FloppyGeometry::new(80, 2, 18))
+    );
+    assert_eq!(
+        FloppyGeometry::from_size(2880 * 1024),
+        Some(FloppyGeometry::new(80, 2, 36))
+    );
+    assert_eq!(FloppyGeometry::from_size(1000), None);
+    // A truncated 720K image still mounts through its BPB.
+    let mut data = vec![0u8; 100 * 512];
+    data[0x0b..0x0d].copy_from_slice(&512u16.to_le_bytes());
+    data[0x13..0x15].copy_from_slice(&1440u16.to_le_bytes());
+    data[0x18] = 9;
+    data[0x1a] = 2;
+    let image = FloppyImage::from_bytes(data).unwrap();
+    assert_eq!(image.geometry, FloppyGeometry::new(80, 2, 9));
+    assert_eq!(image.read_sector(0, 1, 1).unwrap().len(), 512);
+    assert_eq!(
+        image.read_sector(10, 0, 1),
+        Err(FloppyError::SectorNotFound)
+    );
+}
+
+#[test]
+fn test_floppy_write_protect_and_write_back() {
+    let path = std::env::temp_dir().join(format!("emupc-floppy-{}.img", std::process::id()));
+    fs::write(&path, vec![0u8; 360 * 1024]).unwrap();
+    let mut image = FloppyImage::open(&path).unwrap();
+    assert_eq!(image.geometry, FloppyGeometry::new(40, 2, 9));
+    image.write_sector(1, 1, 9, &[0xa5; 512]).unwrap();
+    assert_eq!(fs::read(&path).unwrap()[(3 * 9 + 8) * 512], 0xa5);
+    image.write_protected = true;
+    assert_eq!(
+        image.write_sector(0, 0, 1, &[0; 512]),
+        Err(FloppyError::WriteProtected)
+    );
+    fs::remove_file(&path).unwrap();
+    // A write the file never saw is an error, not a silent success.
+    image.write_protected = false;
+    let err = image.write_sector(0, 0, 1, &[0; 512]).unwrap_err();
+    assert_eq!(err, FloppyError::Io(io::ErrorKind::NotFound));
+    assert_eq!(err.bios_status(), 0x20);
+}
+
+#[test]
+fn test_floppy_overlay_session() {
+    let path = std::env::temp_dir().join(format!("emupc-floppy-ovl-{}.img", std::process::id()));
+    fs::write(&path, vec![0x11u8; 160 * 1024]).unwrap();
+    let mut image = FloppyImage::open(&path).unwrap();
+    image.write_protected = true;
+    image.begin_overlay();
+    assert!(!image.is_write_protected());
+    image.write_sector(0, 0, 2, &[0x22; 512]).unwrap();
+    assert_eq!(image.read_sector(0, 0, 2).unwrap()[0], 0x22);
+    assert_eq!(fs::read(&path).unwrap()[512], 0x11);
+    image.discard_overlay();
+    assert_eq!(image.read_sector(0, 0, 2).unwrap()[0], 0x11);
+    image.write_sector(0, 0, 3, &[0x33; 512]).unwrap();
+    assert!(image.commit_overlay().is_err());
+    image.write_protected = false;
+    image.commit_overlay().unwrap();
+    assert_eq!(fs::read(&path).unwrap()[1024], 0x33);
+    // The committed state is the new base for discards.
+    image.write_sector(0, 0, 3, &[0x44; 512]).unwrap();
+    image.discard_overlay();
+    assert_eq!(image.read_sector(0, 0, 3).unwrap()[0], 0x33);
+    // A commit the file never saw keeps the session to discard.
+    image.write_sector(0, 0, 3, &[0x55; 512]).unwrap();
+    fs::remove_file(&path).unwrap();
+    let err = image.commit_overlay().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    image.discard_overlay();
+    assert_eq!(image.read_sector(0, 0, 3).unwrap()[0], 0x33);
+    // IMD and TD0 files cannot take the changes at all.
+    let sectors = image.track(0, 0).to_vec();
+    let mut image = FloppyImage::from_tracks(vec![(0, 0, sectors)], FloppyFormat::Imd).unwrap();
+    image.path = Some(path);
+    image.begin_overlay();
+    image.write_sector(0, 0, 3, &[0x66; 512]).unwrap();
+    let err = image.commit_overlay().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+    image.discard_overlay();
+    assert_eq!(image.read_sector(0, 0, 3).unwrap()[0], 0x33);
+}
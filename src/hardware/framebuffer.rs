@@ -0,0 +1,251 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+/// Largest block a stored (uncompressed) deflate block can hold.
+const STORED_BLOCK_MAX: usize = 0xffff;
+
+/// One complete emulated frame as 0x00RRGGBB pixels, row by row. Video
+/// adapters draw into their own and hand it over when the frame ends, so
+/// the latest one is always whole.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Framebuffer {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u32>,
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if (crc & 1) != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+impl Framebuffer {
+    /// A black frame.
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![0; width * height],
+        }
+    }
+
+    /// Changes the size, blacking out the whole frame.
+    pub fn resize(&mut self, width: usize, height: usize) {
+        self.width = width;
+        self.height = height;
+        self.pixels.clear();
+        self.pixels.resize(width * height, 0);
+    }
+
+    pub fn fill(&mut self, color: u32) {
+        self.pixels.fill(color);
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> u32 {
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn row(&self, y: usize) -> &[u32] {
+        &self.pixels[y * self.width..][..self.width]
+    }
+
+    pub fn row_mut(&mut self, y: usize) -> &mut [u32] {
+        &mut self.pixels[y * self.width..][..self.width]
+    }
+
+    /// Number of pixels that differ from `other`, or None if the sizes
+    /// differ.
+    pub fn diff(&self, other: &Framebuffer) -> Option<usize> {
+        if (self.width, self.height) != (other.width, other.height) {
+            return None;
+        }
+        Some(
+            self.pixels
+                .iter()
+                .zip(&other.pixels)
+                .filter(|(a, b)| a != b)
+                .count(),
+        )
+    }
+
+    /// Encodes the frame as an 8-bit RGB PNG. The image data is stored
+    /// without compression, which keeps the encoder to a page of code.
+    pub fn to_png(&self) -> Vec<u8> {
+        let mut raw = Vec::with_capacity((self.width * 3 + 1) * self.height);
+        for y in 0..self.height {
+            // Filter type 0: the row as it is.
+            raw.push(0);
+            for &pixel in self.row(y) {
+                raw.extend_from_slice(&pixel.to_be_bytes()[1..]);
+            }
+        }
+        let mut zlib = vec![0x78, 0x01];
+        let mut blocks = raw.chunks(STORED_BLOCK_MAX).peekable();
+        if blocks.peek().is_none() {
+            zlib.extend_from_slice(&[0x01, 0x00, 0x00, 0xff, 0xff]);
+        }
+        while let Some(block) = blocks.next() {
+            zlib.push(blocks.peek().is_none() as u8);
+            zlib.extend_from_slice(&(block.len() as u16).to_le_bytes());
+            zlib.extend_from_slice(&(!(block.len() as u16)).to_le_bytes());
+            zlib.extend_from_slice(block);
+        }
+        zlib.extend_from_slice(&adler32(&raw).to_be_bytes());
+
+        let mut header = Vec::with_capacity(13);
+        header.extend_from_slice(&(self.width as u32).to_be_bytes());
+        header.extend_from_slice(&(self.height as u32).to_be_bytes());
+        // 8 bits per sample, RGB, deflate, adaptive filtering, no
+        // interlace.
+        header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+        let mut png = PNG_SIGNATURE.to_vec();
+        write_chunk(&mut png, b"IHDR", &header);
+        write_chunk(&mut png, b"IDAT", &zlib);
+        write_chunk(&mut png, b"IEND", &[]);
+        png
+    }
+
+    pub fn save_png<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_png())
+    }
+
+    /// Decodes a PNG written by `to_png`, such as a golden image saved by
+    /// an earlier run. Compressed image data is not supported.
+    pub fn from_png(png: &[u8]) -> io::Result<Framebuffer> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message);
+        if png.len() < 8 || png[..8] != PNG_SIGNATURE {
+            return Err(invalid("not a PNG file"));
+        }
+        let mut header = None;
+        let mut zlib = Vec::new();
+        let mut pos = 8;
+        while pos + 12 <= png.len() {
+            let len = u32::from_be_bytes(png[pos..pos + 4].try_into().unwrap()) as usize;
+            let kind = &png[pos + 4..pos + 8];
+            let data = png
+                .get(pos + 8..pos + 8 + len)
+                .ok_or_else(|| invalid("truncated chunk"))?;
+            match kind {
+                b"IHDR" if len == 13 => header = Some(data),
+                b"IDAT" => zlib.extend_from_slice(data),
+                b"IEND" => break,
+                _ => {}
+            }
+            pos += len + 12;
+        }
+        let header = header.ok_or_else(|| invalid("missing IHDR"))?;
+        let width = u32::from_be_bytes(header[0..4].try_into().unwrap()) as usize;
+        let height = u32::from_be_bytes(header[4..8].try_into().unwrap()) as usize;
+        if header[8..] != [8, 2, 0, 0, 0] {
+            return Err(invalid("only 8-bit RGB PNGs are supported"));
+        }
+
+        let mut raw = Vec::new();
+        let mut pos = 2;
+        loop {
+            let block = zlib
+                .get(pos..pos + 5)
+                .ok_or_else(|| invalid("truncated data"))?;
+            if (block[0] & 0x06) != 0 {
+                return Err(invalid("compressed PNGs are not supported"));
+            }
+            let len = u16::from_le_bytes([block[1], block[2]]) as usize;
+            let data = zlib
+                .get(pos + 5..pos + 5 + len)
+                .ok_or_else(|| invalid("truncated data"))?;
+            raw.extend_from_slice(data);
+            pos += 5 + len;
+            if (block[0] & 0x01) != 0 {
+                break;
+            }
+        }
+
+        let stride = width * 3 + 1;
+        if raw.len() < stride * height {
+            return Err(invalid("truncated image"));
+        }
+        let mut frame = Framebuffer::new(width, height);
+        for y in 0..height {
+            let line = &raw[y * stride..][..stride];
+            if line[0] != 0 {
+                return Err(invalid("filtered PNGs are not supported"));
+            }
+            for (pixel, rgb) in frame.row_mut(y).iter_mut().zip(line[1..].chunks(3)) {
+                *pixel = u32::from_be_bytes([0, rgb[0], rgb[1], rgb[2]]);
+            }
+        }
+        Ok(frame)
+    }
+
+    pub fn load_png<P: AsRef<Path>>(path: P) -> io::Result<Framebuffer> {
+        Framebuffer::from_png(&fs::read(path)?)
+    }
+}
+
+#[test]
+fn test_framebuffer_png_round_trip() {
+    assert_eq!(crc32(b"IEND"), 0xae42_6082);
+    assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+    // Big enough to need several stored blocks.
+    let mut frame = Framebuffer::new(640, 200);
+    for (i, pixel) in frame.pixels.iter_mut().enumerate() {
+        *pixel = (i as u32).wrapping_mul(0x9e37_79b9) & 0xff_ffff;
+    }
+    let png = frame.to_png();
+    assert_eq!(png[..8], PNG_SIGNATURE);
+    assert_eq!(&png[12..16], b"IHDR");
+    assert_eq!(
+        &png[png.len() - 12..],
+        &[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xae, 0x42, 0x60, 0x82]
+    );
+    let decoded = Framebuffer::from_png(&png).unwrap();
+    assert_eq!(decoded.diff(&frame), Some(0));
+    // Saved screenshots load back as golden images.
+    let path = std::env::temp_dir().join("emupc_framebuffer_test.png");
+    frame.save_png(&path).unwrap();
+    let mut golden = Framebuffer::load_png(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(golden, frame);
+    golden.row_mut(199)[639] ^= 1;
+    golden.pixels[0] ^= 0x01_0000;
+    assert_eq!(golden.diff(&frame), Some(2));
+    assert_eq!(Framebuffer::new(320, 200).diff(&frame), None);
+    let empty = Framebuffer::new(0, 0).to_png();
+    assert_eq!(
+        Framebuffer::from_png(&empty).unwrap(),
+        Framebuffer::new(0, 0)
+    );
+    assert!(Framebuffer::from_png(b"GIF89a").is_err());
+}
@@ -0,0 +1,307 @@
+use crate::hardware::overlay::DiskOverlay;
+use crate::hardware::vhd::Vhd;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct HardDiskGeometry {
+    pub cylinders: u16,
+    pub heads: u8,
+    pub sectors_per_track: u8,
+}
+
+impl HardDiskGeometry {
+    pub const fn new(cylinders: u16, heads: u8, sectors_per_track: u8) -> Self {
+        Self {
+            cylinders,
+            heads,
+            sectors_per_track,
+        }
+    }
+
+    pub fn total_sectors(&self) -> usize {
+        self.cylinders as usize * self.heads as usize * self.sectors_per_track as usize
+    }
+
+    /// Block address of a cylinder, head and 1-based sector, if it lies on
+    /// the disk.
+    pub fn lba(&self, cylinder: u16, head: u8, sector: u8) -> Option<usize> {
+        if cylinder >= self.cylinders
+            || head >= self.heads
+            || sector == 0
+            || sector > self.sectors_per_track
+        {
+            return None;
+        }
+        Some(
+            (cylinder as usize * self.heads as usize + head as usize)
+                * self.sectors_per_track as usize
+                + sector as usize
+                - 1,
+        )
+    }
+
+    /// Cylinder, head and 1-based sector of a block address.
+    pub fn chs(&self, lba: usize) -> (u16, u8, u8) {
+        let spt = self.sectors_per_track.max(1) as usize;
+        let track = lba / spt;
+        let heads = self.heads.max(1) as usize;
+        (
+            (track / heads) as u16,
+            (track % heads) as u8,
+            (lba % spt + 1) as u8,
+        )
+    }
+}
+
+/// Sectors per allocation unit of the in-memory sector store.
+const CHUNK_SECTORS: usize = 128;
+
+static ZERO_SECTOR: [u8; 512] = [0; 512];
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum HardDiskFormat {
+    #[default]
+    Raw,
+    VhdFixed,
+    VhdDynamic,
+}
+
+/// A fixed disk of 512-byte sectors, held sparsely so that never-written
+/// areas of large images cost no memory, optionally backed by an image
+/// file that writes go through to.
+#[derive(Debug, Clone, Default)]
+pub struct HardDiskImage {
+    /// Sector data in runs of `CHUNK_SECTORS`, None where all zero.
+    pub chunks: Vec<Option<Vec<u8>>>,
+    pub geometry: HardDiskGeometry,
+    pub format: HardDiskFormat,
+    /// Footer and block allocation table of a VHD image.
+    pub vhd: Option<Vhd>,
+    /// Copy-on-write layer that takes every write while attached, leaving
+    /// the sectors above and the image file untouched.
+    pub overlay: Option<DiskOverlay>,
+    pub path: Option<PathBuf>,
+    pub write_protected: bool,
+}
+
+impl HardDiskImage {
+    /// An empty disk of the given geometry.
+    pub fn new(geometry: HardDiskGeometry) -> Self {
+        Self {
+            chunks: vec![None; geometry.total_sectors().div_ceil(CHUNK_SECTORS)],
+            geometry,
+            ..Default::default()
+        }
+    }
+
+    /// Wraps a raw image, padding or truncating it to the geometry.
+    pub fn from_bytes(data: Vec<u8>, geometry: HardDiskGeometry) -> Self {
+        let mut image = Self::new(geometry);
+        let len = (geometry.total_sectors() << 9).min(data.len());
+        for (index, chunk) in data[..len].chunks(CHUNK_SECTORS << 9).enumerate() {
+            image.load_sectors(index * CHUNK_SECTORS, chunk);
+        }
+        image
+    }
+
+    /// Stores sectors read from an image file, starting at `lba`, without
+    /// writing them back.
+    pub fn load_sectors(&mut self, lba: usize, data: &[u8]) {
+        for (i, sector) in data.chunks(512).enumerate() {
+            if lba + i < self.total_sectors() && sector.iter().any(|&byte| byte != 0) {
+                self.sector_mut(lba + i)[..sector.len()].copy_from_slice(sector);
+            }
+        }
+    }
+
+    /// Loads a raw or VHD image file. Raw images take the given geometry,
+    /// VHD images the one in their footer. Read-only files are mounted
+    /// write protected.
+    pub fn open<P: AsRef<Path>>(path: P, geometry: HardDiskGeometry) -> io::Result<Self> {
+        let path = path.as_ref();
+        let data = fs::read(path)?;
+        let mut image = if Vhd::detect(&data) {
+            Self::from_vhd(&data)?
+        } else if data.len() < geometry.total_sectors() << 9 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "hard disk image smaller than its geometry",
+            ));
+        } else {
+            Self::from_bytes(data, geometry)
+        };
+        image.write_protected = fs::metadata(path)?.permissions().readonly();
+        image.path = Some(path.to_path_buf());
+        Ok(image)
+    }
+
+    pub fn total_sectors(&self) -> usize {
+        self.geometry.total_sectors()
+    }
+
+    pub fn read_sector(&self, lba: usize) -> Option<&[u8]> {
+        if lba >= self.total_sectors() {
+            return None;
+        }
+        if let Some(data) = self.overlay.as_ref().and_then(|o| o.read_sector(lba)) {
+            return Some(data);
+        }
+        Some(self.base_sector(lba))
+    }
+
+    fn base_sector(&self, lba: usize) -> &[u8] {
+        match &self.chunks[lba / CHUNK_SECTORS] {
+            Some(chunk) => {
+                let offset = (lba % CHUNK_SECTORS) << 9;
+                &chunk[offset..offset + 512]
+            }
+            None => &ZERO_SECTOR,
+        }
+    }
+
+    fn sector_mut(&mut self, lba: usize) -> &mut [u8] {
+        let chunk =
+            self.chunks[lba / CHUNK_SECTORS].get_or_insert_with(|| vec![0; CHUNK_SECTORS << 9]);
+        let offset = (lba % CHUNK_SECTORS) << 9;
+        &mut chunk[offset..offset + 512]
+    }
+
+    /// Writes a sector to the overlay if one is attached, otherwise to the
+    /// disk and through to the backing file. Fails if the sector is out of
+    /// range, the disk is write protected or the file write fails.
+    pub fn write_sector(&mut self, lba: usize, data: &[u8]) -> io::Result<()> {
+        if lba >= self.total_sectors() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "sector beyond the end of the disk",
+            ));
+        }
+        if let Some(overlay) = &mut self.overlay {
+            return overlay.write_sector(lba, data);
+        }
+        self.write_base_sector(lba, data)
+    }
+
+    fn write_base_sector(&mut self, lba: usize, data: &[u8]) -> io::Result<()> {
+        if self.write_protected {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "hard disk image is write protected",
+            ));
+        }
+        let len = data.len().min(512);
+        if self.chunks[lba / CHUNK_SECTORS].is_some() || data[..len].iter().any(|&b| b != 0) {
+            let sector = self.sector_mut(lba);
+            sector[..len].copy_from_slice(&data[..len]);
+            sector[len..].fill(0);
+        }
+        self.write_back(lba)
+    }
+
+    /// Starts a copy-on-write session: from now on writes land in the
+    /// overlay and the image is only read.
+    pub fn attach_overlay(&mut self, overlay: DiskOverlay) {
+        self.overlay = Some(overlay);
+    }
+
+    /// Ends a session, returning its overlay with whatever it holds.
+    pub fn detach_overlay(&mut self) -> Option<DiskOverlay> {
+        self.overlay.take()
+    }
+
+    /// Writes the overlay's sectors into the image and its file, leaving
+    /// the session running with an empty overlay. The overlay is kept whole
+    /// if any sector fails to reach the file.
+    pub fn commit_overlay(&mut self) -> io::Result<()> {
+        let mut overlay = match self.overlay.take() {
+            Some(overlay) => overlay,
+            None => return Ok(()),
+        };
+        if self.write_protected {
+            self.overlay = Some(overlay);
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "hard disk image is write protected",
+            ));
+        }
+        let result = overlay
+            .sectors
+            .iter()
+            .try_for_each(|(&lba, data)| self.write_base_sector(lba, data))
+            .and_then(|()| overlay.clear());
+        self.overlay = Some(overlay);
+        result
+    }
+
+    /// Throws away every write made since the session started or was last
+    /// committed.
+    pub fn discard_overlay(&mut self) -> io::Result<()> {
+        match &mut self.overlay {
+            Some(overlay) => overlay.clear(),
+            None => Ok(()),
+        }
+    }
+
+    fn write_back(&mut self, lba: usize) -> io::Result<()> {
+        let path = match &self.path {
+            Some(path) => path.clone(),
+            None => return Ok(()),
+        };
+        let mut file: File = OpenOptions::new().read(true).write(true).open(path)?;
+        let data = self.base_sector(lba).to_vec();
+        match &mut self.vhd {
+            Some(vhd) if self.format == HardDiskFormat::VhdDynamic => {
+                vhd.write_sector(&mut file, lba, &data)
+            }
+            _ => {
+                file.seek(SeekFrom::Start((lba << 9) as u64))?;
+                file.write_all(&data)
+            }
+        }
+    }
+}
+
+#[test]
+fn test_hard_disk_overlay_commit_and_discard() {
+    let dir = std::env::temp_dir();
+    let base = dir.join(format!("emupc-overlay-base-{}.img", std::process::id()));
+    let journal = dir.join(format!("emupc-overlay-{}.ovl", std::process::id()));
+    let geometry = HardDiskGeometry::new(10, 2, 17);
+    fs::write(&base, vec![0x11u8; geometry.total_sectors() << 9]).unwrap();
+    let mut image = HardDiskImage::open(&base, geometry).unwrap();
+    image.write_protected = true;
+    image.attach_overlay(DiskOverlay::open(&journal).unwrap());
+    // Writes land in the overlay even though the base is read-only.
+    image.write_sector(3, &[0x22; 512]).unwrap();
+    assert_eq!(image.read_sector(3).unwrap(), &[0x22; 512][..]);
+    assert_eq!(fs::read(&base).unwrap()[3 << 9], 0x11);
+    // The journal resumes the session.
+    let overlay = DiskOverlay::open(&journal).unwrap();
+    assert_eq!(overlay.read_sector(3).unwrap(), &[0x22; 512][..]);
+    image.discard_overlay().unwrap();
+    assert_eq!(image.read_sector(3).unwrap(), &[0x11; 512][..]);
+    image.write_sector(4, &[0x33; 512]).unwrap();
+    assert!(image.commit_overlay().is_err());
+    image.write_protected = false;
+    image.commit_overlay().unwrap();
+    assert!(image.overlay.as_ref().unwrap().sectors.is_empty());
+    assert_eq!(image.read_sector(4).unwrap(), &[0x33; 512][..]);
+    assert_eq!(fs::read(&base).unwrap()[4 << 9], 0x33);
+    assert_eq!(fs::read(&journal).unwrap().len(), 8);
+    // A commit that cannot reach the image keeps the overlay and journal.
+    image.write_sector(5, &[0x44; 512]).unwrap();
+    fs::remove_file(&base).unwrap();
+    assert!(image.commit_overlay().is_err());
+    assert_eq!(
+        image.overlay.as_ref().unwrap().read_sector(5).unwrap(),
+        &[0x44; 512][..]
+    );
+    assert_eq!(fs::read(&journal).unwrap().len(), 8 + 8 + 512);
+    // A write the journal never took fails rather than vanishing.
+    image.overlay.as_mut().unwrap().path = Some(dir.join("emupc-missing-dir/disk.ovl"));
+    assert!(image.write_sector(6, &[0x55; 512]).is_err());
+    assert_eq!(image.read_sector(6).unwrap(), &[0x11; 512][..]);
+    fs::remove_file(&journal).unwrap();
+}
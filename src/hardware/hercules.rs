@@ -0,0 +1,192 @@
+#[cfg(test)]
+use crate::hardware::crtc::run_frames;
+use crate::hardware::mda::*;
+use crate::hardware::textscreen::TextScreen;
+
+const CONFIG_ALLOW_GRAPHICS: u8 = 0x01;
+const CONFIG_ENABLE_PAGE1: u8 = 0x02;
+
+const MODE_GRAPHICS: u8 = 0x02;
+const MODE_VIDEO_ENABLE: u8 = 0x08;
+const MODE_PAGE1: u8 = 0x80;
+
+/// Hercules Graphics Card: an MDA with 64K of memory at B000:0 and a
+/// 720x348 graphics mode. The configuration switch at 0x3BF has to allow
+/// graphics before the mode control port can select it, and has to
+/// enable the second page before it appears at B800:0. Each graphics
+/// page interleaves four banks of 8K, one per scanline of a CRTC row.
+#[derive(Debug, Clone)]
+pub struct HGC {
+    /// The text mode half: CRTC, font and framebuffer, with text at the
+    /// start of the 64K of memory.
+    pub mda: MDA,
+    pub config: u8,
+    pub mode: u8,
+}
+
+impl HGC {
+    pub fn new() -> Self {
+        let mut mda = MDA::new();
+        mda.vram = vec![0; 0x10000];
+        Self {
+            mda,
+            config: 0,
+            mode: 0,
+        }
+    }
+
+    /// Whether an address in B000:0-BFFF:F reaches the card.
+    pub fn maps(&self, addr: u32) -> bool {
+        (addr & 0xffff) < 0x8000 || (self.config & CONFIG_ENABLE_PAGE1) != 0
+    }
+
+    pub fn read_vram(&self, addr: u32) -> u8 {
+        self.mda.vram[(addr & 0xffff) as usize]
+    }
+
+    pub fn write_vram(&mut self, addr: u32, data: u8) {
+        self.mda.vram[(addr & 0xffff) as usize] = data;
+    }
+
+    fn graphics(&self) -> bool {
+        (self.mode & MODE_GRAPHICS) != 0 && (self.config & CONFIG_ALLOW_GRAPHICS) != 0
+    }
+
+    /// The displayed text page, unless the card is showing graphics.
+    pub fn text_screen(&self) -> Option<TextScreen> {
+        if self.graphics() {
+            return None;
+        }
+        Some(self.mda.text_screen())
+    }
+
+    /// Runs the card for `cycles` clocks of a CPU running at `cpu_clock`
+    /// Hz. Graphics mode clocks the CRTC every sixteen dots instead of
+    /// nine.
+    pub fn tick(&mut self, cycles: usize, cpu_clock: u64) {
+        self.mda.phase += cycles as u64 * MDA_DOT_CLOCK;
+        loop {
+            let char_dots = if self.graphics() { 16 } else { 9 };
+            let period = cpu_clock * char_dots;
+            if self.mda.phase < period {
+                break;
+            }
+            self.mda.phase -= period;
+            if self.mda.crtc.display_enable() {
+                if self.graphics() {
+                    self.draw_graphics();
+                } else {
+                    self.mda.draw_char();
+                }
+            }
+            if self.mda.crtc.clock() {
+                self.mda.end_frame();
+            }
+        }
+    }
+
+    /// Draws the sixteen pixels under the beam into the back buffer.
+    fn draw_graphics(&mut self) {
+        let crtc = &self.mda.crtc;
+        let x = crtc.hcount as usize * 16;
+        let y = crtc.line as usize;
+        if x + 16 > MDA_WIDTH || y >= MDA_HEIGHT || (self.mode & MODE_VIDEO_ENABLE) == 0 {
+            return;
+        }
+        let page = if (self.mode & MODE_PAGE1) != 0 && (self.config & CONFIG_ENABLE_PAGE1) != 0 {
+            0x8000
+        } else {
+            0
+        };
+        let offset = page | ((crtc.ra as usize & 3) << 13) | ((crtc.ma as usize * 2) & 0x1fff);
+        let vram = &self.mda.vram;
+        let data = u16::from_be_bytes([vram[offset], vram[offset + 1]]);
+        let pixels = &mut self.mda.back.row_mut(y)[x..][..16];
+        for (x, pixel) in pixels.iter_mut().enumerate() {
+            *pixel = if (data & (0x8000 >> x)) != 0 {
+                MDA_GREEN
+            } else {
+                MDA_BLACK
+            };
+        }
+    }
+
+    /// Bit 7 of the status port is low during vertical sync, which tells
+    /// a Hercules card apart from an MDA.
+    pub fn rb(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x3ba => {
+                let crtc = &self.mda.crtc;
+                0x70 | (crtc.hsync as u8) | ((crtc.vsync as u8) << 3) | ((!crtc.vsync as u8) << 7)
+            }
+            _ => self.mda.rb(addr),
+        }
+    }
+
+    pub fn wb(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x3b8 => {
+                self.mode = data;
+                // The text half always runs from the 16 MHz clock, with
+                // no high resolution bit to set.
+                self.mda.wb(addr, data | 0x01);
+            }
+            0x3bf => self.config = data,
+            _ => self.mda.wb(addr, data),
+        }
+    }
+}
+
+impl Default for HGC {
+    fn default() -> HGC {
+        HGC::new()
+    }
+}
+
+#[test]
+fn test_hgc_graphics_pages() {
+    let pixel = |hgc: &HGC, x: usize, y: usize| hgc.mda.frame.pixel(x, y);
+    let mut hgc = HGC::new();
+    assert!(!hgc.maps(0xb_8000));
+    // 720x348 graphics: 45 characters of 16 dots, 87 rows of 4 lines.
+    let graphics = [
+        0x35, 0x2d, 0x2e, 0x07, 0x5b, 0x02, 0x57, 0x57, 0x02, 0x03, 0x00, 0x00,
+    ];
+    for (index, &value) in graphics.iter().enumerate() {
+        hgc.wb(0x3b4, index as u8);
+        hgc.wb(0x3b5, value);
+    }
+    hgc.wb(0x3bf, CONFIG_ALLOW_GRAPHICS | CONFIG_ENABLE_PAGE1);
+    assert!(hgc.maps(0xb_8000));
+    hgc.write_vram(0xb_8000, 0x80);
+    hgc.write_vram(0xb_a000, 0x01);
+    hgc.write_vram(0xb_805a, 0xff);
+    hgc.write_vram(0xb_0000, 0xff);
+    hgc.wb(0x3b8, MODE_GRAPHICS | MODE_VIDEO_ENABLE | MODE_PAGE1);
+    let mut vsync_seen = false;
+    let mut active_seen = false;
+    while hgc.mda.crtc.frame_count < 2 {
+        hgc.tick(200, 4_772_727);
+        let status = hgc.rb(0x3ba);
+        vsync_seen |= (status & 0x80) == 0;
+        active_seen |= (status & 0x80) != 0;
+    }
+    assert!(vsync_seen && active_seen);
+    assert_eq!(pixel(&hgc, 0, 0), MDA_GREEN);
+    assert_eq!(pixel(&hgc, 1, 0), MDA_BLACK);
+    // Scanline 1 comes from the second bank.
+    assert_eq!(pixel(&hgc, 7, 1), MDA_GREEN);
+    assert_eq!(pixel(&hgc, 0, 1), MDA_BLACK);
+    // The second CRTC row starts 90 bytes in.
+    assert_eq!(pixel(&hgc, 7, 4), MDA_GREEN);
+    assert_eq!(pixel(&hgc, 8, 4), MDA_BLACK);
+    // Page 0 at B000:0.
+    hgc.wb(0x3b8, MODE_GRAPHICS | MODE_VIDEO_ENABLE);
+    run_frames(&mut hgc, |hgc| hgc.mda.crtc.frame_count, HGC::tick);
+    assert_eq!(pixel(&hgc, 7, 0), MDA_GREEN);
+    assert_eq!(pixel(&hgc, 7, 1), MDA_BLACK);
+    // Without the configuration switch the card stays in text mode.
+    hgc.wb(0x3bf, 0);
+    run_frames(&mut hgc, |hgc| hgc.mda.crtc.frame_count, HGC::tick);
+    assert!(!hgc.graphics());
+}
@@ -0,0 +1,155 @@
+use crate::hardware::floppy::*;
+use crate::hardware::mfm::MfmTrack;
+use std::io;
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("HFE: {}", msg))
+}
+
+/// Track encoding value for IBM PC MFM.
+const ISOIBM_MFM_ENCODING: u8 = 0;
+
+impl FloppyImage {
+    /// Parses a version 1 HxC Floppy Emulator image: a 512-byte header, a
+    /// table of track offsets and lengths in 512-byte blocks, and for each
+    /// cylinder its two sides interleaved in 256-byte halves of each block,
+    /// least significant cell first.
+    pub fn from_hfe(data: &[u8]) -> io::Result<FloppyImage> {
+        if data.len() < 512 || !data.starts_with(b"HXCPICFE") {
+            return Err(invalid("bad signature"));
+        }
+        if data[8] != 0 {
+            return Err(invalid("only revision 1 images are supported"));
+        }
+        let (cylinders, sides) = (data[9], data[10]);
+        if data[11] != ISOIBM_MFM_ENCODING {
+            return Err(invalid("only MFM images are supported"));
+        }
+        let word = |offset: usize| u16::from_le_bytes([data[offset], data[offset + 1]]) as usize;
+        let data_rate = word(12) as u16;
+        let table = word(18) * 512;
+        let mut tracks = Vec::new();
+        for cylinder in 0..cylinders {
+            let entry = table + cylinder as usize * 4;
+            if entry + 4 > data.len() {
+                return Err(invalid("truncated track table"));
+            }
+            let offset = word(entry) * 512;
+            let side_len = word(entry + 2) / 2;
+            if offset + side_len.div_ceil(256) * 512 > data.len() {
+                return Err(invalid("truncated track data"));
+            }
+            for head in 0..sides.min(2) {
+                let mut track = MfmTrack::new(side_len * 8, data_rate);
+                for i in 0..side_len {
+                    let byte = data[offset + (i / 256) * 512 + head as usize * 256 + i % 256];
+                    track.cells[i] = byte.reverse_bits();
+                }
+                tracks.push((cylinder, head, track));
+            }
+        }
+        let mut image = FloppyImage::from_flux(tracks, FloppyFormat::Hfe)?;
+        image.write_protected = data[20] == 0;
+        Ok(image)
+    }
+
+    /// Writes the image as a version 1 HFE. The format has no way to mark
+    /// weak cells, so they are written as their current values.
+    pub fn to_hfe(&self) -> Vec<u8> {
+        let geometry = self.geometry;
+        let tracks: Vec<Vec<MfmTrack>> = (0..geometry.cylinders)
+            .map(|cylinder| {
+                (0..geometry.heads)
+                    .map(|head| self.flux_track(cylinder, head))
+                    .collect()
+            })
+            .collect();
+        let data_rate = tracks
+            .iter()
+            .flatten()
+            .map(|track| track.data_rate)
+            .max()
+            .unwrap_or(250);
+        let mut data = vec![0xff; 512];
+        data[..8].copy_from_slice(b"HXCPICFE");
+        data[8] = 0;
+        data[9] = geometry.cylinders;
+        data[10] = geometry.heads;
+        data[11] = ISOIBM_MFM_ENCODING;
+        data[12..14].copy_from_slice(&data_rate.to_le_bytes());
+        data[14..16].copy_from_slice(&300u16.to_le_bytes());
+        // Interface mode IBM PC DD or HD.
+        data[16] = if data_rate >= 500 { 1 } else { 0 };
+        data[17] = 1;
+        data[18..20].copy_from_slice(&1u16.to_le_bytes());
+        data[20] = if self.write_protected { 0 } else { 0xff };
+        let table_blocks = (tracks.len() * 4).div_ceil(512).max(1);
+        data.resize((1 + table_blocks) * 512, 0xff);
+        for (cylinder, sides) in tracks.iter().enumerate() {
+            let side_len = sides
+                .iter()
+                .map(|track| track.len.div_ceil(8))
+                .max()
+                .unwrap_or(0);
+            let offset = data.len() / 512;
+            let entry = 512 + cylinder * 4;
+            data[entry..entry + 2].copy_from_slice(&(offset as u16).to_le_bytes());
+            data[entry + 2..entry + 4].copy_from_slice(&(side_len as u16 * 2).to_le_bytes());
+            let start = data.len();
+            data.resize(start + side_len.div_ceil(256) * 512, 0);
+            for (head, track) in sides.iter().enumerate() {
+                for i in 0..side_len {
+                    // A shorter side is padded by carrying on round the track.
+                    let byte = if track.len == 0 {
+                        0
+                    } else {
+                        (0..8).fold(0u8, |byte, bit| (byte << 1) | track.cell(i * 8 + bit) as u8)
+                    };
+                    data[start + (i / 256) * 512 + head * 256 + i % 256] = byte.reverse_bits();
+                }
+            }
+        }
+        data
+    }
+}
+
+#[test]
+fn test_hfe_round_trip() {
+    let mut raw = vec![0u8; 360 * 1024];
+    for (i, byte) in raw.iter_mut().enumerate() {
+        *byte = (i / 512) as u8;
+    }
+    let image = FloppyImage::from_bytes(raw).unwrap();
+    let hfe = image.to_hfe();
+    assert_eq!(&hfe[..8], b"HXCPICFE");
+    assert_eq!(u16::from_le_bytes([hfe[12], hfe[13]]), 250);
+    // 100000 cells per side, both sides interleaved.
+    assert_eq!(u16::from_le_bytes([hfe[514], hfe[515]]), 25000);
+    let mut image = FloppyImage::from_hfe(&hfe).unwrap();
+    assert_eq!(image.format, FloppyFormat::Hfe);
+    assert_eq!(image.geometry, FloppyGeometry::new(40, 2, 9));
+    assert_eq!(
+        image.read_sector(39, 1, 9).unwrap(),
+        &[(719 % 256) as u8; 512][..]
+    );
+    // Writes go through the bit cells and survive another round trip.
+    image.write_sector(2, 0, 4, &[0x5a; 512]).unwrap();
+    assert_eq!(
+        image.flux_track(2, 0).sectors(false)[3].sector.data,
+        vec![0x5a; 512]
+    );
+    let mut image = FloppyImage::from_hfe(&image.to_hfe()).unwrap();
+    assert_eq!(image.read_sector(2, 0, 4).unwrap(), &[0x5a; 512][..]);
+    assert_eq!(image.read_sector(2, 0, 5).unwrap(), &[40; 512][..]);
+    // Rewriting the file is part of the write, so its failure is too.
+    image.path = Some(std::env::temp_dir().join("emupc-missing-dir/disk.hfe"));
+    assert_eq!(
+        image.write_sector(2, 0, 4, &[0xa5; 512]),
+        Err(FloppyError::Io(io::ErrorKind::NotFound))
+    );
+    let ids = [SectorId::new(2, 0, 1, 2)];
+    assert_eq!(
+        image.format_track(2, 0, &ids, 0xf6),
+        Err(FloppyError::Io(io::ErrorKind::NotFound))
+    );
+}
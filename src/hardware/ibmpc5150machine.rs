@@ -1,48 +1,233 @@
 use crate::cpu8086::*;
+use crate::hardware::cga::*;
+use crate::hardware::dma::*;
+use crate::hardware::fdc::*;
+use crate::hardware::floppy::*;
+use crate::hardware::framebuffer::*;
+use crate::hardware::harddisk::*;
+use crate::hardware::hercules::*;
+use crate::hardware::mda::*;
+use crate::hardware::pic::*;
 use crate::hardware::pit::*;
+use crate::hardware::ppi::*;
+use crate::hardware::textscreen::*;
+use crate::hardware::xebec::*;
 use std::fs;
 
 #[derive(Clone, Debug, Default)]
 pub struct IbmPc5150Hardware {
     pub ram: Vec<u8>,
     pub bios_rom: Vec<u8>,
+    pub pic: PIC,
+    pub dma: DMA,
+    pub fdc: FDC,
+    pub hdc: XebecHdc,
+    pub mda: Option<MDA>,
+    pub hgc: Option<HGC>,
+    pub cga: Option<CGA>,
     pub pit: PIT,
+    pub ppi: PPI,
+    pub pit_cycles: usize,
 }
 
+/// CPU clocks per PIT input clock (4.77 MHz / 1.19 MHz).
+pub const PIT_DIVIDER_5150: usize = 4;
+
+pub const CPU_CLOCK_5150: u64 = 4_772_727;
+
 impl IbmPc5150Hardware {
     pub fn new() -> IbmPc5150Hardware {
+        let mut pit = PIT::new(PitType::PIT8253);
+        pit.set_gate(0, true);
+        pit.set_gate(1, true);
+        let ppi = PPI::default();
         IbmPc5150Hardware {
-            ram: vec![0; 0x10000],
+            ram: vec![0; ppi.switches.memory_kb() as usize * 1024],
             bios_rom: fs::read("roms/machines/ibmpc/BIOS_5150_24APR81_U33.BIN").unwrap(),
-            pit: PIT::new(),
+            pic: PIC::new(),
+            dma: DMA::new(false),
+            fdc: FDC::new(),
+            hdc: XebecHdc::new(),
+            mda: None,
+            hgc: None,
+            cga: {
+                let mut cga = CGA::new();
+                cga.load_font(&fs::read(CGA_FONT_PATH).unwrap());
+                Some(cga)
+            },
+            pit,
+            ppi,
+            pit_cycles: 0,
         }
     }
     pub fn tick(&mut self, cycles: usize) {
-        self.pit.tick(cycles);
+        self.pit_cycles += cycles;
+        while self.pit_cycles >= PIT_DIVIDER_5150 {
+            self.pit_cycles -= PIT_DIVIDER_5150;
+            let refresh = self.pit.out(1);
+            self.pit.clock();
+            self.pic.set_irq(0, self.pit.out(0));
+            // PIT channel 1 requests a DRAM refresh cycle on DMA channel 0.
+            if !refresh && self.pit.out(1) {
+                self.dma.transfer_from_memory(0, &self.ram, 1);
+            }
+        }
+        self.ppi.timer2_out = self.pit.out(2);
+        self.fdc.tick(&mut self.dma, &mut self.ram);
+        self.pic.set_irq(6, self.fdc.irq());
+        self.hdc.tick(&mut self.dma, &mut self.ram);
+        self.pic.set_irq(XEBEC_IRQ, self.hdc.irq());
+        if let Some(mda) = &mut self.mda {
+            mda.tick(cycles, CPU_CLOCK_5150);
+        }
+        if let Some(hgc) = &mut self.hgc {
+            hgc.tick(cycles, CPU_CLOCK_5150);
+        }
+        if let Some(cga) = &mut self.cga {
+            cga.tick(cycles, CPU_CLOCK_5150);
+        }
+    }
+    /// Fits `memory_kb` of RAM, as the planar switches then report it.
+    pub fn set_memory(&mut self, memory_kb: u32) {
+        self.ppi.switches.set_memory(memory_kb);
+        self.ram = vec![0; self.ppi.switches.memory_kb() as usize * 1024];
+    }
+    /// Installs a monochrome display adapter and sets the planar switches
+    /// to report it.
+    pub fn install_mda(&mut self) {
+        let mut mda = MDA::new();
+        mda.font = fs::read(MDA_FONT_PATH).unwrap();
+        self.mda = Some(mda);
+        self.hgc = None;
+        self.ppi.switches.set_video(VideoType::Mda);
+    }
+    /// Installs a Hercules Graphics Card in place of an MDA.
+    pub fn install_hercules(&mut self) {
+        let mut hgc = HGC::new();
+        hgc.mda.font = fs::read(MDA_FONT_PATH).unwrap();
+        self.hgc = Some(hgc);
+        self.mda = None;
+        self.ppi.switches.set_video(VideoType::Mda);
+    }
+    /// The last complete frame of the primary display: the monochrome
+    /// card when one is installed, as the switches report, else the CGA.
+    pub fn framebuffer(&self) -> Option<&Framebuffer> {
+        if let Some(mda) = &self.mda {
+            return Some(&mda.frame);
+        }
+        if let Some(hgc) = &self.hgc {
+            return Some(&hgc.mda.frame);
+        }
+        self.cga.as_ref().map(|cga| &cga.frame)
+    }
+    /// The text page of the primary display, unless it is in a graphics
+    /// mode.
+    pub fn text_screen(&self) -> Option<TextScreen> {
+        if let Some(mda) = &self.mda {
+            return Some(mda.text_screen());
+        }
+        if let Some(hgc) = &self.hgc {
+            return hgc.text_screen();
+        }
+        self.cga.as_ref().and_then(|cga| cga.text_screen())
+    }
+    /// Attaches a hard disk to the fixed disk adapter, mapping its option
+    /// ROM at C800:0.
+    pub fn attach_hard_disk(&mut self, drive: usize, image: HardDiskImage) {
+        if self.hdc.rom.is_empty() {
+            self.hdc.rom = fs::read(XEBEC_ROM_PATH).unwrap();
+        }
+        self.hdc.drives[drive] = Some(image);
+    }
+    /// Feeds a scancode from the keyboard into the PPI, raising IRQ1.
+    pub fn key_event(&mut self, scancode: u8) {
+        self.ppi.key_event(scancode);
+        self.pic.set_irq(1, self.ppi.keyboard_irq());
+    }
+    /// Level at the speaker: PIT channel 2 output gated by PPI port B bit 1.
+    pub fn speaker_out(&self) -> bool {
+        self.pit.out(2) && self.ppi.speaker_data()
     }
 }
 
-impl<'a> Cpu8086Context for IbmPc5150Hardware {
+impl Cpu8086Context for IbmPc5150Hardware {
     fn mem_read_byte(&mut self, addr: u32) -> u8 {
         let actual_addr = addr & 0xf_ffff;
         match actual_addr {
-            0..=0x1_0000 => self.ram[(actual_addr & 0xffff) as usize],
+            0..=0x9_ffff => self.ram.get(actual_addr as usize).copied().unwrap_or(0xff),
+            0xb_0000..=0xb_7fff if self.mda.is_some() => {
+                self.mda.as_ref().unwrap().read_vram(actual_addr)
+            }
+            0xb_0000..=0xb_7fff if self.hgc.is_some() => {
+                self.hgc.as_ref().unwrap().read_vram(actual_addr)
+            }
+            0xb_8000..=0xb_ffff if self.hgc.as_ref().is_some_and(|hgc| hgc.maps(actual_addr)) => {
+                self.hgc.as_ref().unwrap().read_vram(actual_addr)
+            }
+            0xb_8000..=0xb_ffff if self.cga.is_some() => {
+                self.cga.as_ref().unwrap().read_vram(actual_addr)
+            }
+            0xc_8000..=0xc_9fff if !self.hdc.rom.is_empty() => {
+                self.hdc.rom[(actual_addr & 0x1fff) as usize % self.hdc.rom.len()]
+            }
             0xf_e000..=0xf_ffff => self.bios_rom[(actual_addr & 0x1fff) as usize],
             _ => 0xff,
         }
     }
     fn mem_write_byte(&mut self, addr: u32, value: u8) {
         let actual_addr = addr & 0xf_ffff;
-        if let 0..=0x0a_0000 = actual_addr {
-            self.ram[(actual_addr & 0xffff) as usize] = value
+        match actual_addr {
+            0..=0x9_ffff => {
+                if let Some(byte) = self.ram.get_mut(actual_addr as usize) {
+                    *byte = value;
+                }
+            }
+            0xb_0000..=0xb_7fff => {
+                if let Some(mda) = &mut self.mda {
+                    mda.write_vram(actual_addr, value);
+                }
+                if let Some(hgc) = &mut self.hgc {
+                    hgc.write_vram(actual_addr, value);
+                }
+            }
+            0xb_8000..=0xb_ffff => {
+                match &mut self.hgc {
+                    Some(hgc) if hgc.maps(actual_addr) => hgc.write_vram(actual_addr, value),
+                    _ => {
+                        if let Some(cga) = &mut self.cga {
+                            cga.write_vram(actual_addr, value);
+                        }
+                    }
+                }
+            }
+            _ => {}
         }
     }
 
     fn io_read_byte(&mut self, addr: u16) -> u8 {
         match addr {
+            0x0000..=0x001f => self.dma.rb(addr),
+            0x0020..=0x0021 => self.pic.rb(addr),
             0x0040..=0x0043 => self.pit.rb(addr),
+            0x0060..=0x0063 => {
+                self.ppi.timer2_out = self.pit.out(2);
+                self.ppi.rb(addr)
+            }
+            0x03f0..=0x03f7 => {
+                let value = self.fdc.rb(addr);
+                self.pic.set_irq(6, self.fdc.irq());
+                value
+            }
+            0x0320..=0x0323 => {
+                let value = self.hdc.rb(addr);
+                self.pic.set_irq(XEBEC_IRQ, self.hdc.irq());
+                value
+            }
+            0x03b0..=0x03bf if self.mda.is_some() => self.mda.as_mut().unwrap().rb(addr),
+            0x03b0..=0x03bf if self.hgc.is_some() => self.hgc.as_mut().unwrap().rb(addr),
+            0x03d0..=0x03df if self.cga.is_some() => self.cga.as_mut().unwrap().rb(addr),
             _ => {
-                println!("Unimplemented IO read");
+                trace!("Unimplemented IO read");
                 0xff
             }
         }
@@ -50,8 +235,68 @@ impl<'a> Cpu8086Context for IbmPc5150Hardware {
 
     fn io_write_byte(&mut self, addr: u16, value: u8) {
         match addr {
+            0x0000..=0x001f => self.dma.wb(addr, value),
+            0x0020..=0x0021 => self.pic.wb(addr, value),
             0x0040..=0x0043 => self.pit.wb(addr, value),
-            _ => println!("Unimplemented IO write"),
+            0x0060..=0x0063 => {
+                self.ppi.wb(addr, value);
+                self.pit.set_gate(2, self.ppi.timer2_gate());
+                self.pic.set_irq(1, self.ppi.keyboard_irq());
+            }
+            // Write-only 4-bit page registers for channels 1-3.
+            0x0080..=0x009f => {
+                if let Some(channel) = DMA_PAGE_PORTS
+                    .iter()
+                    .position(|&port| port == (addr & 0x83))
+                {
+                    self.dma.channels[channel].page = value & 0x0f;
+                }
+            }
+            0x03f0..=0x03f7 => {
+                self.fdc.wb(addr, value);
+                self.pic.set_irq(6, self.fdc.irq());
+            }
+            0x0320..=0x0323 => {
+                self.hdc.wb(addr, value);
+                self.pic.set_irq(XEBEC_IRQ, self.hdc.irq());
+            }
+            0x03b0..=0x03bf if self.mda.is_some() => self.mda.as_mut().unwrap().wb(addr, value),
+            0x03b0..=0x03bf if self.hgc.is_some() => self.hgc.as_mut().unwrap().wb(addr, value),
+            0x03d0..=0x03df if self.cga.is_some() => self.cga.as_mut().unwrap().wb(addr, value),
+            _ => trace!("Unimplemented IO write"),
         }
     }
+
+    fn intr_pending(&mut self) -> bool {
+        self.pic.intr()
+    }
+
+    fn intr_acknowledge(&mut self) -> u8 {
+        self.pic.acknowledge()
+    }
+
+    fn floppy(&mut self, drive: u8) -> Option<&mut FloppyImage> {
+        self.fdc.drives.get_mut(drive as usize)?.image.as_mut()
+    }
+
+    fn hard_disk(&mut self, drive: u8) -> Option<&mut HardDiskImage> {
+        self.hdc.drives.get_mut(drive as usize)?.as_mut()
+    }
+}
+
+#[test]
+fn test_ram_sized_from_switches() {
+    let mut hardware = IbmPc5150Hardware::default();
+    hardware.set_memory(640);
+    assert_eq!(hardware.ram.len(), 0xa_0000);
+    hardware.mem_write_byte(0x1_2345, 0x5a);
+    hardware.mem_write_byte(0x9_ffff, 0xa5);
+    assert_eq!(hardware.mem_read_byte(0x1_2345), 0x5a);
+    assert_eq!(hardware.mem_read_byte(0x2345), 0x00);
+    assert_eq!(hardware.mem_read_byte(0x9_ffff), 0xa5);
+    // Nothing answers above the RAM fitted.
+    hardware.set_memory(128);
+    hardware.mem_write_byte(0x2_0000, 0x5a);
+    assert_eq!(hardware.mem_read_byte(0x2_0000), 0xff);
+    assert_eq!(hardware.mem_read_byte(0x1_ffff), 0x00);
 }
@@ -1,12 +1,48 @@
 use crate::cpu286::*;
+use crate::hardware::ata::*;
+use crate::hardware::dma::*;
+use crate::hardware::ega::*;
+use crate::hardware::fdc::*;
+use crate::hardware::framebuffer::*;
+use crate::hardware::harddisk::*;
+use crate::hardware::hercules::*;
+use crate::hardware::mda::*;
+use crate::hardware::pic::*;
+use crate::hardware::pit::*;
+use crate::hardware::textscreen::*;
+use crate::hardware::vga::*;
 use std::fs;
 
 #[derive(Clone, Debug, Default)]
 pub struct IbmPcAtHardware {
     pub ram: Vec<u8>,
     pub bios_rom: Vec<u8>,
+    pub pic: PicPair,
+    pub dma: DmaPair,
+    pub fdc: FDC,
+    pub ata: AtaController,
+    pub mda: Option<MDA>,
+    pub hgc: Option<HGC>,
+    pub ega: Option<EGA>,
+    pub vga: Option<VGA>,
+    pub pit: PIT,
+    pub pit_cycles: usize,
+    /// System control port B at 0x61: timer 2 gate, speaker data and
+    /// parity check enables.
+    pub port_b: u8,
+    /// Refresh request toggle read back on port 0x61 bit 4.
+    pub refresh: bool,
+    pub refresh_clocks: usize,
 }
 
+/// CPU clocks per PIT input clock (6 MHz / 1.19 MHz, rounded).
+pub const PIT_DIVIDER_AT: usize = 5;
+
+pub const CPU_CLOCK_AT: u64 = 6_000_000;
+
+/// PIT input clocks between DRAM refresh requests (15 us).
+const REFRESH_PIT_CLOCKS: usize = 18;
+
 impl IbmPcAtHardware {
     pub fn new() -> IbmPcAtHardware {
         IbmPcAtHardware {
@@ -25,18 +61,150 @@ impl IbmPcAtHardware {
                 }
                 bios
             },
+            pic: PicPair::new(),
+            dma: DmaPair::new(),
+            fdc: FDC::new(),
+            ata: AtaController::new(),
+            mda: None,
+            hgc: None,
+            ega: None,
+            vga: None,
+            pit: {
+                let mut pit = PIT::new(PitType::PIT8254);
+                pit.set_gate(0, true);
+                pit.set_gate(1, true);
+                pit
+            },
+            pit_cycles: 0,
+            port_b: 0,
+            refresh: false,
+            refresh_clocks: 0,
         }
     }
-    pub fn tick(&mut self, _cycles: usize) {
-
+    pub fn tick(&mut self, cycles: usize) {
+        self.pit_cycles += cycles;
+        while self.pit_cycles >= PIT_DIVIDER_AT {
+            self.pit_cycles -= PIT_DIVIDER_AT;
+            self.pit.clock();
+            self.pic.set_irq(0, self.pit.out(0));
+            self.refresh_clocks += 1;
+            if self.refresh_clocks == REFRESH_PIT_CLOCKS {
+                self.refresh_clocks = 0;
+                self.refresh = !self.refresh;
+            }
+        }
+        self.fdc.tick(&mut self.dma, &mut self.ram);
+        self.pic.set_irq(6, self.fdc.irq());
+        if let Some(mda) = &mut self.mda {
+            mda.tick(cycles, CPU_CLOCK_AT);
+        }
+        if let Some(hgc) = &mut self.hgc {
+            hgc.tick(cycles, CPU_CLOCK_AT);
+        }
+        if let Some(ega) = &mut self.ega {
+            ega.tick(cycles, CPU_CLOCK_AT);
+        }
+        if let Some(vga) = &mut self.vga {
+            vga.tick(cycles, CPU_CLOCK_AT);
+        }
+    }
+    pub fn install_mda(&mut self) {
+        let mut mda = MDA::new();
+        mda.font = fs::read(MDA_FONT_PATH).unwrap();
+        self.mda = Some(mda);
+        self.hgc = None;
+    }
+    /// Installs a Hercules Graphics Card in place of an MDA.
+    pub fn install_hercules(&mut self) {
+        let mut hgc = HGC::new();
+        hgc.mda.font = fs::read(MDA_FONT_PATH).unwrap();
+        self.hgc = Some(hgc);
+        self.mda = None;
+    }
+    /// Installs an EGA, whose option ROM at C000:0 the BIOS finds and
+    /// runs during its ROM scan.
+    pub fn install_ega(&mut self) {
+        let mut ega = EGA::new();
+        ega.rom = fs::read(EGA_ROM_PATH).unwrap();
+        self.ega = Some(ega);
+        self.vga = None;
+    }
+    /// Installs a VGA in place of an EGA, with its BIOS at C000:0.
+    pub fn install_vga(&mut self) {
+        let mut vga = VGA::new();
+        vga.ega.rom = fs::read(VGA_ROM_PATH).unwrap();
+        self.vga = Some(vga);
+        self.ega = None;
+    }
+    /// The last complete frame of the primary display, preferring a
+    /// colour adapter to a monochrome one as the BIOS does.
+    pub fn framebuffer(&self) -> Option<&Framebuffer> {
+        if let Some(vga) = &self.vga {
+            return Some(&vga.ega.frame);
+        }
+        if let Some(ega) = &self.ega {
+            return Some(&ega.frame);
+        }
+        if let Some(mda) = &self.mda {
+            return Some(&mda.frame);
+        }
+        self.hgc.as_ref().map(|hgc| &hgc.mda.frame)
+    }
+    /// The text page of the primary display, unless it is in a graphics
+    /// mode.
+    pub fn text_screen(&self) -> Option<TextScreen> {
+        if let Some(vga) = &self.vga {
+            return vga.text_screen();
+        }
+        if let Some(ega) = &self.ega {
+            return ega.text_screen();
+        }
+        if let Some(mda) = &self.mda {
+            return Some(mda.text_screen());
+        }
+        self.hgc.as_ref().and_then(|hgc| hgc.text_screen())
+    }
+    pub fn attach_hard_disk(&mut self, drive: usize, image: HardDiskImage) {
+        self.ata.attach(drive, image);
+    }
+    /// Level at the speaker: PIT channel 2 output gated by port 0x61 bit 1.
+    pub fn speaker_out(&self) -> bool {
+        self.pit.out(2) && (self.port_b & 0x02) != 0
     }
 }
 
 impl<'a> Cpu286Context for IbmPcAtHardware {
     fn mem_read_byte(&mut self, addr: u32) -> u8 {
         let actual_addr = addr & 0xff_ffff;
+        if let Some(ega) = &mut self.ega {
+            if let Some(offset) = ega.window(actual_addr) {
+                return ega.read_vram(offset);
+            }
+        }
+        if let Some(vga) = &mut self.vga {
+            if let Some(offset) = vga.window(actual_addr) {
+                return vga.read_vram(offset);
+            }
+        }
         match actual_addr {
             0..=0x0a_0000 => self.ram[(actual_addr & 0xffff) as usize],
+            0x0b_0000..=0x0b_7fff if self.mda.is_some() => {
+                self.mda.as_ref().unwrap().read_vram(actual_addr)
+            }
+            0x0b_0000..=0x0b_7fff if self.hgc.is_some() => {
+                self.hgc.as_ref().unwrap().read_vram(actual_addr)
+            }
+            0x0b_8000..=0x0b_ffff if self.hgc.as_ref().is_some_and(|hgc| hgc.maps(actual_addr)) => {
+                self.hgc.as_ref().unwrap().read_vram(actual_addr)
+            }
+            0x0c_0000..=0x0c_7fff if self.ega.is_some() => {
+                let rom = &self.ega.as_ref().unwrap().rom;
+                rom.get((actual_addr & 0x7fff) as usize).copied().unwrap_or(0xff)
+            }
+            0x0c_0000..=0x0c_7fff if self.vga.is_some() => {
+                let rom = &self.vga.as_ref().unwrap().ega.rom;
+                rom.get((actual_addr & 0x7fff) as usize).copied().unwrap_or(0xff)
+            }
             0x0f_0000..=0x0f_ffff => self.bios_rom[(actual_addr & 0xffff) as usize],
             0xff_0000..=0xff_ffff => self.bios_rom[(actual_addr & 0xffff) as usize],
             _ => 0xff,
@@ -44,14 +212,148 @@ impl<'a> Cpu286Context for IbmPcAtHardware {
     }
     fn mem_write_byte(&mut self, addr: u32, value: u8) {
         let actual_addr = addr & 0xff_ffff;
-        if let 0..=0x0a_0000 = actual_addr {
-            self.ram[(actual_addr & 0xffff) as usize] = value
+        if let Some(ega) = &mut self.ega {
+            if let Some(offset) = ega.window(actual_addr) {
+                ega.write_vram(offset, value);
+                return;
+            }
+        }
+        if let Some(vga) = &mut self.vga {
+            if let Some(offset) = vga.window(actual_addr) {
+                vga.write_vram(offset, value);
+                return;
+            }
+        }
+        match actual_addr {
+            0..=0x0a_0000 => self.ram[(actual_addr & 0xffff) as usize] = value,
+            0x0b_0000..=0x0b_7fff => {
+                if let Some(mda) = &mut self.mda {
+                    mda.write_vram(actual_addr, value);
+                }
+                if let Some(hgc) = &mut self.hgc {
+                    hgc.write_vram(actual_addr, value);
+                }
+            }
+            0x0b_8000..=0x0b_ffff => {
+                if let Some(hgc) = self.hgc.as_mut().filter(|hgc| hgc.maps(actual_addr)) {
+                    hgc.write_vram(actual_addr, value);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn io_read_byte(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x001f | 0x0080..=0x008f | 0x00c0..=0x00df => self.dma.rb(addr),
+            0x0020..=0x0021 | 0x00a0..=0x00a1 => self.pic.rb(addr),
+            0x0040..=0x0043 => self.pit.rb(addr),
+            0x0061 => {
+                (self.port_b & 0x0f) | ((self.refresh as u8) << 4) | ((self.pit.out(2) as u8) << 5)
+            }
+            0x01f0..=0x01f7 | 0x03f6 => {
+                let value = self.ata.rb(addr);
+                self.pic.set_irq(ATA_IRQ, self.ata.irq());
+                value
+            }
+            0x03b0..=0x03df if self.ega.as_ref().is_some_and(|ega| ega.decodes(addr)) => {
+                self.ega.as_mut().unwrap().rb(addr)
+            }
+            0x03b0..=0x03df if self.vga.as_ref().is_some_and(|vga| vga.decodes(addr)) => {
+                self.vga.as_mut().unwrap().rb(addr)
+            }
+            0x03b0..=0x03bf if self.mda.is_some() => self.mda.as_mut().unwrap().rb(addr),
+            0x03b0..=0x03bf if self.hgc.is_some() => self.hgc.as_mut().unwrap().rb(addr),
+            0x03f0..=0x03f7 => {
+                let value = self.fdc.rb(addr);
+                self.pic.set_irq(6, self.fdc.irq());
+                value
+            }
+            _ => 0xff,
+        }
+    }
+
+    fn io_write_byte(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x001f | 0x0080..=0x008f | 0x00c0..=0x00df => self.dma.wb(addr, value),
+            0x0020..=0x0021 | 0x00a0..=0x00a1 => self.pic.wb(addr, value),
+            0x0040..=0x0043 => self.pit.wb(addr, value),
+            0x0061 => {
+                self.port_b = value;
+                self.pit.set_gate(2, (value & 0x01) != 0);
+            }
+            0x01f0..=0x01f7 | 0x03f6 => {
+                self.ata.wb(addr, value);
+                self.pic.set_irq(ATA_IRQ, self.ata.irq());
+            }
+            0x03b0..=0x03df if self.ega.as_ref().is_some_and(|ega| ega.decodes(addr)) => {
+                self.ega.as_mut().unwrap().wb(addr, value)
+            }
+            0x03b0..=0x03df if self.vga.as_ref().is_some_and(|vga| vga.decodes(addr)) => {
+                self.vga.as_mut().unwrap().wb(addr, value)
+            }
+            0x03b0..=0x03bf if self.mda.is_some() => self.mda.as_mut().unwrap().wb(addr, value),
+            0x03b0..=0x03bf if self.hgc.is_some() => self.hgc.as_mut().unwrap().wb(addr, value),
+            0x03f0..=0x03f7 => {
+                self.fdc.wb(addr, value);
+                self.pic.set_irq(6, self.fdc.irq());
+            }
+            _ => (),
+        }
+    }
+
+    fn io_read_word(&mut self, addr: u16) -> u16 {
+        if addr == 0x01f0 {
+            let value = self.ata.read_data();
+            self.pic.set_irq(ATA_IRQ, self.ata.irq());
+            return value;
+        }
+        let lo = self.io_read_byte(addr);
+        let hi = self.io_read_byte(addr.wrapping_add(1));
+        u16::from_le_bytes([lo, hi])
+    }
+
+    fn io_write_word(&mut self, addr: u16, value: u16) {
+        if addr == 0x01f0 {
+            self.ata.write_data(value);
+            self.pic.set_irq(ATA_IRQ, self.ata.irq());
+            return;
         }
+        self.io_write_byte(addr, value as u8);
+        self.io_write_byte(addr.wrapping_add(1), (value >> 8) as u8);
+    }
+
+    fn intr_pending(&mut self) -> bool {
+        self.pic.intr()
     }
 
-    fn io_read_byte(&mut self, _addr: u16) -> u8 {
-        0xff
+    fn intr_acknowledge(&mut self) -> u8 {
+        self.pic.acknowledge()
     }
+}
 
-    fn io_write_byte(&mut self, _addr: u16, _value: u8) {}
+#[test]
+fn test_port_61_speaker_and_refresh() {
+    let mut hardware = IbmPcAtHardware::default();
+    // Counter 2, mode 3, count 4.
+    hardware.io_write_byte(0x43, 0xb6);
+    hardware.io_write_byte(0x42, 0x04);
+    hardware.io_write_byte(0x42, 0x00);
+    hardware.tick(PIT_DIVIDER_AT * 8);
+    // The gate is low, so OUT2 never moves.
+    assert_eq!(hardware.io_read_byte(0x61) & 0x20, 0x20);
+    hardware.io_write_byte(0x61, 0x03);
+    assert_eq!(hardware.io_read_byte(0x61) & 0x0f, 0x03);
+    let mut levels = Vec::new();
+    for _ in 0..4 {
+        hardware.tick(PIT_DIVIDER_AT);
+        levels.push(hardware.speaker_out());
+        assert_eq!((hardware.io_read_byte(0x61) & 0x20) != 0, hardware.pit.out(2));
+    }
+    assert!(levels.contains(&true) && levels.contains(&false));
+    hardware.io_write_byte(0x61, 0x01);
+    assert!(!hardware.speaker_out());
+    let refresh = hardware.io_read_byte(0x61) & 0x10;
+    hardware.tick(PIT_DIVIDER_AT * REFRESH_PIT_CLOCKS);
+    assert_ne!(hardware.io_read_byte(0x61) & 0x10, refresh);
 }
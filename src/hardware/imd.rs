@@ -0,0 +1,134 @@
+use crate::hardware::floppy::*;
+use std::io;
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("IMD: {}", msg))
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + len)
+            .ok_or_else(|| invalid("unexpected end of file"))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> io::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+}
+
+impl FloppyImage {
+    /// Parses an ImageDisk image: an ASCII header ended by 0x1A, then for
+    /// each track its mode, cylinder, head, sector count, size code and
+    /// sector numbering map, optional cylinder and head maps, and one data
+    /// record per sector. The disk is mounted write protected, since
+    /// writes cannot be saved.
+    pub fn from_imd(data: &[u8]) -> io::Result<FloppyImage> {
+        let header_end = data
+            .iter()
+            .position(|&byte| byte == 0x1a)
+            .ok_or_else(|| invalid("missing header terminator"))?;
+        let mut reader = Reader {
+            data,
+            pos: header_end + 1,
+        };
+        let mut tracks = Vec::new();
+        while reader.pos < data.len() {
+            let _mode = reader.byte()?;
+            let cylinder = reader.byte()?;
+            let head_flags = reader.byte()?;
+            let count = reader.byte()? as usize;
+            let size_code = reader.byte()?;
+            let head = head_flags & 0x0f;
+            let numbers = reader.bytes(count)?;
+            let cylinders = if (head_flags & 0x80) != 0 {
+                reader.bytes(count)?.to_vec()
+            } else {
+                vec![cylinder; count]
+            };
+            let heads = if (head_flags & 0x40) != 0 {
+                reader.bytes(count)?.to_vec()
+            } else {
+                vec![head; count]
+            };
+            let sizes: Vec<usize> = if size_code == 0xff {
+                let table = reader.bytes(count * 2)?;
+                table
+                    .chunks(2)
+                    .map(|pair| u16::from_le_bytes([pair[0], pair[1]]) as usize)
+                    .collect()
+            } else if size_code <= 6 {
+                vec![128 << size_code; count]
+            } else {
+                return Err(invalid("bad sector size"));
+            };
+            let mut sectors = Vec::with_capacity(count);
+            for i in 0..count {
+                let size = sizes[i];
+                let record = reader.byte()?;
+                let data = match record {
+                    0 => Vec::new(),
+                    1 | 3 | 5 | 7 => reader.bytes(size)?.to_vec(),
+                    2 | 4 | 6 | 8 => vec![reader.byte()?; size],
+                    _ => return Err(invalid("bad sector record type")),
+                };
+                sectors.push(Sector {
+                    id: SectorId::new(
+                        cylinders[i],
+                        heads[i],
+                        numbers[i],
+                        size.trailing_zeros().saturating_sub(7) as u8,
+                    ),
+                    data,
+                    deleted: matches!(record, 3 | 4 | 7 | 8),
+                    crc_error: matches!(record, 5..=8),
+                });
+            }
+            tracks.push((cylinder, head, sectors));
+        }
+        let mut image = FloppyImage::from_tracks(tracks, FloppyFormat::Imd)?;
+        image.write_protected = true;
+        Ok(image)
+    }
+}
+
+#[test]
+fn test_imd_loader() {
+    let mut data = b"IMD 1.18: 01/01/2000 00:00:00\r\ntest\x1a".to_vec();
+    // Cylinder 0 head 0: sectors 0x41 and 0x43 of 1024 bytes, the second
+    // compressed and marked deleted.
+    data.extend_from_slice(&[5, 0, 0, 2, 3, 0x41, 0x43]);
+    data.push(1);
+    data.extend_from_slice(&[0x11; 1024]);
+    data.extend_from_slice(&[4, 0xe5]);
+    // Cylinder 1 head 1 with a cylinder map and a sector with a CRC error.
+    data.extend_from_slice(&[5, 1, 0x81, 1, 2, 1, 7, 6, 0x22]);
+    let mut image = FloppyImage::from_imd(&data).unwrap();
+    assert_eq!(image.format, FloppyFormat::Imd);
+    assert_eq!(image.geometry, FloppyGeometry::new(2, 2, 2));
+    let track = image.track(0, 0);
+    assert_eq!(track[0].id, SectorId::new(0, 0, 0x41, 3));
+    assert_eq!(track[0].data[1023], 0x11);
+    assert!(track[1].deleted);
+    assert_eq!(track[1].data, vec![0xe5; 1024]);
+    let track = image.track(1, 1);
+    assert_eq!(track[0].id, SectorId::new(7, 1, 1, 2));
+    assert!(track[0].crc_error && !track[0].deleted);
+    assert!(image.track(1, 0).is_empty());
+    assert_eq!(
+        image.write_sector(0, 0, 0x41, &[0; 1024]),
+        Err(FloppyError::WriteProtected)
+    );
+    // A track on cylinder 255 is refused rather than overflowing.
+    data.extend_from_slice(&[5, 0xff, 0, 1, 2, 1, 2, 0xe5]);
+    let err = FloppyImage::from_imd(&data).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
@@ -0,0 +1,249 @@
+#[cfg(test)]
+use crate::hardware::crtc::run_frames;
+use crate::hardware::crtc::Crtc6845;
+use crate::hardware::framebuffer::Framebuffer;
+use crate::hardware::textscreen::TextScreen;
+
+/// Character generator ROM of the IBM Monochrome Display Adapter.
+pub const MDA_FONT_PATH: &str = "roms/video/mda/mda.rom";
+
+pub const MDA_WIDTH: usize = 720;
+pub const MDA_HEIGHT: usize = 350;
+
+/// 16.257 MHz dot clock, nine dots per character.
+pub const MDA_DOT_CLOCK: u64 = 16_257_000;
+
+const MODE_HIRES: u8 = 0x01;
+const MODE_VIDEO_ENABLE: u8 = 0x08;
+const MODE_BLINK: u8 = 0x20;
+
+pub const MDA_BLACK: u32 = 0x00_0000;
+pub const MDA_GREEN: u32 = 0x00_aa00;
+pub const MDA_BRIGHT_GREEN: u32 = 0x55_ff55;
+
+/// IBM Monochrome Display Adapter: 4K of text memory at B000:0 mirrored
+/// up to B7FF:F, a 6845 CRTC at 0x3B4/0x3B5, mode control at 0x3B8 and
+/// status at 0x3BA, drawn into a 720x350 RGB framebuffer with the 9x14
+/// character ROM.
+#[derive(Debug, Clone)]
+pub struct MDA {
+    pub vram: Vec<u8>,
+    /// Character ROM: rows 0-7 of each glyph in the first 2K, rows 8-13
+    /// in the second. Empty until loaded, drawing blank glyphs.
+    pub font: Vec<u8>,
+    pub crtc: Crtc6845,
+    pub mode: u8,
+    /// Dot clocks owed, scaled by the CPU clock.
+    pub phase: u64,
+    /// The last completed frame.
+    pub frame: Framebuffer,
+    /// The frame being drawn.
+    pub back: Framebuffer,
+}
+
+impl MDA {
+    pub fn new() -> Self {
+        let mut crtc = Crtc6845::new();
+        // The BIOS mode 7 parameters.
+        crtc.load(&[
+            0x61, 0x50, 0x52, 0x0f, 0x19, 0x06, 0x19, 0x19, 0x02, 0x0d, 0x0b, 0x0c,
+        ]);
+        Self {
+            vram: vec![0; 0x1000],
+            font: Vec::new(),
+            crtc,
+            mode: 0,
+            phase: 0,
+            frame: Framebuffer::new(MDA_WIDTH, MDA_HEIGHT),
+            back: Framebuffer::new(MDA_WIDTH, MDA_HEIGHT),
+        }
+    }
+
+    pub fn read_vram(&self, addr: u32) -> u8 {
+        self.vram[(addr & 0xfff) as usize]
+    }
+
+    pub fn write_vram(&mut self, addr: u32, data: u8) {
+        self.vram[(addr & 0xfff) as usize] = data;
+    }
+
+    /// Runs the adapter for `cycles` clocks of a CPU running at
+    /// `cpu_clock` Hz, one character clock at a time.
+    pub fn tick(&mut self, cycles: usize, cpu_clock: u64) {
+        self.phase += cycles as u64 * MDA_DOT_CLOCK;
+        let period = cpu_clock * 9;
+        while self.phase >= period {
+            self.phase -= period;
+            if self.crtc.display_enable() {
+                self.draw_char();
+            }
+            if self.crtc.clock() {
+                self.end_frame();
+            }
+        }
+    }
+
+    /// The characters and attributes of the displayed page.
+    pub fn text_screen(&self) -> TextScreen {
+        let columns = self.crtc.regs[1] as usize;
+        let start = self.crtc.start_address() as usize;
+        let mut screen = TextScreen::read(columns, self.crtc.regs[6] as usize, |row, column| {
+            let addr = (start + row * columns + column) * 2;
+            (self.vram[addr & 0xfff], self.vram[(addr + 1) & 0xfff])
+        });
+        if (self.crtc.regs[10] & 0x60) != 0x20 {
+            let cursor = self.crtc.cursor_address() as usize;
+            screen.place_cursor(cursor.wrapping_sub(start) & 0x3fff, columns);
+        }
+        screen
+    }
+
+    /// Shows the frame just drawn and starts a blank one.
+    pub fn end_frame(&mut self) {
+        std::mem::swap(&mut self.frame, &mut self.back);
+        self.back.fill(MDA_BLACK);
+    }
+
+    /// Row `line` of the glyph for `ch`, nine dots wide in the low bits.
+    /// Box drawing characters 0xC0-0xDF extend their eighth dot into the
+    /// ninth.
+    fn glyph_row(&self, ch: u8, line: usize) -> u16 {
+        let offset = if line < 8 {
+            ch as usize * 8 + line
+        } else {
+            0x800 + ch as usize * 8 + line - 8
+        };
+        let bits = self.font.get(offset).copied().unwrap_or(0) as u16;
+        let ninth = if (0xc0..=0xdf).contains(&ch) {
+            bits & 1
+        } else {
+            0
+        };
+        (bits << 1) | ninth
+    }
+
+    /// Draws the character under the beam into the back buffer.
+    pub fn draw_char(&mut self) {
+        let x = self.crtc.hcount as usize * 9;
+        let y = self.crtc.line as usize;
+        if x + 9 > MDA_WIDTH || y >= MDA_HEIGHT {
+            return;
+        }
+        if (self.mode & MODE_VIDEO_ENABLE) == 0 || (self.mode & MODE_HIRES) == 0 {
+            return;
+        }
+        let addr = self.crtc.ma as usize * 2;
+        let ch = self.vram[addr & 0xfff];
+        let attr = self.vram[(addr + 1) & 0xfff];
+        let line = self.crtc.ra as usize;
+        let bright = if (attr & 0x08) != 0 {
+            MDA_BRIGHT_GREEN
+        } else {
+            MDA_GREEN
+        };
+        let (mut fg, mut bg) = match attr & 0x77 {
+            0x00 => (MDA_BLACK, MDA_BLACK),
+            0x70 => (MDA_BLACK, MDA_GREEN),
+            _ => (bright, MDA_BLACK),
+        };
+        if (attr & 0x80) != 0 {
+            if (self.mode & MODE_BLINK) == 0 {
+                // With blinking off bit 7 brightens the background.
+                if bg != MDA_BLACK {
+                    bg = MDA_BRIGHT_GREEN;
+                }
+            } else if (self.crtc.frame_count & 0x10) == 0 {
+                fg = bg;
+            }
+        }
+        let mut bits = self.glyph_row(ch, line);
+        if (attr & 0x77) == 0x01 && line == 12 {
+            bits = 0x1ff;
+        }
+        if self.crtc.cursor() {
+            bits = 0x1ff;
+            fg = bright;
+        }
+        let pixels = &mut self.back.row_mut(y)[x..][..9];
+        for (x, pixel) in pixels.iter_mut().enumerate() {
+            *pixel = if (bits & (0x100 >> x)) != 0 { fg } else { bg };
+        }
+    }
+
+    pub fn rb(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x3b0..=0x3b7 => self.crtc.rb(addr),
+            0x3ba => 0xf0 | (self.crtc.hsync as u8) | ((self.crtc.vsync as u8) << 3),
+            _ => 0xff,
+        }
+    }
+
+    pub fn wb(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x3b0..=0x3b7 => self.crtc.wb(addr, data),
+            0x3b8 => self.mode = data,
+            _ => {}
+        }
+    }
+}
+
+impl Default for MDA {
+    fn default() -> MDA {
+        MDA::new()
+    }
+}
+
+#[test]
+fn test_mda_text_rendering() {
+    let pixel = |mda: &MDA, x: usize, y: usize| mda.frame.pixel(x, y);
+    let mut mda = MDA::new();
+    // A font where every glyph is a single dot in its first and eighth
+    // columns on each row.
+    mda.font = vec![0x81; 0x1000];
+    mda.wb(0x3b8, MODE_HIRES | MODE_VIDEO_ENABLE);
+    mda.wb(0x3b4, 10);
+    mda.wb(0x3b5, 0x20);
+    mda.write_vram(0xb_0000, b'A');
+    mda.write_vram(0xb_0001, 0x07);
+    mda.write_vram(0xb_0002, 0xc4);
+    mda.write_vram(0xb_0003, 0x0f);
+    mda.write_vram(0xb_0004, b'_');
+    mda.write_vram(0xb_0005, 0x01);
+    mda.write_vram(0xb_0006, b'R');
+    mda.write_vram(0xb_0007, 0x70);
+    // One frame at the 5150's clock is about 95800 CPU cycles.
+    let mut hsync = false;
+    let mut vsync = false;
+    while mda.crtc.frame_count == 0 {
+        mda.tick(100, 4_772_727);
+        let status = mda.rb(0x3ba);
+        hsync |= (status & 0x01) != 0;
+        vsync |= (status & 0x08) != 0;
+    }
+    assert!(hsync && vsync);
+    assert_eq!(pixel(&mda, 0, 0), MDA_GREEN);
+    assert_eq!(pixel(&mda, 1, 0), MDA_BLACK);
+    assert_eq!(pixel(&mda, 7, 0), MDA_GREEN);
+    assert_eq!(pixel(&mda, 8, 0), MDA_BLACK);
+    // Box drawing characters carry their eighth dot into the ninth.
+    assert_eq!(pixel(&mda, 9 + 8, 0), MDA_BRIGHT_GREEN);
+    assert_eq!(pixel(&mda, 9 + 6, 0), MDA_BLACK);
+    // Underlined characters fill scanline 12.
+    assert_eq!(pixel(&mda, 18 + 4, 12), MDA_GREEN);
+    assert_eq!(pixel(&mda, 18 + 4, 11), MDA_BLACK);
+    // Reverse video.
+    assert_eq!(pixel(&mda, 27, 0), MDA_BLACK);
+    assert_eq!(pixel(&mda, 27 + 1, 0), MDA_GREEN);
+    // The cursor on the first cell covers scanlines 11-12 once shown.
+    mda.wb(0x3b4, 10);
+    mda.wb(0x3b5, 0x0b);
+    run_frames(&mut mda, |mda| mda.crtc.frame_count, MDA::tick);
+    assert_eq!(pixel(&mda, 4, 11), MDA_GREEN);
+    assert_eq!(pixel(&mda, 4, 10), MDA_BLACK);
+    mda.wb(0x3b4, 14);
+    assert_eq!(mda.rb(0x3b5), 0);
+    // Disabling video blanks the screen.
+    mda.wb(0x3b8, MODE_HIRES);
+    run_frames(&mut mda, |mda| mda.crtc.frame_count, MDA::tick);
+    assert!(mda.frame.pixels.iter().all(|&p| p == MDA_BLACK));
+}
@@ -0,0 +1,387 @@
+use crate::hardware::floppy::{Sector, SectorId};
+
+/// Cell pattern of an A1 address mark byte with its missing clock bit.
+const SYNC_A1: u16 = 0x4489;
+/// Cell pattern of the C2 bytes before the index address mark.
+const SYNC_C2: u16 = 0x5224;
+
+/// Cells an ID field may be followed by before its data mark is given up on.
+const DATA_MARK_WINDOW: usize = 64 * 16;
+
+/// CRC-CCITT as computed by the controller over address marks and fields.
+pub fn crc16(mut crc: u16, data: &[u8]) -> u16 {
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if (crc & 0x8000) != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// One revolution of an MFM track as raw bit cells, clock and data cells
+/// interleaved, starting at the index hole.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MfmTrack {
+    /// Bit cells packed most significant bit first.
+    pub cells: Vec<u8>,
+    /// Cells without a reliable flux transition, which read back randomly.
+    /// Empty if the track has no weak cells.
+    pub weak: Vec<u8>,
+    /// Number of bit cells in the revolution.
+    pub len: usize,
+    /// Data rate in kbit/s; the cell rate is twice this.
+    pub data_rate: u16,
+    rng: u32,
+}
+
+/// A sector found on a track along with where its data field starts.
+#[derive(Debug, Clone)]
+pub struct MfmSector {
+    pub sector: Sector,
+    /// Cell position of the data address mark's first sync byte.
+    pub data_pos: Option<usize>,
+}
+
+struct Writer<'a> {
+    track: &'a mut MfmTrack,
+    pos: usize,
+    prev: bool,
+}
+
+impl<'a> Writer<'a> {
+    fn cells(&mut self, pattern: u16) {
+        for bit in (0..16).rev() {
+            self.track.set_cell(self.pos, ((pattern >> bit) & 1) != 0);
+            self.pos += 1;
+        }
+        self.prev = (pattern & 1) != 0;
+    }
+
+    fn byte(&mut self, byte: u8) {
+        let mut pattern = 0u16;
+        let mut prev = self.prev;
+        for bit in (0..8).rev() {
+            let data = ((byte >> bit) & 1) != 0;
+            let clock = !prev && !data;
+            pattern = (pattern << 2) | ((clock as u16) << 1) | data as u16;
+            prev = data;
+        }
+        self.cells(pattern);
+    }
+
+    fn fill(&mut self, byte: u8, count: usize) {
+        for _ in 0..count {
+            self.byte(byte);
+        }
+    }
+
+    /// Writes an address mark with its sync bytes, a field and its CRC.
+    fn field(&mut self, mark: u8, data: &[u8], crc_error: bool) {
+        for _ in 0..3 {
+            self.cells(SYNC_A1);
+        }
+        self.byte(mark);
+        for &byte in data {
+            self.byte(byte);
+        }
+        let mut crc = crc16(crc16(0xffff, &[0xa1, 0xa1, 0xa1, mark]), data);
+        if crc_error {
+            crc = !crc;
+        }
+        self.byte((crc >> 8) as u8);
+        self.byte(crc as u8);
+    }
+}
+
+impl MfmTrack {
+    pub fn new(len: usize, data_rate: u16) -> Self {
+        Self {
+            cells: vec![0; len.div_ceil(8)],
+            weak: Vec::new(),
+            len,
+            data_rate,
+            rng: 1,
+        }
+    }
+
+    /// Cells in one revolution at 300 RPM.
+    pub fn nominal_len(data_rate: u16) -> usize {
+        data_rate as usize * 400
+    }
+
+    /// Lays out sectors in the IBM System/34 format: index mark, then for
+    /// each sector an ID field and, unless the sector has no data, a data
+    /// field, with gap 3 shrunk to fit a nominal revolution where possible.
+    pub fn from_sectors(sectors: &[Sector], data_rate: u16) -> Self {
+        let fixed = 80 + 12 + 4 + 50;
+        let per_sector: usize = sectors
+            .iter()
+            .map(|sector| {
+                let data = if sector.data.is_empty() {
+                    0
+                } else {
+                    12 + 4 + sector.id.size() + 2
+                };
+                12 + 4 + 4 + 2 + 22 + data
+            })
+            .sum();
+        let nominal = Self::nominal_len(data_rate) / 16;
+        let gap3 = match sectors.len() {
+            0 => 0,
+            count => (nominal.saturating_sub(fixed + per_sector) / count).clamp(1, 84),
+        };
+        let used = fixed + per_sector + gap3 * sectors.len();
+        let mut track = Self::new(nominal.max(used) * 16, data_rate);
+        let len = track.len;
+        let mut writer = Writer {
+            track: &mut track,
+            pos: 0,
+            prev: false,
+        };
+        writer.fill(0x4e, 80);
+        writer.fill(0x00, 12);
+        for _ in 0..3 {
+            writer.cells(SYNC_C2);
+        }
+        writer.byte(0xfc);
+        writer.fill(0x4e, 50);
+        for sector in sectors {
+            let id = sector.id;
+            writer.fill(0x00, 12);
+            let id_field = [id.cylinder, id.head, id.sector, id.size_code];
+            writer.field(0xfe, &id_field, false);
+            writer.fill(0x4e, 22);
+            if !sector.data.is_empty() {
+                writer.fill(0x00, 12);
+                let mut data = sector.data.clone();
+                data.resize(id.size(), 0);
+                let mark = if sector.deleted { 0xf8 } else { 0xfb };
+                writer.field(mark, &data, sector.crc_error);
+            }
+            writer.fill(0x4e, gap3);
+        }
+        while writer.pos < len {
+            writer.byte(0x4e);
+        }
+        track
+    }
+
+    pub fn cell(&self, pos: usize) -> bool {
+        let pos = pos % self.len;
+        ((self.cells[pos >> 3] >> (7 - (pos & 7))) & 1) != 0
+    }
+
+    /// Writing a cell lays down a clean transition, clearing its weak flag.
+    pub fn set_cell(&mut self, pos: usize, value: bool) {
+        let pos = pos % self.len;
+        let mask = 0x80 >> (pos & 7);
+        if value {
+            self.cells[pos >> 3] |= mask;
+        } else {
+            self.cells[pos >> 3] &= !mask;
+        }
+        if !self.weak.is_empty() {
+            self.weak[pos >> 3] &= !mask;
+        }
+    }
+
+    pub fn is_weak(&self, pos: usize) -> bool {
+        let pos = pos % self.len;
+        !self.weak.is_empty() && ((self.weak[pos >> 3] >> (7 - (pos & 7))) & 1) != 0
+    }
+
+    pub fn set_weak(&mut self, pos: usize, weak: bool) {
+        let pos = pos % self.len;
+        if self.weak.is_empty() {
+            if !weak {
+                return;
+            }
+            self.weak = vec![0; self.cells.len()];
+        }
+        let mask = 0x80 >> (pos & 7);
+        if weak {
+            self.weak[pos >> 3] |= mask;
+        } else {
+            self.weak[pos >> 3] &= !mask;
+        }
+    }
+
+    fn read_cell(&mut self, pos: usize, randomize: bool) -> bool {
+        if randomize && self.is_weak(pos) {
+            self.rng = self.rng.wrapping_mul(1103515245).wrapping_add(12345);
+            return (self.rng >> 30) & 1 != 0;
+        }
+        self.cell(pos)
+    }
+
+    fn read_word(&mut self, pos: usize, randomize: bool) -> u16 {
+        (0..16).fold(0, |word, i| {
+            (word << 1) | self.read_cell(pos + i, randomize) as u16
+        })
+    }
+
+    /// Decodes the byte whose 16 cells start at `pos`, skipping the clocks.
+    fn read_byte(&mut self, pos: usize, randomize: bool) -> u8 {
+        (0..8).fold(0, |byte, i| {
+            (byte << 1) | self.read_cell(pos + i * 2 + 1, randomize) as u8
+        })
+    }
+
+    fn read_bytes(&mut self, pos: usize, len: usize, randomize: bool) -> Vec<u8> {
+        (0..len)
+            .map(|i| self.read_byte(pos + i * 16, randomize))
+            .collect()
+    }
+
+    /// Reads a data field at a sync position found by `sectors`, returning
+    /// its contents, whether the mark was deleted and whether the CRC
+    /// failed. Weak cells read differently on every call if `randomize`.
+    pub fn read_data_field(
+        &mut self,
+        pos: usize,
+        size: usize,
+        randomize: bool,
+    ) -> Option<(Vec<u8>, bool, bool)> {
+        if (0..3).any(|i| self.read_word(pos + i * 16, randomize) != SYNC_A1) {
+            return None;
+        }
+        let mark = self.read_byte(pos + 48, randomize);
+        if mark != 0xfb && mark != 0xf8 {
+            return None;
+        }
+        let mut data = self.read_bytes(pos + 64, size + 2, randomize);
+        let crc = crc16(crc16(0xffff, &[0xa1, 0xa1, 0xa1, mark]), &data);
+        data.truncate(size);
+        Some((data, mark == 0xf8, crc != 0))
+    }
+
+    /// Finds every sector with a valid ID field in one revolution, pairing
+    /// each with the data field that follows it, if any.
+    pub fn sectors(&mut self, randomize: bool) -> Vec<MfmSector> {
+        let mut sectors: Vec<MfmSector> = Vec::new();
+        if self.len < 16 {
+            return sectors;
+        }
+        let mut pending: Option<(usize, usize)> = None;
+        let mut window = 0u16;
+        let mut pos = 0;
+        // A data field may run past the index hole into the next revolution.
+        while pos < self.len || pending.is_some_and(|(_, end)| pos < end + DATA_MARK_WINDOW) {
+            window = (window << 1) | self.read_cell(pos, randomize) as u16;
+            pos += 1;
+            if window != SYNC_A1 || pos < 16 {
+                continue;
+            }
+            let start = pos - 16;
+            if let Some((_, end)) = pending {
+                if start > end + DATA_MARK_WINDOW {
+                    pending = None;
+                }
+            }
+            if self.read_word(pos, randomize) != SYNC_A1
+                || self.read_word(pos + 16, randomize) != SYNC_A1
+            {
+                continue;
+            }
+            let mark = self.read_byte(pos + 32, randomize);
+            match mark {
+                0xfe if start < self.len => {
+                    let field = self.read_bytes(pos + 48, 6, randomize);
+                    if crc16(crc16(0xffff, &[0xa1, 0xa1, 0xa1, 0xfe]), &field) != 0 {
+                        continue;
+                    }
+                    sectors.push(MfmSector {
+                        sector: Sector {
+                            id: SectorId::new(field[0], field[1], field[2], field[3]),
+                            ..Default::default()
+                        },
+                        data_pos: None,
+                    });
+                    pos += 48 + 6 * 16;
+                    pending = Some((sectors.len() - 1, pos));
+                    window = 0;
+                }
+                0xfb | 0xf8 => {
+                    if let Some((index, _)) = pending.take() {
+                        let size = sectors[index].sector.id.size();
+                        if let Some((data, deleted, crc_error)) =
+                            self.read_data_field(start, size, randomize)
+                        {
+                            let found = &mut sectors[index];
+                            found.sector.data = data;
+                            found.sector.deleted = deleted;
+                            found.sector.crc_error = crc_error;
+                            found.data_pos = Some(start % self.len);
+                            pos += 48 + (size + 2) * 16;
+                            window = 0;
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        sectors
+    }
+
+    /// Rewrites the data field at a sync position with a fresh mark, data
+    /// and CRC, as the controller does for WRITE DATA.
+    pub fn write_data_field(&mut self, pos: usize, data: &[u8], deleted: bool) {
+        let prev = self.cell(pos + self.len - 1);
+        let mut writer = Writer {
+            track: self,
+            pos,
+            prev,
+        };
+        writer.field(if deleted { 0xf8 } else { 0xfb }, data, false);
+        writer.byte(0x4e);
+    }
+}
+
+#[test]
+fn test_mfm_track_encode_decode() {
+    let sectors: Vec<Sector> = (1..=9)
+        .map(|r| Sector {
+            id: SectorId::new(0, 0, r, 2),
+            data: vec![r; 512],
+            deleted: r == 3,
+            crc_error: r == 5,
+        })
+        .collect();
+    let mut track = MfmTrack::from_sectors(&sectors, 250);
+    assert_eq!(track.len, 100000);
+    let found = track.sectors(false);
+    assert_eq!(found.len(), 9);
+    for (found, sector) in found.iter().zip(&sectors) {
+        assert_eq!(found.sector.id, sector.id);
+        assert_eq!(found.sector.data, sector.data);
+        assert_eq!(found.sector.deleted, sector.deleted);
+        assert_eq!(found.sector.crc_error, sector.crc_error);
+    }
+    // Rewriting a data field clears its CRC error and deleted mark.
+    let pos = found[4].data_pos.unwrap();
+    track.write_data_field(pos, &[0xaa; 512], false);
+    let found = track.sectors(false);
+    assert_eq!(found[4].sector.data, vec![0xaa; 512]);
+    assert!(!found[4].sector.crc_error);
+    assert_eq!(found[5].sector.data, vec![6; 512]);
+    // Weak cells in a data field read back differently each time.
+    let pos = found[0].data_pos.unwrap() + 64 + 100 * 16;
+    for i in 0..64 {
+        track.set_weak(pos + i, true);
+    }
+    assert!(!track.sectors(false)[0].sector.crc_error);
+    let reads: Vec<_> = (0..4)
+        .map(|_| {
+            track
+                .read_data_field(found[0].data_pos.unwrap(), 512, true)
+                .unwrap()
+        })
+        .collect();
+    assert!(reads.iter().all(|(_, _, crc_error)| *crc_error));
+    assert!(reads.iter().any(|(data, _, _)| data != &reads[0].0));
+}
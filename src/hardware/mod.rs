@@ -4,9 +4,38 @@ use crate::ibmpc5150machine::*;
 use crate::cpu286::*;
 use crate::ibmpcatmachine::*;
 
+pub mod ata;
+pub mod cga;
+pub mod crtc;
+pub mod d86f;
+pub mod dma;
+pub mod ega;
+pub mod fdc;
+pub mod floppy;
+pub mod framebuffer;
+pub mod harddisk;
+pub mod hercules;
+pub mod hfe;
 pub mod ibmpc5150machine;
 pub mod ibmpcatmachine;
+pub mod imd;
+pub mod mda;
+pub mod mfm;
+pub mod overlay;
+pub mod pic;
 pub mod pit;
+pub mod ppi;
+pub mod td0;
+pub mod textscreen;
+pub mod vga;
+pub mod vhd;
+pub mod xebec;
+
+use crate::framebuffer::Framebuffer;
+use crate::textscreen::TextScreen;
+
+/// CPU cycles between looks at the screen while waiting for text.
+const TEXT_POLL_CYCLES: u64 = 10_000;
 
 #[derive(Clone, Debug, Default)]
 pub struct IbmPc5150Machine {
@@ -24,6 +53,32 @@ impl IbmPc5150Machine {
     pub fn tick(&mut self, cycles: usize) {
         self.hardware.tick(cycles);
     }
+    /// The latest complete frame of the primary display adapter.
+    pub fn framebuffer(&self) -> Option<&Framebuffer> {
+        self.hardware.framebuffer()
+    }
+    pub fn text_screen(&self) -> Option<TextScreen> {
+        self.hardware.text_screen()
+    }
+    /// Runs the machine until `pattern` shows up within a row of the text
+    /// screen or `timeout_cycles` CPU cycles have passed, and reports
+    /// whether it did.
+    pub fn wait_for_text(&mut self, pattern: &str, timeout_cycles: u64) -> bool {
+        let mut cycles = 0;
+        let mut next_check = 0;
+        while cycles < timeout_cycles {
+            if cycles >= next_check {
+                if self.text_screen().is_some_and(|s| s.contains(pattern)) {
+                    return true;
+                }
+                next_check = cycles + TEXT_POLL_CYCLES;
+            }
+            let elapsed = self.cpu.tick(&mut self.hardware);
+            self.tick(elapsed);
+            cycles += elapsed as u64;
+        }
+        self.text_screen().is_some_and(|s| s.contains(pattern))
+    }
 }
 
 #[derive(Clone, Debug, Default)]
@@ -42,4 +97,52 @@ impl IbmPcAtMachine {
     pub fn tick(&mut self, cycles: usize) {
         self.hardware.tick(cycles);
     }
+    /// The latest complete frame of the primary display adapter.
+    pub fn framebuffer(&self) -> Option<&Framebuffer> {
+        self.hardware.framebuffer()
+    }
+    pub fn text_screen(&self) -> Option<TextScreen> {
+        self.hardware.text_screen()
+    }
+    /// Runs the machine until `pattern` shows up within a row of the text
+    /// screen or `timeout_cycles` CPU cycles have passed, and reports
+    /// whether it did.
+    pub fn wait_for_text(&mut self, pattern: &str, timeout_cycles: u64) -> bool {
+        let mut cycles = 0;
+        let mut next_check = 0;
+        while cycles < timeout_cycles {
+            if cycles >= next_check {
+                if self.text_screen().is_some_and(|s| s.contains(pattern)) {
+                    return true;
+                }
+                next_check = cycles + TEXT_POLL_CYCLES;
+            }
+            let elapsed = self.cpu.tick(&mut self.hardware);
+            self.tick(elapsed);
+            cycles += elapsed as u64;
+        }
+        self.text_screen().is_some_and(|s| s.contains(pattern))
+    }
+}
+
+#[test]
+fn test_wait_for_text() {
+    let mut machine = IbmPc5150Machine::default();
+    machine.hardware.ram = vec![0; 0x10000];
+    machine.hardware.mda = Some(crate::mda::MDA::new());
+    // mov ax,0xb000; mov ds,ax; mov word [0],0x0741; mov byte [2],'>';
+    // jmp $
+    let program = [
+        0xb8, 0x00, 0xb0, 0x8e, 0xd8, 0xc7, 0x06, 0x00, 0x00, 0x41, 0x07, 0xc6, 0x06, 0x02, 0x00,
+        0x3e, 0xeb, 0xfe,
+    ];
+    machine.hardware.ram[0x7c00..0x7c00 + program.len()].copy_from_slice(&program);
+    machine.cpu.regs.ip = 0;
+    machine.cpu.regs.seg_regs[1] = 0x7c0;
+    assert!(!machine.text_screen().unwrap().contains("A"));
+    assert!(machine.wait_for_text("A>", 100_000));
+    let screen = machine.text_screen().unwrap();
+    assert_eq!(screen.find("A>"), Some((0, 0)));
+    assert_eq!(screen.rows[0][0].attr, 0x07);
+    assert!(!machine.wait_for_text("C>", 50_000));
 }
@@ -0,0 +1,74 @@
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+const MAGIC: &[u8; 8] = b"EPCOVL01";
+
+/// Sectors written on top of a read-only base disk, kept apart from it
+/// until committed. An overlay file journals each write as a little-endian
+/// block address followed by the sector, so a session can be resumed.
+#[derive(Debug, Clone, Default)]
+pub struct DiskOverlay {
+    pub sectors: BTreeMap<usize, Vec<u8>>,
+    pub path: Option<PathBuf>,
+}
+
+impl DiskOverlay {
+    /// An overlay held in memory only.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Opens an overlay file, replaying the writes in it, or creates an
+    /// empty one.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let mut overlay = Self {
+            sectors: BTreeMap::new(),
+            path: Some(path.to_path_buf()),
+        };
+        if !path.exists() {
+            fs::write(path, MAGIC)?;
+            return Ok(overlay);
+        }
+        let data = fs::read(path)?;
+        if !data.starts_with(MAGIC) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a disk overlay file",
+            ));
+        }
+        // A record cut short by a crash is dropped.
+        for record in data[MAGIC.len()..].chunks_exact(8 + 512) {
+            let lba = u64::from_le_bytes(record[..8].try_into().unwrap()) as usize;
+            overlay.sectors.insert(lba, record[8..].to_vec());
+        }
+        Ok(overlay)
+    }
+
+    pub fn read_sector(&self, lba: usize) -> Option<&[u8]> {
+        self.sectors.get(&lba).map(|data| data.as_slice())
+    }
+
+    pub fn write_sector(&mut self, lba: usize, data: &[u8]) -> io::Result<()> {
+        let mut sector = data.to_vec();
+        sector.resize(512, 0);
+        if let Some(path) = &self.path {
+            let mut file = OpenOptions::new().append(true).open(path)?;
+            file.write_all(&(lba as u64).to_le_bytes())?;
+            file.write_all(&sector)?;
+        }
+        self.sectors.insert(lba, sector);
+        Ok(())
+    }
+
+    /// Forgets every write, emptying the overlay file.
+    pub fn clear(&mut self) -> io::Result<()> {
+        self.sectors.clear();
+        match &self.path {
+            Some(path) => fs::write(path, MAGIC),
+            None => Ok(()),
+        }
+    }
+}
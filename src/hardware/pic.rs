@@ -0,0 +1,426 @@
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PicInitState {
+    Ready,
+    Icw2,
+    Icw3,
+    Icw4,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PIC {
+    pub irr: u8,
+    pub isr: u8,
+    pub imr: u8,
+    pub icw1: u8,
+    pub icw3: u8,
+    pub icw4: u8,
+    pub vector_base: u8,
+    pub init_state: PicInitState,
+    /// Current level of the IR0-IR7 inputs.
+    pub lines: u8,
+    /// Rising edges seen on each input since its last acknowledge.
+    pub edges: u8,
+    pub lowest_priority: u8,
+    pub rotate_in_aeoi: bool,
+    pub special_mask: bool,
+    pub read_isr: bool,
+    pub poll: bool,
+}
+
+impl PIC {
+    pub fn new() -> Self {
+        Self {
+            irr: 0,
+            isr: 0,
+            imr: 0xff,
+            icw1: 0,
+            icw3: 0,
+            icw4: 0,
+            vector_base: 0,
+            init_state: PicInitState::Ready,
+            lines: 0,
+            edges: 0,
+            lowest_priority: 7,
+            rotate_in_aeoi: false,
+            special_mask: false,
+            read_isr: false,
+            poll: false,
+        }
+    }
+
+    pub fn level_triggered(&self) -> bool {
+        (self.icw1 & 0x08) != 0
+    }
+
+    pub fn single(&self) -> bool {
+        (self.icw1 & 0x02) != 0
+    }
+
+    pub fn auto_eoi(&self) -> bool {
+        (self.icw4 & 0x02) != 0
+    }
+
+    pub fn special_fully_nested(&self) -> bool {
+        (self.icw4 & 0x10) != 0
+    }
+
+    /// True for a master whose ICW3 marks `irq` as having a slave attached.
+    pub fn is_cascade_input(&self, irq: u8) -> bool {
+        !self.single() && (self.icw3 & (1 << irq)) != 0
+    }
+
+    fn update_irr(&mut self) {
+        self.irr = if self.level_triggered() {
+            self.lines
+        } else {
+            self.lines & self.edges
+        };
+    }
+
+    pub fn set_irq(&mut self, irq: u8, level: bool) {
+        let bit = 1u8 << (irq & 7);
+        if level && (self.lines & bit) == 0 {
+            self.edges |= bit;
+        }
+        if level {
+            self.lines |= bit;
+        } else {
+            self.lines &= !bit;
+        }
+        self.update_irr();
+    }
+
+    /// Priority levels in order, highest first.
+    fn priorities(&self) -> impl Iterator<Item = u8> {
+        let first = (self.lowest_priority + 1) & 7;
+        (0..8).map(move |i| (first + i) & 7)
+    }
+
+    /// Returns the highest priority in-service level that blocks `irq`.
+    fn blocking_isr(&self) -> Option<u8> {
+        let isr = if self.special_mask {
+            self.isr & !self.imr
+        } else {
+            self.isr
+        };
+        self.priorities().find(|&level| (isr & (1 << level)) != 0)
+    }
+
+    /// Returns the IR level that would be serviced by the next acknowledge.
+    pub fn pending_irq(&self) -> Option<u8> {
+        let requests = self.irr & !self.imr;
+        let blocking = self.blocking_isr();
+        for level in self.priorities() {
+            let bit = 1u8 << level;
+            if Some(level) == blocking {
+                // A level in service blocks itself and everything below it,
+                // except for a cascaded slave in special fully nested mode.
+                if (requests & bit) != 0
+                    && (self.isr & bit) != 0
+                    && self.special_fully_nested()
+                    && self.is_cascade_input(level)
+                {
+                    return Some(level);
+                }
+                if !self.special_mask {
+                    return None;
+                }
+            }
+            if (requests & bit) != 0 && (self.isr & bit) == 0 {
+                return Some(level);
+            }
+        }
+        None
+    }
+
+    pub fn intr(&self) -> bool {
+        self.pending_irq().is_some()
+    }
+
+    /// Performs the INTA sequence, returning the serviced IR level or IR7
+    /// for a spurious interrupt.
+    pub fn acknowledge_irq(&mut self) -> u8 {
+        match self.pending_irq() {
+            Some(irq) => {
+                let bit = 1u8 << irq;
+                self.edges &= !bit;
+                if self.auto_eoi() {
+                    if self.rotate_in_aeoi {
+                        self.lowest_priority = irq;
+                    }
+                } else {
+                    self.isr |= bit;
+                }
+                self.update_irr();
+                irq
+            }
+            None => 7,
+        }
+    }
+
+    pub fn acknowledge(&mut self) -> u8 {
+        let irq = self.acknowledge_irq();
+        self.vector_base | irq
+    }
+
+    fn eoi(&mut self, level: Option<u8>, rotate: bool) {
+        let level = match level {
+            Some(level) => level,
+            None => match self.priorities().find(|&level| (self.isr & (1 << level)) != 0) {
+                Some(level) => level,
+                None => return,
+            },
+        };
+        self.isr &= !(1 << level);
+        if rotate {
+            self.lowest_priority = level;
+        }
+    }
+
+    pub fn rb(&mut self, addr: u16) -> u8 {
+        if self.poll {
+            self.poll = false;
+            return match self.pending_irq() {
+                Some(_) => 0x80 | self.acknowledge_irq(),
+                None => 0,
+            };
+        }
+        match addr & 1 {
+            0 => {
+                if self.read_isr {
+                    self.isr
+                } else {
+                    self.irr
+                }
+            }
+            _ => self.imr,
+        }
+    }
+
+    pub fn wb(&mut self, addr: u16, data: u8) {
+        match addr & 1 {
+            0 => {
+                if (data & 0x10) != 0 {
+                    // ICW1
+                    self.icw1 = data;
+                    self.icw3 = 0;
+                    if (data & 0x01) == 0 {
+                        self.icw4 = 0;
+                    }
+                    self.imr = 0;
+                    self.isr = 0;
+                    self.edges = 0;
+                    self.lowest_priority = 7;
+                    self.rotate_in_aeoi = false;
+                    self.special_mask = false;
+                    self.read_isr = false;
+                    self.poll = false;
+                    self.init_state = PicInitState::Icw2;
+                    self.update_irr();
+                } else if (data & 0x08) == 0 {
+                    // OCW2
+                    let level = data & 7;
+                    match data >> 5 {
+                        0 => self.rotate_in_aeoi = false,
+                        1 => self.eoi(None, false),
+                        3 => self.eoi(Some(level), false),
+                        4 => self.rotate_in_aeoi = true,
+                        5 => self.eoi(None, true),
+                        6 => self.lowest_priority = level,
+                        7 => self.eoi(Some(level), true),
+                        _ => (),
+                    }
+                } else {
+                    // OCW3
+                    if (data & 0x40) != 0 {
+                        self.special_mask = (data & 0x20) != 0;
+                    }
+                    if (data & 0x02) != 0 {
+                        self.read_isr = (data & 0x01) != 0;
+                    }
+                    self.poll = (data & 0x04) != 0;
+                }
+            }
+            _ => match self.init_state {
+                PicInitState::Icw2 => {
+                    self.vector_base = data & 0xf8;
+                    self.init_state = if !self.single() {
+                        PicInitState::Icw3
+                    } else if (self.icw1 & 0x01) != 0 {
+                        PicInitState::Icw4
+                    } else {
+                        PicInitState::Ready
+                    };
+                }
+                PicInitState::Icw3 => {
+                    self.icw3 = data;
+                    self.init_state = if (self.icw1 & 0x01) != 0 {
+                        PicInitState::Icw4
+                    } else {
+                        PicInitState::Ready
+                    };
+                }
+                PicInitState::Icw4 => {
+                    self.icw4 = data;
+                    self.init_state = PicInitState::Ready;
+                }
+                PicInitState::Ready => self.imr = data,
+            },
+        }
+    }
+}
+
+impl Default for PIC {
+    fn default() -> PIC {
+        PIC::new()
+    }
+}
+
+/// Master and slave 8259As as wired on the AT: the slave's INT output
+/// drives IR2 of the master.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PicPair {
+    pub master: PIC,
+    pub slave: PIC,
+}
+
+impl PicPair {
+    pub fn new() -> Self {
+        Self {
+            master: PIC::new(),
+            slave: PIC::new(),
+        }
+    }
+
+    fn update_cascade(&mut self) {
+        let slave_intr = self.slave.intr();
+        self.master.set_irq(2, slave_intr);
+    }
+
+    /// Sets the level of IRQ0-IRQ15.
+    pub fn set_irq(&mut self, irq: u8, level: bool) {
+        if irq < 8 {
+            self.master.set_irq(irq, level);
+        } else {
+            self.slave.set_irq(irq & 7, level);
+            self.update_cascade();
+        }
+    }
+
+    pub fn intr(&self) -> bool {
+        self.master.intr()
+    }
+
+    pub fn acknowledge(&mut self) -> u8 {
+        let irq = self.master.acknowledge_irq();
+        let vector = if self.master.is_cascade_input(irq) {
+            self.slave.acknowledge()
+        } else {
+            self.master.vector_base | irq
+        };
+        self.update_cascade();
+        vector
+    }
+
+    pub fn rb(&mut self, addr: u16) -> u8 {
+        let value = if (addr & 0x80) != 0 {
+            self.slave.rb(addr)
+        } else {
+            self.master.rb(addr)
+        };
+        self.update_cascade();
+        value
+    }
+
+    pub fn wb(&mut self, addr: u16, data: u8) {
+        if (addr & 0x80) != 0 {
+            self.slave.wb(addr, data);
+        } else {
+            self.master.wb(addr, data);
+        }
+        self.update_cascade();
+    }
+}
+
+#[cfg(test)]
+fn init_pic(pic: &mut PIC, vector_base: u8) {
+    pic.wb(0x20, 0x13);
+    pic.wb(0x21, vector_base);
+    pic.wb(0x21, 0x01);
+    pic.wb(0x21, 0x00);
+}
+
+#[test]
+fn test_pic_priority_and_eoi() {
+    let mut pic = PIC::new();
+    init_pic(&mut pic, 0x08);
+    pic.set_irq(3, true);
+    pic.set_irq(1, true);
+    assert_eq!(pic.rb(0x20), 0x0a);
+    assert_eq!(pic.acknowledge(), 0x09);
+    // IR3 is blocked until IR1 is acknowledged with an EOI.
+    assert!(!pic.intr());
+    pic.wb(0x20, 0x0b);
+    assert_eq!(pic.rb(0x20), 0x02);
+    pic.wb(0x20, 0x20);
+    assert_eq!(pic.acknowledge(), 0x0b);
+    pic.wb(0x20, 0x63);
+    assert_eq!(pic.rb(0x20), 0x00);
+}
+
+#[test]
+fn test_pic_edge_mask_and_poll() {
+    let mut pic = PIC::new();
+    init_pic(&mut pic, 0x08);
+    pic.wb(0x21, 0x01);
+    pic.set_irq(0, true);
+    assert!(!pic.intr());
+    pic.wb(0x21, 0x00);
+    assert!(pic.intr());
+    assert_eq!(pic.acknowledge(), 0x08);
+    pic.wb(0x20, 0x20);
+    // The line is still high, but no new edge has been seen.
+    assert!(!pic.intr());
+    pic.set_irq(0, false);
+    pic.set_irq(0, true);
+    pic.wb(0x20, 0x0c);
+    assert_eq!(pic.rb(0x20), 0x80);
+    assert_eq!(pic.rb(0x20), 0x00);
+}
+
+#[test]
+fn test_pic_rotation_and_special_mask() {
+    let mut pic = PIC::new();
+    init_pic(&mut pic, 0x08);
+    pic.set_irq(0, true);
+    pic.set_irq(1, true);
+    assert_eq!(pic.acknowledge(), 0x08);
+    // Rotate on non-specific EOI makes IR0 the lowest priority.
+    pic.wb(0x20, 0xa0);
+    pic.set_irq(0, false);
+    pic.set_irq(0, true);
+    assert_eq!(pic.acknowledge(), 0x09);
+    pic.wb(0x21, 0x02);
+    pic.wb(0x20, 0x68);
+    assert_eq!(pic.acknowledge(), 0x08);
+}
+
+#[test]
+fn test_pic_cascade() {
+    let mut pics = PicPair::new();
+    pics.wb(0x20, 0x11);
+    pics.wb(0x21, 0x08);
+    pics.wb(0x21, 0x04);
+    pics.wb(0x21, 0x01);
+    pics.wb(0xa0, 0x11);
+    pics.wb(0xa1, 0x70);
+    pics.wb(0xa1, 0x02);
+    pics.wb(0xa1, 0x01);
+    pics.wb(0x21, 0x00);
+    pics.wb(0xa1, 0x00);
+    pics.set_irq(14, true);
+    assert!(pics.intr());
+    assert_eq!(pics.acknowledge(), 0x76);
+    assert_eq!(pics.master.isr, 0x04);
+    assert_eq!(pics.slave.isr, 0x40);
+}
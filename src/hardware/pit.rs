@@ -6,17 +6,42 @@ pub enum AccessMode {
     LowThenHigh = 3,
 }
 
-pub enum PitCtrState {}
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PitCtrState {
+    /// No count has been written since the control word.
+    Idle,
+    /// A count has been written and is loaded on the next clock.
+    Load,
+    /// Modes 1 and 5: waiting for a rising edge on the gate input.
+    WaitingForTrigger,
+    Counting,
+}
 
 #[derive(Debug, Clone, Copy)]
 pub struct PitCounter {
     pub timer_mode: u8,
+    /// Byte expected by the next count write; toggles in LowThenHigh mode.
     pub access_mode: AccessMode,
+    /// Byte returned by the next count read; toggles in LowThenHigh mode.
+    pub read_mode: AccessMode,
+    pub bcd: bool,
+    /// Counting element, always held in binary.
     pub count: u16,
+    /// Count register, always held in binary.
+    pub reload: u16,
+    pub low_byte: u8,
+    pub latch: Option<u16>,
+    pub status_latch: Option<u8>,
+    pub state: PitCtrState,
+    pub null_count: bool,
+    pub triggered: bool,
+    /// Set for odd counts in mode 3 until the first half cycle completes.
+    pub odd_reload: bool,
     pub gate: bool,
     pub out: bool,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum PitType {
     PIT8253,
     PIT8254,
@@ -24,47 +49,459 @@ pub enum PitType {
 
 #[derive(Debug, Clone, Copy)]
 pub struct PIT {
+    pub pit_type: PitType,
     pub counters: [PitCounter; 3],
     pub ctrl: u8,
 }
 
-impl PIT {
+fn from_bcd(value: u16) -> u16 {
+    (value & 0xf)
+        + ((value >> 4) & 0xf) * 10
+        + ((value >> 8) & 0xf) * 100
+        + ((value >> 12) & 0xf) * 1000
+}
+
+fn to_bcd(value: u16) -> u16 {
+    (value % 10)
+        | ((value / 10 % 10) << 4)
+        | ((value / 100 % 10) << 8)
+        | ((value / 1000 % 10) << 12)
+}
+
+impl PitCounter {
     pub fn new() -> Self {
         Self {
-            counters: [PitCounter {
-                timer_mode: 0,
-                access_mode: AccessMode::LowThenHigh,
-                count: 0xffffu16,
-                gate: false,
-                out: false,
-            }; 3],
+            timer_mode: 0,
+            access_mode: AccessMode::LowThenHigh,
+            read_mode: AccessMode::LowThenHigh,
+            bcd: false,
+            count: 0xffffu16,
+            reload: 0,
+            low_byte: 0,
+            latch: None,
+            status_latch: None,
+            state: PitCtrState::Idle,
+            null_count: true,
+            triggered: false,
+            odd_reload: false,
+            gate: false,
+            out: false,
+        }
+    }
+
+    /// Number of clocks represented by a count of zero.
+    fn modulus(&self) -> u32 {
+        if self.bcd {
+            10000
+        } else {
+            0x10000
+        }
+    }
+
+    fn load(&mut self) {
+        self.count = self.reload;
+        self.null_count = false;
+        self.odd_reload = (self.reload & 1) != 0;
+    }
+
+    /// Decrements the counting element, returning true on terminal count.
+    fn decrement(&mut self, amount: u32) -> bool {
+        let count = if self.count == 0 {
+            self.modulus()
+        } else {
+            self.count as u32
+        };
+        if count <= amount {
+            self.count = 0;
+            true
+        } else {
+            self.count = (count - amount) as u16;
+            false
+        }
+    }
+
+    pub fn set_control(&mut self, data: u8) {
+        self.access_mode = match (data >> 4) & 3 {
+            1 => AccessMode::AlwaysLow,
+            2 => AccessMode::AlwaysHigh,
+            _ => AccessMode::LowThenHigh,
+        };
+        self.read_mode = self.access_mode;
+        self.timer_mode = (data >> 1) & 7;
+        if self.timer_mode >= 6 {
+            self.timer_mode -= 4;
+        }
+        self.bcd = (data & 1) != 0;
+        self.latch = None;
+        self.status_latch = None;
+        self.state = PitCtrState::Idle;
+        self.null_count = true;
+        self.triggered = false;
+        self.out = self.timer_mode != 0;
+    }
+
+    pub fn latch_count(&mut self) {
+        if self.latch.is_none() {
+            self.latch = Some(self.count);
+            self.read_mode = match self.access_mode {
+                AccessMode::HighThenLow => AccessMode::LowThenHigh,
+                mode => mode,
+            };
+        }
+    }
+
+    pub fn latch_status(&mut self) {
+        if self.status_latch.is_none() {
+            let rw = match self.access_mode {
+                AccessMode::HighThenLow => AccessMode::LowThenHigh,
+                mode => mode,
+            } as u8;
+            self.status_latch = Some(
+                ((self.out as u8) << 7)
+                    | ((self.null_count as u8) << 6)
+                    | (rw << 4)
+                    | (self.timer_mode << 1)
+                    | self.bcd as u8,
+            );
+        }
+    }
+
+    pub fn read(&mut self) -> u8 {
+        if let Some(status) = self.status_latch.take() {
+            return status;
+        }
+        let value = self.latch.unwrap_or(self.count);
+        let value = if self.bcd { to_bcd(value) } else { value };
+        let (byte, done) = match self.read_mode {
+            AccessMode::AlwaysLow => (value as u8, true),
+            AccessMode::AlwaysHigh => ((value >> 8) as u8, true),
+            AccessMode::LowThenHigh => {
+                self.read_mode = AccessMode::HighThenLow;
+                (value as u8, false)
+            }
+            AccessMode::HighThenLow => {
+                self.read_mode = AccessMode::LowThenHigh;
+                ((value >> 8) as u8, true)
+            }
+        };
+        if done {
+            self.latch = None;
+        }
+        byte
+    }
+
+    pub fn write(&mut self, data: u8) {
+        let value = match self.access_mode {
+            AccessMode::AlwaysLow => data as u16,
+            AccessMode::AlwaysHigh => (data as u16) << 8,
+            AccessMode::LowThenHigh => {
+                self.low_byte = data;
+                self.access_mode = AccessMode::HighThenLow;
+                if self.timer_mode == 0 {
+                    // Writing the first byte stops the count in mode 0.
+                    self.out = false;
+                    self.state = PitCtrState::Idle;
+                }
+                return;
+            }
+            AccessMode::HighThenLow => {
+                self.access_mode = AccessMode::LowThenHigh;
+                self.low_byte as u16 | ((data as u16) << 8)
+            }
+        };
+        self.reload = if self.bcd {
+            from_bcd(value) % 10000
+        } else {
+            value
+        };
+        self.null_count = true;
+        match self.timer_mode {
+            0 => {
+                self.out = false;
+                self.state = PitCtrState::Load;
+            }
+            1 | 5 => {
+                if self.state == PitCtrState::Idle {
+                    self.state = PitCtrState::WaitingForTrigger;
+                }
+            }
+            2 | 3 => {
+                if self.state == PitCtrState::Idle {
+                    self.state = PitCtrState::Load;
+                }
+            }
+            _ => self.state = PitCtrState::Load,
+        }
+    }
+
+    pub fn set_gate(&mut self, gate: bool) {
+        if gate && !self.gate {
+            self.triggered = true;
+            if self.state == PitCtrState::Counting && matches!(self.timer_mode, 2 | 3) {
+                self.state = PitCtrState::Load;
+            }
+        }
+        if !gate && matches!(self.timer_mode, 2 | 3) {
+            self.out = true;
+        }
+        self.gate = gate;
+    }
+
+    /// Advances the counter by one input clock.
+    pub fn clock(&mut self) {
+        let triggered = std::mem::take(&mut self.triggered);
+        match self.state {
+            PitCtrState::Idle => return,
+            PitCtrState::WaitingForTrigger => {
+                if triggered {
+                    self.load();
+                    self.out = self.timer_mode != 1;
+                    self.state = PitCtrState::Counting;
+                }
+                return;
+            }
+            PitCtrState::Load => {
+                if self.gate || matches!(self.timer_mode, 0 | 4) {
+                    self.load();
+                    self.state = PitCtrState::Counting;
+                    if matches!(self.timer_mode, 2 | 3) {
+                        self.out = true;
+                    }
+                }
+                return;
+            }
+            PitCtrState::Counting => (),
+        }
+        match self.timer_mode {
+            0 => {
+                if self.gate && self.decrement(1) {
+                    self.out = true;
+                }
+            }
+            1 | 5 => {
+                if triggered {
+                    self.load();
+                    self.out = self.timer_mode != 1;
+                    return;
+                }
+                if self.timer_mode == 5 && !self.out {
+                    self.out = true;
+                }
+                if self.decrement(1) {
+                    self.out = self.timer_mode == 1;
+                }
+            }
+            2 => {
+                if !self.gate {
+                    return;
+                }
+                if !self.out {
+                    self.out = true;
+                    self.load();
+                } else if self.decrement(1) || self.count == 1 {
+                    self.out = false;
+                }
+            }
+            3 => {
+                if !self.gate {
+                    return;
+                }
+                let amount = if self.odd_reload {
+                    self.odd_reload = false;
+                    if self.out {
+                        1
+                    } else {
+                        3
+                    }
+                } else {
+                    2
+                };
+                if self.decrement(amount) {
+                    self.out = !self.out;
+                    self.load();
+                }
+            }
+            _ => {
+                if !self.out {
+                    self.out = true;
+                }
+                if self.gate && self.decrement(1) {
+                    self.out = false;
+                }
+            }
+        }
+    }
+}
+
+impl Default for PitCounter {
+    fn default() -> PitCounter {
+        PitCounter::new()
+    }
+}
+
+impl PIT {
+    pub fn new(pit_type: PitType) -> Self {
+        Self {
+            pit_type,
+            counters: [PitCounter::new(); 3],
             ctrl: 0,
         }
     }
-    pub fn tick(&mut self, _cycles: usize) {
-        println!("PIT TICKED");
+
+    /// Advances all three counters by `cycles` input clocks.
+    pub fn tick(&mut self, cycles: usize) {
+        for _ in 0..cycles {
+            self.clock();
+        }
+    }
+
+    pub fn clock(&mut self) {
+        for counter in self.counters.iter_mut() {
+            counter.clock();
+        }
     }
 
-    pub fn rb(&mut self, _addr: u16) -> u8 {
-        0
+    pub fn set_gate(&mut self, counter: usize, gate: bool) {
+        self.counters[counter].set_gate(gate);
+    }
+
+    pub fn out(&self, counter: usize) -> bool {
+        self.counters[counter].out
+    }
+
+    pub fn rb(&mut self, addr: u16) -> u8 {
+        match addr & 3 {
+            3 => 0xff,
+            counter => self.counters[counter as usize].read(),
+        }
     }
 
     pub fn wb(&mut self, addr: u16, data: u8) {
         match addr & 3 {
             3 => {
                 if (data >> 6) == 3 {
-                    todo!();
+                    if self.pit_type == PitType::PIT8254 {
+                        for counter in 0..3 {
+                            if (data & (2 << counter)) == 0 {
+                                continue;
+                            }
+                            if (data & 0x20) == 0 {
+                                self.counters[counter].latch_count();
+                            }
+                            if (data & 0x10) == 0 {
+                                self.counters[counter].latch_status();
+                            }
+                        }
+                    }
                 } else {
                     self.ctrl = data;
+                    let counter = &mut self.counters[(data >> 6) as usize];
+                    if (data & 0x30) == 0 {
+                        counter.latch_count();
+                    } else {
+                        counter.set_control(data);
+                    }
                 }
             }
-            _ => todo!(),
+            counter => self.counters[counter as usize].write(data),
         }
     }
 }
 
 impl Default for PIT {
     fn default() -> PIT {
-        PIT::new()
+        PIT::new(PitType::PIT8253)
     }
 }
+
+#[cfg(test)]
+fn pit_with_gates() -> PIT {
+    let mut pit = PIT::new(PitType::PIT8254);
+    for counter in 0..3 {
+        pit.set_gate(counter, true);
+    }
+    pit
+}
+
+#[test]
+fn test_pit_mode0_terminal_count() {
+    let mut pit = pit_with_gates();
+    pit.wb(0x43, 0x30);
+    assert!(!pit.out(0));
+    pit.wb(0x40, 0x05);
+    pit.wb(0x40, 0x00);
+    pit.tick(5);
+    assert!(!pit.out(0));
+    pit.tick(1);
+    assert!(pit.out(0));
+    pit.tick(1);
+    assert!(pit.out(0));
+    assert_eq!(pit.rb(0x40), 0xff);
+    assert_eq!(pit.rb(0x40), 0xff);
+}
+
+#[test]
+fn test_pit_mode2_rate_generator() {
+    let mut pit = pit_with_gates();
+    pit.wb(0x43, 0x54);
+    pit.wb(0x41, 0x04);
+    pit.tick(1);
+    let outs: Vec<bool> = (0..8)
+        .map(|_| {
+            pit.tick(1);
+            pit.out(1)
+        })
+        .collect();
+    assert_eq!(outs, [true, true, false, true, true, true, false, true]);
+}
+
+#[test]
+fn test_pit_mode3_square_wave() {
+    let mut pit = pit_with_gates();
+    pit.wb(0x43, 0xb6);
+    pit.wb(0x42, 0x05);
+    pit.wb(0x42, 0x00);
+    pit.tick(1);
+    let outs: Vec<bool> = (0..10)
+        .map(|_| {
+            pit.tick(1);
+            pit.out(2)
+        })
+        .collect();
+    assert_eq!(
+        outs,
+        [true, true, false, false, true, true, true, false, false, true]
+    );
+    pit.set_gate(2, false);
+    assert!(pit.out(2));
+}
+
+#[test]
+fn test_pit_latch_and_bcd() {
+    let mut pit = pit_with_gates();
+    pit.wb(0x43, 0x31);
+    pit.wb(0x40, 0x00);
+    pit.wb(0x40, 0x10);
+    pit.tick(3);
+    pit.wb(0x43, 0x00);
+    pit.tick(5);
+    assert_eq!(pit.rb(0x40), 0x98);
+    assert_eq!(pit.rb(0x40), 0x09);
+    assert_eq!(pit.rb(0x40), 0x93);
+}
+
+#[test]
+fn test_pit_readback_status() {
+    let mut pit = pit_with_gates();
+    pit.wb(0x43, 0x94);
+    pit.wb(0x42, 0x10);
+    pit.wb(0x43, 0xc8);
+    assert_eq!(pit.rb(0x42), 0xd4);
+    pit.tick(1);
+    pit.wb(0x43, 0xe8);
+    assert_eq!(pit.rb(0x42), 0x94);
+    let mut pit = PIT::new(PitType::PIT8253);
+    pit.wb(0x43, 0x94);
+    pit.wb(0x43, 0xe8);
+    assert_eq!(pit.rb(0x42), 0xff);
+}
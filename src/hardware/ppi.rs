@@ -0,0 +1,247 @@
+use std::collections::VecDeque;
+
+/// Display adapter selected by SW1 switches 5-6.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VideoType {
+    /// No adapter, or one with its own BIOS such as the EGA.
+    Other = 0,
+    Cga40 = 1,
+    Cga80 = 2,
+    Mda = 3,
+}
+
+/// SW1 and SW2 on the 5150 planar, as read by the BIOS: a set bit is a
+/// switch in the "off" position.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DipSwitches {
+    pub sw1: u8,
+    pub sw2: u8,
+}
+
+impl DipSwitches {
+    /// Builds the switch settings for `memory_kb` of RAM (16K steps on the
+    /// planar up to 64K, then 32K steps of I/O channel RAM), a display
+    /// adapter and 0-4 floppy drives.
+    pub fn new(memory_kb: u32, video: VideoType, floppy_count: u8) -> Self {
+        let mut sw1 = (video as u8) << 4;
+        if floppy_count > 0 {
+            sw1 |= 0x01;
+            sw1 |= ((floppy_count.min(4) - 1) & 3) << 6;
+        }
+        let mut switches = Self { sw1, sw2: 0 };
+        switches.set_memory(memory_kb);
+        switches
+    }
+
+    pub fn set_memory(&mut self, memory_kb: u32) {
+        let planar_kb = memory_kb.clamp(16, 64);
+        self.sw1 = (self.sw1 & !0x0c) | ((((planar_kb / 16) - 1) as u8) << 2);
+        self.sw2 = (memory_kb.saturating_sub(64) / 32).min(0x1f) as u8;
+    }
+
+    /// The amount of RAM the switches report, which is what the machine
+    /// has fitted.
+    pub fn memory_kb(&self) -> u32 {
+        let planar_kb = (((self.sw1 >> 2) & 3) as u32 + 1) * 16;
+        planar_kb + (self.sw2 & 0x1f) as u32 * 32
+    }
+
+    pub fn set_video(&mut self, video: VideoType) {
+        self.sw1 = (self.sw1 & !0x30) | ((video as u8) << 4);
+    }
+}
+
+impl Default for DipSwitches {
+    fn default() -> DipSwitches {
+        DipSwitches::new(64, VideoType::Cga80, 1)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PPI {
+    pub switches: DipSwitches,
+    /// Mode control word last written to port 0x63.
+    pub ctrl: u8,
+    pub port_a: u8,
+    pub port_b: u8,
+    pub port_c: u8,
+    /// Scancode latched in the keyboard shift register, if any.
+    pub scancode: Option<u8>,
+    pub keyboard_queue: VecDeque<u8>,
+    /// Inputs sampled on port C reads.
+    pub timer2_out: bool,
+    pub cassette_in: bool,
+}
+
+impl PPI {
+    pub fn new(switches: DipSwitches) -> Self {
+        Self {
+            switches,
+            ctrl: 0x99,
+            port_a: 0,
+            port_b: 0,
+            port_c: 0,
+            scancode: None,
+            keyboard_queue: VecDeque::new(),
+            timer2_out: false,
+            cassette_in: false,
+        }
+    }
+
+    pub fn timer2_gate(&self) -> bool {
+        (self.port_b & 0x01) != 0
+    }
+
+    pub fn speaker_data(&self) -> bool {
+        (self.port_b & 0x02) != 0
+    }
+
+    /// PB6 low holds the keyboard clock line low.
+    pub fn keyboard_clock(&self) -> bool {
+        (self.port_b & 0x40) != 0
+    }
+
+    /// PB7 high clears the keyboard shift register and selects SW1 on port A.
+    pub fn keyboard_clear(&self) -> bool {
+        (self.port_b & 0x80) != 0
+    }
+
+    /// Level of IRQ1, asserted while a scancode is latched.
+    pub fn keyboard_irq(&self) -> bool {
+        self.scancode.is_some() && !self.keyboard_clear()
+    }
+
+    /// Queues a scancode sent by the keyboard.
+    pub fn key_event(&mut self, scancode: u8) {
+        self.keyboard_queue.push_back(scancode);
+        self.update_keyboard();
+    }
+
+    fn update_keyboard(&mut self) {
+        if self.scancode.is_none() && self.keyboard_clock() && !self.keyboard_clear() {
+            self.scancode = self.keyboard_queue.pop_front();
+        }
+    }
+
+    fn port_a_input(&self) -> u8 {
+        if self.keyboard_clear() {
+            self.switches.sw1
+        } else {
+            self.scancode.unwrap_or(0)
+        }
+    }
+
+    fn port_c_input(&self) -> u8 {
+        // PB2 selects SW2 switches 1-4, otherwise switch 5 is read.
+        let switches = if (self.port_b & 0x04) != 0 {
+            self.switches.sw2 & 0x0f
+        } else {
+            (self.switches.sw2 >> 4) & 0x01
+        };
+        switches | ((self.cassette_in as u8) << 4) | ((self.timer2_out as u8) << 5)
+    }
+
+    pub fn rb(&mut self, addr: u16) -> u8 {
+        match addr & 3 {
+            0 => {
+                if (self.ctrl & 0x10) != 0 {
+                    self.port_a_input()
+                } else {
+                    self.port_a
+                }
+            }
+            1 => self.port_b,
+            2 => {
+                let mut value = self.port_c;
+                if (self.ctrl & 0x01) != 0 {
+                    value = (value & 0xf0) | (self.port_c_input() & 0x0f);
+                }
+                if (self.ctrl & 0x08) != 0 {
+                    value = (value & 0x0f) | (self.port_c_input() & 0xf0);
+                }
+                value
+            }
+            _ => self.ctrl,
+        }
+    }
+
+    pub fn wb(&mut self, addr: u16, data: u8) {
+        match addr & 3 {
+            0 => self.port_a = data,
+            1 => {
+                let old = self.port_b;
+                self.port_b = data;
+                if (data & 0x80) != 0 {
+                    self.scancode = None;
+                }
+                if (old & 0x40) == 0 && (data & 0x40) != 0 {
+                    // Releasing the clock line resets the keyboard, which
+                    // answers with its self-test result.
+                    self.keyboard_queue.clear();
+                    self.keyboard_queue.push_back(0xaa);
+                }
+                self.update_keyboard();
+            }
+            2 => self.port_c = data,
+            _ => {
+                if (data & 0x80) != 0 {
+                    self.ctrl = data;
+                    self.port_a = 0;
+                    self.port_b = 0;
+                    self.port_c = 0;
+                } else {
+                    let bit = 1 << ((data >> 1) & 7);
+                    if (data & 1) != 0 {
+                        self.port_c |= bit;
+                    } else {
+                        self.port_c &= !bit;
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Default for PPI {
+    fn default() -> PPI {
+        PPI::new(DipSwitches::default())
+    }
+}
+
+#[test]
+fn test_dip_switches() {
+    let switches = DipSwitches::new(640, VideoType::Mda, 2);
+    assert_eq!(switches.sw1, 0x7d);
+    assert_eq!(switches.sw2, 18);
+    assert_eq!(switches.memory_kb(), 640);
+    let switches = DipSwitches::new(32, VideoType::Cga40, 0);
+    assert_eq!(switches.sw1, 0x14);
+    assert_eq!(switches.sw2, 0);
+    assert_eq!(switches.memory_kb(), 32);
+    let mut switches = switches;
+    switches.set_memory(96);
+    assert_eq!((switches.sw1, switches.sw2), (0x1c, 1));
+    assert_eq!(switches.memory_kb(), 96);
+}
+
+#[test]
+fn test_ppi_switch_and_keyboard_ports() {
+    let mut ppi = PPI::new(DipSwitches::new(256, VideoType::Cga80, 1));
+    ppi.wb(0x63, 0x99);
+    ppi.wb(0x61, 0x84);
+    assert_eq!(ppi.rb(0x60), 0x2d);
+    assert_eq!(ppi.rb(0x62) & 0x0f, 0x06);
+    ppi.wb(0x61, 0x80);
+    assert_eq!(ppi.rb(0x62) & 0x0f, 0x00);
+    // Hold the keyboard clock low, then release it to reset the keyboard.
+    ppi.wb(0x61, 0x08);
+    ppi.wb(0x61, 0x48);
+    assert!(ppi.keyboard_irq());
+    assert_eq!(ppi.rb(0x60), 0xaa);
+    ppi.key_event(0x1e);
+    assert_eq!(ppi.rb(0x60), 0xaa);
+    ppi.wb(0x61, 0xc8);
+    assert!(!ppi.keyboard_irq());
+    ppi.wb(0x61, 0x48);
+    assert_eq!(ppi.rb(0x60), 0x1e);
+}
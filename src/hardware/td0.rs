@@ -0,0 +1,369 @@
+use crate::hardware::floppy::*;
+use std::io;
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("TD0: {}", msg))
+}
+
+/// Ring buffer size, lookahead and minimum match of Teledisk's LZSS stage.
+const LZ_N: usize = 4096;
+const LZ_F: usize = 60;
+const LZ_THRESHOLD: usize = 2;
+const N_CHAR: usize = 256 - LZ_THRESHOLD + LZ_F;
+const T: usize = N_CHAR * 2 - 1;
+const R: usize = T - 1;
+const MAX_FREQ: u16 = 0x8000;
+
+/// Decoder for the LZSS with adaptive Huffman coding ("LZHUF") used by
+/// Teledisk's advanced compression.
+struct Lzhuf<'a> {
+    input: &'a [u8],
+    bit_pos: usize,
+    freq: [u16; T + 1],
+    prnt: [usize; T + N_CHAR],
+    son: [usize; T],
+    d_code: [u8; 256],
+    d_len: [u8; 256],
+}
+
+impl<'a> Lzhuf<'a> {
+    fn new(input: &'a [u8]) -> Self {
+        let mut lzhuf = Self {
+            input,
+            bit_pos: 0,
+            freq: [0; T + 1],
+            prnt: [0; T + N_CHAR],
+            son: [0; T],
+            d_code: [0; 256],
+            d_len: [0; 256],
+        };
+        // Upper six bits of a position: code lengths 3 to 8 bits.
+        let mut index = 0;
+        let mut code = 0;
+        for (codes, len) in [(1, 3), (3, 4), (8, 5), (12, 6), (24, 7), (16, 8)] {
+            for _ in 0..codes {
+                for _ in 0..(1 << (8 - len)) {
+                    lzhuf.d_code[index] = code;
+                    lzhuf.d_len[index] = len;
+                    index += 1;
+                }
+                code += 1;
+            }
+        }
+        for i in 0..N_CHAR {
+            lzhuf.freq[i] = 1;
+            lzhuf.son[i] = i + T;
+            lzhuf.prnt[i + T] = i;
+        }
+        let mut i = 0;
+        for j in N_CHAR..=R {
+            lzhuf.freq[j] = lzhuf.freq[i] + lzhuf.freq[i + 1];
+            lzhuf.son[j] = i;
+            lzhuf.prnt[i] = j;
+            lzhuf.prnt[i + 1] = j;
+            i += 2;
+        }
+        lzhuf.freq[T] = 0xffff;
+        lzhuf.prnt[R] = 0;
+        lzhuf
+    }
+
+    fn exhausted(&self) -> bool {
+        self.bit_pos >= self.input.len() * 8
+    }
+
+    fn bit(&mut self) -> usize {
+        let byte = self.input.get(self.bit_pos >> 3).copied().unwrap_or(0);
+        let bit = (byte >> (7 - (self.bit_pos & 7))) & 1;
+        self.bit_pos += 1;
+        bit as usize
+    }
+
+    fn bits(&mut self, count: usize) -> usize {
+        (0..count).fold(0, |value, _| (value << 1) | self.bit())
+    }
+
+    fn reconstruct(&mut self) {
+        let mut j = 0;
+        for i in 0..T {
+            if self.son[i] >= T {
+                self.freq[j] = self.freq[i].div_ceil(2);
+                self.son[j] = self.son[i];
+                j += 1;
+            }
+        }
+        let mut i = 0;
+        for j in N_CHAR..T {
+            let f = self.freq[i] + self.freq[i + 1];
+            self.freq[j] = f;
+            let mut k = j - 1;
+            while f < self.freq[k] {
+                k -= 1;
+            }
+            k += 1;
+            self.freq.copy_within(k..j, k + 1);
+            self.freq[k] = f;
+            self.son.copy_within(k..j, k + 1);
+            self.son[k] = i;
+            i += 2;
+        }
+        for i in 0..T {
+            let k = self.son[i];
+            self.prnt[k] = i;
+            if k < T {
+                self.prnt[k + 1] = i;
+            }
+        }
+    }
+
+    fn update(&mut self, c: usize) {
+        if self.freq[R] == MAX_FREQ {
+            self.reconstruct();
+        }
+        let mut c = self.prnt[c + T];
+        loop {
+            self.freq[c] += 1;
+            let k = self.freq[c];
+            let mut l = c + 1;
+            if k > self.freq[l] {
+                while k > self.freq[l + 1] {
+                    l += 1;
+                }
+                self.freq[c] = self.freq[l];
+                self.freq[l] = k;
+                let i = self.son[c];
+                self.prnt[i] = l;
+                if i < T {
+                    self.prnt[i + 1] = l;
+                }
+                let j = self.son[l];
+                self.son[l] = i;
+                self.prnt[j] = c;
+                if j < T {
+                    self.prnt[j + 1] = c;
+                }
+                self.son[c] = j;
+                c = l;
+            }
+            c = self.prnt[c];
+            if c == 0 {
+                break;
+            }
+        }
+    }
+
+    fn decode_char(&mut self) -> usize {
+        let mut c = self.son[R];
+        while c < T {
+            c = self.son[c + self.bit()];
+        }
+        c -= T;
+        self.update(c);
+        c
+    }
+
+    fn decode_position(&mut self) -> usize {
+        let i = self.bits(8);
+        let high = (self.d_code[i] as usize) << 6;
+        let extra = self.d_len[i] as usize - 2;
+        let low = (i << extra) | self.bits(extra);
+        high | (low & 0x3f)
+    }
+
+    fn decompress(mut self) -> Vec<u8> {
+        let mut text = [0x20u8; LZ_N];
+        let mut r = LZ_N - LZ_F;
+        let mut output = Vec::new();
+        while !self.exhausted() {
+            let c = self.decode_char();
+            if c < 256 {
+                output.push(c as u8);
+                text[r] = c as u8;
+                r = (r + 1) & (LZ_N - 1);
+            } else {
+                let start = r.wrapping_sub(self.decode_position() + 1) & (LZ_N - 1);
+                for k in 0..(c - 255 + LZ_THRESHOLD) {
+                    let byte = text[(start + k) & (LZ_N - 1)];
+                    output.push(byte);
+                    text[r] = byte;
+                    r = (r + 1) & (LZ_N - 1);
+                }
+            }
+        }
+        output
+    }
+}
+
+/// Expands a sector data block using encoding method 0 (raw), 1 (repeated
+/// two-byte pattern) or 2 (run-length encoded fragments).
+fn decode_sector(block: &[u8], size: usize) -> io::Result<Vec<u8>> {
+    let (&method, body) = block
+        .split_first()
+        .ok_or_else(|| invalid("empty sector data"))?;
+    let truncated = || invalid("truncated sector data");
+    let mut data = Vec::with_capacity(size);
+    match method {
+        0 => data.extend_from_slice(body),
+        1 => {
+            let mut pos = 0;
+            while data.len() < size {
+                let chunk = body.get(pos..pos + 4).ok_or_else(truncated)?;
+                let count = u16::from_le_bytes([chunk[0], chunk[1]]);
+                for _ in 0..count {
+                    data.extend_from_slice(&chunk[2..4]);
+                }
+                pos += 4;
+            }
+        }
+        2 => {
+            let mut pos = 0;
+            while data.len() < size {
+                let header = body.get(pos..pos + 2).ok_or_else(truncated)?;
+                pos += 2;
+                if header[0] == 0 {
+                    let len = header[1] as usize;
+                    data.extend_from_slice(body.get(pos..pos + len).ok_or_else(truncated)?);
+                    pos += len;
+                } else {
+                    let len = 1 << header[0];
+                    let fragment = body.get(pos..pos + len).ok_or_else(truncated)?;
+                    for _ in 0..header[1] {
+                        data.extend_from_slice(fragment);
+                    }
+                    pos += len;
+                }
+            }
+        }
+        _ => return Err(invalid("unknown sector encoding")),
+    }
+    data.resize(size, 0);
+    Ok(data)
+}
+
+impl FloppyImage {
+    /// Parses a Teledisk image. "TD" images are stored as is; "td" images
+    /// use advanced compression over everything after the 12-byte header.
+    /// The disk is mounted write protected, since writes cannot be saved.
+    pub fn from_td0(data: &[u8]) -> io::Result<FloppyImage> {
+        if data.len() < 12 {
+            return Err(invalid("file too short"));
+        }
+        let header = &data[..12];
+        let body = match &header[..2] {
+            b"TD" => data[12..].to_vec(),
+            b"td" => Lzhuf::new(&data[12..]).decompress(),
+            _ => return Err(invalid("bad signature")),
+        };
+        let mut pos = 0;
+        let mut take = |len: usize| -> io::Result<&[u8]> {
+            let bytes = body
+                .get(pos..pos + len)
+                .ok_or_else(|| invalid("unexpected end of file"))?;
+            pos += len;
+            Ok(bytes)
+        };
+        if (header[7] & 0x80) != 0 {
+            let comment = take(10)?;
+            let len = u16::from_le_bytes([comment[2], comment[3]]) as usize;
+            take(len)?;
+        }
+        let mut tracks = Vec::new();
+        loop {
+            let count = take(1)?[0];
+            if count == 0xff {
+                break;
+            }
+            let track = take(3)?;
+            let (cylinder, head) = (track[0], track[1] & 0x7f);
+            let mut sectors = Vec::with_capacity(count as usize);
+            for _ in 0..count {
+                let id = take(6)?;
+                let flags = id[4];
+                let id = SectorId::new(id[0], id[1], id[2], id[3]);
+                // Flags 0x10 and 0x20 mark sectors stored without data.
+                let data = if (flags & 0x30) == 0 {
+                    let len = take(2)?;
+                    let len = u16::from_le_bytes([len[0], len[1]]) as usize;
+                    decode_sector(take(len)?, id.size())?
+                } else {
+                    Vec::new()
+                };
+                sectors.push(Sector {
+                    id,
+                    data,
+                    deleted: (flags & 0x04) != 0,
+                    crc_error: (flags & 0x02) != 0,
+                });
+            }
+            tracks.push((cylinder, head, sectors));
+        }
+        let mut image = FloppyImage::from_tracks(tracks, FloppyFormat::Td0)?;
+        image.write_protected = true;
+        Ok(image)
+    }
+}
+
+#[cfg(test)]
+fn td0_test_body() -> Vec<u8> {
+    let mut body = Vec::new();
+    // Track 0/0 with two 512-byte sectors: a pattern-encoded one and an
+    // RLE-encoded deleted one.
+    body.extend_from_slice(&[2, 0, 0, 0]);
+    body.extend_from_slice(&[0, 0, 1, 2, 0x00, 0]);
+    body.extend_from_slice(&[5, 0, 1, 0x00, 0x01, 0xaa, 0x55]);
+    body.extend_from_slice(&[0, 0, 2, 2, 0x04, 0]);
+    body.extend_from_slice(&[10, 0, 2, 0, 3, 1, 2, 3, 1, 0xff, 0x21, 0x21]);
+    // Track 1/0 with an ID-only sector.
+    body.extend_from_slice(&[1, 1, 0, 0]);
+    body.extend_from_slice(&[1, 0, 9, 3, 0x20, 0]);
+    body.push(0xff);
+    body
+}
+
+#[test]
+fn test_td0_loader() {
+    let mut data = b"TD\x00\x00\x15\x02\x01\x00\x00\x01\x00\x00".to_vec();
+    data.extend(td0_test_body());
+    let image = FloppyImage::from_td0(&data).unwrap();
+    assert_eq!(image.format, FloppyFormat::Td0);
+    let track = image.track(0, 0);
+    assert_eq!(track[0].data[..4], [0xaa, 0x55, 0xaa, 0x55]);
+    assert_eq!(track[0].data.len(), 512);
+    assert!(track[1].deleted);
+    assert_eq!(track[1].data[..5], [1, 2, 3, 0x21, 0x21]);
+    assert_eq!(track[1].data[511], 0x21);
+    let track = image.track(1, 0);
+    assert_eq!(track[0].id, SectorId::new(1, 0, 9, 3));
+    assert!(track[0].data.is_empty());
+    assert!(image.is_write_protected());
+}
+
+#[cfg(test)]
+fn lzhuf_encode(lzhuf: &mut Lzhuf, symbol: usize, bits: &mut Vec<u8>) {
+    let mut path = Vec::new();
+    let mut node = lzhuf.prnt[symbol + T];
+    while node != R {
+        path.push((node & 1) as u8);
+        node = lzhuf.prnt[node];
+    }
+    bits.extend(path.iter().rev());
+    lzhuf.update(symbol);
+}
+
+#[test]
+fn test_lzhuf_literals_and_matches() {
+    // 'A' followed by a three-byte match at distance one, encoded against
+    // a second copy of the adaptive tree.
+    let mut lzhuf = Lzhuf::new(&[]);
+    let mut bits = Vec::new();
+    lzhuf_encode(&mut lzhuf, b'A' as usize, &mut bits);
+    lzhuf_encode(&mut lzhuf, 256 + 3 - LZ_THRESHOLD - 1, &mut bits);
+    // Position 0 is the 3-bit code 000 followed by six zero bits.
+    bits.extend_from_slice(&[0; 9]);
+    let mut input = vec![0u8; bits.len().div_ceil(8)];
+    for (i, bit) in bits.iter().enumerate() {
+        input[i / 8] |= bit << (7 - i % 8);
+    }
+    let output = Lzhuf::new(&input).decompress();
+    assert_eq!(output[..4], *b"AAAA");
+}
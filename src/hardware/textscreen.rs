@@ -0,0 +1,137 @@
+/// Code page 437 glyphs for the control characters 0x00-0x1F.
+const CP437_LOW: &str = " ☺☻♥♦♣♠•◘○◙♂♀♪♫☼►◄↕‼¶§▬↨↑↓→←∟↔▲▼";
+
+/// Code page 437 glyphs for 0x80-0xFF.
+const CP437_HIGH: &str = "ÇüéâäàåçêëèïîìÄÅÉæÆôöòûùÿÖÜ¢£¥₧ƒáíóúñÑªº¿⌐¬½¼¡«»\
+    ░▒▓│┤╡╢╖╕╣║╗╝╜╛┐└┴┬├─┼╞╟╚╔╩╦╠═╬╧╨╤╥╙╘╒╓╫╪┘┌█▄▌▐▀\
+    αßΓπΣσµτΦΘΩδ∞φε∩≡±≥≤⌠⌡÷≈°∙·√ⁿ²■\u{a0}";
+
+/// The Unicode character a code page 437 glyph shows. NUL shows as a
+/// space, as it does on screen.
+pub fn cp437_to_char(code: u8) -> char {
+    match code {
+        0x00..=0x1f => CP437_LOW.chars().nth(code as usize).unwrap(),
+        0x7f => '⌂',
+        0x80..=0xff => CP437_HIGH.chars().nth(code as usize - 0x80).unwrap(),
+        _ => code as char,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextCell {
+    /// The byte in video memory.
+    pub code: u8,
+    pub attr: u8,
+}
+
+impl TextCell {
+    pub fn char(&self) -> char {
+        cp437_to_char(self.code)
+    }
+}
+
+/// The displayed page of a text mode, read out of video memory.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TextScreen {
+    pub columns: usize,
+    pub rows: Vec<Vec<TextCell>>,
+    /// Row and column of the hardware cursor, if it is on the page and
+    /// enabled.
+    pub cursor: Option<(usize, usize)>,
+}
+
+impl TextScreen {
+    /// Reads `rows` rows of `columns` character/attribute pairs from
+    /// `cell`, which the adapter maps to its video memory.
+    pub fn read<F>(columns: usize, rows: usize, cell: F) -> TextScreen
+    where
+        F: Fn(usize, usize) -> (u8, u8),
+    {
+        TextScreen {
+            columns,
+            rows: (0..rows)
+                .map(|row| {
+                    (0..columns)
+                        .map(|column| {
+                            let (code, attr) = cell(row, column);
+                            TextCell { code, attr }
+                        })
+                        .collect()
+                })
+                .collect(),
+            cursor: None,
+        }
+    }
+
+    /// Places the cursor `cells` character clocks past the start of the
+    /// page, where rows begin every `stride` of them.
+    pub fn place_cursor(&mut self, cells: usize, stride: usize) {
+        let (row, column) = (cells / stride.max(1), cells % stride.max(1));
+        self.cursor = if row < self.rows.len() && column < self.columns {
+            Some((row, column))
+        } else {
+            None
+        };
+    }
+
+    /// Row `row` as Unicode text.
+    pub fn row_text(&self, row: usize) -> String {
+        self.rows[row].iter().map(TextCell::char).collect()
+    }
+
+    /// The whole screen as Unicode text, one line per row.
+    pub fn text(&self) -> String {
+        (0..self.rows.len())
+            .map(|row| self.row_text(row))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Row and column of the first occurrence of `pattern` within a row.
+    pub fn find(&self, pattern: &str) -> Option<(usize, usize)> {
+        (0..self.rows.len()).find_map(|row| {
+            let text = self.row_text(row);
+            text.find(pattern)
+                .map(|offset| (row, text[..offset].chars().count()))
+        })
+    }
+
+    pub fn contains(&self, pattern: &str) -> bool {
+        self.find(pattern).is_some()
+    }
+}
+
+#[test]
+fn test_text_screen_cp437() {
+    assert_eq!(CP437_LOW.chars().count(), 0x20);
+    assert_eq!(CP437_HIGH.chars().count(), 0x80);
+    assert_eq!(cp437_to_char(b'A'), 'A');
+    assert_eq!(cp437_to_char(0x01), '☺');
+    assert_eq!(cp437_to_char(0x80), 'Ç');
+    assert_eq!(cp437_to_char(0xb3), '│');
+    assert_eq!(cp437_to_char(0xc4), '─');
+    assert_eq!(cp437_to_char(0xdb), '█');
+    assert_eq!(cp437_to_char(0xe1), 'ß');
+    assert_eq!(cp437_to_char(0xfe), '■');
+    // Two rows of four cells, starting a row in.
+    let mut vram = [0u8; 32];
+    for (i, &code) in b"    A>  \xc9\xcd\xbb ".iter().enumerate() {
+        vram[i * 2] = code;
+        vram[i * 2 + 1] = 0x07;
+    }
+    vram[9] = 0x70;
+    let screen = TextScreen::read(4, 2, |row, column| {
+        let offset = (4 + row * 4 + column) * 2;
+        (vram[offset], vram[offset + 1])
+    });
+    assert_eq!(screen.text(), "A>  \n╔═╗ ");
+    assert_eq!(screen.rows[0][0].attr, 0x70);
+    assert_eq!(screen.find("═╗"), Some((1, 1)));
+    assert!(screen.contains("A>"));
+    assert!(!screen.contains("C>"));
+    let mut screen = screen;
+    screen.place_cursor(6, 4);
+    assert_eq!(screen.cursor, Some((1, 2)));
+    screen.place_cursor(9, 4);
+    assert_eq!(screen.cursor, None);
+}
@@ -0,0 +1,462 @@
+use crate::hardware::ega::*;
+use crate::hardware::framebuffer::Framebuffer;
+use crate::hardware::textscreen::TextScreen;
+
+/// BIOS option ROM of the IBM VGA, mapped at C000:0.
+pub const VGA_ROM_PATH: &str = "roms/video/vga/ibm_vga.bin";
+
+/// Largest frame the CRTC may describe; anything bigger is clipped.
+const MAX_WIDTH: usize = 1024;
+const MAX_HEIGHT: usize = 768;
+
+const VGA_DOT_CLOCKS: [u64; 2] = [25_175_000, 28_322_000];
+
+/// IBM Video Graphics Array: the EGA's planes, sequencer, graphics and
+/// attribute controllers, plus a 256-entry DAC at 0x3C6-0x3C9 that every
+/// colour passes through. Chain-4 addressing spreads consecutive bytes
+/// across the four planes for mode 13h; with it off the same memory is
+/// the unchained "Mode X". Text modes use 9-dot characters unless the
+/// clocking mode selects 8, and the CRTC's maximum scan line register
+/// can scan each row twice to turn 200 lines into 400.
+#[derive(Debug, Clone)]
+pub struct VGA {
+    /// The planar half: memory, latches, registers and framebuffer.
+    pub ega: EGA,
+    /// 256 entries of 6-bit red, green and blue.
+    pub dac: Vec<u8>,
+    pub dac_read_index: u8,
+    pub dac_write_index: u8,
+    /// Component of the current entry the next data access reaches.
+    pub dac_component: u8,
+    /// Set after an index is written to 0x3C7 rather than 0x3C8.
+    pub dac_reading: bool,
+    pub pel_mask: u8,
+}
+
+impl VGA {
+    pub fn new() -> Self {
+        Self {
+            ega: EGA::new(),
+            dac: vec![0; 768],
+            dac_read_index: 0,
+            dac_write_index: 0,
+            dac_component: 0,
+            dac_reading: false,
+            pel_mask: 0xff,
+        }
+    }
+
+    pub fn decodes(&self, addr: u16) -> bool {
+        self.ega.decodes(addr)
+    }
+
+    pub fn window(&self, addr: u32) -> Option<usize> {
+        self.ega.window(addr)
+    }
+
+    fn chain4(&self) -> bool {
+        (self.ega.seq[4] & 0x08) != 0
+    }
+
+    /// In chain-4 mode the low two address bits select the plane.
+    pub fn read_vram(&mut self, offset: usize) -> u8 {
+        if self.chain4() {
+            self.ega.read_planes(offset & !3, offset & 3)
+        } else {
+            self.ega.read_vram(offset)
+        }
+    }
+
+    pub fn write_vram(&mut self, offset: usize, data: u8) {
+        if self.chain4() {
+            let map_mask = self.ega.seq[2] & (1 << (offset & 3));
+            self.ega.write_planes(offset & !3, data, map_mask);
+        } else {
+            self.ega.write_vram(offset, data);
+        }
+    }
+
+    fn dot_clock(&self) -> u64 {
+        VGA_DOT_CLOCKS[((self.ega.misc_output >> 2) & 1) as usize]
+    }
+
+    /// Dots per character, before the sequencer's dot clock halving.
+    fn char_width(&self) -> usize {
+        if (self.ega.seq[1] & 0x01) != 0 {
+            8
+        } else {
+            9
+        }
+    }
+
+    fn dot_doubling(&self) -> bool {
+        (self.ega.seq[1] & 0x08) != 0
+    }
+
+    fn horizontal_total(&self) -> u64 {
+        self.ega.crtc[0] as u64 + 5
+    }
+
+    /// The overflow register holds bits 8 and 9 of the vertical counts;
+    /// the line compare's bit 9 is in the maximum scan line register.
+    fn vertical_total(&self) -> u64 {
+        let crtc = &self.ega.crtc;
+        (crtc[6] as u64 | ((crtc[7] as u64 & 0x01) << 8) | ((crtc[7] as u64 & 0x20) << 4)) + 2
+    }
+
+    fn vertical_display_end(&self) -> u64 {
+        let crtc = &self.ega.crtc;
+        (crtc[0x12] as u64 | ((crtc[7] as u64 & 0x02) << 7) | ((crtc[7] as u64 & 0x40) << 3)) + 1
+    }
+
+    fn vertical_retrace_start(&self) -> u64 {
+        let crtc = &self.ega.crtc;
+        crtc[0x10] as u64 | ((crtc[7] as u64 & 0x04) << 6) | ((crtc[7] as u64 & 0x80) << 2)
+    }
+
+    fn line_compare(&self) -> u64 {
+        let crtc = &self.ega.crtc;
+        crtc[0x18] as u64 | ((crtc[7] as u64 & 0x10) << 4) | ((crtc[9] as u64 & 0x40) << 3)
+    }
+
+    fn vertical_retrace(&self) -> bool {
+        let line = self.ega.position / self.horizontal_total();
+        let start = self.vertical_retrace_start();
+        let length = (self.ega.crtc[0x11] as u64).wrapping_sub(start) & 0x0f;
+        line >= start && line < start + length
+    }
+
+    fn display_enable(&self) -> bool {
+        let column = self.ega.position % self.horizontal_total();
+        let line = self.ega.position / self.horizontal_total();
+        column <= self.ega.crtc[1] as u64 && line < self.vertical_display_end()
+    }
+
+    /// Runs the adapter for `cycles` clocks of a CPU running at
+    /// `cpu_clock` Hz, drawing a frame each time the CRTC wraps.
+    pub fn tick(&mut self, cycles: usize, cpu_clock: u64) {
+        let dots = (self.char_width() as u64) << self.dot_doubling() as u64;
+        let period = cpu_clock * dots;
+        self.ega.phase += cycles as u64 * self.dot_clock();
+        self.ega.position += self.ega.phase / period;
+        self.ega.phase %= period;
+        let frame_len = self.horizontal_total() * self.vertical_total();
+        while self.ega.position >= frame_len {
+            self.ega.position -= frame_len;
+            self.render();
+        }
+    }
+
+    /// 0x00RRGGBB colour of a DAC entry, scaling 6-bit components to 8.
+    pub fn dac_color(&self, index: u8) -> u32 {
+        let entry = &self.dac[(index & self.pel_mask) as usize * 3..][..3];
+        entry.iter().fold(0, |rgb, &c| {
+            (rgb << 8) | ((c as u32) << 2) | ((c as u32) >> 4)
+        })
+    }
+
+    /// DAC index of an attribute controller input in 16-colour modes.
+    /// The colour select register supplies bits 6-7, and bits 4-5 too
+    /// when the mode control register asks for it.
+    fn palette_index(&self, input: u8) -> u8 {
+        let attr = &self.ega.attr;
+        let mut color = attr[(input & attr[0x12] & 0x0f) as usize] & 0x3f;
+        if (attr[0x10] & 0x80) != 0 {
+            color = (color & 0x0f) | ((attr[0x14] & 0x03) << 4);
+        }
+        color | ((attr[0x14] & 0x0c) << 4)
+    }
+
+    /// Colours of the dots of one character clock.
+    fn char_dots(&self, ma: usize, scan: usize, dots: &mut [u32; 9]) {
+        let ega = &self.ega;
+        let addr = ega.scan_address(ma, scan);
+        let mode = ega.attr[0x10];
+        if (mode & 0x01) == 0 {
+            let (bits, fg, bg) = ega.text_cell(addr, ma & 0xffff, scan);
+            let (fg, bg) = (
+                self.dac_color(self.palette_index(fg)),
+                self.dac_color(self.palette_index(bg)),
+            );
+            for (x, dot) in dots.iter_mut().enumerate().take(8) {
+                *dot = if (bits & (0x80 >> x)) != 0 { fg } else { bg };
+            }
+            // The ninth column repeats the eighth for the line drawing
+            // characters, so boxes join up.
+            let ch = ega.planes[0][addr];
+            dots[8] = if (mode & 0x04) != 0 && (0xc0..=0xdf).contains(&ch) {
+                dots[7]
+            } else {
+                bg
+            };
+        } else if (mode & 0x40) != 0 {
+            // 256 colours: one byte from each plane in turn, each two
+            // dots wide.
+            for (x, dot) in dots.iter_mut().enumerate().take(8) {
+                *dot = self.dac_color(ega.planes[x / 2][addr]);
+            }
+        } else {
+            for (dot, &pixel) in dots.iter_mut().zip(&ega.graphics_pixels(addr)) {
+                *dot = self.dac_color(self.palette_index(pixel));
+            }
+        }
+    }
+
+    /// The displayed text page, unless the attribute controller is in a
+    /// graphics mode.
+    pub fn text_screen(&self) -> Option<TextScreen> {
+        let lines = self.vertical_display_end() as usize;
+        let double_scan = (self.ega.crtc[9] & 0x80) != 0;
+        self.ega.text_screen_lines(lines >> double_scan as usize)
+    }
+
+    /// Draws the whole frame from video memory into the EGA's `frame`.
+    pub fn render(&mut self) {
+        self.ega.frame_count += 1;
+        let char_width = if (self.ega.attr[0x10] & 0x01) != 0 {
+            8
+        } else {
+            self.char_width()
+        };
+        let double = self.dot_doubling() as usize;
+        let columns = self.ega.crtc[1] as usize + 1;
+        let width = ((columns * char_width) << double).min(MAX_WIDTH);
+        let height = (self.vertical_display_end() as usize).min(MAX_HEIGHT);
+        let mut frame = Framebuffer::new(width, height);
+        let crtc = &self.ega.crtc;
+        // Cleared palette address source, sequencer reset or screen off
+        // show the overscan colour.
+        let screen_on = (self.ega.attr_index & 0x20) != 0
+            && (self.ega.seq[0] & 0x03) == 0x03
+            && (self.ega.seq[1] & 0x20) == 0;
+        let row_height = (crtc[9] & 0x1f) as usize + 1;
+        let double_scan = (crtc[9] & 0x80) != 0;
+        let offset = crtc[0x13] as usize * 2;
+        let line_compare = self.line_compare() as usize;
+        let mut row_start = ((crtc[0x0c] as usize) << 8) | crtc[0x0d] as usize;
+        let mut scan = (crtc[8] & 0x1f) as usize;
+        let mut dots = [0u32; 9];
+        for y in 0..height {
+            if !screen_on {
+                break;
+            }
+            if y == line_compare {
+                row_start = 0;
+                scan = 0;
+            }
+            let line = frame.row_mut(y);
+            for column in 0..columns {
+                self.char_dots(row_start + column, scan, &mut dots);
+                for (x, &dot) in dots.iter().enumerate().take(char_width) {
+                    let x = (column * char_width + x) << double;
+                    if x < width {
+                        line[x] = dot;
+                        if double == 1 && x + 1 < width {
+                            line[x + 1] = dot;
+                        }
+                    }
+                }
+            }
+            if !double_scan || (y & 1) != 0 {
+                scan += 1;
+                if scan == row_height {
+                    scan = 0;
+                    row_start += offset;
+                }
+            }
+        }
+        self.ega.frame = frame;
+    }
+
+    fn input_status_1(&mut self) -> u8 {
+        self.ega.attr_flip_flop = false;
+        (!self.display_enable() as u8) | ((self.vertical_retrace() as u8) << 3)
+    }
+
+    fn dac_advance(&mut self, reading: bool) {
+        self.dac_component += 1;
+        if self.dac_component == 3 {
+            self.dac_component = 0;
+            if reading {
+                self.dac_read_index = self.dac_read_index.wrapping_add(1);
+            } else {
+                self.dac_write_index = self.dac_write_index.wrapping_add(1);
+            }
+        }
+    }
+
+    pub fn rb(&mut self, addr: u16) -> u8 {
+        let crtc_base = if self.ega.color_mode() { 0x3d0 } else { 0x3b0 };
+        match addr {
+            0x3c6 => self.pel_mask,
+            0x3c7 => {
+                if self.dac_reading {
+                    0x03
+                } else {
+                    0x00
+                }
+            }
+            0x3c8 => self.dac_write_index,
+            0x3c9 => {
+                let value =
+                    self.dac[self.dac_read_index as usize * 3 + self.dac_component as usize];
+                self.dac_advance(true);
+                value
+            }
+            0x3ca => 0,
+            0x3cc => self.ega.misc_output,
+            _ if addr == crtc_base + 0x0a => self.input_status_1(),
+            _ => self.ega.rb(addr),
+        }
+    }
+
+    pub fn wb(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x3c6 => self.pel_mask = data,
+            0x3c7 => {
+                self.dac_read_index = data;
+                self.dac_component = 0;
+                self.dac_reading = true;
+            }
+            0x3c8 => {
+                self.dac_write_index = data;
+                self.dac_component = 0;
+                self.dac_reading = false;
+            }
+            0x3c9 => {
+                self.dac[self.dac_write_index as usize * 3 + self.dac_component as usize] =
+                    data & 0x3f;
+                self.dac_advance(false);
+            }
+            _ => self.ega.wb(addr, data),
+        }
+    }
+}
+
+impl Default for VGA {
+    fn default() -> VGA {
+        VGA::new()
+    }
+}
+
+#[cfg(test)]
+fn vga_set_dac(vga: &mut VGA, index: u8, rgb: [u8; 3]) {
+    vga.wb(0x3c8, index);
+    for value in rgb {
+        vga.wb(0x3c9, value);
+    }
+}
+
+#[test]
+fn test_vga_mode13_and_mode_x() {
+    let pixel = |vga: &VGA, x: usize, y: usize| vga.ega.frame.pixel(x, y);
+    let mut vga = VGA::new();
+    // The BIOS parameters of mode 13h, 320x200 in 256 colours.
+    ega_set_mode(
+        &mut vga.ega,
+        0x63,
+        &[0x01, 0x0f, 0x00, 0x0e],
+        &[
+            0x5f, 0x4f, 0x50, 0x82, 0x54, 0x80, 0xbf, 0x1f, 0x00, 0x41, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x9c, 0x0e, 0x8f, 0x28, 0x40, 0x96, 0xb9, 0xa3, 0xff,
+        ],
+        &[
+            0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d,
+            0x0e, 0x0f, 0x41, 0x00, 0x0f, 0x00, 0x00,
+        ],
+        &[0x00, 0x00, 0x00, 0x00, 0x00, 0x40, 0x05, 0x0f, 0xff],
+    );
+    vga_set_dac(&mut vga, 1, [0x3f, 0, 0]);
+    vga_set_dac(&mut vga, 2, [0, 0x3f, 0]);
+    vga.wb(0x3c7, 2);
+    assert_eq!(vga.rb(0x3c7), 0x03);
+    assert_eq!([vga.rb(0x3c9), vga.rb(0x3c9)], [0, 0x3f]);
+    // Chain-4: consecutive bytes land in consecutive planes.
+    vga.write_vram(0, 1);
+    vga.write_vram(1, 2);
+    vga.write_vram(321, 2);
+    assert_eq!((vga.ega.planes[0][0], vga.ega.planes[1][0]), (1, 2));
+    assert_eq!(vga.ega.planes[1][320], 2);
+    assert_eq!(vga.read_vram(1), 2);
+    let mut retrace = false;
+    while vga.ega.frame_count < 2 {
+        vga.tick(100, 6_000_000);
+        retrace |= (vga.rb(0x3da) & 0x08) != 0;
+    }
+    assert!(retrace);
+    assert_eq!((vga.ega.frame.width, vga.ega.frame.height), (640, 400));
+    // Every pixel is two dots wide and every row scanned twice.
+    assert_eq!(pixel(&vga, 0, 0), 0xff_0000);
+    assert_eq!(pixel(&vga, 1, 1), 0xff_0000);
+    assert_eq!(pixel(&vga, 2, 0), 0x00_ff00);
+    assert_eq!(pixel(&vga, 2, 2), 0x00_ff00);
+    assert_eq!(pixel(&vga, 4, 0), 0);
+    // Mode X: chain-4 off, byte mode, and a split screen at line 100
+    // under a second page at 0x1000.
+    vga.wb(0x3c4, 4);
+    vga.wb(0x3c5, 0x06);
+    for (index, value) in [
+        (0x07, 0x0f),
+        (0x09, 0x01),
+        (0x0c, 0x10),
+        (0x14, 0x00),
+        (0x17, 0xe3),
+        (0x18, 100),
+    ] {
+        vga.wb(0x3d4, index);
+        vga.wb(0x3d5, value);
+    }
+    vga.write_vram(1, 2);
+    vga.wb(0x3c4, 2);
+    vga.wb(0x3c5, 0x01);
+    vga.write_vram(0x1000, 1);
+    vga.render();
+    assert_eq!(pixel(&vga, 0, 0), 0xff_0000);
+    assert_eq!(pixel(&vga, 2, 0), 0);
+    assert_eq!(pixel(&vga, 8, 0), 0);
+    assert_eq!(pixel(&vga, 0, 100), 0xff_0000);
+    assert_eq!(pixel(&vga, 2, 100), 0x00_ff00);
+    assert_eq!(pixel(&vga, 8, 100), 0x00_ff00);
+    assert_eq!(pixel(&vga, 14, 100), 0x00_ff00);
+}
+
+#[test]
+fn test_vga_text_mode() {
+    let mut vga = VGA::new();
+    // The BIOS parameters of mode 3: 80x25 with 9x16 characters.
+    ega_set_mode(
+        &mut vga.ega,
+        0x67,
+        &[0x00, 0x03, 0x00, 0x02],
+        &[
+            0x5f, 0x4f, 0x50, 0x82, 0x55, 0x81, 0xbf, 0x1f, 0x00, 0x4f, 0x0d, 0x0e, 0x00, 0x00,
+            0x00, 0x00, 0x9c, 0x8e, 0x8f, 0x28, 0x1f, 0x96, 0xb9, 0xa3, 0xff,
+        ],
+        &[
+            0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x14, 0x07, 0x38, 0x39, 0x3a, 0x3b, 0x3c, 0x3d,
+            0x3e, 0x3f, 0x0c, 0x00, 0x0f, 0x08, 0x00,
+        ],
+        &[0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x0e, 0x00, 0xff],
+    );
+    vga_set_dac(&mut vga, 7, [0x2a, 0x2a, 0x2a]);
+    vga.write_vram(0, 0xc4);
+    vga.write_vram(1, 0x07);
+    vga.write_vram(2, b'A');
+    vga.write_vram(3, 0x07);
+    vga.ega.planes[2][0xc4 * 32 + 1] = 0xff;
+    vga.ega.planes[2][b'A' as usize * 32 + 1] = 0x81;
+    vga.render();
+    assert_eq!((vga.ega.frame.width, vga.ega.frame.height), (720, 400));
+    let row = vga.ega.frame.row(1);
+    // The line drawing character fills all nine columns; 'A' leaves the
+    // ninth blank.
+    assert!(row[..9].iter().all(|&dot| dot == 0xaa_aaaa));
+    assert_eq!(row[9], 0xaa_aaaa);
+    assert_eq!(row[10], 0);
+    assert_eq!(row[16], 0xaa_aaaa);
+    assert_eq!(row[17], 0);
+    assert_eq!(vga.ega.frame.pixel(9, 0), 0);
+    let screen = vga.text_screen().unwrap();
+    assert_eq!((screen.columns, screen.rows.len()), (80, 25));
+    assert!(screen.row_text(0).starts_with("─A "));
+}
@@ -0,0 +1,284 @@
+use crate::hardware::harddisk::*;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("VHD: {}", msg))
+}
+
+const DISK_TYPE_FIXED: u32 = 2;
+const DISK_TYPE_DYNAMIC: u32 = 3;
+
+/// Block size used for new dynamic images.
+const DEFAULT_BLOCK_SIZE: usize = 0x20_0000;
+
+const UNUSED_BLOCK: u32 = 0xffff_ffff;
+
+/// Seconds from the Unix epoch to the VHD epoch, 2000-01-01.
+const VHD_EPOCH: u64 = 946_684_800;
+
+fn be32(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ])
+}
+
+fn be64(data: &[u8], offset: usize) -> u64 {
+    ((be32(data, offset) as u64) << 32) | be32(data, offset + 4) as u64
+}
+
+/// One's complement of the byte sum of a footer or header, skipping its
+/// checksum field.
+fn checksum(data: &[u8], field: usize) -> u32 {
+    let sum = data
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| !(field..field + 4).contains(i))
+        .fold(0u32, |sum, (_, &byte)| sum.wrapping_add(byte as u32));
+    !sum
+}
+
+/// VHD metadata kept alongside the sectors so writes can be placed in the
+/// file.
+#[derive(Debug, Clone, Default)]
+pub struct Vhd {
+    pub footer: Vec<u8>,
+    /// Sector offset of each block of a dynamic image, `UNUSED_BLOCK` if
+    /// not allocated. Empty for fixed images.
+    pub bat: Vec<u32>,
+    pub bat_offset: u64,
+    pub block_size: usize,
+}
+
+impl Vhd {
+    /// Whether a file ends in a VHD footer.
+    pub fn detect(data: &[u8]) -> bool {
+        data.len() >= 512 && data[data.len() - 512..].starts_with(b"conectix")
+    }
+
+    /// Builds a footer for a disk of the given geometry and type.
+    fn new_footer(geometry: HardDiskGeometry, disk_type: u32, data_offset: u64) -> Vec<u8> {
+        let size = (geometry.total_sectors() as u64) << 9;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let mut footer = vec![0u8; 512];
+        footer[0..8].copy_from_slice(b"conectix");
+        footer[8..12].copy_from_slice(&2u32.to_be_bytes());
+        footer[12..16].copy_from_slice(&0x0001_0000u32.to_be_bytes());
+        footer[16..24].copy_from_slice(&data_offset.to_be_bytes());
+        let timestamp = now.as_secs().saturating_sub(VHD_EPOCH) as u32;
+        footer[24..28].copy_from_slice(&timestamp.to_be_bytes());
+        footer[28..32].copy_from_slice(b"epc ");
+        footer[32..36].copy_from_slice(&0x0001_0000u32.to_be_bytes());
+        footer[36..40].copy_from_slice(b"Wi2k");
+        footer[40..48].copy_from_slice(&size.to_be_bytes());
+        footer[48..56].copy_from_slice(&size.to_be_bytes());
+        footer[56..58].copy_from_slice(&geometry.cylinders.to_be_bytes());
+        footer[58] = geometry.heads;
+        footer[59] = geometry.sectors_per_track;
+        footer[60..64].copy_from_slice(&disk_type.to_be_bytes());
+        footer[68..84].copy_from_slice(&now.as_nanos().to_be_bytes());
+        let sum = checksum(&footer, 64);
+        footer[64..68].copy_from_slice(&sum.to_be_bytes());
+        footer
+    }
+
+    /// Bytes of sector bitmap in front of each block, padded to a sector.
+    fn bitmap_len(&self) -> usize {
+        (self.block_size / 512).div_ceil(8).div_ceil(512) * 512
+    }
+
+    /// Writes one sector of a dynamic image, allocating its block at the
+    /// end of the file and moving the footer after it if needed.
+    pub fn write_sector(&mut self, file: &mut File, lba: usize, data: &[u8]) -> io::Result<()> {
+        let sectors_per_block = self.block_size / 512;
+        let block = lba / sectors_per_block;
+        let index = lba % sectors_per_block;
+        if self.bat[block] == UNUSED_BLOCK {
+            let end = file.seek(SeekFrom::End(0))? - 512;
+            let offset = end.div_ceil(512) * 512;
+            let mut contents = vec![0u8; self.bitmap_len() + self.block_size];
+            contents.extend_from_slice(&self.footer);
+            file.seek(SeekFrom::Start(offset))?;
+            file.write_all(&contents)?;
+            self.bat[block] = (offset / 512) as u32;
+            file.seek(SeekFrom::Start(self.bat_offset + block as u64 * 4))?;
+            file.write_all(&self.bat[block].to_be_bytes())?;
+        }
+        let start = self.bat[block] as u64 * 512;
+        file.seek(SeekFrom::Start(
+            start + self.bitmap_len() as u64 + index as u64 * 512,
+        ))?;
+        file.write_all(data)?;
+        let mut bits = [0u8];
+        file.seek(SeekFrom::Start(start + index as u64 / 8))?;
+        file.read_exact(&mut bits)?;
+        bits[0] |= 0x80 >> (index % 8);
+        file.seek(SeekFrom::Start(start + index as u64 / 8))?;
+        file.write_all(&bits)
+    }
+}
+
+impl HardDiskImage {
+    /// Parses a fixed or dynamic VHD, checking the footer and dynamic
+    /// header checksums and taking the geometry from the footer.
+    pub fn from_vhd(data: &[u8]) -> io::Result<HardDiskImage> {
+        if !Vhd::detect(data) {
+            return Err(invalid("missing footer"));
+        }
+        let footer = &data[data.len() - 512..];
+        if be32(footer, 64) != checksum(footer, 64) {
+            return Err(invalid("footer checksum mismatch"));
+        }
+        let geometry = HardDiskGeometry::new(
+            u16::from_be_bytes([footer[56], footer[57]]),
+            footer[58],
+            footer[59],
+        );
+        let size = be64(footer, 48) as usize;
+        let mut image = HardDiskImage::new(geometry);
+        let mut vhd = Vhd {
+            footer: footer.to_vec(),
+            ..Default::default()
+        };
+        match be32(footer, 60) {
+            DISK_TYPE_FIXED => {
+                let len = size.min(data.len() - 512);
+                image.load_sectors(0, &data[..len]);
+                image.format = HardDiskFormat::VhdFixed;
+            }
+            DISK_TYPE_DYNAMIC => {
+                let offset = be64(footer, 16) as usize;
+                let header = data
+                    .get(offset..offset + 1024)
+                    .ok_or_else(|| invalid("truncated dynamic header"))?;
+                if !header.starts_with(b"cxsparse") {
+                    return Err(invalid("bad dynamic header"));
+                }
+                if be32(header, 36) != checksum(header, 36) {
+                    return Err(invalid("dynamic header checksum mismatch"));
+                }
+                vhd.bat_offset = be64(header, 16);
+                vhd.block_size = be32(header, 32) as usize;
+                if vhd.block_size == 0 || !vhd.block_size.is_multiple_of(512) {
+                    return Err(invalid("bad block size"));
+                }
+                let entries = be32(header, 28) as usize;
+                // Writes anywhere on the disk must find a table entry.
+                if entries.saturating_mul(vhd.block_size) < geometry.total_sectors() * 512 {
+                    return Err(invalid("block allocation table smaller than the disk"));
+                }
+                let bat = vhd.bat_offset as usize;
+                let table = data
+                    .get(bat..bat + entries * 4)
+                    .ok_or_else(|| invalid("truncated block allocation table"))?;
+                vhd.bat = table.chunks(4).map(|entry| be32(entry, 0)).collect();
+                let sectors_per_block = vhd.block_size / 512;
+                for (block, &entry) in vhd.bat.iter().enumerate() {
+                    if entry == UNUSED_BLOCK {
+                        continue;
+                    }
+                    let start = entry as usize * 512;
+                    let contents = data
+                        .get(start..start + vhd.bitmap_len() + vhd.block_size)
+                        .ok_or_else(|| invalid("truncated block"))?;
+                    let (bitmap, sectors) = contents.split_at(vhd.bitmap_len());
+                    for (index, sector) in sectors.chunks(512).enumerate() {
+                        if (bitmap[index / 8] & (0x80 >> (index % 8))) != 0 {
+                            image.load_sectors(block * sectors_per_block + index, sector);
+                        }
+                    }
+                }
+                image.format = HardDiskFormat::VhdDynamic;
+            }
+            _ => return Err(invalid("only fixed and dynamic disks are supported")),
+        }
+        image.vhd = Some(vhd);
+        Ok(image)
+    }
+
+    /// Creates an empty fixed or dynamic VHD file and opens it.
+    pub fn create_vhd<P: AsRef<Path>>(
+        path: P,
+        geometry: HardDiskGeometry,
+        dynamic: bool,
+    ) -> io::Result<HardDiskImage> {
+        let size = geometry.total_sectors() << 9;
+        let data = if dynamic {
+            let footer = Vhd::new_footer(geometry, DISK_TYPE_DYNAMIC, 512);
+            let entries = size.div_ceil(DEFAULT_BLOCK_SIZE);
+            let mut header = vec![0u8; 1024];
+            header[0..8].copy_from_slice(b"cxsparse");
+            header[8..16].copy_from_slice(&u64::MAX.to_be_bytes());
+            header[16..24].copy_from_slice(&1536u64.to_be_bytes());
+            header[24..28].copy_from_slice(&0x0001_0000u32.to_be_bytes());
+            header[28..32].copy_from_slice(&(entries as u32).to_be_bytes());
+            header[32..36].copy_from_slice(&(DEFAULT_BLOCK_SIZE as u32).to_be_bytes());
+            let sum = checksum(&header, 36);
+            header[36..40].copy_from_slice(&sum.to_be_bytes());
+            let mut data = footer.clone();
+            data.extend_from_slice(&header);
+            data.resize(1536 + (entries * 4).div_ceil(512) * 512, 0xff);
+            data.extend_from_slice(&footer);
+            data
+        } else {
+            let mut data = vec![0u8; size];
+            data.extend_from_slice(&Vhd::new_footer(geometry, DISK_TYPE_FIXED, u64::MAX));
+            data
+        };
+        fs::write(&path, data)?;
+        HardDiskImage::open(path, geometry)
+    }
+}
+
+#[test]
+fn test_vhd_fixed_and_dynamic() {
+    let geometry = HardDiskGeometry::new(615, 4, 17);
+    let dir = std::env::temp_dir();
+    for dynamic in [false, true] {
+        let path = dir.join(format!("emupc-vhd-{}-{}.vhd", std::process::id(), dynamic));
+        let mut image = HardDiskImage::create_vhd(&path, geometry, dynamic).unwrap();
+        assert_eq!(image.geometry, geometry);
+        assert!(image.read_sector(0).unwrap().iter().all(|&b| b == 0));
+        // Sectors in two different blocks of a dynamic image.
+        image.write_sector(5, &[0x11; 512]).unwrap();
+        image.write_sector(40000, &[0x22; 512]).unwrap();
+        image.write_sector(6, &[0x33; 512]).unwrap();
+        let data = fs::read(&path).unwrap();
+        let image = HardDiskImage::from_vhd(&data).unwrap();
+        let format = if dynamic {
+            HardDiskFormat::VhdDynamic
+        } else {
+            HardDiskFormat::VhdFixed
+        };
+        assert_eq!(image.format, format);
+        assert_eq!(image.read_sector(5).unwrap(), &[0x11; 512][..]);
+        assert_eq!(image.read_sector(6).unwrap(), &[0x33; 512][..]);
+        assert_eq!(image.read_sector(40000).unwrap(), &[0x22; 512][..]);
+        assert_eq!(image.read_sector(7).unwrap(), &[0; 512][..]);
+        if dynamic {
+            // Footer copy, header, one sector of BAT and two blocks.
+            let block = 512 + 0x20_0000;
+            assert_eq!(data.len(), 512 + 1024 + 512 + 2 * block + 512);
+            // A table too short for the geometry is rejected.
+            let mut data = data.clone();
+            data[512 + 28..512 + 32].copy_from_slice(&1u32.to_be_bytes());
+            let sum = checksum(&data[512..512 + 1024], 36);
+            data[512 + 36..512 + 40].copy_from_slice(&sum.to_be_bytes());
+            let err = HardDiskImage::from_vhd(&data).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+        // A corrupted footer is rejected.
+        let mut data = data;
+        let footer = data.len() - 512 + 58;
+        data[footer] ^= 1;
+        assert!(HardDiskImage::from_vhd(&data).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
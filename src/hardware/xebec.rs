@@ -0,0 +1,464 @@
+use crate::hardware::dma::*;
+use crate::hardware::harddisk::*;
+use std::collections::VecDeque;
+
+/// DMA channel and IRQ line of the fixed disk adapter.
+pub const XEBEC_DMA_CHANNEL: usize = 3;
+pub const XEBEC_IRQ: u8 = 5;
+
+/// Option ROM mapped at C800:0 when a hard disk is attached.
+pub const XEBEC_ROM_PATH: &str = "roms/hdd/xebec/ibm_xebec_62x0822_1985.bin";
+
+/// Sense error codes.
+const ERROR_NOT_READY: u8 = 0x04;
+const ERROR_WRITE_FAULT: u8 = 0x03;
+const ERROR_SECTOR_NOT_FOUND: u8 = 0x14;
+const ERROR_INVALID_COMMAND: u8 = 0x20;
+const ERROR_ILLEGAL_ADDRESS: u8 = 0x21;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum XebecPhase {
+    Idle,
+    /// Selected and taking the six-byte command block.
+    Command,
+    Execution,
+    /// Moving blocks between the disk and the host.
+    Transfer,
+    Status,
+}
+
+/// The IBM XT fixed disk adapter, a Xebec controller driving up to two
+/// drives through a SASI-style handshake on ports 0x320-0x323.
+#[derive(Debug, Clone)]
+pub struct XebecHdc {
+    pub rom: Vec<u8>,
+    pub drives: [Option<HardDiskImage>; 2],
+    /// Drive type switches read back on port 0x322.
+    pub switches: u8,
+    pub phase: XebecPhase,
+    pub command: Vec<u8>,
+    /// Bytes waiting for the host, or written by it, in PIO transfers.
+    pub buffer: VecDeque<u8>,
+    /// DMA (bit 0) and interrupt (bit 1) enables written to port 0x323.
+    pub mask: u8,
+    /// Completion status byte: error (bit 1) and drive (bit 5).
+    pub status: u8,
+    /// Error code and address returned by REQUEST SENSE.
+    pub sense: [u8; 4],
+    pub sector_buffer: Vec<u8>,
+    pub irq: bool,
+    lba: usize,
+    remaining: usize,
+}
+
+impl XebecHdc {
+    pub fn new() -> Self {
+        Self {
+            rom: Vec::new(),
+            drives: Default::default(),
+            switches: 0,
+            phase: XebecPhase::Idle,
+            command: Vec::new(),
+            buffer: VecDeque::new(),
+            mask: 0,
+            status: 0,
+            sense: [0; 4],
+            sector_buffer: vec![0; 512],
+            irq: false,
+            lba: 0,
+            remaining: 0,
+        }
+    }
+
+    /// Level of IRQ5, gated by the interrupt enable in the mask register.
+    pub fn irq(&self) -> bool {
+        self.irq && (self.mask & 0x02) != 0
+    }
+
+    fn reset(&mut self) {
+        self.phase = XebecPhase::Idle;
+        self.command.clear();
+        self.buffer.clear();
+        self.irq = false;
+    }
+
+    fn drive(&self) -> usize {
+        ((self.command[1] >> 5) & 1) as usize
+    }
+
+    /// Cylinder, head and 0-based sector from the command block.
+    fn address(&self) -> (u16, u8, u8) {
+        let cmd = &self.command;
+        let cylinder = (((cmd[2] & 0xc0) as u16) << 2) | cmd[3] as u16;
+        (cylinder, cmd[1] & 0x1f, cmd[2] & 0x3f)
+    }
+
+    fn finish(&mut self, error: Option<u8>) {
+        let drive = self.drive() as u8;
+        let cmd = &self.command;
+        self.sense = match error {
+            Some(code) => [0x80 | code, (drive << 5) | (cmd[1] & 0x1f), cmd[2], cmd[3]],
+            None => [0, drive << 5, 0, 0],
+        };
+        self.status = (drive << 5) | if error.is_some() { 0x02 } else { 0 };
+        self.phase = XebecPhase::Status;
+        self.irq = true;
+    }
+
+    /// Size of the blocks a data command moves.
+    fn block_len(&self) -> usize {
+        match self.command[0] {
+            0x03 => 4,
+            0x0c => 8,
+            0x0d => 1,
+            0xe5 | 0xe6 => 516,
+            _ => 512,
+        }
+    }
+
+    fn reading(&self) -> bool {
+        matches!(self.command[0], 0x03 | 0x08 | 0x0d | 0x0e | 0xe5)
+    }
+
+    /// Status and parameter commands always go through the data port.
+    fn uses_dma(&self) -> bool {
+        (self.mask & 0x01) != 0 && !matches!(self.command[0], 0x03 | 0x0c | 0x0d)
+    }
+
+    fn read_block(&mut self) -> Result<Vec<u8>, u8> {
+        match self.command[0] {
+            0x03 => Ok(self.sense.to_vec()),
+            0x0d => Ok(vec![0]),
+            0x0e => Ok(self.sector_buffer.clone()),
+            _ => {
+                let image = self.drives[self.drive()].as_ref().ok_or(ERROR_NOT_READY)?;
+                let mut data = image
+                    .read_sector(self.lba)
+                    .ok_or(ERROR_SECTOR_NOT_FOUND)?
+                    .to_vec();
+                // READ LONG appends the four ECC bytes.
+                data.resize(self.block_len(), 0);
+                Ok(data)
+            }
+        }
+    }
+
+    fn write_block(&mut self, data: &[u8]) -> Result<(), u8> {
+        match self.command[0] {
+            // Drive characteristics are taken from the image instead.
+            0x0c => Ok(()),
+            0x0f => {
+                self.sector_buffer = data.to_vec();
+                Ok(())
+            }
+            _ => {
+                let lba = self.lba;
+                let image = self.drives[self.drive()].as_mut().ok_or(ERROR_NOT_READY)?;
+                if lba >= image.geometry.total_sectors() {
+                    return Err(ERROR_SECTOR_NOT_FOUND);
+                }
+                if image.write_sector(lba, &data[..512]).is_err() {
+                    return Err(ERROR_WRITE_FAULT);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Block address of the command block's disk address.
+    fn command_lba(&self) -> Result<usize, u8> {
+        let image = self.drives[self.drive()].as_ref().ok_or(ERROR_NOT_READY)?;
+        let (cylinder, head, sector) = self.address();
+        image
+            .geometry
+            .lba(cylinder, head, sector.wrapping_add(1))
+            .ok_or(ERROR_ILLEGAL_ADDRESS)
+    }
+
+    fn execute(&mut self) {
+        let count = match self.command[4] {
+            0 => 256,
+            count => count as usize,
+        };
+        let present = self.drives[self.drive()].is_some();
+        let result = match self.command[0] {
+            0x00 | 0x01 | 0xe3 if !present => Err(ERROR_NOT_READY),
+            0x00 | 0x01 | 0xe0 | 0xe3 | 0xe4 => Ok(()),
+            0x03 | 0x0c | 0x0d | 0x0e | 0x0f => {
+                self.remaining = 1;
+                self.phase = XebecPhase::Transfer;
+                return;
+            }
+            0x08 | 0x0a | 0xe5 | 0xe6 => self.command_lba().map(|lba| {
+                self.lba = lba;
+                self.remaining = count;
+                self.phase = XebecPhase::Transfer;
+            }),
+            0x05 => self.command_lba().and_then(|lba| {
+                let total = self.drives[self.drive()]
+                    .as_ref()
+                    .unwrap()
+                    .geometry
+                    .total_sectors();
+                if lba + count > total {
+                    Err(ERROR_SECTOR_NOT_FOUND)
+                } else {
+                    Ok(())
+                }
+            }),
+            0x0b => self.command_lba().map(|_| ()),
+            0x04 | 0x06 | 0x07 => self.format(),
+            _ => Err(ERROR_INVALID_COMMAND),
+        };
+        if self.phase == XebecPhase::Execution {
+            self.finish(result.err());
+        }
+    }
+
+    /// FORMAT DRIVE clears every sector from the track addressed on,
+    /// FORMAT TRACK and FORMAT BAD TRACK just that track.
+    fn format(&mut self) -> Result<(), u8> {
+        let start = self.command_lba()?;
+        let whole_disk = self.command[0] == 0x04;
+        let drive = self.drive();
+        let image = self.drives[drive].as_mut().unwrap();
+        let spt = image.geometry.sectors_per_track as usize;
+        let first = start - start % spt;
+        let end = if whole_disk {
+            image.geometry.total_sectors()
+        } else {
+            first + spt
+        };
+        for lba in first..end {
+            if image.write_sector(lba, &[]).is_err() {
+                return Err(ERROR_WRITE_FAULT);
+            }
+        }
+        Ok(())
+    }
+
+    /// Moves the next block of a data command, through DMA channel 3 when
+    /// enabled and otherwise through the data port.
+    fn transfer<D: DmaTransfer>(&mut self, dma: &mut D, ram: &mut [u8]) {
+        let len = self.block_len();
+        if self.reading() {
+            if !self.buffer.is_empty() {
+                return;
+            }
+            if self.remaining == 0 {
+                return self.finish(None);
+            }
+            if self.uses_dma() && !dma.is_ready(XEBEC_DMA_CHANNEL) {
+                return;
+            }
+            let data = match self.read_block() {
+                Ok(data) => data,
+                Err(code) => return self.finish(Some(code)),
+            };
+            self.lba += 1;
+            self.remaining -= 1;
+            if self.uses_dma() {
+                let (_, tc) = dma.transfer_to_memory(XEBEC_DMA_CHANNEL, ram, &data);
+                if tc {
+                    self.remaining = 0;
+                }
+            } else {
+                self.buffer.extend(data);
+            }
+        } else {
+            if self.remaining == 0 {
+                return self.finish(None);
+            }
+            let data = if self.uses_dma() {
+                if !dma.is_ready(XEBEC_DMA_CHANNEL) {
+                    return;
+                }
+                let (mut data, tc) = dma.transfer_from_memory(XEBEC_DMA_CHANNEL, ram, len);
+                if tc {
+                    self.remaining = 1;
+                }
+                data.resize(len, 0);
+                data
+            } else {
+                if self.buffer.len() < len {
+                    return;
+                }
+                self.buffer.drain(..len).collect()
+            };
+            if let Err(code) = self.write_block(&data) {
+                return self.finish(Some(code));
+            }
+            self.lba += 1;
+            self.remaining -= 1;
+        }
+    }
+
+    /// Runs a command whose block has been written and moves its data.
+    pub fn tick<D: DmaTransfer>(&mut self, dma: &mut D, ram: &mut [u8]) {
+        if self.phase == XebecPhase::Execution {
+            self.execute();
+        }
+        // A DMA transfer runs to completion in one go.
+        while self.phase == XebecPhase::Transfer {
+            let before = (self.remaining, self.buffer.len());
+            self.transfer(dma, ram);
+            if (self.remaining, self.buffer.len()) == before {
+                break;
+            }
+        }
+    }
+
+    /// Hardware status: request (bit 0), input to the host (bit 1),
+    /// command/status (bit 2), busy (bit 3) and interrupt (bit 5).
+    fn hardware_status(&self) -> u8 {
+        let interrupt = if self.irq { 0x20 } else { 0 };
+        interrupt
+            | match self.phase {
+                XebecPhase::Idle => 0,
+                XebecPhase::Command => 0x0d,
+                XebecPhase::Execution => 0x08,
+                XebecPhase::Transfer if self.uses_dma() => 0x08,
+                XebecPhase::Transfer if self.reading() && !self.buffer.is_empty() => 0x0b,
+                XebecPhase::Transfer
+                    if !self.reading()
+                        && self.remaining > 0
+                        && self.buffer.len() < self.block_len() =>
+                {
+                    0x09
+                }
+                XebecPhase::Transfer => 0x08,
+                XebecPhase::Status => 0x0f,
+            }
+    }
+
+    pub fn rb(&mut self, addr: u16) -> u8 {
+        match addr & 3 {
+            0 => match self.phase {
+                XebecPhase::Transfer => self.buffer.pop_front().unwrap_or(0xff),
+                XebecPhase::Status => {
+                    self.phase = XebecPhase::Idle;
+                    self.irq = false;
+                    self.status
+                }
+                _ => 0xff,
+            },
+            1 => self.hardware_status(),
+            2 => self.switches,
+            _ => 0xff,
+        }
+    }
+
+    pub fn wb(&mut self, addr: u16, data: u8) {
+        match addr & 3 {
+            0 => match self.phase {
+                XebecPhase::Command => {
+                    self.command.push(data);
+                    if self.command.len() == 6 {
+                        self.phase = XebecPhase::Execution;
+                    }
+                }
+                XebecPhase::Transfer if !self.reading() => self.buffer.push_back(data),
+                _ => {}
+            },
+            1 => self.reset(),
+            2 => {
+                self.phase = XebecPhase::Command;
+                self.command.clear();
+                self.buffer.clear();
+                self.irq = false;
+            }
+            _ => self.mask = data,
+        }
+    }
+}
+
+impl Default for XebecHdc {
+    fn default() -> XebecHdc {
+        XebecHdc::new()
+    }
+}
+
+#[cfg(test)]
+fn run_hdc_command<D: DmaTransfer>(
+    hdc: &mut XebecHdc,
+    dma: &mut D,
+    ram: &mut [u8],
+    block: [u8; 6],
+    data_out: &[u8],
+) -> (u8, Vec<u8>) {
+    hdc.wb(0x322, 0);
+    for byte in block {
+        assert_eq!(hdc.rb(0x321) & 0x0f, 0x0d);
+        hdc.wb(0x320, byte);
+    }
+    hdc.tick(dma, ram);
+    for &byte in data_out {
+        assert_eq!(hdc.rb(0x321) & 0x0f, 0x09);
+        hdc.wb(0x320, byte);
+        hdc.tick(dma, ram);
+    }
+    let mut data_in = Vec::new();
+    while hdc.rb(0x321) & 0x0f == 0x0b {
+        data_in.push(hdc.rb(0x320));
+        hdc.tick(dma, ram);
+    }
+    assert_eq!(hdc.rb(0x321) & 0x0f, 0x0f);
+    (hdc.rb(0x320), data_in)
+}
+
+#[test]
+fn test_xebec_read_write_and_sense() {
+    let geometry = HardDiskGeometry::new(20, 4, 17);
+    let mut data = vec![0u8; geometry.total_sectors() << 9];
+    for (i, byte) in data.iter_mut().enumerate() {
+        *byte = (i >> 9) as u8;
+    }
+    let mut hdc = XebecHdc::new();
+    hdc.drives[0] = Some(HardDiskImage::from_bytes(data, geometry));
+    let mut dma = DMA::new(false);
+    let mut ram = vec![0u8; 0x10000];
+    hdc.wb(0x323, 0x02);
+    let (status, _) = run_hdc_command(&mut hdc, &mut dma, &mut ram, [0, 0, 0, 0, 0, 0], &[]);
+    assert_eq!(status, 0x00);
+    let (status, _) = run_hdc_command(&mut hdc, &mut dma, &mut ram, [0, 0x20, 0, 0, 0, 0], &[]);
+    assert_eq!(status, 0x22);
+    // Cylinder 1 head 2 sector 3 (0-based) is block 4 * 17 + 2 * 17 + 3.
+    let (status, data) = run_hdc_command(&mut hdc, &mut dma, &mut ram, [0x08, 2, 3, 1, 2, 0], &[]);
+    assert_eq!(status, 0x00);
+    assert_eq!(data.len(), 1024);
+    assert_eq!(data[0], 105);
+    assert_eq!(data[512], 106);
+    // Write one sector through DMA channel 3.
+    ram[0x2000..0x2200].fill(0xc3);
+    dma.wb(0x0b, 0x4b);
+    dma.wb(0x0c, 0);
+    dma.wb(0x06, 0x00);
+    dma.wb(0x06, 0x20);
+    dma.wb(0x07, 0xff);
+    dma.wb(0x07, 0x01);
+    dma.wb(0x0a, 0x03);
+    hdc.wb(0x323, 0x03);
+    let (status, _) = run_hdc_command(&mut hdc, &mut dma, &mut ram, [0x0a, 0, 0, 0, 1, 0], &[]);
+    assert_eq!(status, 0x00);
+    assert!(!hdc.irq());
+    let image = hdc.drives[0].as_ref().unwrap();
+    assert_eq!(image.read_sector(0).unwrap(), &[0xc3; 512][..]);
+    // An address past the last cylinder is reported through REQUEST SENSE.
+    hdc.wb(0x323, 0x02);
+    let (status, _) = run_hdc_command(&mut hdc, &mut dma, &mut ram, [0x08, 0, 0, 30, 1, 0], &[]);
+    assert_eq!(status, 0x02);
+    let (status, sense) = run_hdc_command(&mut hdc, &mut dma, &mut ram, [0x03, 0, 0, 0, 0, 0], &[]);
+    assert_eq!(status, 0x00);
+    assert_eq!(sense, vec![0xa1, 0, 0, 30]);
+    // Write the sector buffer through the data port and read it back.
+    let (status, _) = run_hdc_command(
+        &mut hdc,
+        &mut dma,
+        &mut ram,
+        [0x0f, 0, 0, 0, 0, 0],
+        &[0x5a; 512],
+    );
+    assert_eq!(status, 0x00);
+    let (_, data) = run_hdc_command(&mut hdc, &mut dma, &mut ram, [0x0e, 0, 0, 0, 0, 0], &[]);
+    assert_eq!(data, vec![0x5a; 512]);
+}
@@ -1,16 +1,33 @@
 extern crate bitflags;
 
-use crate::cpu8086::*;
+use crate::hardware::floppy::FloppyImage;
 use crate::hardware::*;
-use std::fs;
+use std::sync::atomic::AtomicBool;
+
+/// Whether the CPUs print every instruction they execute. The terminal
+/// frontend turns this off so the trace doesn't overwrite the screen.
+pub static TRACE: AtomicBool = AtomicBool::new(true);
+
+macro_rules! trace {
+    ($($arg:tt)*) => {
+        if crate::TRACE.load(std::sync::atomic::Ordering::Relaxed) {
+            println!($($arg)*);
+        }
+    };
+}
 
 pub mod cpu286;
 pub mod cpu8086;
 pub mod hardware;
+pub mod terminal;
 
 #[allow(dead_code)]
-
 fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("--terminal") {
+        run_terminal_mode(args.get(2).map_or("pcdos10.img", String::as_str));
+        return;
+    }
     let mut machine = IbmPc5150Machine::new();
     //let mut cpu_thread = SchedulerThread::new(4_772_727);
     //let mut pit_thread = SchedulerThread::new(1_193_182);
@@ -23,12 +40,14 @@ fn main() {
     //scheduler.threads[1].schedule(4, pit_func, &mut machine);
     //scheduler.threads[0].schedule(1, cpu_func, &mut machine.cpu);
 
-    let bootsector: Vec<u8> = fs::read("pcdos10.img").unwrap();
+    let floppy = FloppyImage::open("pcdos10.img").unwrap();
     //for i in 0..=511 {
     //    machine.hardware.ram[i + 0x7c00] = bootsector[i];
     //}
-    machine.hardware.ram[0x7c00..0x7e00].clone_from_slice(&bootsector[..512]);
-    machine.cpu.floppy = bootsector.clone();
+    machine.hardware.ram[0x7c00..0x7e00].clone_from_slice(floppy.read_sector(0, 0, 1).unwrap());
+    machine.hardware.fdc.insert_disk(0, floppy);
+    machine.cpu.set_hle_hook(0x10, true);
+    machine.cpu.set_hle_hook(0x13, true);
 
     //let com: Vec<u8> = fs::read("diamonds.com").unwrap();
     //machine.hardware.ram[0x1000..0x1000+com.len()].copy_from_slice(&com[0..]);
@@ -41,3 +60,23 @@ fn main() {
         machine.tick(cycles);
     }
 }
+
+/// `emupc-rs --terminal [image]`: boots the BIOS with a floppy image in
+/// drive A: and shows the text screen on the host terminal. Ctrl-]
+/// quits.
+fn run_terminal_mode(image: &str) {
+    TRACE.store(false, std::sync::atomic::Ordering::Relaxed);
+    let floppy = match FloppyImage::open(image) {
+        Ok(floppy) => floppy,
+        Err(err) => {
+            eprintln!("Cannot open floppy image {}: {}", image, err);
+            std::process::exit(1);
+        }
+    };
+    let mut machine = IbmPc5150Machine::new();
+    machine.hardware.fdc.insert_disk(0, floppy);
+    if let Err(err) = terminal::run_terminal(&mut machine) {
+        eprintln!("Terminal frontend failed: {}", err);
+        std::process::exit(1);
+    }
+}
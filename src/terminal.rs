@@ -0,0 +1,298 @@
+use crate::hardware::ibmpc5150machine::CPU_CLOCK_5150;
+use crate::hardware::textscreen::TextScreen;
+use crate::hardware::IbmPc5150Machine;
+use std::fmt::Write as _;
+use std::io::{self, Read, Write};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Ctrl-], which leaves the frontend rather than reaching the emulated
+/// keyboard.
+pub const QUIT_KEY: u8 = 0x1d;
+
+/// Screen refreshes per second of emulated and host time.
+const REFRESH_RATE: u64 = 60;
+
+/// ANSI colour numbers of the IBM colours 0-7, which put blue in bit 0
+/// where ANSI puts red.
+const ANSI_COLORS: [u8; 8] = [0, 4, 2, 6, 1, 5, 3, 7];
+
+const SCANCODE_LEFT_SHIFT: u8 = 0x2a;
+const SCANCODE_CTRL: u8 = 0x1d;
+
+/// Scancode set 1 of the keys on the main block of a US keyboard,
+/// unshifted and shifted.
+const KEYBOARD_ROWS: [(u8, &str, &str); 5] = [
+    (0x02, "1234567890-=", "!@#$%^&*()_+"),
+    (0x10, "qwertyuiop[]", "QWERTYUIOP{}"),
+    (0x1e, "asdfghjkl;'`", "ASDFGHJKL:\"~"),
+    (0x2b, "\\zxcvbnm,./", "|ZXCVBNM<>?"),
+    (0x39, " ", " "),
+];
+
+/// SGR escape for a text mode attribute: bright foregrounds from bit 3,
+/// blinking from bit 7.
+pub fn ansi_attr(attr: u8) -> String {
+    let fg = ANSI_COLORS[(attr & 0x07) as usize] + if (attr & 0x08) != 0 { 90 } else { 30 };
+    let bg = ANSI_COLORS[((attr >> 4) & 0x07) as usize] + 40;
+    let blink = if (attr & 0x80) != 0 { ";5" } else { "" };
+    format!("\x1b[0;{};{}{}m", fg, bg, blink)
+}
+
+/// Draws text screens on an ANSI terminal, sending only the cells that
+/// changed since the last one.
+#[derive(Debug, Clone, Default)]
+pub struct AnsiRenderer {
+    previous: Option<TextScreen>,
+}
+
+impl AnsiRenderer {
+    pub fn new() -> Self {
+        Self { previous: None }
+    }
+
+    /// The output that takes the terminal from the last screen drawn to
+    /// `screen`. A screen of a different size is redrawn whole.
+    pub fn update(&mut self, screen: &TextScreen) -> String {
+        let previous = self.previous.as_ref().filter(|previous| {
+            previous.columns == screen.columns && previous.rows.len() == screen.rows.len()
+        });
+        let mut out = String::new();
+        if previous.is_none() {
+            out.push_str("\x1b[0m\x1b[2J");
+        }
+        let mut attr = None;
+        let mut position = None;
+        for (y, row) in screen.rows.iter().enumerate() {
+            for (x, cell) in row.iter().enumerate() {
+                if previous.is_some_and(|previous| previous.rows[y][x] == *cell) {
+                    continue;
+                }
+                if position != Some((y, x)) {
+                    let _ = write!(out, "\x1b[{};{}H", y + 1, x + 1);
+                }
+                if attr != Some(cell.attr) {
+                    out.push_str(&ansi_attr(cell.attr));
+                    attr = Some(cell.attr);
+                }
+                out.push(cell.char());
+                position = Some((y, x + 1));
+            }
+        }
+        let cursor_moved = previous.is_none_or(|previous| previous.cursor != screen.cursor);
+        if !out.is_empty() || cursor_moved {
+            match screen.cursor {
+                Some((y, x)) => {
+                    let _ = write!(out, "\x1b[{};{}H\x1b[?25h", y + 1, x + 1);
+                }
+                None => out.push_str("\x1b[?25l"),
+            }
+        }
+        self.previous = Some(screen.clone());
+        out
+    }
+}
+
+fn key_scancodes(scancodes: &mut Vec<u8>, key: u8, modifier: Option<u8>) {
+    if let Some(modifier) = modifier {
+        scancodes.push(modifier);
+    }
+    scancodes.extend_from_slice(&[key, key | 0x80]);
+    if let Some(modifier) = modifier {
+        scancodes.push(modifier | 0x80);
+    }
+}
+
+/// Scancode of the key that types `ch`, and whether it needs shift.
+fn char_scancode(ch: u8) -> Option<(u8, bool)> {
+    KEYBOARD_ROWS.iter().find_map(|&(first, plain, shifted)| {
+        if let Some(index) = plain.bytes().position(|c| c == ch) {
+            Some((first + index as u8, false))
+        } else {
+            shifted
+                .bytes()
+                .position(|c| c == ch)
+                .map(|index| (first + index as u8, true))
+        }
+    })
+}
+
+/// Translates bytes typed on the host terminal into the make and break
+/// scancodes of an XT keyboard. Cursor and editing keys arrive as VT100
+/// escape sequences; an escape byte on its own is the Esc key.
+pub fn host_keys_to_scancodes(input: &[u8]) -> Vec<u8> {
+    let mut scancodes = Vec::new();
+    let mut i = 0;
+    while i < input.len() {
+        let byte = input[i];
+        i += 1;
+        let (key, modifier) = match byte {
+            0x1b => {
+                let sequence = &input[i..];
+                let (key, len) = match sequence {
+                    [b'[', b'A', ..] => (0x48, 2),
+                    [b'[', b'B', ..] => (0x50, 2),
+                    [b'[', b'C', ..] => (0x4d, 2),
+                    [b'[', b'D', ..] => (0x4b, 2),
+                    [b'[', b'H', ..] => (0x47, 2),
+                    [b'[', b'F', ..] => (0x4f, 2),
+                    [b'[', b'2', b'~', ..] => (0x52, 3),
+                    [b'[', b'3', b'~', ..] => (0x53, 3),
+                    [b'[', b'5', b'~', ..] => (0x49, 3),
+                    [b'[', b'6', b'~', ..] => (0x51, 3),
+                    [b'O', key @ b'P'..=b'S', ..] => (0x3b + (key - b'P'), 2),
+                    _ => (0x01, 0),
+                };
+                i += len;
+                (key, None)
+            }
+            b'\r' | b'\n' => (0x1c, None),
+            0x08 | 0x7f => (0x0e, None),
+            b'\t' => (0x0f, None),
+            0x01..=0x1a => {
+                let (key, _) = char_scancode(byte - 1 + b'a').unwrap();
+                (key, Some(SCANCODE_CTRL))
+            }
+            _ => match char_scancode(byte) {
+                Some((key, true)) => (key, Some(SCANCODE_LEFT_SHIFT)),
+                Some((key, false)) => (key, None),
+                None => continue,
+            },
+        };
+        key_scancodes(&mut scancodes, key, modifier);
+    }
+    scancodes
+}
+
+fn stty(args: &[&str]) -> io::Result<String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other("stty failed"));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Puts the host terminal in raw mode, without echo, until dropped.
+pub struct RawMode {
+    saved: String,
+}
+
+impl RawMode {
+    pub fn enable() -> io::Result<RawMode> {
+        let saved = stty(&["-g"])?;
+        stty(&["raw", "-echo"])?;
+        Ok(RawMode { saved })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = stty(&[&self.saved]);
+    }
+}
+
+/// Reads stdin on its own thread so the emulation never waits for keys.
+fn spawn_stdin_reader() -> Receiver<Vec<u8>> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut stdin = io::stdin();
+        let mut buf = [0u8; 64];
+        while let Ok(len @ 1..) = stdin.read(&mut buf) {
+            if sender.send(buf[..len].to_vec()).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+/// Runs the machine in real time, drawing its text screen on the host
+/// terminal and typing host keystrokes on its keyboard, until the quit
+/// key is pressed. Graphics modes leave the last text screen up.
+pub fn run_terminal(machine: &mut IbmPc5150Machine) -> io::Result<()> {
+    let _raw_mode = RawMode::enable()?;
+    let input = spawn_stdin_reader();
+    let mut stdout = io::stdout().lock();
+    // Switch to the alternate screen, leaving the shell's intact.
+    stdout.write_all(b"\x1b[?1049h")?;
+    let mut renderer = AnsiRenderer::new();
+    let refresh = Duration::from_secs(1) / REFRESH_RATE as u32;
+    let mut next_refresh = Instant::now();
+    'running: loop {
+        let mut cycles = 0;
+        while cycles < CPU_CLOCK_5150 / REFRESH_RATE {
+            let elapsed = machine.cpu.tick(&mut machine.hardware);
+            machine.tick(elapsed);
+            cycles += elapsed as u64;
+        }
+        while let Ok(bytes) = input.try_recv() {
+            if bytes.contains(&QUIT_KEY) {
+                break 'running;
+            }
+            for scancode in host_keys_to_scancodes(&bytes) {
+                machine.hardware.key_event(scancode);
+            }
+        }
+        if let Some(screen) = machine.text_screen() {
+            let out = renderer.update(&screen);
+            if !out.is_empty() {
+                stdout.write_all(out.as_bytes())?;
+                stdout.flush()?;
+            }
+        }
+        next_refresh += refresh;
+        let now = Instant::now();
+        if next_refresh > now {
+            thread::sleep(next_refresh - now);
+        } else {
+            next_refresh = now;
+        }
+    }
+    stdout.write_all(b"\x1b[0m\x1b[?25h\x1b[?1049l")?;
+    stdout.flush()
+}
+
+#[test]
+fn test_terminal_keys_and_rendering() {
+    assert_eq!(host_keys_to_scancodes(b"a"), [0x1e, 0x9e]);
+    assert_eq!(
+        host_keys_to_scancodes(b"A\r"),
+        [0x2a, 0x1e, 0x9e, 0xaa, 0x1c, 0x9c]
+    );
+    assert_eq!(host_keys_to_scancodes(b":"), [0x2a, 0x27, 0xa7, 0xaa]);
+    assert_eq!(host_keys_to_scancodes(b"\x03"), [0x1d, 0x2e, 0xae, 0x9d]);
+    assert_eq!(
+        host_keys_to_scancodes(b"\x1b[A\x1b[6~\x1bOP"),
+        [0x48, 0xc8, 0x51, 0xd1, 0x3b, 0xbb]
+    );
+    assert_eq!(host_keys_to_scancodes(b"\x1b"), [0x01, 0x81]);
+    assert_eq!(host_keys_to_scancodes(b"\x7f\t"), [0x0e, 0x8e, 0x0f, 0x8f]);
+    assert_eq!(ansi_attr(0x07), "\x1b[0;37;40m");
+    assert_eq!(ansi_attr(0x1e), "\x1b[0;93;44m");
+    assert_eq!(ansi_attr(0xc4), "\x1b[0;31;41;5m");
+
+    let mut screen = TextScreen::read(4, 2, |row, column| (b"A>  ...."[row * 4 + column], 0x07));
+    screen.place_cursor(2, 4);
+    let mut renderer = AnsiRenderer::new();
+    let first = renderer.update(&screen);
+    assert!(first.starts_with("\x1b[0m\x1b[2J\x1b[1;1H\x1b[0;37;40mA>  \x1b[2;1H...."));
+    assert!(first.ends_with("\x1b[1;3H\x1b[?25h"));
+    // Nothing changed, nothing sent.
+    assert_eq!(renderer.update(&screen), "");
+    // One changed cell: move there, set its colour, write it, put the
+    // cursor back.
+    let mut changed = screen.clone();
+    changed.rows[0][0].code = b'C';
+    changed.rows[1][3].attr = 0x70;
+    assert_eq!(
+        renderer.update(&changed),
+        "\x1b[1;1H\x1b[0;37;40mC\x1b[2;4H\x1b[0;30;47m.\x1b[1;3H\x1b[?25h"
+    );
+    changed.cursor = None;
+    assert_eq!(renderer.update(&changed), "\x1b[?25l");
+}